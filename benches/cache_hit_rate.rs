@@ -0,0 +1,109 @@
+// Compares the hit rates of the cache eviction policies on point lookups, alone and mixed with
+// large range scans.
+//
+// Run with: cargo bench --bench cache_hit_rate
+
+use levelerdb::cache::{new_clock_cache, new_lru_cache, new_two_queue_cache, Cache};
+use levelerdb::slice::Slice;
+use std::sync::Arc;
+use std::time::Instant;
+
+// Capacity of each cache, in entries (every entry is charged 1).
+const CAPACITY: usize = 10_000;
+// Keys read by point lookups; they fit in the cache with room to spare.
+const HOT_KEYS: u64 = 6_000;
+const POINT_LOOKUPS: usize = 2_000_000;
+// A scan of SCAN_LENGTH never repeated keys runs after every SCAN_INTERVAL point lookups.
+const SCAN_INTERVAL: usize = 100_000;
+const SCAN_LENGTH: u64 = 50_000;
+
+// xorshift64*; the benchmark only needs a fast, repeatable sequence.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Skewed towards small values: the minimum of two uniform draws.
+    fn skewed(&mut self, n: u64) -> u64 {
+        (self.next() % n).min(self.next() % n)
+    }
+}
+
+// Look key up, inserting it on a miss like the table reader does for blocks.
+fn access(cache: &dyn Cache<u64>, key: u64) -> bool {
+    let buf = key.to_le_bytes();
+    let k = Slice::from(&buf[..]);
+    if cache.lookup(&k).is_some() {
+        return true;
+    }
+    drop(cache.insert(&k, Arc::new(key), 1));
+    false
+}
+
+struct Result {
+    hit_rate: f64,
+    micros_per_op: f64,
+}
+
+fn run(cache: &dyn Cache<u64>, scans: bool) -> Result {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut next_scan_key = HOT_KEYS;
+    let mut hits = 0;
+    let mut ops = 0;
+    let start = Instant::now();
+    for i in 0..POINT_LOOKUPS {
+        if scans && i % SCAN_INTERVAL == SCAN_INTERVAL - 1 {
+            for key in next_scan_key..next_scan_key + SCAN_LENGTH {
+                access(cache, key);
+            }
+            next_scan_key += SCAN_LENGTH;
+            ops += SCAN_LENGTH as usize;
+        }
+        if access(cache, rng.skewed(HOT_KEYS)) {
+            hits += 1;
+        }
+        ops += 1;
+    }
+    Result {
+        hit_rate: hits as f64 / POINT_LOOKUPS as f64,
+        micros_per_op: start.elapsed().as_secs_f64() * 1e6 / ops as f64,
+    }
+}
+
+fn main() {
+    type NewCache = fn(usize) -> Arc<dyn Cache<u64>>;
+    let policies: [(&str, NewCache); 3] = [
+        ("lru", new_lru_cache),
+        ("clock", new_clock_cache),
+        ("2q", new_two_queue_cache),
+    ];
+
+    println!("capacity:    {} entries", CAPACITY);
+    println!("hot keys:    {}", HOT_KEYS);
+    println!(
+        "scans:       {} keys every {} point lookups",
+        SCAN_LENGTH, SCAN_INTERVAL
+    );
+    println!("------------------------------------------------");
+    println!(
+        "{:<8} {:>10} {:>14} {:>12}",
+        "policy", "workload", "hit rate", "micros/op"
+    );
+    for (name, new_cache) in policies {
+        for scans in [false, true] {
+            let result = run(&*new_cache(CAPACITY), scans);
+            println!(
+                "{:<8} {:>10} {:>13.2}% {:>12.3}",
+                name,
+                if scans { "scans" } else { "points" },
+                result.hit_rate * 100.0,
+                result.micros_per_op
+            );
+        }
+    }
+}
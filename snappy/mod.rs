@@ -1,9 +1,23 @@
 // snappy/snappy.h
+// snappy/snappy.cc
 
+#[path = "snappy-internal.rs"]
+mod snappy_internal;
+#[path = "snappy-sinksource.rs"]
+mod snappy_sinksource;
+#[path = "snappy-stubs-internal.rs"]
+mod snappy_stubs_internal;
+#[path = "snappy-stubs-public.rs"]
 mod snappy_stubs_public;
-pub use snappy_stubs_public::iovec;
 
-// NOTE: All &mut are pointers in C++
+pub use snappy_sinksource::{ByteArraySource, Sink, Source, UncheckedByteArraySink};
+pub use snappy_stubs_public::Iovec;
+
+use snappy_internal::{compress_fragment, WorkingMemory, CHAR_TABLE, LITERAL, MAXIMUM_TAG_LENGTH};
+use snappy_stubs_internal::Varint;
+
+// NOTE: C++ takes pointers and lengths for flat buffers and string* for outputs; slices and
+// Vec<u8> are used instead so the codec needs no unsafe code
 
 // A light-weight compression algorithm. It is designed for speed of compression and
 // decompression, rather than for the utmost in space savings.
@@ -13,147 +27,1229 @@ pub use snappy_stubs_public::iovec;
 // might look at first using BMDiff and then compressing the output of BMDiff with Snappy.
 
 // ------------------------------------------------------------------------
-// Generic compression/decompresion routines.
+// Generic compression/decompression routines.
 // ------------------------------------------------------------------------
 
-// Compress the bytes read from "source" and append to "sink". Return the number of bytes
+// Compress the bytes read from "reader" and append to "writer". Return the number of bytes
 // written.
-// NOTE: C++ uses Source*, Sink*
-// NOTE: C++ overloads compress
-pub fn compress_source(source: &mut Source, sink: &mut Sink) -> usize;
+// NOTE: C++ overloads Compress
+pub fn compress_source(reader: &mut dyn Source, writer: &mut dyn Sink) -> usize {
+    let mut written = 0;
+    let mut n = reader.available();
+    let mut ulength = [0u8; Varint::MAX32];
+    let p = Varint::encode32(&mut ulength, n as u32);
+    writer.append(&ulength[..p]);
+    written += p;
+
+    let mut wmem = WorkingMemory::new(n);
+
+    while n > 0 {
+        // Get next block to compress (without copying if possible)
+        let num_to_read = n.min(BLOCK_SIZE);
+        let in_place = reader.peek().len() >= num_to_read;
+        if !in_place {
+            // Read into scratch buffer
+            let scratch = wmem.get_scratch_input();
+            let mut bytes_read = 0;
+            while bytes_read < num_to_read {
+                let fragment = reader.peek();
+                let n = fragment.len().min(num_to_read - bytes_read);
+                scratch[bytes_read..bytes_read + n].copy_from_slice(&fragment[..n]);
+                bytes_read += n;
+                reader.skip(n);
+            }
+        }
+
+        // Get encoding table for compression
+        let (table, scratch_input, scratch_output) = wmem.get_hash_table(num_to_read);
+        let fragment = if in_place {
+            &reader.peek()[..num_to_read]
+        } else {
+            &scratch_input[..num_to_read]
+        };
+
+        // Compress input fragment and append to dest
+        let max_output = max_compressed_length(num_to_read);
+        let dest = &mut scratch_output[..max_output];
+        let end = compress_fragment(fragment, dest, table);
+        writer.append(&dest[..end]);
+        written += end;
+
+        n -= num_to_read;
+        if in_place {
+            reader.skip(num_to_read);
+        }
+    }
+
+    written
+}
 
 // Find the uncompressed length of the given stream, as given by the header. Note that the true
 // length could deviate from this; the stream could e.g. be truncated.
 //
-// Also not that this leaves "source" in a state that is unsuitable for further operations, such
+// Also note that this leaves "source" in a state that is unsuitable for further operations, such
 // as raw_uncompress(). You will need to rewind or recreate the source yourself before attempting
 // any further calls.
-// NOTE: C++ uses Source*, Sink*
-// TODO: Return u32 and bool?
-pub fn get_uncompressed_length(source: &mut Source, result: &mut u32) -> bool;
+// NOTE: C++ overloads GetUncompressedLength
+pub fn get_uncompressed_length_source(source: &mut dyn Source, result: &mut u32) -> bool {
+    let mut decompressor = SnappyDecompressor::new(source);
+    decompressor.read_uncompressed_length(result)
+}
 
 // ------------------------------------------------------------------------
 // Higher-level string based routines (should be sufficient for most users)
 // ------------------------------------------------------------------------
 
-// Sets "compressed" to the compressed version of "input[0,input_length-1]". Original contents of
-// "compressed" are lost.
-//
-// REQUIRES: "input" is not an alias of "compressed".
-// NOTE: C++ uses Stirng*
-pub fn compress(input: *const u8, input_length: usize, compressed: &mut String) -> usize;
+// Sets "compressed" to the compressed version of "input". Original contents of "compressed" are
+// lost.
+// NOTE: C++ uses string*
+pub fn compress(input: &[u8], compressed: &mut Vec<u8>) -> usize {
+    // Pre-grow the buffer to the max length of the compressed output
+    compressed.clear();
+    compressed.resize(max_compressed_length(input.len()), 0);
 
-// Decompress "compressed[0,compressed_length-1]" to "uncompressed". Original contents of
-// "uncompressed" are lost.
-//
-// REQUIRES: "compressed[]" is not an alias of "uncompressed".
+    let mut compressed_length = 0;
+    raw_compress(input, compressed, &mut compressed_length);
+    compressed.truncate(compressed_length);
+    compressed_length
+}
+
+// Decompress "compressed" to "uncompressed". Original contents of "uncompressed" are lost.
 //
 // returns false if the message is corrupted and could not be decompressed.
 // NOTE: C++ uses string*
-pub fn uncompress(compressed: *const u8, compressed_length: usize, uncompressed &mut String) -> bool;
+pub fn uncompress(compressed: &[u8], uncompressed: &mut Vec<u8>) -> bool {
+    let mut ulength = 0;
+    if !get_uncompressed_length(compressed, &mut ulength) {
+        return false;
+    }
+    // NOTE: C++ resizes the string to ulength up front. The output is grown as it's produced
+    // instead, so a corrupted length can't cause a huge allocation.
+    uncompressed.clear();
+    let mut reader = ByteArraySource::new(compressed);
+    let mut writer = SnappyVecWriter::new(std::mem::take(uncompressed));
+    let ok = internal_uncompress(&mut reader, &mut writer);
+    *uncompressed = writer.into_inner();
+    ok
+}
 
 // Decompress "compressed" to "uncompressed".
 //
 // returns false if the message is corrupted and could not be decompressed.
-// NOTE: C++ uses Source* and Sink*
-pub fn uncompress_source(compressed: &mut Source, uncompressed: &mut Sink) -> bool;
+// NOTE: C++ overloads Uncompress
+pub fn uncompress_source(compressed: &mut dyn Source, uncompressed: &mut dyn Sink) -> bool {
+    // NOTE: C++ writes straight into the sink's buffer when it can provide one large enough, and
+    // otherwise into blocks that are appended to the sink. Since the sink can't hand out buffers
+    // here, the output is collected and appended once.
+    let mut writer = SnappyVecWriter::new(Vec::new());
+    let ok = internal_uncompress(compressed, &mut writer);
+    uncompressed.append_and_take_ownership(writer.into_inner());
+    ok
+}
 
 // This routine uncompresses as much of the "compressed" as possible into sink. It returns the
-// number of valid bytes added to sink (extra invalid bytes may have been added to errors; the
-// caler should ignore those). The emitted data typically has length get_uncompressed_length(),
+// number of valid bytes added to sink (extra invalid bytes may have been added due to errors; the
+// caller should ignore those). The emitted data typically has length get_uncompressed_length(),
 // but may be shorter if an error is encountered.
-// NOTE: C++ uses Source* and Sink*
-pub fn uncompress_as_much_as_possible(compressed: &mut Source, uncompressed: &mut Sink) -> usize;
+pub fn uncompress_as_much_as_possible(
+    compressed: &mut dyn Source,
+    uncompressed: &mut dyn Sink,
+) -> usize {
+    let mut writer = SnappyVecWriter::new(Vec::new());
+    internal_uncompress(compressed, &mut writer);
+    let produced = writer.produced();
+    uncompressed.append_and_take_ownership(writer.into_inner());
+    produced
+}
 
 // ------------------------------------------------------------------------
 // Lower-level character array based routines. May be useful for efficiency
-// reasons in certain cirsumstances.
+// reasons in certain circumstances.
 // ------------------------------------------------------------------------
 
-// REQUIRES: "compressed" must point to an area of memory that is at least
-// "max_compressed_length(input_length)" bytes in length.
+// REQUIRES: "compressed" must be at least "max_compressed_length(input.len())" bytes in length.
 //
-// Takes the data stored in "input[0..input_length]" and stores it in the array pointed to by
-// "compressed".
+// Takes the data stored in "input" and stores it in "compressed".
 //
 // "compressed_length" is set to the length of the compressed output.
 //
 // Example:
-//    let mut output = " ".repeat(snappy::max_compressed_length(input_length));
-//    let mut output_length = 0usize;
-//    raw_compress(input, input_length, output, output_length.as_mut_ptr());
-//    ...  process(output, output_length) ...
-
-// Given data from the byte source "compressed[0..compressed_length-1]" generated by calling the
-// snappy::compress routine, this routine stores the uncompressed data to
-// "uncompressed[0..get_uncompressed_length(compressed,compressed_length)-1]".
-// Returns false if the  message is corrupted and could not be decrypted.
-pub fn raw_uncompress(compressed: *const u8, compressed_length: usize, uncompressed: *mut u8) -> bool;
-
-// Given data from the byte source "compressed" generated by calling the snappy::compress_source
-// routine, this routine stores the uncompressed data to
-// "uncompressed[0..get_uncompressed_length(compressed,compressed_length)-1]".
-// Returns false if the  message is corrupted and could not be decrypted.
-// NOTE: C++ uses Source*
-// NOTE: C++ overloads raw_uncompress
-pub fn raw_uncompress_source(commpressed: &mut Source, uncompressed: *mut u8) -> bool;
-
-// Given data from the byte source "compressed[0..compressed_length-1]" generated by calling the
-// snappy::compress routine, this routine stores the uncompressed data to the iovec "iov". The
-// number of physical buffers in "iov" is given by iov_cnt and their cumulative size must be at
-// least get_uncompressed_length(compressed). The individual buffers in "iov" must not overlap
-// with each other.
+//    let mut output = vec![0; snappy::max_compressed_length(input.len())];
+//    let mut output_length = 0;
+//    raw_compress(input, &mut output, &mut output_length);
+//    ... process(&output[..output_length]) ...
+pub fn raw_compress(input: &[u8], compressed: &mut [u8], compressed_length: &mut usize) {
+    let mut reader = ByteArraySource::new(input);
+    let mut writer = UncheckedByteArraySink::new(compressed);
+    compress_source(&mut reader, &mut writer);
+
+    // Compute how many bytes were added
+    *compressed_length = writer.current_destination();
+}
+
+// Given data in "compressed" generated by calling the compress routine, this routine stores the
+// uncompressed data to "uncompressed[0..get_uncompressed_length(compressed)-1]".
+// Returns false if the message is corrupted and could not be decrypted.
+pub fn raw_uncompress(compressed: &[u8], uncompressed: &mut [u8]) -> bool {
+    let mut reader = ByteArraySource::new(compressed);
+    raw_uncompress_source(&mut reader, uncompressed)
+}
+
+// Given data from the byte source "compressed" generated by calling the compress_source routine,
+// this routine stores the uncompressed data to
+// "uncompressed[0..get_uncompressed_length(compressed)-1]".
+// Returns false if the message is corrupted and could not be decrypted.
+// NOTE: C++ overloads RawUncompress
+pub fn raw_uncompress_source(compressed: &mut dyn Source, uncompressed: &mut [u8]) -> bool {
+    let mut output = SnappyArrayWriter::new(uncompressed);
+    internal_uncompress(compressed, &mut output)
+}
+
+// Given data in "compressed" generated by calling the compress routine, this routine stores the
+// uncompressed data to the iovec "iov". The cumulative size of the buffers in "iov" must be at
+// least get_uncompressed_length(compressed).
 //
 // Returns false if the message is corrupted and could not be decrypted.
-pub fn raw_uncompress_to_iovec(compressed: *const u8, compressed_length: usize iov: &iovec, iov_cnt: usize) -> bool;
+// NOTE: C++ takes a pointer to the iovecs and their count; the slices are disjoint by construction
+pub fn raw_uncompress_to_iovec(compressed: &[u8], iov: &mut [Iovec]) -> bool {
+    let mut reader = ByteArraySource::new(compressed);
+    raw_uncompress_to_iovec_source(&mut reader, iov)
+}
 
-// Given data from the byte source "compressed" generated by calling the snappy::compress_source
-// routine, this routine stores the uncompressed data to the iovec "iov". The number of physical
-// buffers in "iov" is given by iov_cnt and their cumulative size must be at least
-// get_uncompressed_length(compressed). The individual buffers in "iov" must not overlap with each
-// other.
+// Given data from the byte source "compressed" generated by calling the compress_source routine,
+// this routine stores the uncompressed data to the iovec "iov". The cumulative size of the
+// buffers in "iov" must be at least get_uncompressed_length(compressed).
 //
 // Returns false if the message is corrupted and could not be decrypted.
-// NOTE: C++ uses Source* and iovec*
-// NOTE: C++ overloads raw_uncompress_to_iovec
-pub fn raw_uncompress_to_iovec_source(compressed: &mut Source, iov: &iovec, iov_cnt: usize) -> bool;
+// NOTE: C++ overloads RawUncompressToIOVec
+pub fn raw_uncompress_to_iovec_source(compressed: &mut dyn Source, iov: &mut [Iovec]) -> bool {
+    let mut output = SnappyIOVecWriter::new(iov);
+    internal_uncompress(compressed, &mut output)
+}
 
 // Returns the maximal size of the compressed representation of input data that is "source_bytes"
 // bytes in length;
-pub fn max_compressed_length(source_bytes: usize) -> usize;
+pub fn max_compressed_length(source_bytes: usize) -> usize {
+    // Compressed data can be defined as:
+    //    compressed := item* literal*
+    //    item       := literal* copy
+    //
+    // The trailing literal sequence has a space blowup of at most 62/60 since a literal of length
+    // 60 needs one tag byte + one extra byte for length information.
+    //
+    // Item blowup is trickier to measure. Suppose the "copy" op copies 4 bytes of data. Because of
+    // a special check in the encoding code, we produce a 4-byte copy only if the offset is < 65536.
+    // Therefore the copy op takes 3 bytes to encode, and this type of item leads to at most the
+    // 62/60 blowup for representing literals.
+    //
+    // Suppose the "copy" op copies 5 bytes of data. If the offset is big enough, it will take 5
+    // bytes to encode the copy op. Therefore the worst case here is a one-byte literal followed by
+    // a five-byte copy. I.e., 6 bytes of input turn into 7 bytes of "compressed" data.
+    //
+    // This last factor dominates the blowup, so the final estimate is:
+    32 + source_bytes + source_bytes / 6
+}
 
 // REQUIRES: "compressed" was produced by raw_compress() or compress(). Returns true and stores
 // the length of the uncompressed data in "result". Returns false on parsing error.
 // This operation takes O(1) time.
-// NOTE: C++ uses size_t*
-pub fn get_uncompressed_length(compressed: *const u8, compressed_length: usize, result: &mut usize) -> bool;
+pub fn get_uncompressed_length(compressed: &[u8], result: &mut usize) -> bool {
+    match Varint::parse32_with_limit(compressed) {
+        Some((v, _)) => {
+            *result = v as usize;
+            true
+        }
+        None => false,
+    }
+}
 
 // Returns true iff the contents of "compressed" can be uncompressed successfully. Does not return
-// the uncompressed data. Takes time proportional to compressed_length, but is usually at least a
+// the uncompressed data. Takes time proportional to compressed.len(), but is usually at least a
 // factor of four faster than actual decompression.
-pub fn is_valid_compressed_buffer(compressed: *const u8, compressed_length: usize) -> bool;
+pub fn is_valid_compressed_buffer(compressed: &[u8]) -> bool {
+    let mut reader = ByteArraySource::new(compressed);
+    is_valid_compressed(&mut reader)
+}
 
 // Returns true iff the contents of "compressed" can be uncompressed successfully. Does not return
 // the uncompressed data. Takes time proportional to "compressed" length, but is usually at least
 // a factor of four faster than actual decompression. On success, consumes all of "compressed". On
 // failure, consumes an unspecified prefix of "compressed".
-pub fn is_valid_compressed(compressed: &mut Source) -> bool;
+pub fn is_valid_compressed(compressed: &mut dyn Source) -> bool {
+    let mut writer = SnappyDecompressionValidator::default();
+    internal_uncompress(compressed, &mut writer)
+}
 
-// The size of a compression block. Note that many parts of the compressioni code assums that
+// The size of a compression block. Note that many parts of the compression code assumes that
 // BLOCK_SIZE <= 65536; in particular, the hash table can only store 16-bit offsets, and
 // emit_copy() also assumes the offset is 65535. Note also that if you change this, it will affect
 // the framing format (see framing_format.txt).
 //
 // Note that there might be older data around is compressed with larger block sizes, so the
 // decompression code should not rely on the non-existence of long backreferences.
-// NOTE: C++ uses int
 const BLOCK_LOG: usize = 16;
 const BLOCK_SIZE: usize = 1 << BLOCK_LOG;
 
-// NOTE: C++ uses int
 const MIN_HASH_TABLE_BITS: usize = 8;
 const MIN_HASH_TABLE_SIZE: usize = 1 << MIN_HASH_TABLE_BITS;
 
-// NOTE: C++ uses int
 const MAX_HASH_TABLE_BITS: usize = 14;
 const MAX_HASH_TABLE_SIZE: usize = 1 << MAX_HASH_TABLE_BITS;
+
+// Mapping from i in range [0,4] to a mask to extract the bottom 8*i bits
+const WORDMASK: [u32; 5] = [0, 0xff, 0xffff, 0xffffff, 0xffffffff];
+
+// Reads the "n" little-endian bytes at the start of "buf".
+// NOTE: C++ loads 4 bytes and masks them with WORDMASK, which may read past the tag
+#[inline]
+fn load_le(buf: &[u8], n: usize) -> u32 {
+    let value = buf[..n]
+        .iter()
+        .rev()
+        .fold(0u32, |acc, &b| (acc << 8) | b as u32);
+    value & WORDMASK[n]
+}
+
+// Helper class for decompression
+struct SnappyDecompressor<'a> {
+    // Underlying source of bytes to decompress
+    reader: &'a mut dyn Source,
+    // Current position in the buffer being read
+    ip: usize,
+    // End of the buffer being read
+    ip_limit: usize,
+    // Bytes peeked from reader (need to skip)
+    peeked: usize,
+    // Whether the tag being read was stitched together in scratch rather than being read from the
+    // reader's buffer
+    // NOTE: C++ points ip into scratch instead
+    in_scratch: bool,
+    // Hit end of input without an error?
+    eof: bool,
+    // See refill_tag().
+    scratch: [u8; MAXIMUM_TAG_LENGTH],
+}
+
+impl<'a> SnappyDecompressor<'a> {
+    fn new(reader: &'a mut dyn Source) -> Self {
+        Self {
+            reader,
+            ip: 0,
+            ip_limit: 0,
+            peeked: 0,
+            in_scratch: false,
+            eof: false,
+            scratch: [0; MAXIMUM_TAG_LENGTH],
+        }
+    }
+
+    // Returns true iff we have hit the end of the input without an error.
+    fn eof(&self) -> bool {
+        self.eof
+    }
+
+    // The buffer being read, i.e., [0, ip_limit) of either the reader's buffer or scratch.
+    fn buffer(&mut self) -> &[u8] {
+        if self.in_scratch {
+            &self.scratch[..self.ip_limit]
+        } else {
+            &self.reader.peek()[..self.ip_limit]
+        }
+    }
+
+    // Skips the bytes peeked from the reader and peeks at the next region of it.
+    fn next_fragment(&mut self) -> usize {
+        self.reader.skip(self.peeked);
+        let available = self.reader.available();
+        let n = self.reader.peek().len().min(available);
+        self.peeked = n;
+        self.ip = 0;
+        self.ip_limit = n;
+        self.in_scratch = false;
+        n
+    }
+
+    // Read the uncompressed length stored at the start of the compressed data. On success,
+    // stores the length in "result" and returns true. On failure, returns false.
+    fn read_uncompressed_length(&mut self, result: &mut u32) -> bool {
+        assert_eq!(self.ip, self.ip_limit); // Must not have read anything yet
+                                            // Length is encoded in 1..5 bytes
+        *result = 0;
+        let mut shift = 0u32;
+        loop {
+            if shift >= 32 {
+                return false;
+            }
+            if self.reader.available() == 0 {
+                return false;
+            }
+            let c = match self.reader.peek().first() {
+                Some(&c) => c,
+                None => return false,
+            };
+            self.reader.skip(1);
+            let val = (c & 0x7f) as u32;
+            if ((val << shift) >> shift) != val {
+                return false;
+            }
+            *result |= val << shift;
+            if c < 128 {
+                break;
+            }
+            shift += 7;
+        }
+        true
+    }
+
+    // Process the next item found in the input. Returns true if successful, false on error or
+    // end of input.
+    fn decompress_all_tags<W: Writer>(&mut self, writer: &mut W) {
+        loop {
+            // Make sure the next tag and its trailer are in the buffer.
+            if self.ip_limit - self.ip < MAXIMUM_TAG_LENGTH && !self.refill_tag() {
+                return;
+            }
+
+            let ip = self.ip;
+            let buf = self.buffer();
+            let c = buf[ip];
+            let entry = CHAR_TABLE[c as usize] as usize;
+            let trailer_length = entry >> 11;
+            if c & 0x3 == LITERAL {
+                let mut literal_length = (c >> 2) as usize + 1;
+                let mut ip = ip + 1;
+                if literal_length >= 61 {
+                    // Long literal.
+                    let literal_length_length = literal_length - 60;
+                    literal_length = load_le(&buf[ip..], literal_length_length) as usize + 1;
+                    ip += literal_length_length;
+                }
+
+                let mut avail = self.ip_limit - ip;
+                while avail < literal_length {
+                    if !writer.append(&self.buffer()[ip..ip + avail]) {
+                        return;
+                    }
+                    literal_length -= avail;
+                    avail = self.next_fragment();
+                    ip = 0;
+                    if avail == 0 {
+                        return; // Premature end of input
+                    }
+                }
+                if !writer.append(&self.buffer()[ip..ip + literal_length]) {
+                    return;
+                }
+                self.ip = ip + literal_length;
+            } else {
+                // copy_offset/256 is encoded in bits 8..10. By just fetching those bits, we get
+                // copy_offset (since the bit-field starts at bit 8).
+                let trailer = load_le(&buf[ip + 1..], trailer_length) as usize;
+                let length = entry & 0xff;
+                let copy_offset = entry & 0x700;
+                self.ip = ip + 1 + trailer_length;
+                if !writer.append_from_self(copy_offset + trailer, length) {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Ensure that all of the tag metadata for the next tag is available in the buffer being
+    // read. Returns false on error or end of input.
+    fn refill_tag(&mut self) -> bool {
+        if self.ip == self.ip_limit {
+            // Fetch a new fragment from the reader
+            if self.next_fragment() == 0 {
+                self.eof = true;
+                return false;
+            }
+        }
+
+        // Read the tag character
+        assert!(self.ip < self.ip_limit);
+        let ip = self.ip;
+        let c = self.buffer()[ip];
+        let entry = CHAR_TABLE[c as usize] as usize;
+        let needed = (entry >> 11) + 1; // +1 byte for 'c'
+        assert!(needed <= self.scratch.len());
+
+        // Read more bytes from reader if needed
+        let mut nbuf = self.ip_limit - self.ip;
+        if nbuf < needed {
+            // Stitch together bytes from ip and reader to form the word contents. We do not
+            // simply read the bytes from the reader since they may be stored in a different
+            // buffer.
+            let mut scratch = [0u8; MAXIMUM_TAG_LENGTH];
+            let ip = self.ip;
+            scratch[..nbuf].copy_from_slice(&self.buffer()[ip..]);
+            self.reader.skip(self.peeked); // All peeked bytes are used up
+            self.peeked = 0;
+            while nbuf < needed {
+                let available = self.reader.available();
+                let fragment = self.reader.peek();
+                let length = fragment.len().min(available);
+                if length == 0 {
+                    return false;
+                }
+                let to_add = length.min(needed - nbuf);
+                scratch[nbuf..nbuf + to_add].copy_from_slice(&fragment[..to_add]);
+                nbuf += to_add;
+                self.reader.skip(to_add);
+            }
+            assert_eq!(nbuf, needed);
+            self.scratch = scratch;
+            self.in_scratch = true;
+            self.ip = 0;
+            self.ip_limit = needed;
+        }
+        true
+    }
+}
+
+impl Drop for SnappyDecompressor<'_> {
+    fn drop(&mut self) {
+        // Advance past any bytes we peeked at from the reader
+        self.reader.skip(self.peeked);
+    }
+}
+
+// NOTE: C++ templates the decompression routines on the writer type
+trait Writer {
+    fn set_expected_length(&mut self, len: usize);
+    fn check_length(&self) -> bool;
+    fn append(&mut self, ip: &[u8]) -> bool;
+    fn append_from_self(&mut self, offset: usize, len: usize) -> bool;
+}
+
+fn internal_uncompress<W: Writer>(r: &mut dyn Source, writer: &mut W) -> bool {
+    // Read the uncompressed length from the front of the compressed input
+    let mut decompressor = SnappyDecompressor::new(r);
+    let mut uncompressed_len = 0;
+    if !decompressor.read_uncompressed_length(&mut uncompressed_len) {
+        return false;
+    }
+    internal_uncompress_all_tags(&mut decompressor, writer, uncompressed_len)
+}
+
+fn internal_uncompress_all_tags<W: Writer>(
+    decompressor: &mut SnappyDecompressor,
+    writer: &mut W,
+    uncompressed_len: u32,
+) -> bool {
+    writer.set_expected_length(uncompressed_len as usize);
+
+    // Process the entire input
+    decompressor.decompress_all_tags(writer);
+    decompressor.eof() && writer.check_length()
+}
+
+// Copies "len" bytes starting "offset" bytes before "op" in "buf" to "op". The regions may
+// overlap, in which case the pattern starting at "op - offset" is repeated.
+// NOTE: C++ calls this IncrementalCopy and takes pointers
+#[inline]
+fn incremental_copy(buf: &mut [u8], op: usize, offset: usize, len: usize) {
+    let src = op - offset;
+    if offset >= len {
+        buf.copy_within(src..src + len, op);
+    } else {
+        for i in 0..len {
+            buf[op + i] = buf[src + i];
+        }
+    }
+}
+
+// A type that writes to a flat array. Note that this is not a "ByteSink", but a type that
+// matches the Writer trait that SnappyDecompressor::decompress_all_tags() expects.
+struct SnappyArrayWriter<'a> {
+    base: &'a mut [u8],
+    op: usize,
+    op_limit: usize,
+    expected: usize,
+}
+
+impl<'a> SnappyArrayWriter<'a> {
+    fn new(dst: &'a mut [u8]) -> Self {
+        Self {
+            base: dst,
+            op: 0,
+            op_limit: 0,
+            expected: 0,
+        }
+    }
+}
+
+impl Writer for SnappyArrayWriter<'_> {
+    fn set_expected_length(&mut self, len: usize) {
+        // NOTE: C++ trusts the array to be large enough; writes are bounded by it here too
+        self.expected = len;
+        self.op_limit = len.min(self.base.len());
+    }
+
+    fn check_length(&self) -> bool {
+        self.op == self.expected
+    }
+
+    fn append(&mut self, ip: &[u8]) -> bool {
+        let len = ip.len();
+        let space_left = self.op_limit - self.op;
+        if space_left < len {
+            return false;
+        }
+        self.base[self.op..self.op + len].copy_from_slice(ip);
+        self.op += len;
+        true
+    }
+
+    fn append_from_self(&mut self, offset: usize, len: usize) -> bool {
+        // Check if we try to append from before the start of the buffer. Also rejects
+        // offset == 0.
+        if offset.wrapping_sub(1) >= self.op {
+            return false;
+        }
+        if len > self.op_limit - self.op {
+            return false;
+        }
+        incremental_copy(self.base, self.op, offset, len);
+        self.op += len;
+        true
+    }
+}
+
+// A Writer that collects the output in a Vec, growing it as the output is produced.
+// NOTE: Stands in for C++'s SnappyScatteredWriter, which writes into blocks handed out by a
+// SnappySinkAllocator
+struct SnappyVecWriter {
+    output: Vec<u8>,
+    expected: usize,
+}
+
+impl SnappyVecWriter {
+    fn new(output: Vec<u8>) -> Self {
+        Self {
+            output,
+            expected: 0,
+        }
+    }
+
+    // Return the number of bytes actually uncompressed so far
+    fn produced(&self) -> usize {
+        self.output.len()
+    }
+
+    fn into_inner(self) -> Vec<u8> {
+        self.output
+    }
+}
+
+impl Writer for SnappyVecWriter {
+    fn set_expected_length(&mut self, len: usize) {
+        assert!(self.output.is_empty());
+        self.expected = len;
+    }
+
+    fn check_length(&self) -> bool {
+        self.output.len() == self.expected
+    }
+
+    fn append(&mut self, ip: &[u8]) -> bool {
+        if self.expected - self.output.len() < ip.len() {
+            return false;
+        }
+        self.output.extend_from_slice(ip);
+        true
+    }
+
+    fn append_from_self(&mut self, offset: usize, len: usize) -> bool {
+        let op = self.output.len();
+        // Check if we try to append from before the start of the buffer. Also rejects
+        // offset == 0.
+        if offset.wrapping_sub(1) >= op {
+            return false;
+        }
+        if len > self.expected - op {
+            return false;
+        }
+        self.output.resize(op + len, 0);
+        incremental_copy(&mut self.output, op, offset, len);
+        true
+    }
+}
+
+// A Writer that drops everything on the floor and just does validation
+#[derive(Default)]
+struct SnappyDecompressionValidator {
+    expected: usize,
+    produced: usize,
+}
+
+impl Writer for SnappyDecompressionValidator {
+    fn set_expected_length(&mut self, len: usize) {
+        self.expected = len;
+    }
+
+    fn check_length(&self) -> bool {
+        self.expected == self.produced
+    }
+
+    fn append(&mut self, ip: &[u8]) -> bool {
+        self.produced += ip.len();
+        self.produced <= self.expected
+    }
+
+    fn append_from_self(&mut self, offset: usize, len: usize) -> bool {
+        // Check if we try to append from before the start of the buffer. Also rejects
+        // offset == 0.
+        if self.produced <= offset.wrapping_sub(1) {
+            return false;
+        }
+        self.produced += len;
+        self.produced <= self.expected
+    }
+}
+
+// A Writer that writes to an iovec.
+struct SnappyIOVecWriter<'a, 'b> {
+    output_iov: &'a mut [Iovec<'b>],
+
+    // We are currently writing into output_iov[curr_iov_index].
+    curr_iov_index: usize,
+
+    // Bytes written to output_iov[curr_iov_index] so far.
+    curr_iov_written: usize,
+
+    // Total bytes decompressed into output_iov so far.
+    total_written: usize,
+
+    // Maximum number of bytes that will be decompressed into output_iov.
+    output_limit: usize,
+}
+
+impl<'a, 'b> SnappyIOVecWriter<'a, 'b> {
+    // Does not take ownership of iov. iov must be valid during the entire lifetime of the
+    // SnappyIOVecWriter.
+    fn new(iov: &'a mut [Iovec<'b>]) -> Self {
+        Self {
+            output_iov: iov,
+            curr_iov_index: 0,
+            curr_iov_written: 0,
+            total_written: 0,
+            output_limit: usize::MAX,
+        }
+    }
+
+    fn curr_iov_remaining(&self) -> usize {
+        self.output_iov[self.curr_iov_index].len() - self.curr_iov_written
+    }
+}
+
+impl Writer for SnappyIOVecWriter<'_, '_> {
+    fn set_expected_length(&mut self, len: usize) {
+        self.output_limit = len;
+    }
+
+    fn check_length(&self) -> bool {
+        self.total_written == self.output_limit
+    }
+
+    fn append(&mut self, mut ip: &[u8]) -> bool {
+        if self.total_written + ip.len() > self.output_limit {
+            return false;
+        }
+
+        while !ip.is_empty() {
+            if self.curr_iov_index >= self.output_iov.len() {
+                return false;
+            }
+            if self.curr_iov_remaining() == 0 {
+                // This iovec is full. Go to the next one.
+                if self.curr_iov_index + 1 >= self.output_iov.len() {
+                    return false;
+                }
+                self.curr_iov_index += 1;
+                self.curr_iov_written = 0;
+                continue;
+            }
+
+            let to_write = ip.len().min(self.curr_iov_remaining());
+            let start = self.curr_iov_written;
+            self.output_iov[self.curr_iov_index][start..start + to_write]
+                .copy_from_slice(&ip[..to_write]);
+            self.curr_iov_written += to_write;
+            self.total_written += to_write;
+            ip = &ip[to_write..];
+        }
+
+        true
+    }
+
+    fn append_from_self(&mut self, mut offset: usize, mut len: usize) -> bool {
+        // Check if we try to append from before the start of the buffer. Also rejects
+        // offset == 0.
+        if offset.wrapping_sub(1) >= self.total_written {
+            return false;
+        }
+        let space_left = self.output_limit - self.total_written;
+        if len > space_left {
+            return false;
+        }
+
+        // Locate the iovec from which we need to start the copy.
+        let mut from_iov_index = self.curr_iov_index;
+        let mut from_iov_offset = self.curr_iov_written;
+        while offset > 0 {
+            if from_iov_offset >= offset {
+                from_iov_offset -= offset;
+                break;
+            }
+
+            offset -= from_iov_offset;
+            assert!(from_iov_index > 0);
+            from_iov_index -= 1;
+            from_iov_offset = self.output_iov[from_iov_index].len();
+        }
+
+        // Copy "len" bytes starting from the iovec pointed to by from_iov_index to the current
+        // iovec.
+        while len > 0 {
+            assert!(from_iov_index <= self.curr_iov_index);
+            if from_iov_index != self.curr_iov_index {
+                let from_iov = &self.output_iov[from_iov_index];
+                let to_copy = (from_iov.len() - from_iov_offset).min(len);
+                // NOTE: C++ appends straight from the earlier iovec; the bytes are copied out
+                // first since the iovecs can't be borrowed mutably and immutably at once
+                let bytes = from_iov[from_iov_offset..from_iov_offset + to_copy].to_vec();
+                if !self.append(&bytes) {
+                    return false;
+                }
+                len -= to_copy;
+                if len > 0 {
+                    from_iov_index += 1;
+                    from_iov_offset = 0;
+                }
+            } else {
+                let mut to_copy = self.curr_iov_remaining();
+                if to_copy == 0 {
+                    // This iovec is full. Go to the next one.
+                    if self.curr_iov_index + 1 >= self.output_iov.len() {
+                        return false;
+                    }
+                    self.curr_iov_index += 1;
+                    self.curr_iov_written = 0;
+                    continue;
+                }
+                if to_copy > len {
+                    to_copy = len;
+                }
+                let op = self.curr_iov_written;
+                incremental_copy(
+                    &mut self.output_iov[self.curr_iov_index][..op + to_copy],
+                    op,
+                    op - from_iov_offset,
+                    to_copy,
+                );
+                self.curr_iov_written += to_copy;
+                from_iov_offset += to_copy;
+                self.total_written += to_copy;
+                len -= to_copy;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::snappy_internal::{find_match_length, COPY_4_BYTE_OFFSET};
+    use super::*;
+    use crate::util::random::Random;
+
+    // A Source that only hands out "fragment" bytes at a time.
+    struct FragmentedSource<'a> {
+        data: &'a [u8],
+        fragment: usize,
+    }
+
+    impl Source for FragmentedSource<'_> {
+        fn available(&self) -> usize {
+            self.data.len()
+        }
+
+        fn peek(&mut self) -> &[u8] {
+            &self.data[..self.fragment.min(self.data.len())]
+        }
+
+        fn skip(&mut self, n: usize) {
+            self.data = &self.data[n..];
+        }
+    }
+
+    fn random_bytes(rnd: &mut Random, len: usize) -> Vec<u8> {
+        (0..len).map(|_| rnd.uniform(256) as u8).collect()
+    }
+
+    // Returns data with a lot of repetition, in the spirit of the "skewed" inputs used by C++.
+    fn compressible_bytes(rnd: &mut Random, len: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let run = (rnd.skewed(10) as usize).min(len - data.len());
+            if !data.is_empty() && rnd.one_in(2) {
+                let start = rnd.uniform(data.len() as u32) as usize;
+                let end = (start + run).min(data.len());
+                data.extend_from_within(start..end);
+            } else {
+                let b = b'a' + rnd.uniform(4) as u8;
+                data.extend(std::iter::repeat_n(b, run));
+            }
+            if run == 0 {
+                data.push(b'z');
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    // Compresses "input" every way possible and checks that it can be uncompressed every way
+    // possible. Returns the compressed size.
+    fn verify_string(input: &[u8]) -> usize {
+        let mut compressed = Vec::new();
+        let written = compress(input, &mut compressed);
+        assert_eq!(written, compressed.len());
+        assert!(compressed.len() <= max_compressed_length(input.len()));
+        assert!(is_valid_compressed_buffer(&compressed));
+
+        let mut ulength = 0;
+        assert!(get_uncompressed_length(&compressed, &mut ulength));
+        assert_eq!(input.len(), ulength);
+
+        let mut uncompressed = Vec::new();
+        assert!(uncompress(&compressed, &mut uncompressed));
+        assert_eq!(input, &uncompressed[..]);
+
+        let mut raw = vec![0u8; input.len()];
+        assert!(raw_uncompress(&compressed, &mut raw));
+        assert_eq!(input, &raw[..]);
+
+        // The streaming API must give the same results when fed in small pieces
+        for fragment in [1, 7, BLOCK_SIZE - 1] {
+            let mut source = FragmentedSource {
+                data: input,
+                fragment,
+            };
+            let mut sink = Vec::new();
+            assert_eq!(written, compress_source(&mut source, &mut sink));
+            assert_eq!(compressed, sink);
+
+            let mut source = FragmentedSource {
+                data: &compressed,
+                fragment,
+            };
+            let mut sink = Vec::new();
+            assert!(uncompress_source(&mut source, &mut sink));
+            assert_eq!(input, &sink[..]);
+
+            let mut source = FragmentedSource {
+                data: &compressed,
+                fragment,
+            };
+            assert!(is_valid_compressed(&mut source));
+            assert_eq!(0, source.available());
+        }
+
+        compressed.len()
+    }
+
+    #[test]
+    fn test_simple_tests() {
+        verify_string(b"");
+        verify_string(b"a");
+        verify_string(b"ab");
+        verify_string(b"abc");
+
+        verify_string(&[b"aaaaaaa".as_slice(), &[b'b'; 16], b"aaaaa", b"abc"].concat());
+        verify_string(&[b"aaaaaaa".as_slice(), &[b'b'; 256], b"aaaaa", b"abc"].concat());
+        verify_string(&[b"aaaaaaa".as_slice(), &[b'b'; 2047], b"aaaaa", b"abc"].concat());
+        verify_string(&[b"aaaaaaa".as_slice(), &[b'b'; 65536], b"aaaaa", b"abc"].concat());
+        verify_string(&[b"abcaaaaaaa".as_slice(), &[b'b'; 65536], b"aaaaa", b"abc"].concat());
+    }
+
+    // Verify max blowup (lots of four-byte copies)
+    #[test]
+    fn test_max_blowup() {
+        let mut rnd = Random::new(301);
+        let mut input = Vec::new();
+        for _ in 0..80000 {
+            input.extend_from_slice(&rnd.next().to_le_bytes());
+        }
+        for i in (0..80000).step_by(4).rev() {
+            let word = input[i..i + 4].to_vec();
+            input.extend_from_slice(&word);
+        }
+        verify_string(&input);
+    }
+
+    #[test]
+    fn test_random_data() {
+        let mut rnd = Random::new(301);
+        for i in 0..100 {
+            let len = if i < 10 {
+                BLOCK_SIZE - 5 + i
+            } else {
+                rnd.skewed(17) as usize
+            };
+            let random = random_bytes(&mut rnd, len);
+            verify_string(&random);
+
+            let compressible = compressible_bytes(&mut rnd, len);
+            let compressed_length = verify_string(&compressible);
+            if len > 1000 {
+                assert!(compressed_length < len / 2, "{} {}", compressed_length, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_encoding() {
+        // Inputs shorter than 15 bytes are emitted as a single literal
+        let mut compressed = Vec::new();
+        compress(b"abcabcabcab", &mut compressed);
+        assert_eq!(b"\x0b\x28abcabcabcab", &compressed[..]);
+
+        // A literal followed by an overlapping copy with a 1-byte offset
+        let mut uncompressed = Vec::new();
+        assert!(uncompress(b"\x0b\x08abc\x11\x03", &mut uncompressed));
+        assert_eq!(b"abcabcabcab", &uncompressed[..]);
+
+        // 2-byte offset copies
+        assert!(uncompress(b"\x0b\x08abc\x1e\x03\x00", &mut uncompressed));
+        assert_eq!(b"abcabcabcab", &uncompressed[..]);
+
+        // Long literals store their length in the bytes after the tag
+        let literal = vec![b'x'; 300];
+        let mut stream = vec![0xac, 0x02, (61 << 2) as u8, 0x2b, 0x01];
+        stream.extend_from_slice(&literal);
+        assert!(uncompress(&stream, &mut uncompressed));
+        assert_eq!(literal, uncompressed);
+
+        // Long runs are split into copies of at most 64 bytes
+        let run = vec![b'y'; 200];
+        compress(&run, &mut compressed);
+        assert_eq!(
+            b"\xc8\x01\x00y\xfe\x01\x00\xfe\x01\x00\xfe\x01\x00\x0d\x01",
+            &compressed[..]
+        );
+        assert!(uncompress(&compressed, &mut uncompressed));
+        assert_eq!(run, uncompressed);
+    }
+
+    // Check that we can read streams with four-byte offsets, which the compressor never emits
+    #[test]
+    fn test_four_byte_offset() {
+        let fragment1 = b"012345689abcdefghijklmnopqrstuvwxyz";
+        let fragment2 = b"some other string";
+
+        // How many times each fragment is emitted.
+        let n1 = 2;
+        let n2 = 100000 / fragment2.len();
+        let length = n1 * fragment1.len() + n2 * fragment2.len();
+
+        let mut compressed = Vec::new();
+        let mut ulength = [0u8; Varint::MAX32];
+        let n = Varint::encode32(&mut ulength, length as u32);
+        compressed.extend_from_slice(&ulength[..n]);
+
+        let mut src = Vec::new();
+        let emit_literal = |compressed: &mut Vec<u8>, literal: &[u8]| {
+            compressed.push(LITERAL | (((literal.len() - 1) as u8) << 2));
+            compressed.extend_from_slice(literal);
+        };
+        emit_literal(&mut compressed, fragment1);
+        src.extend_from_slice(fragment1);
+        for _ in 0..n2 {
+            emit_literal(&mut compressed, fragment2);
+            src.extend_from_slice(fragment2);
+        }
+        compressed.push(COPY_4_BYTE_OFFSET | (((fragment1.len() - 1) as u8) << 2));
+        let offset = (src.len() as u32).to_le_bytes();
+        compressed.extend_from_slice(&offset);
+        src.extend_from_slice(fragment1);
+        assert_eq!(length, src.len());
+
+        let mut uncompressed = Vec::new();
+        assert!(is_valid_compressed_buffer(&compressed));
+        assert!(uncompress(&compressed, &mut uncompressed));
+        assert_eq!(src, uncompressed);
+    }
+
+    #[test]
+    fn test_corrupted_input() {
+        let mut uncompressed = Vec::new();
+        let mut ulength = 0;
+
+        // The length varint is too long
+        let bad = b"\xff\xff\xff\xff\xff\x0f";
+        assert!(!get_uncompressed_length(bad, &mut ulength));
+        assert!(!is_valid_compressed_buffer(bad));
+        assert!(!uncompress(bad, &mut uncompressed));
+        assert!(!get_uncompressed_length(b"\x80", &mut ulength));
+
+        // A copy with a zero offset
+        let zero_offset = b"\x05\x12\x00\x00";
+        assert!(!is_valid_compressed_buffer(zero_offset));
+        assert!(!uncompress(zero_offset, &mut uncompressed));
+
+        // A copy from before the start of the output
+        let before_start = b"\x0b\x08abc\x11\x04";
+        assert!(!is_valid_compressed_buffer(before_start));
+        assert!(!uncompress(before_start, &mut uncompressed));
+
+        let mut source = b"making sure we don't crash with corrupted input".to_vec();
+        let mut compressed = Vec::new();
+        compress(&source, &mut compressed);
+        assert!(uncompress(&compressed, &mut uncompressed));
+        assert_eq!(source, uncompressed);
+
+        // The output can't be longer than the length in the header
+        compressed[0] -= 1;
+        assert!(!is_valid_compressed_buffer(&compressed));
+        assert!(!uncompress(&compressed, &mut uncompressed));
+        let mut raw = vec![0u8; source.len()];
+        assert!(!raw_uncompress(&compressed, &mut raw));
+
+        // Nor shorter
+        compressed[0] += 2;
+        assert!(!is_valid_compressed_buffer(&compressed));
+        assert!(!uncompress(&compressed, &mut uncompressed));
+
+        // A huge length is rejected without allocating the output
+        compressed[0] = 0xff;
+        compressed.splice(1..1, [0xff, 0xff, 0xff, 0x0f]);
+        assert!(!is_valid_compressed_buffer(&compressed));
+        assert!(!uncompress(&compressed, &mut uncompressed));
+
+        // Truncated input
+        source = compressible_bytes(&mut Random::new(301), 10000);
+        compress(&source, &mut compressed);
+        for len in [compressed.len() - 1, compressed.len() / 2, 2] {
+            assert!(!is_valid_compressed_buffer(&compressed[..len]));
+            assert!(!uncompress(&compressed[..len], &mut uncompressed));
+
+            // As much as possible is still recovered
+            let mut reader = ByteArraySource::new(&compressed[..len]);
+            let mut sink = Vec::new();
+            let produced = uncompress_as_much_as_possible(&mut reader, &mut sink);
+            assert!(produced < source.len());
+            assert_eq!(source[..produced], sink[..produced]);
+        }
+
+        // Random corruption never panics
+        let mut rnd = Random::new(301);
+        for _ in 0..1000 {
+            let mut corrupted = compressed.clone();
+            for _ in 0..1 + rnd.uniform(4) {
+                let i = rnd.uniform(corrupted.len() as u32) as usize;
+                corrupted[i] = rnd.uniform(256) as u8;
+            }
+            let valid = is_valid_compressed_buffer(&corrupted);
+            assert_eq!(valid, uncompress(&corrupted, &mut uncompressed));
+        }
+    }
+
+    #[test]
+    fn test_iovec() {
+        let mut rnd = Random::new(301);
+        let input = compressible_bytes(&mut rnd, 5000);
+        let mut compressed = Vec::new();
+        compress(&input, &mut compressed);
+
+        for sizes in [
+            vec![5000],
+            vec![1; 5000],
+            vec![1, 2, 3, 4, 4990],
+            vec![1000, 0, 3000, 0, 1000],
+            [vec![7; 714], vec![2]].concat(),
+        ] {
+            let mut buffers: Vec<Vec<u8>> = sizes.iter().map(|&n| vec![0u8; n]).collect();
+            let mut iov: Vec<Iovec> = buffers.iter_mut().map(|b| Iovec::new(b)).collect();
+            assert!(raw_uncompress_to_iovec(&compressed, &mut iov));
+            drop(iov);
+            assert_eq!(input, buffers.concat());
+        }
+
+        // The iovecs must be able to hold all of the output
+        let mut buffers = [vec![0u8; 1000], vec![0u8; 3999]];
+        let mut iov: Vec<Iovec> = buffers.iter_mut().map(|b| Iovec::new(b)).collect();
+        assert!(!raw_uncompress_to_iovec(&compressed, &mut iov));
+        assert!(!raw_uncompress_to_iovec(&compressed, &mut []));
+    }
+
+    #[test]
+    fn test_get_uncompressed_length_source() {
+        let mut compressed = Vec::new();
+        compress(&[b'a'; 1000], &mut compressed);
+        let mut result = 0;
+        let mut source = FragmentedSource {
+            data: &compressed,
+            fragment: 1,
+        };
+        assert!(get_uncompressed_length_source(&mut source, &mut result));
+        assert_eq!(1000, result);
+
+        let mut source = ByteArraySource::new(b"\x80\x80");
+        assert!(!get_uncompressed_length_source(&mut source, &mut result));
+    }
+
+    #[test]
+    fn test_find_match_length() {
+        // Exercise all different code paths through the function.
+        // 64-bit version:
+
+        // Hit s1_limit in 64-bit loop, hit s1_limit in single-character loop.
+        assert_eq!(6, find_match_length(b"012345", b"012345"));
+        assert_eq!(11, find_match_length(b"01234567abc", b"01234567abc"));
+
+        // Hit s1_limit in 64-bit loop, find a non-match in single-character loop.
+        assert_eq!(9, find_match_length(b"01234567abc", b"01234567axc"));
+
+        // Same, but edge cases.
+        assert_eq!(12, find_match_length(b"01234567abc!", b"01234567abc!"));
+        assert_eq!(11, find_match_length(b"01234567abc!", b"01234567abc?"));
+
+        // Find non-match at once in first loop.
+        assert_eq!(
+            0,
+            find_match_length(b"01234567xxxxxxxx", b"?1234567xxxxxxxx")
+        );
+        assert_eq!(
+            1,
+            find_match_length(b"01234567xxxxxxxx", b"0?234567xxxxxxxx")
+        );
+        assert_eq!(
+            4,
+            find_match_length(b"01234567xxxxxxxx", b"01237654xxxxxxxx")
+        );
+        assert_eq!(
+            6,
+            find_match_length(b"01234567xxxxxxxx", b"012345?7xxxxxxxx")
+        );
+
+        // Find non-match in first loop after one block.
+        assert_eq!(
+            8,
+            find_match_length(b"abcdefgh01234567xxxxxxxx", b"abcdefgh?1234567xxxxxxxx")
+        );
+        assert_eq!(
+            12,
+            find_match_length(b"abcdefgh01234567xxxxxxxx", b"abcdefgh0123?567xxxxxxxx")
+        );
+        assert_eq!(
+            14,
+            find_match_length(b"abcdefgh01234567xxxxxxxx", b"abcdefgh012345?7xxxxxxxx")
+        );
+
+        // s1 may be longer than s2
+        assert_eq!(3, find_match_length(b"abcdef", b"abc"));
+        assert_eq!(0, find_match_length(b"abc", b""));
+    }
+}
@@ -1,376 +1,342 @@
 // snappy/snappy-internal.h
+// snappy/snappy.cc (compression internals)
 
-use std::arch::asm;
+// NOTE: C++ also defines SSSE3/NEON shuffle helpers here, which are only used by the unaligned
+// fast paths of the decompressor. Those rely on reading and writing past the end of buffers, so
+// neither they nor the fast paths are ported.
 
-#[path = "snappy-stubs-internal.rs"]
-mod snappy_stubs_internal;
-use snappy_stubs_internal::{
-    Bits, LittleEndian, SNAPPY_PREDICT_TRUE, UNALIGNED_LOAD32, UNALIGNED_LOAD64,
-};
+use super::snappy_stubs_internal::{Bits, LittleEndian};
+use super::{max_compressed_length, BLOCK_SIZE, MAX_HASH_TABLE_SIZE, MIN_HASH_TABLE_SIZE};
 
-#[cfg(all(
-    any(target_arch = "x86", target_arch = "x86_64"),
-    target_feature = "ssse3",
-    target_feature = "sse2"
-))]
-pub(crate) mod internal {
-    #[cfg(target_arch = "x86")]
-    use std::arch::x86::*;
-    #[cfg(target_arch = "x86_64")]
-    use std::arch::x86_64::*;
-
-    type V128 = __m128i;
+// Working memory performs a single allocation to hold all scratch space required for compression.
+// NOTE: C++ carves the table and scratch buffers out of one allocation; each is its own Vec here
+pub(crate) struct WorkingMemory {
+    // The hashtable
+    table: Vec<u16>,
+    // The input scratch buffer
+    input: Vec<u8>,
+    // The output scratch buffer
+    output: Vec<u8>,
+}
 
-    // Load 128 bits of integer data. `src` must be 16-byte aligned.
-    // NOTE: C++ uses const*
-    #[inline]
-    pub(crate) fn v128_load(src: &V128) -> V128 {
-        unsafe { _mm_load_si128(src as *const _) }
+impl WorkingMemory {
+    pub(crate) fn new(input_size: usize) -> Self {
+        let max_fragment_size = input_size.min(BLOCK_SIZE);
+        Self {
+            table: vec![0; calculate_table_size(max_fragment_size)],
+            input: vec![0; max_fragment_size],
+            output: vec![0; max_compressed_length(max_fragment_size)],
+        }
     }
 
-    // Load 128 bits of integer data. `src` does not need to be aligned.
-    #[inline]
-    pub(crate) fn v128_loadu(src: &V128) -> V128 {
-        unsafe { _mm_loadu_si128(src as *const _) }
+    // Clears a hash table large enough for "fragment_size" bytes of input and returns it along
+    // with the input and output scratch buffers.
+    // NOTE: C++ returns the table and stores its size in an out-param, and hands out the scratch
+    // buffers through get_scratch_input/get_scratch_output. They're returned together here so
+    // they can be borrowed at the same time.
+    pub(crate) fn get_hash_table(
+        &mut self,
+        fragment_size: usize,
+    ) -> (&mut [u16], &mut [u8], &mut [u8]) {
+        let htsize = calculate_table_size(fragment_size);
+        let table = &mut self.table[..htsize];
+        table.fill(0);
+        (table, &mut self.input, &mut self.output)
     }
 
-    // Store 128 bits of integer data. `dst` does not need to be aligned.
-    #[inline]
-    pub(crate) fn v128_storeu(dst: &mut V128, val: V128) {
-        unsafe {
-            _mm_storeu_ui128(dst as *mut _, val);
-        }
+    pub(crate) fn get_scratch_input(&mut self) -> &mut [u8] {
+        &mut self.input
     }
+}
 
-    // Shuffle packed 8-bit integers using shuffle mask.
-    // Each packed integer in the shuffle mask must be in [0,16).
-    #[inline]
-    pub(crate) fn v128_shuffle(input: V128, shuffle_mask: V128) -> V128 {
-        unsafe { _mm_shuffle_epi8(input, shuffle_mask) }
+fn calculate_table_size(input_size: usize) -> usize {
+    const _: () = assert!(MAX_HASH_TABLE_SIZE >= MIN_HASH_TABLE_SIZE);
+    if input_size > MAX_HASH_TABLE_SIZE {
+        return MAX_HASH_TABLE_SIZE;
     }
-
-    // Constructs V128 with 16 chars (bytes) |c|.
-    #[inline]
-    pub(crate) fn v128_dup_char(c: u8) -> V128 {
-        unsafe { _mm_set1_epi8(c as i8) }
+    if input_size < MIN_HASH_TABLE_SIZE {
+        return MIN_HASH_TABLE_SIZE;
     }
+    // This is equivalent to Log2Ceiling(input_size), assuming input_size > 1.
+    // 2 << Log2Floor(x - 1) is equivalent to 1 << (1 + Log2Floor(x - 1)).
+    2 << Bits::log2_floor(input_size as u32 - 1)
 }
 
-#[cfg(
-    all(
-        target_arch = "AArch64"
-        target_feature = "neon"
-    )
-)]
-pub(crate) mod internal {
-    use std::arch::aarch64::*;
+// Any hash function will produce a valid compressed bitstream, but a good hash function reduces
+// the number of collisions and thus yields better compression for compressible input, and more
+// speed for incompressible input. Of course, it doesn't hurt if the hash function is reasonably
+// fast either, as it gets called a lot.
+#[inline]
+fn hash_bytes(bytes: u32, shift: u32) -> usize {
+    const MUL: u32 = 0x1e35a7bd;
+    (bytes.wrapping_mul(MUL) >> shift) as usize
+}
 
-    type V128 = uint8x16_t;
+#[inline]
+fn hash(p: &[u8], shift: u32) -> usize {
+    hash_bytes(LittleEndian::load32(p), shift)
+}
 
-    // Load 128 bits of integer data. `src` must be 16-byte aligned.
-    // NOTE: C++ uses const*
-    #[inline]
-    pub(crate) fn v128_load(src: &V128) -> V128 {
-        unsafe { vld1q_u8(src as *const _ as *const _) }
-    }
+// Flat array compression that does not emit the "uncompressed length" prefix. Compresses "input"
+// string to the "op" buffer.
+//
+// REQUIRES: "input.len() <= BLOCK_SIZE"
+// REQUIRES: "op" is at least "max_compressed_length(input.len())" in size.
+// REQUIRES: All elements in "table" are initialized to zero.
+// REQUIRES: "table.len()" is a power of two
+//
+// Returns the compressed size of "input".
+// NOTE: C++ returns an "end" pointer into the "op" buffer
+pub(crate) fn compress_fragment(input: &[u8], op: &mut [u8], table: &mut [u16]) -> usize {
+    // "ip" is the input pointer, and "op" is the output pointer.
+    let mut ip = 0usize;
+    let mut o = 0usize;
+    assert!(input.len() <= BLOCK_SIZE);
+    assert!(table.len().is_power_of_two());
+    let shift = (32 - Bits::log2_floor(table.len() as u32)) as u32;
+    let ip_end = input.len();
+    let base_ip = ip;
+    // Bytes in [next_emit, ip) will be emitted as literal bytes. Or [next_emit, ip_end) after the
+    // main loop.
+    let mut next_emit = ip;
 
-    // Load 128 bits of integer data. `src` does not need to be aligned.
-    #[inline]
-    pub(crate) fn v128_loadu(src: &V128) -> V128 {
-        unsafe { vld1q_u8(src as *const _ as *const _) }
-    }
+    const INPUT_MARGIN_BYTES: usize = 15;
+    if input.len() >= INPUT_MARGIN_BYTES {
+        let ip_limit = input.len() - INPUT_MARGIN_BYTES;
 
-    // Store 128 bits of integer data. `dst` does not need to be aligned.
-    #[inline]
-    pub(crate) fn v128_storeu(dst: &mut V128, val: V128) {
-        unsafe {
-            vst1q_u8(dst as *mut _ as *mut _, val);
+        ip += 1;
+        let mut next_hash = hash(&input[ip..], shift);
+        'emit_remainder: loop {
+            assert!(next_emit < ip);
+            // The body of this loop calls emit_literal once and then emit_copy one or more times.
+            // (The exception is that when we're close to exhausting the input we goto
+            // emit_remainder.)
+            //
+            // In the first iteration of this loop we're just starting, so there's nothing to copy,
+            // so calling emit_literal once is necessary. And we only start a new iteration when
+            // the current iteration has determined that a call to emit_literal will precede the
+            // next call to emit_copy (if any).
+            //
+            // Step 1: Scan forward in the input looking for a 4-byte-long match. If we get close to
+            // exhausting the input then goto emit_remainder.
+            //
+            // Heuristic match skipping: If 32 bytes are scanned with no matches found, start
+            // looking only at every other byte. If 32 more bytes are scanned (or skipped), look at
+            // every third byte, etc.. When a match is found, immediately go back to looking at
+            // every byte. This is a small loss (~5% performance, ~0.1% density) for compressible
+            // data due to more bookkeeping, but for non-compressible data (such as JPEG) it's a
+            // huge win since the compressor quickly "realizes" the data is incompressible and
+            // doesn't bother looking for matches everywhere.
+            //
+            // The "skip" variable keeps track of how many bytes there are since the last match;
+            // dividing it by 32 (ie. right-shifting by five) gives the number of bytes to move
+            // ahead for each iteration.
+            let mut skip = 32usize;
+
+            let mut next_ip = ip;
+            let mut candidate;
+            loop {
+                ip = next_ip;
+                let h = next_hash;
+                assert_eq!(h, hash(&input[ip..], shift));
+                let bytes_between_hash_lookups = skip >> 5;
+                skip += bytes_between_hash_lookups;
+                next_ip = ip + bytes_between_hash_lookups;
+                if next_ip > ip_limit {
+                    break 'emit_remainder;
+                }
+                next_hash = hash(&input[next_ip..], shift);
+                candidate = base_ip + table[h] as usize;
+                assert!(candidate < ip);
+
+                table[h] = (ip - base_ip) as u16;
+                if LittleEndian::load32(&input[ip..]) == LittleEndian::load32(&input[candidate..]) {
+                    break;
+                }
+            }
+
+            // Step 2: A 4-byte match has been found. We'll later see if more than 4 bytes match.
+            // But, prior to the match, input bytes [next_emit, ip) are unmatched. Emit them as
+            // "literal bytes."
+            assert!(next_emit + 16 <= ip_end);
+            o = emit_literal(op, o, &input[next_emit..ip]);
+
+            // Step 3: Call emit_copy, and then see if another emit_copy could be our next move.
+            // Repeat until we find no match for the input immediately after what was consumed by
+            // the last emit_copy call.
+            //
+            // If we exit this loop normally then we need to call emit_literal next, though we
+            // don't yet know how big the literal will be. We handle that by proceeding to the next
+            // iteration of the main loop. We also can exit this loop via goto if we get close to
+            // exhausting the input.
+            let mut input_bytes;
+            loop {
+                // We have a 4-byte match at ip, and no need to emit any "literal bytes" prior to
+                // ip.
+                let base = ip;
+                let matched =
+                    4 + find_match_length(&input[candidate + 4..], &input[ip + 4..ip_end]);
+                ip += matched;
+                let offset = base - candidate;
+                assert_eq!(
+                    input[base..base + matched],
+                    input[candidate..candidate + matched]
+                );
+                o = emit_copy(op, o, offset, matched);
+                next_emit = ip;
+                if ip >= ip_limit {
+                    break 'emit_remainder;
+                }
+                // We are now looking for a 4-byte match again. We read table[hash(ip, shift)] for
+                // that. To improve compression, we also update table[hash(ip - 1, shift)] and
+                // table[hash(ip, shift)].
+                input_bytes = LittleEndian::load64(&input[ip - 1..]);
+                let prev_hash = hash_bytes(input_bytes as u32, shift);
+                table[prev_hash] = (ip - base_ip - 1) as u16;
+                let cur_hash = hash_bytes((input_bytes >> 8) as u32, shift);
+                candidate = base_ip + table[cur_hash] as usize;
+                let candidate_bytes = LittleEndian::load32(&input[candidate..]);
+                table[cur_hash] = (ip - base_ip) as u16;
+                if (input_bytes >> 8) as u32 != candidate_bytes {
+                    break;
+                }
+            }
+
+            next_hash = hash_bytes((input_bytes >> 16) as u32, shift);
+            ip += 1;
         }
     }
 
-    // Shuffle packed 8-bit integers using shuffle mask.
-    // Each packed integer in the shuffle mask must be in [0,16).
-    #[inline]
-    pub(crate) fn v128_shuffle(input: V128, shuffle_mask: V128) -> V128 {
-        assert!(vminvq_u8(shuffle_mask) >= 0 && vmaxvq_u8(shuffle_mask) <= 15);
-        unsafe { vqtbl1q_u8(input, shuffle_mask) }
+    // emit_remainder:
+    // Emit the remaining bytes as a literal
+    if next_emit < ip_end {
+        o = emit_literal(op, o, &input[next_emit..ip_end]);
     }
 
-    // Constructs V128 with 16 chars (bytes) |c|.
-    #[inline]
-    pub(crate) fn v128_dup_char(c: u8) -> V128 {
-        unsafe { vdupq_n_u8(c) }
+    o
+}
+
+// Writes "literal" to "op" at "o" and returns the position just past it.
+// NOTE: C++ takes an allow_fast_path flag for an unaligned 16-byte copy, which isn't ported
+fn emit_literal(op: &mut [u8], mut o: usize, literal: &[u8]) -> usize {
+    let len = literal.len();
+    assert!(len > 0); // Zero-length literals are disallowed
+    let mut n = len - 1;
+    if n < 60 {
+        // Fits in tag byte
+        op[o] = LITERAL | ((n as u8) << 2);
+        o += 1;
+    } else {
+        let base = o;
+        o += 1;
+        let mut count = 0u8;
+        while n > 0 {
+            op[o] = n as u8;
+            o += 1;
+            n >>= 8;
+            count += 1;
+        }
+        assert!(count >= 1);
+        assert!(count <= 4);
+        op[base] = LITERAL | ((59 + count) << 2);
     }
+    op[o..o + len].copy_from_slice(literal);
+    o + len
 }
 
-use internal::*;
+fn emit_copy_at_most_64(op: &mut [u8], mut o: usize, offset: usize, len: usize) -> usize {
+    assert!(len <= 64);
+    assert!(len >= 4);
+    assert!(offset < 65536);
 
-// Working memory performs a single allocation to hold all scratch space required for compression.
-pub(crate) struct WorkingMemory {
-    mem: *mut u8,    // The allocated memory, never null
-    size: usize,     // The size of the allocated memory, never 0
-    table: *mut u16, // The pointer to the hashtable
-    input: *mut u8,  // THhe pointer to the input scratch buffer
-    output: *mut u8, // THhe pointer to the output scratch buffer
+    if len < 12 && offset < 2048 {
+        let len_minus_4 = len - 4;
+        assert!(len_minus_4 < 8); // Must fit in 3 bits
+        op[o] = COPY_1_BYTE_OFFSET + ((len_minus_4 as u8) << 2) + (((offset >> 8) as u8) << 5);
+        op[o + 1] = offset as u8;
+        o += 2;
+    } else {
+        op[o] = COPY_2_BYTE_OFFSET + (((len - 1) as u8) << 2);
+        LittleEndian::store16(&mut op[o + 1..], offset as u16);
+        o += 3;
+    }
+    o
 }
 
-impl WorkingMemory {
-    pub(crate) fn new(input_size: usize) -> Self {
-        //
+fn emit_copy(op: &mut [u8], mut o: usize, offset: usize, mut len: usize) -> usize {
+    // Emit 64 byte copies but make sure to keep at least four bytes reserved.
+    while len >= 68 {
+        o = emit_copy_at_most_64(op, o, offset, 64);
+        len -= 64;
     }
 
-    // Allocated and clears a hash table using memory in "self", stores the number of buckets in
-    // "table_size", and returns a pointer to the base of the hash table.
-    // NOTE: C++ uses int*
-    // TODO: Return table_size as well?
-    pub(crate) fn get_hash_table(&self, fragment_size: usize, table_size: &mut i32) -> *mut u16;
-
-    pub(crate) fn get_scratch_input(&self) -> *mut u8 {
-        self.input
+    // One or two copies will now finish the job.
+    if len > 64 {
+        o = emit_copy_at_most_64(op, o, offset, 60);
+        len -= 60;
     }
 
-    pub(crate) fn get_scratch_output(&self) -> *mut u8 {
-        self.output
-    }
+    // Emit remainder.
+    emit_copy_at_most_64(op, o, offset, len)
 }
 
-// Flat array compression that does not emit the "uncompressed length" prefix. Compresses "input"
-// string to the "op" buffer.
-//
-// REQUIRES: "input_length <= BLOCK_SIZE"
-// REQUIRES: "op" points to an array of memory at least "max_compressed_length(input_length)" in
-// size.
-// REQUIRES: All elements in "table[0..table_size-1]" are initialized to zero.
-//
-// Returns an "end" pointer into "op" buffer.
-// "end - op" is the compressed size of "input".
-// NOTE: C++ uses int
-pub(crate) fn compress_fragment(
-    input: *const u8,
-    input_length: usize,
-    op: *mut u8,
-    table: *mut u16,
-    table_size: i32,
-) -> *mut u8;
-
 // Find the largest n such that
 //
 //   s1[0,n-1] == s2[0,n-1]
-//   and n <= (s2_limit - s2).
-//
-// Return (n, n < 8).
-// Does not read *s2_limit or beyond.
-// Does not read *(s1 + (s2_limit - s2)) or beyond.
-// Requires that s2_limit >= 2.
-//
-// In addition populate "data" with the next 5 bytes from the end of the match. This is only done
-// if 8 bytes are available (s2_limit - s2 >= 8). The point is that on some arch's this can be
-// done faster in this routine than subsequent loading from s2 + n.
+//   and n <= s2.len().
 //
-// Separate implementation for 64-bit, little-endian cpus.
-// NOTE: C++ uses u64*
-// TODO: C++ ifdefs
+// Requires that s1.len() >= s2.len().
+// NOTE: C++ takes pointers with a limit for s2, returns whether n < 8 as well, and loads the
+// bytes following the match for the next hash lookup. Those are optimizations for the unaligned
+// fast path, which isn't ported.
 #[inline]
-fn find_match_length(
-    s1: *const u8,
-    mut s2: *const u8,
-    s2_limit: *const u8,
-    data: &mut u64,
-) -> (usize, bool) {
-    assert!(s2_limit >= s2);
+pub(crate) fn find_match_length(s1: &[u8], s2: &[u8]) -> usize {
     let mut matched = 0usize;
 
-    // This block isn't necessary for correctness; we could just start looping immediately. As an
-    // optimization though, it is useful. It creates some not uncommon code paths that determine,
-    // without extra effort, whether the match length is less than 8. In short, we are hoping to
-    // avoid a conditional branch, and perhaps get better code layout from the C++ compiler.
-    if SNAPPY_PREDICT_TRUE(s2 <= unsafe { s2_limit.sub(16) }) {
-        let a1 = UNALIGNED_LOAD64(s1);
-        let a2 = UNALIGNED_LOAD64(s2);
-        if SNAPPY_PREDICT_TRUE(a1 != a2) {
-            // This code is critical for performance. The reason is that it determines how much to
-            // advance `ip` (s2). This obviously depends on both the loads from the `candidate`
-            // (s1) and `ip`. Furthermore the next `candidate` depends on the advanced `ip`
-            // calculated here through a load, hash and new candidate hash lookup (a lot of
-            // cycles). This makes s1 (ie. `candidate`) the variable that limits throughput. This
-            // is the reason we go through hoops to have this function update `data` for the next
-            // iter. The straightforward code would use *data, given by
-            //
-            // *data = UNALIGNED_LOAD64(s2 + matched_bytes) (Latency of 5 cycles),
-            //
-            // as input for the hash table lookup to find next candidate. However this forces the
-            // load on the data dependency chain of s1, because matched_bytes directly depends on
-            // s1. However matched_bytes is 0..7, so we can also calculate *data by
-            //
-            // *data = AlignRight(UNALIGNED_LOAD64(s2), UNALIGNED_LOAD64(s2 + 8),
-            //                    matched_bytes);
-            //
-            // The loads do not depend on s1 anymore and are thus off the bottleneck. The
-            // straightforward implementation on x86_64 would be to use
-            //
-            // shrd rax, rdx, cl  (cl being matched_bytes * 8)
-            //
-            // unfortunately shrd with a variable shift has a 4 cycle latency. So this only wins 1
-            // cycle. The BMI2 shrx instruction is a 1 cycle variable shift instruction but can
-            // only shift 64 bits. If we focus on just obtaining the least significant 4 bytes, we
-            // can obtain this by
-            //
-            // *data = ConditionalMove(matched_bytes < 4, UNALIGNED_LOAD64(s2),
-            //     UNALIGNED_LOAD64(s2 + 4) >> ((matched_bytes & 3) * 8);
-            //
-            // Writen like above this is not a big win, the conditional move would be a cmp
-            // followed by a cmov (2 cycles) followed by a shift (1 cycle). However
-            // matched_bytes < 4 is equal to
-            // static_cast<uint32_t>(xorval) != 0. Writen that way, the conditional move (2
-            // cycles) can execute in parallel with FindLSBSetNonZero64 (tzcnt), which takes 3
-            // cycles.
-            // TODO: Does this still hold (above)?
-            let xorval = a1 ^ a2;
-            let shift = Bits::find_lsb_set_non_zero64(xorval);
-            let matched_bytes = (shift >> 3) as usize;
-            #[cfg(not(target_arch = "x86-64"))]
-            *data = UNALIGNED_LOAG64(unsafe { s2.add(matched_bytes) });
-            #[cfg(target_arch = "x86-64")]
-            unsafe {
-                // Ideally this would just be
-                //
-                // (In C++) a = static_cast<uint32_t>(xorval) == 0 ? a3 : a2;)
-                //
-                // However clang correctly infers that the above statement participates on a critical
-                // data dependency chain and thus, unfortunately, refuses to use a conditional move
-                // (it's tuned to cut data dependencies). In this case there is a longer parallel
-                // chain anyway AND this will be fairly unpredictable.
-                // TODO: Does this still hold (above)?
-                let a3 = UNALIGNED_LOAD64(s2.add(4));
-                // TODO
-                asm!("testl %k2, %k2",
-                    "cmovzq {1}, {0}",
-                    out(a2),
-                    in(a3), in(xorval),
-                );
-                *data = a2 >> (shift & (3 * 8)) as usize;
-            }
-            return (matched_bytes, true);
-        } else {
-            matched = 8;
-            unsafe {
-                s2 = s2.add(8);
-            }
-        }
-    }
-
     // Find out how long the match is. We loop over the data 64 bits at a time until we find a
     // 64-bit block that doesn't match; then we find the first non-matching bit and use that to
     // calculate the total length of the match.
-    unsafe {
-        while SNAPPY_PREDICT_TRUE(s2 <= s2_limit.sub(16)) {
-            let a1 = UNALIGNED_LOAD64(s1.add(matched));
-            let a2 = UNALIGNED_LOAD64(s2);
-            if a1 == a2 {
-                s2 = s2.add(8);
-                matched += 8;
-            } else {
-                let xorval = a1 ^ a2;
-                let shift = Bits::find_lsb_set_non_zero64(xorval);
-                let matched_bytes = (shift >> 3) as usize;
-                #[cfg(not(target_arch = "x86-64"))]
-                *data = UNALIGNED_LOAG64(unsafe { s2.add(matched_bytes) });
-                #[cfg(target_arch = "x86-64")]
-                {
-                    let a3 = UNALIGNED_LOAD64(s2.add(4));
-                    // TODO
-                    asm!("testl %k2, %k2",
-                        "cmovzq {1}, {0}",
-                        out(a2),
-                        in(a3), in(xorval),
-                    );
-                    *data = a2 >> (shift & (3 * 8)) as usize;
-                }
-                matched += matched_bytes;
-                assert!(matched >= 8);
-                return (matched, false);
-            }
-        }
-        while SNAPPY_PREDICT_TRUE(s2 < s2_limit) {
-            if *s1.add(matched) == *s2 {
-                s2 = s2.add(1);
-                matched += 1;
-            } else {
-                if s2 <= s2_limit.sub(8) {
-                    *data = UNALIGNED_LOAD64(s2);
-                }
-                return (matched, matched < 8);
-            }
-        }
-        (matched, matched < 8)
-    }
-}
-
-#[inline]
-fn find_match_length(
-    s1: *const u8,
-    mut s2: *const u8,
-    s2_limit: *const u8,
-    data: &mut u64,
-) -> (usize, bool) {
-    // Implementation based on the x86-64 version, above.
-    assert!(s2_limit >= s2);
-    // NOTE: C++ uses int
-    let mut matched = 0usize;
-
-    unsafe {
-        while s2 <= s2_limit.sub(4) && UNALIGNED_LOAD32(s2) == UNALIGNED_LOAD32(s1.add(matched)) {
-            s2 = s2.add(4);
-            matched += 4;
-        }
-        if LittleEndian::is_little_endian() && s2 <= s2_limit.sub(4) {
-            let mut x = UNALIGNED_LOAD32(s2) ^ UNALIGNED_LOAD32(s1.add(matched));
-            let matching_bits = Bits::find_lsb_set_non_zero(x);
-            matched += (matching_bits >> 3) as usize;
-            s2 = s2.add((matching_bits >> 3) as usize);
+    while s2.len() - matched >= 8 {
+        let a1 = LittleEndian::load64(&s1[matched..]);
+        let a2 = LittleEndian::load64(&s2[matched..]);
+        if a1 == a2 {
+            matched += 8;
         } else {
-            while s2 < s2_limit && *s1.add(matched) == *s2 {
-                s2 = s2.add(1);
-                matched += 1;
-            }
-        }
-        if s2 <= s2_limit.sub(8) {
-            *data = LittleEndian::load64(s2);
+            let matching_bits = Bits::find_lsb_set_non_zero64(a1 ^ a2);
+            return matched + (matching_bits >> 3) as usize;
         }
     }
-    (matched, matched < 8)
+    while matched < s2.len() && s1[matched] == s2[matched] {
+        matched += 1;
+    }
+    matched
 }
 
-// Lookup tables for decompression code. Give --snappy_dump_decompression_table to the unit test
-// to recompute char_table.
-// TODO: Change above accordingly
+// Lookup tables for decompression code.
 
-// NOTE: C++ has anonymous (untagged) enum and should be used as follows:
-// TODO: Use const?
-// LITERAL as i32 (int)
-#[repr(C)]
-pub(crate) enum __UNTAGGED {
-    LITERAL = 0,
-    COPY_1_BYTE_OFFSET = 1,
-    COPY_2_BYTE_OFFSET = 2,
-    COPY_3_BYTE_OFFSET = 3,
-}
-pub(crate) use __UNTAGED::*;
-// NOTE: C++ uses int
-const MAXIMUM_TAG_LENGTH: i32 = 5; // COPY_4_BYTE_OFFSET plus the actual offset.
+// NOTE: C++ uses an anonymous enum
+pub(crate) const LITERAL: u8 = 0;
+// With implicit length
+pub(crate) const COPY_1_BYTE_OFFSET: u8 = 1;
+pub(crate) const COPY_2_BYTE_OFFSET: u8 = 2;
+// NOTE: Kept to mirror the C++ enum. Never emitted by the compressor and the decompressor handles
+// it through CHAR_TABLE, so only the tests name it
+#[allow(dead_code)]
+pub(crate) const COPY_4_BYTE_OFFSET: u8 = 3;
+pub(crate) const MAXIMUM_TAG_LENGTH: usize = 5; // COPY_4_BYTE_OFFSET plus the actual offset.
 
 // Data stored per entry in lookup table:
 //      Range   Bits-used       Description
 //      -----------------------------------
 //      1..64   0..7            Literal/copy length encoded in opcode byte
-//      1..7    8..10           Copy offset encoded in opcode byte / 256
+//      0..7    8..10           Copy offset encoded in opcode byte / 256
 //      0..4    11..13          Extra bytes after opcode
 //
 // We use eight bits for the length even though 7 would have sufficed because of efficiency
 // reasons:
 //      (1) Extracting a byte is faster than a bit-field
 //      (2) It properly aligns copy offset so we do not need a <<8
-const char_table: [u16; 256] = [
+pub(crate) const CHAR_TABLE: [u16; 256] = [
     0x0001, 0x0804, 0x1001, 0x2001, 0x0002, 0x0805, 0x1002, 0x2002, 0x0003, 0x0806, 0x1003, 0x2003,
     0x0004, 0x0807, 0x1004, 0x2004, 0x0005, 0x0808, 0x1005, 0x2005, 0x0006, 0x0809, 0x1006, 0x2006,
     0x0007, 0x080a, 0x1007, 0x2007, 0x0008, 0x080b, 0x1008, 0x2008, 0x0009, 0x0904, 0x1009, 0x2009,
@@ -1,5 +1,5 @@
-#[repr(C)]
-pub struct iovec {
-    iov_base: *mut std::ffi::c_void,
-    iov_len: usize,
-}
+// snappy/snappy-stubs-public.h
+
+// NOTE: C++ declares struct iovec here when <sys/uio.h> isn't available. IoSliceMut wraps an
+// iovec on unix and is the safe stand-in for it.
+pub type Iovec<'a> = std::io::IoSliceMut<'a>;
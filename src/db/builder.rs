@@ -0,0 +1,86 @@
+// leveldb/db/builder.{h,cc}
+
+use crate::{
+    db::{filename::table_file_name, table_cache::TableCache, version_edit::FileMetaData},
+    env::Env,
+    iter::Iter,
+    options::{Options, ReadOptions},
+    slice::Slice,
+    status::Status,
+    table::TableBuilder,
+};
+
+// Build a Table file from the contents of *iter. The generated file will be named according to
+// meta.number. On success, the rest of *meta will be filled with metadata about the generated
+// table. If no data is present in *iter, meta.file_size will be set to zero, and no Table file
+// will be produced.
+pub(crate) fn build_table(
+    dbname: &str,
+    env: &dyn Env,
+    options: &Options,
+    table_cache: &TableCache,
+    iter: &mut dyn Iter,
+    meta: &mut FileMetaData,
+) -> Status {
+    let mut s = Status::OK();
+    meta.file_size = 0;
+    iter.seek_to_first();
+
+    let fname = table_file_name(dbname, meta.number);
+    if iter.valid() {
+        let mut file = match env.new_writable_file(&fname) {
+            Ok(file) => file,
+            Err(s) => return s.into(),
+        };
+
+        {
+            let mut builder = TableBuilder::new(options, &mut *file);
+            meta.smallest.decode_from(&iter.key());
+            // NOTE: C++ keeps a Slice into the iterator's last key; it is copied here since the
+            // iterator's storage may not outlive the next call
+            let mut key = Vec::new();
+            while iter.valid() {
+                key = iter.key().to_vec();
+                builder.add(&Slice::from(&key), &iter.value());
+                iter.next();
+            }
+            if !key.is_empty() {
+                meta.largest.decode_from(&Slice::from(&key));
+            }
+
+            // Finish and check for builder errors
+            s = builder.finish().into();
+            if s.ok() {
+                meta.file_size = builder.file_size();
+                assert!(meta.file_size > 0);
+            }
+        }
+
+        // Finish and check for file errors
+        if s.ok() {
+            s = file.sync().into();
+        }
+        if s.ok() {
+            s = file.close().into();
+        }
+        drop(file);
+
+        if s.ok() {
+            // Verify that the table is usable
+            let it = table_cache.new_iterator(&ReadOptions::default(), meta.number, meta.file_size);
+            s = it.status().into();
+        }
+    }
+
+    // Check for input iterator errors
+    if let Err(e) = iter.status() {
+        s = e.into();
+    }
+
+    if s.ok() && meta.file_size > 0 {
+        // Keep it
+    } else {
+        let _ = env.remove_file(&fname);
+    }
+    s
+}
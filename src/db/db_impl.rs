@@ -0,0 +1,2357 @@
+// leveldb/include/leveldb/db.h
+// leveldb/db/db_impl.{h,cc}
+
+use crate::{
+    comparator::Comparator,
+    db::{
+        builder::build_table,
+        db_iter::new_db_iterator,
+        dbformat::{
+            config, parse_internal_key, InternalFilterPolicy, InternalKey, InternalKeyComparator,
+            LookupKey, ParsedInternalKey, SequenceNumber, ValueType, MAX_SEQUENCE_NUMBER,
+        },
+        filename::{
+            current_file_name, descriptor_file_name, lock_file_name, log_file_name,
+            parse_file_name, set_current_file, table_file_name, FileType,
+        },
+        log,
+        memtable::MemTable,
+        snapshot::{SnapshotImpl, SnapshotList},
+        table_cache::TableCache,
+        version_edit::{FileMetaData, VersionEdit},
+        version_set::{Compaction, GetStats, Version, VersionSet},
+        write_batch::{WriteBatch, WriteBatchInternal},
+    },
+    env::{self, Env, FileLock, Logger, WritableFile},
+    filter_policy::FilterPolicy,
+    iter::{new_error_iter, Iter},
+    options::{Options, ReadOptions, WriteOptions},
+    slice::Slice,
+    status::{Error, Result, Status},
+    table::{merger::new_merging_iterator, TableBuilder},
+    util::cache::new_lru_cache,
+};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ptr::null_mut;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+
+const NUM_NON_TABLE_CACHE_FILES: i32 = 10;
+
+// Files produced by compaction
+struct Output {
+    number: u64,
+    file_size: u64,
+    smallest: InternalKey,
+    largest: InternalKey,
+}
+
+struct CompactionState {
+    compaction: Compaction,
+
+    // Sequence numbers < smallest_snapshot are not significant since we will never have to
+    // service a snapshot below smallest_snapshot. Therefore if we have seen a sequence number S
+    // <= smallest_snapshot, we can drop all entries for the same key with sequence numbers < S.
+    smallest_snapshot: SequenceNumber,
+
+    outputs: Vec<Output>,
+
+    total_bytes: u64,
+
+    // NOTE: C++ keeps these as locals of DoCompactionWork; they live here since the input is
+    // consumed across several output files
+    current_user_key: Vec<u8>,
+    has_current_user_key: bool,
+    last_sequence_for_key: SequenceNumber,
+}
+
+impl CompactionState {
+    fn new(compaction: Compaction) -> Self {
+        Self {
+            compaction,
+            smallest_snapshot: 0,
+            outputs: Vec::new(),
+            total_bytes: 0,
+            current_user_key: Vec::new(),
+            has_current_user_key: false,
+            last_sequence_for_key: MAX_SEQUENCE_NUMBER,
+        }
+    }
+
+    fn current_output(&mut self) -> &mut Output {
+        self.outputs.last_mut().unwrap()
+    }
+
+    // Returns true if the entry with internal key "key" is obsolete and need not be written to
+    // the compaction output. Must be called on every input entry, in order.
+    fn should_drop(&mut self, user_comparator: &dyn Comparator, key: &Slice) -> bool {
+        let mut ikey = ParsedInternalKey::default();
+        if !parse_internal_key(key, &mut ikey) {
+            // Do not hide error keys
+            self.current_user_key.clear();
+            self.has_current_user_key = false;
+            self.last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+            return false;
+        }
+
+        if !self.has_current_user_key
+            || user_comparator.compare(&ikey.user_key, &Slice::from(&self.current_user_key)) != 0
+        {
+            // First occurrence of this user key
+            self.current_user_key = ikey.user_key.to_vec();
+            self.has_current_user_key = true;
+            self.last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+        }
+
+        let drop = if self.last_sequence_for_key <= self.smallest_snapshot {
+            // Hidden by a newer entry for same user key
+            true
+        } else {
+            // For this user key:
+            // (1) there is no data in higher levels
+            // (2) data in lower levels will have larger sequence numbers
+            // (3) data in layers that are being compacted here and have smaller sequence numbers
+            //     will be dropped in the next few iterations of this loop (by rule (A) above).
+            // Therefore this deletion marker is obsolete and can be dropped.
+            ikey.value_type == ValueType::Deletion
+                && ikey.sequence <= self.smallest_snapshot
+                && self.compaction.is_base_level_for_key(&ikey.user_key)
+        };
+
+        self.last_sequence_for_key = ikey.sequence;
+        drop
+    }
+}
+
+// Fix user-supplied options to be reasonable
+fn clip_to_range<T: Ord>(ptr: &mut T, minvalue: T, maxvalue: T) {
+    if *ptr > maxvalue {
+        *ptr = maxvalue;
+    }
+    if *ptr < minvalue {
+        *ptr = minvalue;
+    }
+}
+
+// NOTE: C++ also creates the info log here when the user did not supply one
+pub(crate) fn sanitize_options(icmp: &InternalKeyComparator, src: &Options) -> Options {
+    let mut result = src.clone();
+    result.comparator = Arc::new(icmp.clone());
+    result.filter_policy = src.filter_policy.as_ref().map(|policy| {
+        Arc::new(InternalFilterPolicy::new(Arc::clone(policy))) as Arc<dyn FilterPolicy>
+    });
+    clip_to_range(
+        &mut result.max_open_files,
+        64 + NUM_NON_TABLE_CACHE_FILES,
+        50000,
+    );
+    clip_to_range(&mut result.write_buffer_size, 64 << 10, 1 << 30);
+    clip_to_range(&mut result.max_file_size, 1 << 20, 1 << 30);
+    clip_to_range(&mut result.block_size, 1 << 10, 4 << 20);
+    if result.block_cache.is_none() {
+        result.block_cache = Some(new_lru_cache(8 << 20));
+    }
+    result
+}
+
+fn table_cache_size(sanitized_options: &Options) -> usize {
+    // Reserve ten files or so for other uses and give the rest to TableCache.
+    (sanitized_options.max_open_files - NUM_NON_TABLE_CACHE_FILES) as usize
+}
+
+// A DB is a persistent ordered map from keys to values.
+// A DB is safe for concurrent access from multiple threads without any external synchronization.
+// NOTE: C++ has an abstract DB class implemented by DBImpl
+pub struct DB {
+    // NOTE: C++ hands "this" to the background compaction; the implementation is shared with it
+    // through an Arc here
+    inner: Arc<DBImpl>,
+}
+
+pub(crate) struct DBImpl {
+    // Constant after construction
+    env: Arc<dyn Env>,
+    internal_comparator: InternalKeyComparator,
+    // options.comparator == &internal_comparator
+    options: Options,
+    dbname: String,
+
+    // table_cache provides its own synchronization
+    table_cache: Arc<TableCache>,
+
+    shutting_down: AtomicBool,
+    // Signalled when background work finishes
+    background_work_finished_signal: Condvar,
+    // Signalled when log_and_apply() finishes writing the MANIFEST
+    manifest_written_signal: Condvar,
+    // So bg thread can detect a non-None imm
+    has_imm: AtomicBool,
+
+    // Serializes writers. Acquired before mutex, which the holder releases while it appends to the
+    // log and inserts into the memtable.
+    // NOTE: C++ keeps a queue of writers and commits the batches at its front as a group; writers
+    // take turns through this lock instead
+    writer_mutex: Mutex<()>,
+
+    // State below is protected by mutex
+    mutex: Mutex<DBState>,
+}
+
+// State protected by DBImpl::mutex
+struct DBState {
+    // Lock over the persistent DB state. Some iff successfully acquired.
+    // NOTE: C++ keeps this outside of the mutex; it is kept here since the DBImpl may outlive the
+    // DB while a background compaction holds on to it
+    db_lock: Option<Box<dyn FileLock>>,
+    mem: Arc<MemTable>,
+    // Memtable being compacted
+    imm: Option<Arc<MemTable>>,
+    // Taken out by the holder of writer_mutex while it writes to the log without mutex
+    log: Option<log::Writer>,
+    logfile_number: u64,
+    // For sampling.
+    seed: u32,
+
+    snapshots: SnapshotList,
+
+    // Set of table files to protect from deletion because they are part of ongoing compactions.
+    pending_outputs: BTreeSet<u64>,
+
+    // Has a background compaction been scheduled or is running?
+    background_compaction_scheduled: bool,
+
+    versions: VersionSet,
+    // Is log_and_apply() writing the MANIFEST without mutex?
+    manifest_write_in_progress: bool,
+
+    // Have we encountered a background error in paranoid mode?
+    bg_error: Status,
+}
+
+impl DB {
+    // Open the database with the specified "name".
+    // Returns the opened database on success and an error on failure.
+    // NOTE: C++ stores a heap-allocated database in *dbptr
+    pub fn open(options: Options, dbname: &str) -> Result<DB> {
+        let db = DB {
+            inner: Arc::new(DBImpl::new(&options, dbname)),
+        };
+        let inner = &db.inner;
+        let state = inner.mutex.lock().unwrap();
+        let mut edit = VersionEdit::new();
+        // Recover handles create_if_missing, error_if_exists
+        let mut save_manifest = false;
+        let (mut state, mut s) = inner.recover(state, &mut edit, &mut save_manifest);
+        if s.ok() && state.log.is_none() {
+            // Create new log and a corresponding memtable.
+            let new_log_number = state.versions.new_file_number();
+            match inner
+                .env
+                .new_writable_file(&log_file_name(dbname, new_log_number))
+            {
+                Ok(lfile) => {
+                    edit.set_log_number(new_log_number);
+                    state.logfile_number = new_log_number;
+                    state.log = Some(log::Writer::new(lfile));
+                }
+                Err(status) => s = status.into(),
+            }
+        }
+        if s.ok() && save_manifest {
+            edit.set_prev_log_number(0); // No older logs needed after recovery.
+            edit.set_log_number(state.logfile_number);
+            (state, s) = inner.log_and_apply(state, &mut edit);
+        }
+        if s.ok() {
+            state = inner.remove_obsolete_files(state);
+            inner.maybe_schedule_compaction(&mut state);
+        }
+        drop(state);
+        Result::from(s)?;
+        Ok(db)
+    }
+
+    // Set the database entry for "key" to "value". Returns Ok on success, and an error on
+    // failure.
+    // Note: consider setting options.sync = true.
+    pub fn put(&self, options: &WriteOptions, key: &Slice, value: &Slice) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(options, &mut batch)
+    }
+
+    // Remove the database entry (if any) for "key". Returns Ok on success, and an error on
+    // failure. It is not an error if "key" did not exist in the database.
+    // Note: consider setting options.sync = true.
+    pub fn delete(&self, options: &WriteOptions, key: &Slice) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(options, &mut batch)
+    }
+
+    // Apply the specified updates to the database.
+    // Returns Ok on success, an error on failure.
+    // Note: consider setting options.sync = true.
+    // NOTE: C++ takes a mutable WriteBatch* and so does this: the sequence number of the batch's
+    // first entry is stamped into its header before the batch is logged, and a shared reference
+    // would force a copy of every batch on the write path. On return the header holds that
+    // sequence number.
+    pub fn write(&self, options: &WriteOptions, updates: &mut WriteBatch) -> Result<()> {
+        self.inner.write(options, Some(updates)).into()
+    }
+
+    // If the database contains an entry for "key" return the corresponding value.
+    //
+    // If there is no entry for "key" return an error for which Error::is_not_found() returns
+    // true.
+    //
+    // May return some other error on failure.
+    // NOTE: C++ stores the value through an out-param
+    pub fn get(&self, options: &ReadOptions, key: &Slice) -> Result<Vec<u8>> {
+        let mut value = Vec::new();
+        Result::from(self.inner.get(options, key, &mut value))?;
+        Ok(value)
+    }
+
+    // Return an iterator over the contents of the database. The result of new_iterator() is
+    // initially invalid (caller must call one of the seek methods on the iterator before using
+    // it).
+    pub fn new_iterator(&self, options: &ReadOptions) -> Box<dyn Iter> {
+        let inner = &self.inner;
+        let snapshot = match options
+            .snapshot
+            .as_deref()
+            .map(|s| s.sequence_number_in(inner))
+        {
+            Some(Err(e)) => return new_error_iter(e),
+            Some(Ok(sequence)) => Some(sequence),
+            None => None,
+        };
+        let (iter, latest_snapshot, seed) = inner.new_internal_iterator(options);
+        let sequence = snapshot.unwrap_or(latest_snapshot);
+        new_db_iterator(
+            Arc::downgrade(inner),
+            Arc::clone(inner.internal_comparator.user_comparator()),
+            iter,
+            sequence,
+            seed,
+        )
+    }
+
+    // Return a handle to the current DB state. Reads created with this handle will all observe a
+    // stable snapshot of the current DB state. The caller must call release_snapshot(result) when
+    // the snapshot is no longer needed.
+    pub fn get_snapshot(&self) -> Arc<Snapshot> {
+        let mut state = self.inner.mutex.lock().unwrap();
+        let sequence_number = state.versions.last_sequence();
+        Arc::new(Snapshot {
+            db: Arc::downgrade(&self.inner),
+            snapshot: AtomicPtr::new(state.snapshots.new_snapshot(sequence_number) as *mut _),
+            sequence_number,
+        })
+    }
+
+    // Release a previously acquired snapshot. The caller must not use "snapshot" after this call.
+    // NOTE: Reads through a clone of a released snapshot fail with an InvalidArgument error
+    pub fn release_snapshot(&self, snapshot: Arc<Snapshot>) {
+        assert!(snapshot.belongs_to(&self.inner));
+        let mut state = self.inner.mutex.lock().unwrap();
+        let list_entry = snapshot.snapshot.swap(null_mut(), Ordering::AcqRel);
+        if !list_entry.is_null() {
+            state.snapshots.delete(list_entry);
+        }
+    }
+}
+
+// Abstract handle to particular state of a DB.
+// A Snapshot is an immutable object and can therefore be safely accessed from multiple threads
+// without any external synchronization.
+// NOTE: C++ declares an abstract class in db.h and hands out the SnapshotImpl itself. A snapshot
+// that is dropped without being released is removed from the DB's list then.
+pub struct Snapshot {
+    db: Weak<DBImpl>,
+    // Entry in the DB's SnapshotList; null once released. Only followed while holding the DB's
+    // mutex.
+    snapshot: AtomicPtr<SnapshotImpl>,
+    sequence_number: SequenceNumber,
+}
+
+impl Snapshot {
+    fn belongs_to(&self, db: &Arc<DBImpl>) -> bool {
+        std::ptr::eq(self.db.as_ptr(), Arc::as_ptr(db))
+    }
+
+    // Return the sequence number to read at if "self" is a live snapshot of "db".
+    fn sequence_number_in(&self, db: &Arc<DBImpl>) -> Result<SequenceNumber> {
+        if !self.belongs_to(db) {
+            Err(Error::invalid_argument(
+                &"snapshot does not belong to this DB".into(),
+                &Slice::new(),
+            ))
+        } else if self.snapshot.load(Ordering::Acquire).is_null() {
+            Err(Error::invalid_argument(
+                &"snapshot has been released".into(),
+                &Slice::new(),
+            ))
+        } else {
+            Ok(self.sequence_number)
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let list_entry = *self.snapshot.get_mut();
+        if list_entry.is_null() {
+            return;
+        }
+        // The DB's list was freed along with the DB if it is gone
+        if let Some(db) = self.db.upgrade() {
+            db.mutex.lock().unwrap().snapshots.delete(list_entry);
+        }
+    }
+}
+
+#[cfg(test)]
+impl DB {
+    // Force current memtable contents to be compacted.
+    pub(crate) fn test_compact_mem_table(&self) -> Status {
+        let inner = &self.inner;
+        // None batch means just wait for earlier writes to be done
+        let mut s = inner.write(&WriteOptions::default(), None);
+        if s.ok() {
+            // Wait until the compaction completes
+            let mut state = inner.mutex.lock().unwrap();
+            while state.imm.is_some() && state.bg_error.ok() {
+                state = inner.background_work_finished_signal.wait(state).unwrap();
+            }
+            if state.imm.is_some() {
+                s = state.bg_error.clone();
+            }
+        }
+        s
+    }
+
+    // Wait until no background compaction is scheduled or running.
+    // NOTE: Not in C++, whose tests compact explicit ranges instead
+    pub(crate) fn test_wait_for_compactions(&self) {
+        let inner = &self.inner;
+        let mut state = inner.mutex.lock().unwrap();
+        while state.background_compaction_scheduled {
+            state = inner.background_work_finished_signal.wait(state).unwrap();
+        }
+    }
+
+    // Return the number of table files at "level".
+    // NOTE: C++ exposes this through the "leveldb.num-files-at-level<N>" property
+    pub(crate) fn test_num_level_files(&self, level: usize) -> usize {
+        self.inner
+            .mutex
+            .lock()
+            .unwrap()
+            .versions
+            .num_level_files(level)
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        // Wait for background work to finish.
+        let inner = &self.inner;
+        let mut state = inner.mutex.lock().unwrap();
+        inner.shutting_down.store(true, Ordering::Release);
+        while state.background_compaction_scheduled {
+            state = inner.background_work_finished_signal.wait(state).unwrap();
+        }
+
+        if let Some(lock) = state.db_lock.take() {
+            let _ = inner.env.unlock_file(lock);
+        }
+    }
+}
+
+impl DBImpl {
+    fn new(raw_options: &Options, dbname: &str) -> Self {
+        let internal_comparator = InternalKeyComparator::new(Arc::clone(&raw_options.comparator));
+        let options = sanitize_options(&internal_comparator, raw_options);
+        let table_cache = Arc::new(TableCache::new(
+            dbname,
+            &options,
+            table_cache_size(&options),
+        ));
+        let versions = VersionSet::new(dbname, &options, &table_cache, &internal_comparator);
+        Self {
+            env: Arc::clone(&raw_options.env),
+            mutex: Mutex::new(DBState {
+                db_lock: None,
+                mem: Arc::new(MemTable::new(&internal_comparator)),
+                imm: None,
+                log: None,
+                logfile_number: 0,
+                seed: 0,
+                snapshots: SnapshotList::new(),
+                pending_outputs: BTreeSet::new(),
+                background_compaction_scheduled: false,
+                versions,
+                manifest_write_in_progress: false,
+                bg_error: Status::OK(),
+            }),
+            internal_comparator,
+            options,
+            dbname: dbname.to_owned(),
+            table_cache,
+            shutting_down: AtomicBool::new(false),
+            background_work_finished_signal: Condvar::new(),
+            manifest_written_signal: Condvar::new(),
+            has_imm: AtomicBool::new(false),
+            writer_mutex: Mutex::new(()),
+        }
+    }
+
+    fn info_log(&self) -> Option<&dyn Logger> {
+        self.options.info_log.as_deref()
+    }
+
+    // A None "updates" forces the memtable to be switched out so that it gets compacted.
+    fn write(self: &Arc<Self>, options: &WriteOptions, updates: Option<&mut WriteBatch>) -> Status {
+        let _writer = self.writer_mutex.lock().unwrap();
+        let state = self.mutex.lock().unwrap();
+
+        // May temporarily unlock and wait.
+        let (mut state, mut status) = self.make_room_for_write(state, updates.is_none());
+        if let (true, Some(updates)) = (status.ok(), updates) {
+            let mut last_sequence = state.versions.last_sequence();
+            WriteBatchInternal::set_sequence(updates, last_sequence + 1);
+            last_sequence += WriteBatchInternal::count(updates) as u64;
+
+            // Add to log and apply to memtable. We can release the lock during this phase since
+            // writer_mutex keeps out concurrent loggers and concurrent writes into mem. Readers
+            // only see the batch once last_sequence is published below.
+            let mut log = state.log.take().unwrap();
+            let mem = Arc::clone(&state.mem);
+            drop(state);
+            status = log.add_record(&WriteBatchInternal::contents(updates));
+            let mut sync_error = false;
+            if status.ok() && options.sync {
+                status = log.file().sync().into();
+                sync_error = !status.ok();
+            }
+            if status.ok() {
+                status = WriteBatchInternal::insert_into(updates, &mem);
+            }
+            state = self.mutex.lock().unwrap();
+            state.log = Some(log);
+            if sync_error {
+                // The state of the log file is indeterminate: the log record we just added may
+                // or may not show up when the DB is re-opened. So we force the DB into a mode
+                // where all future writes fail.
+                self.record_background_error(&mut state, &status);
+            }
+            state.versions.set_last_sequence(last_sequence);
+        }
+        status
+    }
+
+    fn get(self: &Arc<Self>, options: &ReadOptions, key: &Slice, value: &mut Vec<u8>) -> Status {
+        let (mem, imm, current, snapshot) = {
+            let state = self.mutex.lock().unwrap();
+            let snapshot = match &options.snapshot {
+                Some(snapshot) => match snapshot.sequence_number_in(self) {
+                    Ok(sequence) => sequence,
+                    Err(e) => return e.into(),
+                },
+                None => state.versions.last_sequence(),
+            };
+            (
+                Arc::clone(&state.mem),
+                state.imm.clone(),
+                Arc::clone(state.versions.current()),
+                snapshot,
+            )
+        };
+
+        // Unlock while reading from files and memtables
+        let lkey = LookupKey::new(key, snapshot);
+        let mut s = Status::OK();
+        let mut stats = GetStats::default();
+        let found = mem.get(&lkey, value, &mut s)
+            || imm
+                .as_ref()
+                .is_some_and(|imm| imm.get(&lkey, value, &mut s));
+        let have_stat_update = !found;
+        if !found {
+            s = current.get(options, &lkey, value, &mut stats);
+        }
+
+        if have_stat_update {
+            let mut state = self.mutex.lock().unwrap();
+            if current.update_stats(&stats) {
+                self.maybe_schedule_compaction(&mut state);
+            }
+        }
+        s
+    }
+
+    // Return an internal iterator over the current state of the database, along with the
+    // sequence number of the latest write and a seed for the iterator's read sampling.
+    // NOTE: C++ returns the latter two through out parameters
+    fn new_internal_iterator(&self, options: &ReadOptions) -> (Box<dyn Iter>, SequenceNumber, u32) {
+        let mut state = self.mutex.lock().unwrap();
+        let latest_snapshot = state.versions.last_sequence();
+
+        // Collect together all needed child iterators
+        let mut list = vec![state.mem.new_iterator()];
+        if let Some(imm) = &state.imm {
+            list.push(imm.new_iterator());
+        }
+        let current = Arc::clone(state.versions.current());
+        current.add_iterators(options, &mut list);
+        let mut internal_iter =
+            new_merging_iterator(Arc::new(self.internal_comparator.clone()), list);
+        // NOTE: C++ unrefs the memtables and version in a cleanup function; here the cleanup
+        // function keeps them alive until the iterator is dropped. The memtable iterators hold
+        // their memtables already, but the version must outlive the iterator so that its files
+        // are not deleted.
+        internal_iter.register_cleanup(
+            Box::new(move |_, _| {
+                let _ = &current;
+            }),
+            null_mut(),
+            null_mut(),
+        );
+
+        state.seed = state.seed.wrapping_add(1);
+        (internal_iter, latest_snapshot, state.seed)
+    }
+
+    // Record a sample of bytes read at the specified internal key. Samples are taken
+    // approximately once every config::READ_BYTES_PERIOD bytes.
+    pub(crate) fn record_read_sample(self: &Arc<Self>, key: &Slice) {
+        let mut state = self.mutex.lock().unwrap();
+        if state.versions.current().record_read_sample(key) {
+            self.maybe_schedule_compaction(&mut state);
+        }
+    }
+
+    // REQUIRES: mutex is held
+    // REQUIRES: this thread holds writer_mutex
+    fn make_room_for_write<'a>(
+        self: &'a Arc<Self>,
+        mut state: MutexGuard<'a, DBState>,
+        mut force: bool, // force compaction
+    ) -> (MutexGuard<'a, DBState>, Status) {
+        let mut allow_delay = !force;
+        let mut s = Status::OK();
+        loop {
+            if !state.bg_error.ok() {
+                // Yield previous error
+                s = state.bg_error.clone();
+                break;
+            } else if allow_delay
+                && state.versions.num_level_files(0) >= config::L0_SLOWDOWN_WRITES_TRIGGER
+            {
+                // We are getting close to hitting a hard limit on the number of L0 files. Rather
+                // than delaying a single write by several seconds when we hit the hard limit,
+                // start delaying each individual write by 1ms to reduce latency variance. Also,
+                // this delay hands over some CPU to the compaction thread in case it is sharing
+                // the same core as the writer.
+                drop(state);
+                self.env.sleep_for_microseconds(1000);
+                allow_delay = false; // Do not delay a single write more than once
+                state = self.mutex.lock().unwrap();
+            } else if !force
+                && state.mem.approximate_memory_usage() <= self.options.write_buffer_size
+            {
+                // There is room in current memtable
+                break;
+            } else if state.imm.is_some() {
+                // We have filled up the current memtable, but the previous one is still being
+                // compacted, so we wait.
+                env::log(
+                    self.info_log(),
+                    format_args!("Current memtable full; waiting...\n"),
+                );
+                state = self.background_work_finished_signal.wait(state).unwrap();
+            } else if state.versions.num_level_files(0) >= config::L0_STOP_WRITES_TRIGGER {
+                // There are too many level-0 files.
+                env::log(
+                    self.info_log(),
+                    format_args!("Too many L0 files; waiting...\n"),
+                );
+                state = self.background_work_finished_signal.wait(state).unwrap();
+            } else {
+                // Attempt to switch to a new memtable and trigger compaction of old
+                let new_log_number = state.versions.new_file_number();
+                let lfile = match self
+                    .env
+                    .new_writable_file(&log_file_name(&self.dbname, new_log_number))
+                {
+                    Ok(lfile) => lfile,
+                    Err(status) => {
+                        // Avoid chewing through file number space in a tight loop.
+                        state.versions.reuse_file_number(new_log_number);
+                        s = status.into();
+                        break;
+                    }
+                };
+
+                if let Some(mut log) = state.log.take() {
+                    if let Err(e) = log.file().close() {
+                        // We may have lost some data written to the previous log file. Switch to
+                        // the new log file anyway, but record as a background error so we do not
+                        // attempt any more writes.
+                        //
+                        // We could perhaps attempt to save the memtable corresponding to log file
+                        // and suppress the error if that works, but that would add more
+                        // complexity in a critical code path.
+                        self.record_background_error(&mut state, &e.into());
+                    }
+                }
+                state.log = Some(log::Writer::new(lfile));
+                state.logfile_number = new_log_number;
+                let mem = std::mem::replace(
+                    &mut state.mem,
+                    Arc::new(MemTable::new(&self.internal_comparator)),
+                );
+                state.imm = Some(mem);
+                self.has_imm.store(true, Ordering::Release);
+                force = false; // Do not force another compaction if have room
+                self.maybe_schedule_compaction(&mut state);
+            }
+        }
+        (state, s)
+    }
+
+    // Apply "edit" to the current version and save it to the MANIFEST, releasing the mutex while
+    // the MANIFEST is written.
+    // Waits for any MANIFEST write already in progress, since the descriptor log is taken out of
+    // the VersionSet while it is written.
+    // REQUIRES: mutex is held
+    // NOTE: C++ passes the mutex to VersionSet::LogAndApply(), which unlocks it itself
+    fn log_and_apply<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DBState>,
+        edit: &mut VersionEdit,
+    ) -> (MutexGuard<'a, DBState>, Status) {
+        while state.manifest_write_in_progress {
+            state = self.manifest_written_signal.wait(state).unwrap();
+        }
+        state.manifest_write_in_progress = true;
+        let mut manifest_write = state.versions.prepare_log_and_apply(edit);
+
+        // Unlock during expensive MANIFEST log write
+        drop(state);
+        manifest_write.write();
+        state = self.mutex.lock().unwrap();
+
+        let s = state.versions.finish_log_and_apply(edit, manifest_write);
+        state.manifest_write_in_progress = false;
+        self.manifest_written_signal.notify_all();
+        (state, s)
+    }
+
+    fn record_background_error(&self, state: &mut DBState, s: &Status) {
+        if state.bg_error.ok() {
+            state.bg_error = s.clone();
+            self.background_work_finished_signal.notify_all();
+        }
+    }
+
+    // NOTE: C++ passes a pointer to the DBImpl through Env::schedule; the background work keeps
+    // the DBImpl alive through its own Arc here
+    fn maybe_schedule_compaction(self: &Arc<Self>, state: &mut DBState) {
+        if state.background_compaction_scheduled {
+            // Already scheduled
+        } else if self.shutting_down.load(Ordering::Acquire) {
+            // DB is being deleted; no more background compactions
+        } else if !state.bg_error.ok() {
+            // Already got an error; no more changes
+        } else if state.imm.is_none() && !state.versions.needs_compaction() {
+            // No work to be done
+        } else {
+            state.background_compaction_scheduled = true;
+            let db = Arc::clone(self);
+            self.env.schedule(Box::new(move || db.background_call()));
+        }
+    }
+
+    fn background_call(self: &Arc<Self>) {
+        let mut state = self.mutex.lock().unwrap();
+        assert!(state.background_compaction_scheduled);
+        if self.shutting_down.load(Ordering::Acquire) {
+            // No more background work when shutting down.
+        } else if !state.bg_error.ok() {
+            // No more background work after a background error.
+        } else {
+            state = self.background_compaction(state);
+        }
+
+        state.background_compaction_scheduled = false;
+
+        // Previous compaction may have produced too many files in a level, so reschedule another
+        // compaction if needed.
+        self.maybe_schedule_compaction(&mut state);
+        self.background_work_finished_signal.notify_all();
+    }
+
+    fn background_compaction<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DBState>,
+    ) -> MutexGuard<'a, DBState> {
+        if state.imm.is_some() {
+            return self.compact_mem_table(state);
+        }
+
+        let Some(mut c) = state.versions.pick_compaction() else {
+            // Nothing to do
+            return state;
+        };
+
+        let status;
+        if c.is_trivial_move() {
+            // Move file to next level
+            assert_eq!(1, c.num_input_files(0));
+            let f = Arc::clone(c.input(0, 0));
+            let level = c.level();
+            c.edit().remove_file(level, f.number);
+            c.edit()
+                .add_file(level + 1, f.number, f.file_size, &f.smallest, &f.largest);
+            (state, status) = self.log_and_apply(state, c.edit());
+            if !status.ok() {
+                self.record_background_error(&mut state, &status);
+            }
+            env::log(
+                self.info_log(),
+                format_args!(
+                    "Moved #{} to level-{} {} bytes {}: {}\n",
+                    f.number,
+                    level + 1,
+                    f.file_size,
+                    status,
+                    state.versions.level_summary()
+                ),
+            );
+        } else {
+            let mut compact = CompactionState::new(c);
+            (state, status) = self.do_compaction_work(state, &mut compact);
+            if !status.ok() {
+                self.record_background_error(&mut state, &status);
+            }
+            self.cleanup_compaction(&mut state, &compact);
+            compact.compaction.release_inputs();
+            state = self.remove_obsolete_files(state);
+        }
+
+        if status.ok() {
+            // Done
+        } else if self.shutting_down.load(Ordering::Acquire) {
+            // Ignore compaction errors found during shutting down
+        } else {
+            env::log(
+                self.info_log(),
+                format_args!("Compaction error: {}", status),
+            );
+        }
+        state
+    }
+
+    // Compact the in-memory write buffer to disk. Switches to a new log-file/memtable and writes
+    // a new descriptor iff successful. Errors are recorded in bg_error.
+    fn compact_mem_table<'a>(&'a self, state: MutexGuard<'a, DBState>) -> MutexGuard<'a, DBState> {
+        let imm = Arc::clone(state.imm.as_ref().unwrap());
+
+        // Save the contents of the memtable as a new Table
+        let mut edit = VersionEdit::new();
+        let base = Arc::clone(state.versions.current());
+        let (mut state, mut s) = self.write_level0_table(state, &imm, &mut edit, Some(&base));
+        drop(base);
+
+        if s.ok() && self.shutting_down.load(Ordering::Acquire) {
+            s = Status::io_error(
+                &"Deleting DB during memtable compaction".into(),
+                &Slice::new(),
+            );
+        }
+
+        // Replace immutable memtable with the generated Table
+        if s.ok() {
+            edit.set_prev_log_number(0);
+            edit.set_log_number(state.logfile_number); // Earlier logs no longer needed
+            (state, s) = self.log_and_apply(state, &mut edit);
+        }
+
+        if s.ok() {
+            // Commit to the new state
+            state.imm = None;
+            self.has_imm.store(false, Ordering::Release);
+            state = self.remove_obsolete_files(state);
+        } else {
+            self.record_background_error(&mut state, &s);
+        }
+        state
+    }
+
+    fn write_level0_table<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DBState>,
+        mem: &Arc<MemTable>,
+        edit: &mut VersionEdit,
+        base: Option<&Version>,
+    ) -> (MutexGuard<'a, DBState>, Status) {
+        let mut meta = FileMetaData::new();
+        meta.number = state.versions.new_file_number();
+        state.pending_outputs.insert(meta.number);
+        let mut iter = mem.new_iterator();
+        env::log(
+            self.info_log(),
+            format_args!("Level-0 table #{}: started", meta.number),
+        );
+
+        drop(state);
+        let s = build_table(
+            &self.dbname,
+            &*self.env,
+            &self.options,
+            &self.table_cache,
+            &mut *iter,
+            &mut meta,
+        );
+        let mut state = self.mutex.lock().unwrap();
+
+        env::log(
+            self.info_log(),
+            format_args!(
+                "Level-0 table #{}: {} bytes {}",
+                meta.number, meta.file_size, s
+            ),
+        );
+        drop(iter);
+        state.pending_outputs.remove(&meta.number);
+
+        // Note that if file_size is zero, the file has been deleted and should not be added to
+        // the manifest.
+        if s.ok() && meta.file_size > 0 {
+            let min_user_key = meta.smallest.user_key();
+            let max_user_key = meta.largest.user_key();
+            let level = base.map_or(0, |base| {
+                base.pick_level_for_mem_table_output(&min_user_key, &max_user_key)
+            });
+            edit.add_file(
+                level,
+                meta.number,
+                meta.file_size,
+                &meta.smallest,
+                &meta.largest,
+            );
+        }
+        (state, s)
+    }
+
+    fn do_compaction_work<'a>(
+        &'a self,
+        state: MutexGuard<'a, DBState>,
+        compact: &mut CompactionState,
+    ) -> (MutexGuard<'a, DBState>, Status) {
+        let level = compact.compaction.level();
+        env::log(
+            self.info_log(),
+            format_args!(
+                "Compacting {}@{} + {}@{} files",
+                compact.compaction.num_input_files(0),
+                level,
+                compact.compaction.num_input_files(1),
+                level + 1
+            ),
+        );
+
+        assert!(state.versions.num_level_files(level) > 0);
+        assert!(compact.outputs.is_empty());
+        compact.smallest_snapshot = if state.snapshots.is_empty() {
+            state.versions.last_sequence()
+        } else {
+            state.snapshots.oldest().sequence_number()
+        };
+
+        let mut input = state.versions.make_input_iterator(&compact.compaction);
+
+        // Release mutex while we're actually doing the compaction work
+        drop(state);
+
+        input.seek_to_first();
+        let mut status = Status::OK();
+        while self.skip_to_next_output_entry(compact, &mut *input, false) {
+            status = self.write_compaction_output_file(compact, &mut *input);
+            if !status.ok() {
+                break;
+            }
+        }
+
+        if status.ok() && self.shutting_down.load(Ordering::Acquire) {
+            status = Status::io_error(&"Deleting DB during compaction".into(), &Slice::new());
+        }
+        if status.ok() {
+            status = input.status().into();
+        }
+        drop(input);
+
+        let mut state = self.mutex.lock().unwrap();
+        if status.ok() {
+            (state, status) = self.install_compaction_results(state, compact);
+        }
+        if !status.ok() {
+            self.record_background_error(&mut state, &status);
+        }
+        env::log(
+            self.info_log(),
+            format_args!("compacted to: {}", state.versions.level_summary()),
+        );
+        (state, status)
+    }
+
+    // Advances "input" past the entries that are dropped by the compaction. Returns true iff
+    // "input" is left at an entry to write to the current output: false once the input is
+    // exhausted, the DB is shutting down or, when "output_open", the output should be finished
+    // before the entry.
+    // NOTE: C++ does this inline in the loop of DoCompactionWork
+    fn skip_to_next_output_entry(
+        &self,
+        compact: &mut CompactionState,
+        input: &mut dyn Iter,
+        output_open: bool,
+    ) -> bool {
+        while input.valid() && !self.shutting_down.load(Ordering::Acquire) {
+            // Prioritize immutable compaction work
+            if self.has_imm.load(Ordering::Relaxed) {
+                let mut state = self.mutex.lock().unwrap();
+                if state.imm.is_some() {
+                    state = self.compact_mem_table(state);
+                    // Wake up make_room_for_write() if necessary.
+                    self.background_work_finished_signal.notify_all();
+                }
+                drop(state);
+            }
+
+            let key = input.key();
+            if compact.compaction.should_stop_before(&key) && output_open {
+                return false;
+            }
+            if !compact.should_drop(&**self.internal_comparator.user_comparator(), &key) {
+                return true;
+            }
+            input.next();
+        }
+        false
+    }
+
+    // Writes entries of "input", starting at the current one, to a new output file until the
+    // file is large enough or the compaction moves on to a new output.
+    // NOTE: C++ keeps the open output file and its builder in the CompactionState; here the
+    // builder borrows the file, so each output is written and finished by a single call
+    fn write_compaction_output_file(
+        &self,
+        compact: &mut CompactionState,
+        input: &mut dyn Iter,
+    ) -> Status {
+        let mut file = match self.open_compaction_output_file(compact) {
+            Ok(file) => file,
+            Err(s) => return s.into(),
+        };
+        let output_number = compact.current_output().number;
+
+        let mut builder = TableBuilder::new(&self.options, &mut *file);
+        loop {
+            let key = input.key();
+            let output = compact.current_output();
+            if builder.num_entries() == 0 {
+                output.smallest.decode_from(&key);
+            }
+            output.largest.decode_from(&key);
+            builder.add(&key, &input.value());
+            input.next();
+
+            // Close output file if it is big enough
+            if builder.file_size() >= compact.compaction.max_output_file_size()
+                || !self.skip_to_next_output_entry(compact, input, true)
+            {
+                break;
+            }
+        }
+
+        // Check for iterator errors
+        let mut s = Status::from(input.status());
+        let current_entries = builder.num_entries();
+        if s.ok() {
+            s = builder.finish().into();
+        } else {
+            builder.abandon();
+        }
+        let current_bytes = builder.file_size();
+        drop(builder);
+        compact.current_output().file_size = current_bytes;
+        compact.total_bytes += current_bytes;
+
+        // Finish and check for file errors
+        if s.ok() {
+            s = file.sync().into();
+        }
+        if s.ok() {
+            s = file.close().into();
+        }
+        drop(file);
+
+        if s.ok() && current_entries > 0 {
+            // Verify that the table is usable
+            let iter = self.table_cache.new_iterator(
+                &ReadOptions::default(),
+                output_number,
+                current_bytes,
+            );
+            s = iter.status().into();
+            if s.ok() {
+                env::log(
+                    self.info_log(),
+                    format_args!(
+                        "Generated table #{}@{}: {} keys, {} bytes",
+                        output_number,
+                        compact.compaction.level(),
+                        current_entries,
+                        current_bytes
+                    ),
+                );
+            }
+        }
+        s
+    }
+
+    fn open_compaction_output_file(
+        &self,
+        compact: &mut CompactionState,
+    ) -> Result<Box<dyn WritableFile>> {
+        let file_number = {
+            let mut state = self.mutex.lock().unwrap();
+            let file_number = state.versions.new_file_number();
+            state.pending_outputs.insert(file_number);
+            compact.outputs.push(Output {
+                number: file_number,
+                file_size: 0,
+                smallest: InternalKey::default(),
+                largest: InternalKey::default(),
+            });
+            file_number
+        };
+
+        // Make the output file
+        let fname = table_file_name(&self.dbname, file_number);
+        self.env.new_writable_file(&fname)
+    }
+
+    fn install_compaction_results<'a>(
+        &'a self,
+        state: MutexGuard<'a, DBState>,
+        compact: &mut CompactionState,
+    ) -> (MutexGuard<'a, DBState>, Status) {
+        let level = compact.compaction.level();
+        env::log(
+            self.info_log(),
+            format_args!(
+                "Compacted {}@{} + {}@{} files => {} bytes",
+                compact.compaction.num_input_files(0),
+                level,
+                compact.compaction.num_input_files(1),
+                level + 1,
+                compact.total_bytes
+            ),
+        );
+
+        // Add compaction outputs
+        compact.compaction.add_input_deletions();
+        for out in &compact.outputs {
+            compact.compaction.edit().add_file(
+                level + 1,
+                out.number,
+                out.file_size,
+                &out.smallest,
+                &out.largest,
+            );
+        }
+        self.log_and_apply(state, compact.compaction.edit())
+    }
+
+    fn cleanup_compaction(&self, state: &mut DBState, compact: &CompactionState) {
+        for out in &compact.outputs {
+            state.pending_outputs.remove(&out.number);
+        }
+    }
+
+    // Delete any unneeded files and stale in-memory entries.
+    fn remove_obsolete_files<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DBState>,
+    ) -> MutexGuard<'a, DBState> {
+        if !state.bg_error.ok() {
+            // After a background error, we don't know whether a new version may or may not have
+            // been committed, so we cannot safely garbage collect.
+            return state;
+        }
+
+        // Make a set of all of the live files
+        let mut live = state.pending_outputs.clone();
+        state.versions.add_live_files(&mut live);
+
+        let Ok(filenames) = self.env.get_children(&self.dbname) else {
+            // Ignoring errors on purpose
+            return state;
+        };
+        let mut files_to_delete = Vec::new();
+        for filename in filenames {
+            let mut number = 0;
+            let mut file_type = FileType::Log;
+            if parse_file_name(&filename, &mut number, &mut file_type) {
+                let keep = match file_type {
+                    FileType::Log => {
+                        number >= state.versions.log_number()
+                            || number == state.versions.prev_log_number()
+                    }
+                    FileType::Descriptor => {
+                        // Keep my manifest file, and any newer incarnations' (in case there is a
+                        // race that allows other incarnations)
+                        number >= state.versions.manifest_file_number()
+                    }
+                    FileType::Table => live.contains(&number),
+                    FileType::Temp => {
+                        // Any temp files that are currently being written to must be recorded in
+                        // pending_outputs, which is inserted into "live"
+                        live.contains(&number)
+                    }
+                    FileType::Current | FileType::DBLock | FileType::InfoLog => true,
+                };
+
+                if !keep {
+                    if file_type == FileType::Table {
+                        self.table_cache.evict(number);
+                    }
+                    env::log(
+                        self.info_log(),
+                        format_args!("Delete type={:?} #{}\n", file_type, number),
+                    );
+                    files_to_delete.push(filename);
+                }
+            }
+        }
+
+        // While deleting all files unblock other threads. All files being deleted have unique
+        // names which will not collide with newly created files and are therefore safe to delete
+        // while allowing other threads to proceed.
+        drop(state);
+        for filename in files_to_delete {
+            let _ = self
+                .env
+                .remove_file(&format!("{}/{}", self.dbname, filename));
+        }
+        self.mutex.lock().unwrap()
+    }
+
+    fn new_db(&self) -> Status {
+        let mut new_db = VersionEdit::new();
+        new_db.set_comparator_name(self.internal_comparator.user_comparator().name());
+        new_db.set_log_number(0);
+        new_db.set_next_file(2);
+        new_db.set_last_sequence(0);
+
+        let env = &*self.env;
+        let manifest = descriptor_file_name(&self.dbname, 1);
+        let file = match env.new_writable_file(&manifest) {
+            Ok(file) => file,
+            Err(s) => return s.into(),
+        };
+        let mut s;
+        {
+            let mut log = log::Writer::new(file);
+            let mut record = Vec::new();
+            new_db.encode_to(&mut record);
+            s = log.add_record(&Slice::from(&record));
+            if s.ok() {
+                s = log.file().sync().into();
+            }
+            if s.ok() {
+                s = log.file().close().into();
+            }
+        }
+        if s.ok() {
+            // Make "CURRENT" file that points to the new manifest file.
+            s = set_current_file(env, &self.dbname, 1);
+        } else {
+            let _ = env.remove_file(&manifest);
+        }
+        s
+    }
+
+    // Recover the descriptor from persistent storage. May do a significant amount of work to
+    // recover recently logged updates. Any changes to be made to the descriptor are added to
+    // *edit.
+    fn recover<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DBState>,
+        edit: &mut VersionEdit,
+        save_manifest: &mut bool,
+    ) -> (MutexGuard<'a, DBState>, Status) {
+        // Ignore error from create_dir since the creation of the DB is committed only when the
+        // descriptor is created, and this directory may already exist from a previous failed
+        // creation attempt.
+        let _ = self.env.create_dir(&self.dbname);
+        assert!(state.db_lock.is_none());
+        match self.env.lock_file(&lock_file_name(&self.dbname)) {
+            Ok(lock) => state.db_lock = Some(lock),
+            Err(s) => return (state, s.into()),
+        }
+
+        if !self.env.file_exists(&current_file_name(&self.dbname)) {
+            if self.options.create_if_missing {
+                env::log(
+                    self.info_log(),
+                    format_args!("Creating DB {} since it was missing.", self.dbname),
+                );
+                let s = self.new_db();
+                if !s.ok() {
+                    return (state, s);
+                }
+            } else {
+                let s = Status::invalid_argument(
+                    &self.dbname.as_str().into(),
+                    &"does not exist (create_if_missing is false)".into(),
+                );
+                return (state, s);
+            }
+        } else if self.options.error_if_exists {
+            let s = Status::invalid_argument(
+                &self.dbname.as_str().into(),
+                &"exists (error_if_exists is true)".into(),
+            );
+            return (state, s);
+        }
+
+        let s = state.versions.recover(save_manifest);
+        if !s.ok() {
+            return (state, s);
+        }
+        let mut max_sequence = 0;
+
+        // Recover from all newer log files than the ones named in the descriptor (new log files
+        // may have been added by the previous incarnation without registering them in the
+        // descriptor).
+        //
+        // Note that prev_log_number() is no longer used, but we pay attention to it in case we
+        // are recovering a database produced by an older version of leveldb.
+        let min_log = state.versions.log_number();
+        let prev_log = state.versions.prev_log_number();
+        let filenames = match self.env.get_children(&self.dbname) {
+            Ok(filenames) => filenames,
+            Err(s) => return (state, s.into()),
+        };
+        let mut expected = BTreeSet::new();
+        state.versions.add_live_files(&mut expected);
+        let mut logs = Vec::new();
+        for filename in &filenames {
+            let mut number = 0;
+            let mut file_type = FileType::Log;
+            if parse_file_name(filename, &mut number, &mut file_type) {
+                expected.remove(&number);
+                if file_type == FileType::Log && (number >= min_log || number == prev_log) {
+                    logs.push(number);
+                }
+            }
+        }
+        if let Some(missing) = expected.first() {
+            let s = Status::corruption(
+                &format!("{} missing files; e.g.", expected.len())
+                    .as_str()
+                    .into(),
+                &table_file_name(&self.dbname, *missing).as_str().into(),
+            );
+            return (state, s);
+        }
+
+        // Recover in the order in which the logs were generated
+        logs.sort_unstable();
+        for (i, &log_number) in logs.iter().enumerate() {
+            let s;
+            (state, s) = self.recover_log_file(
+                state,
+                log_number,
+                i == logs.len() - 1,
+                save_manifest,
+                edit,
+                &mut max_sequence,
+            );
+            if !s.ok() {
+                return (state, s);
+            }
+
+            // The previous incarnation may not have written any MANIFEST records after allocating
+            // this log number. So we manually update the file number allocation counter in
+            // VersionSet.
+            state.versions.mark_file_number_used(log_number);
+        }
+
+        if state.versions.last_sequence() < max_sequence {
+            state.versions.set_last_sequence(max_sequence);
+        }
+
+        (state, Status::OK())
+    }
+
+    fn recover_log_file<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, DBState>,
+        log_number: u64,
+        last_log: bool,
+        save_manifest: &mut bool,
+        edit: &mut VersionEdit,
+        max_sequence: &mut SequenceNumber,
+    ) -> (MutexGuard<'a, DBState>, Status) {
+        #[derive(Clone)]
+        struct LogReporter {
+            info_log: Option<Arc<dyn Logger>>,
+            fname: String,
+            // None if options.paranoid_checks == false
+            status: Option<Rc<RefCell<Status>>>,
+        }
+
+        impl log::Reporter for LogReporter {
+            fn corruption(&mut self, bytes: usize, s: &Status) {
+                env::log(
+                    self.info_log.as_deref(),
+                    format_args!(
+                        "{}{}: dropping {} bytes; {}",
+                        if self.status.is_none() {
+                            "(ignoring error) "
+                        } else {
+                            ""
+                        },
+                        self.fname,
+                        bytes,
+                        s
+                    ),
+                );
+                if let Some(status) = &self.status {
+                    let mut status = status.borrow_mut();
+                    if status.ok() {
+                        *status = s.clone();
+                    }
+                }
+            }
+        }
+
+        // Open the log file
+        let fname = log_file_name(&self.dbname, log_number);
+        let file = match self.env.new_sequential_file(&fname) {
+            Ok(file) => file,
+            Err(s) => return (state, self.maybe_ignore_error(s.into())),
+        };
+
+        // Create the log reader.
+        let status = Rc::new(RefCell::new(Status::OK()));
+        let mut reporter = LogReporter {
+            info_log: self.options.info_log.clone(),
+            fname: fname.clone(),
+            status: self.options.paranoid_checks.then(|| Rc::clone(&status)),
+        };
+        // We intentionally make log::Reader do checksumming even if paranoid_checks==false so
+        // that corruptions cause entire commits to be skipped instead of propagating bad
+        // information (like overly large sequence numbers).
+        let mut reader = log::Reader::new(file, Some(Box::new(reporter.clone())), true, 0);
+        env::log(
+            self.info_log(),
+            format_args!("Recovering log #{}", log_number),
+        );
+
+        // Read all the records and add to a memtable
+        let mut scratch = Vec::new();
+        let mut batch = WriteBatch::new();
+        let mut compactions = 0;
+        let mut mem: Option<Arc<MemTable>> = None;
+        while let Some(record) = reader
+            .read_record(&mut scratch)
+            .filter(|_| status.borrow().ok())
+        {
+            if record.size() < 12 {
+                log::Reporter::corruption(
+                    &mut reporter,
+                    record.size(),
+                    &Status::corruption(&"log record too small".into(), &Slice::new()),
+                );
+                continue;
+            }
+            WriteBatchInternal::set_contents(&mut batch, &record);
+
+            let m = mem.get_or_insert_with(|| Arc::new(MemTable::new(&self.internal_comparator)));
+            let s = WriteBatchInternal::insert_into(&batch, m);
+            let s = self.maybe_ignore_error(s);
+            if !s.ok() {
+                *status.borrow_mut() = s;
+                break;
+            }
+            let last_seq =
+                WriteBatchInternal::sequence(&batch) + WriteBatchInternal::count(&batch) as u64 - 1;
+            if last_seq > *max_sequence {
+                *max_sequence = last_seq;
+            }
+
+            if m.approximate_memory_usage() > self.options.write_buffer_size {
+                compactions += 1;
+                *save_manifest = true;
+                let s;
+                (state, s) = self.write_level0_table(state, m, edit, None);
+                mem = None;
+                if !s.ok() {
+                    // Reflect errors immediately so that conditions like full file-systems cause
+                    // the DB::open() to fail.
+                    *status.borrow_mut() = s;
+                    break;
+                }
+            }
+        }
+        drop(reader);
+        let mut status = status.take();
+
+        // See if we should keep reusing the last log file.
+        if status.ok() && self.options.reuse_logs && last_log && compactions == 0 {
+            assert!(state.log.is_none());
+            if let Ok(lfile_size) = self.env.get_file_size(&fname) {
+                if let Ok(lfile) = self.env.new_appendable_file(&fname) {
+                    env::log(
+                        self.info_log(),
+                        format_args!("Reusing old log {} \n", fname),
+                    );
+                    state.log = Some(log::Writer::with_dest_length(lfile, lfile_size));
+                    state.logfile_number = log_number;
+                    // mem can be None if lognum exists but was empty.
+                    if let Some(mem) = mem.take() {
+                        state.mem = mem;
+                    }
+                }
+            }
+        }
+
+        if let Some(mem) = mem {
+            // mem did not get reused; compact it.
+            if status.ok() {
+                *save_manifest = true;
+                (state, status) = self.write_level0_table(state, &mem, edit, None);
+            }
+        }
+
+        (state, status)
+    }
+
+    // Ignores "s" unless options.paranoid_checks is set.
+    fn maybe_ignore_error(&self, s: Status) -> Status {
+        if s.ok() || self.options.paranoid_checks {
+            s
+        } else {
+            env::log(self.info_log(), format_args!("Ignoring error {}", s));
+            Status::OK()
+        }
+    }
+}
+
+// Destroy the contents of the specified database.
+// Be very careful using this method.
+//
+// Note: For backwards compatibility, if destroy_db is unable to list the database files, Ok will
+// still be returned masking this failure.
+//
+// NOTE: Only files whose names parse as database files are removed; the directory is kept if
+// anything else remains in it.
+pub fn destroy_db(dbname: &str, options: &Options) -> Result<()> {
+    let env = &options.env;
+    let filenames = match env.get_children(dbname) {
+        Ok(filenames) => filenames,
+        // Ignore error in case directory does not exist
+        Err(_) => return Ok(()),
+    };
+
+    let lockname = lock_file_name(dbname);
+    let lock = env.lock_file(&lockname)?;
+    let mut result = Ok(());
+    let mut number = 0;
+    let mut file_type = FileType::Log;
+    for filename in filenames {
+        // Lock file will be deleted at end
+        if parse_file_name(&filename, &mut number, &mut file_type) && file_type != FileType::DBLock
+        {
+            let del = env.remove_file(&format!("{}/{}", dbname, filename));
+            if result.is_ok() && del.is_err() {
+                result = del;
+            }
+        }
+    }
+    let _ = env.unlock_file(lock); // Ignore error since state is already gone
+    let _ = env.remove_file(&lockname);
+    let _ = env.remove_dir(dbname); // Ignore error in case dir contains other files
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::{bytewise_comparator, Comparator};
+    use crate::env::default_env;
+    use crate::helpers::memenv::new_mem_env;
+    use std::sync::OnceLock;
+
+    // Every test DB lives in one in-memory Env, so the tests don't touch the file system.
+    fn test_env() -> Arc<dyn Env> {
+        static ENV: OnceLock<Arc<dyn Env>> = OnceLock::new();
+        Arc::clone(ENV.get_or_init(|| new_mem_env(default_env())))
+    }
+
+    fn test_options() -> Options {
+        Options {
+            env: test_env(),
+            ..Default::default()
+        }
+    }
+
+    fn test_db_name(name: &str) -> String {
+        let dir = test_env().get_test_directory().unwrap();
+        let dbname = format!("{}/db_test_{}", dir, name);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+        dbname
+    }
+
+    // The in-memory Env doesn't lock files, so the tests of the DB's lock run against the default
+    // Env.
+    fn posix_db_name(name: &str) -> String {
+        let dir = default_env().get_test_directory().unwrap();
+        let dbname = format!("{}/db_test_{}", dir, name);
+        assert!(destroy_db(&dbname, &posix_options()).is_ok());
+        dbname
+    }
+
+    fn posix_options() -> Options {
+        Options {
+            create_if_missing: true,
+            ..Default::default()
+        }
+    }
+
+    fn open_options() -> Options {
+        Options {
+            create_if_missing: true,
+            ..test_options()
+        }
+    }
+
+    fn get(db: &DB, key: &str) -> String {
+        match db.get(&ReadOptions::default(), &key.into()) {
+            Ok(value) => String::from_utf8(value).unwrap(),
+            Err(e) if e.is_not_found() => "NOT_FOUND".to_owned(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_send_sync() {
+        // A DB can be shared between threads without any external synchronization
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DB>();
+    }
+
+    #[test]
+    fn test_missing_without_create() {
+        let dbname = test_db_name("missing");
+        let s = DB::open(test_options(), &dbname).err().unwrap();
+        assert!(s.is_invalid_argument(), "{}", s);
+    }
+
+    #[test]
+    fn test_error_if_exists() {
+        let dbname = test_db_name("error_if_exists");
+        drop(DB::open(open_options(), &dbname).unwrap());
+
+        let options = Options {
+            error_if_exists: true,
+            ..open_options()
+        };
+        let s = DB::open(options, &dbname).err().unwrap();
+        assert!(s.is_invalid_argument(), "{}", s);
+
+        // Opening without error_if_exists still works
+        drop(DB::open(test_options(), &dbname).unwrap());
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_locked() {
+        let dbname = posix_db_name("locked");
+        let db = DB::open(posix_options(), &dbname).unwrap();
+        assert!(DB::open(posix_options(), &dbname).is_err());
+        drop(db);
+        drop(DB::open(posix_options(), &dbname).unwrap());
+        assert!(destroy_db(&dbname, &posix_options()).is_ok());
+    }
+
+    #[test]
+    fn test_put_get_delete() {
+        let dbname = test_db_name("put_get_delete");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+        assert_eq!("NOT_FOUND", get(&db, "foo"));
+        assert!(db.put(&wo, &"foo".into(), &"v1".into()).is_ok());
+        assert_eq!("v1", get(&db, "foo"));
+        assert!(db.put(&wo, &"foo".into(), &"v2".into()).is_ok());
+        assert_eq!("v2", get(&db, "foo"));
+        assert!(db.delete(&wo, &"foo".into()).is_ok());
+        assert_eq!("NOT_FOUND", get(&db, "foo"));
+        // Deleting a missing key is not an error
+        assert!(db.delete(&wo, &"missing".into()).is_ok());
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let dbname = test_db_name("write_batch");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+        assert!(db.put(&wo, &"a".into(), &"va".into()).is_ok());
+
+        let mut batch = WriteBatch::new();
+        batch.put(&"key".into(), &"v1".into());
+        batch.delete(&"key".into());
+        batch.put(&"key".into(), &"v2".into());
+        batch.put(&"key".into(), &"v3".into());
+        batch.delete(&"a".into());
+        assert!(db.write(&wo, &mut batch).is_ok());
+
+        assert_eq!("v3", get(&db, "key"));
+        assert_eq!("NOT_FOUND", get(&db, "a"));
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_concurrent_writes() {
+        // Writers on several threads, with memtable switches, all land and survive reopening
+        let dbname = test_db_name("concurrent_writes");
+        let options = Options {
+            write_buffer_size: 10000,
+            ..open_options()
+        };
+        let db = DB::open(options.clone(), &dbname).unwrap();
+        std::thread::scope(|scope| {
+            for t in 0..4 {
+                let db = &db;
+                scope.spawn(move || {
+                    for i in 0..200 {
+                        let key = format!("{}.{:04}", t, i);
+                        assert!(db
+                            .put(&WriteOptions::default(), &key.as_str().into(), &"v".into())
+                            .is_ok());
+                    }
+                });
+            }
+        });
+        drop(db);
+
+        let db = DB::open(options, &dbname).unwrap();
+        for t in 0..4 {
+            for i in 0..200 {
+                assert_eq!("v", get(&db, &format!("{}.{:04}", t, i)));
+            }
+        }
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_recover() {
+        let dbname = test_db_name("recover");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions { sync: true };
+        assert!(db.put(&wo, &"foo".into(), &"v1".into()).is_ok());
+        assert!(db.put(&wo, &"baz".into(), &"v5".into()).is_ok());
+        drop(db);
+
+        let db = DB::open(open_options(), &dbname).unwrap();
+        assert_eq!("v1", get(&db, "foo"));
+        assert_eq!("v5", get(&db, "baz"));
+        assert!(db.put(&wo, &"bar".into(), &"v2".into()).is_ok());
+        assert!(db.put(&wo, &"foo".into(), &"v3".into()).is_ok());
+        assert!(db.delete(&wo, &"baz".into()).is_ok());
+        drop(db);
+
+        let db = DB::open(open_options(), &dbname).unwrap();
+        assert_eq!("v3", get(&db, "foo"));
+        assert_eq!("v2", get(&db, "bar"));
+        assert_eq!("NOT_FOUND", get(&db, "baz"));
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_paranoid_checks() {
+        let dbname = test_db_name("paranoid_checks");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        assert!(db
+            .put(&WriteOptions::default(), &"foo".into(), &"v1".into())
+            .is_ok());
+        drop(db);
+
+        // Corrupt the payload of the only record in the log. File numbers 1 and 2 are taken by
+        // the descriptors written by new_db() and the first open.
+        let fname = log_file_name(&dbname, 3);
+        let env = test_env();
+        let mut contents = env::read_file_to_string(&*env, &fname).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 0x80;
+        assert!(env::write_string_to_file(&*env, &Slice::from(&contents[..]), &fname).is_ok());
+
+        let options = Options {
+            paranoid_checks: true,
+            ..open_options()
+        };
+        let s = DB::open(options, &dbname).err().unwrap();
+        assert!(s.is_corruption(), "{}", s);
+
+        // The corrupted record is skipped without paranoid checks
+        let db = DB::open(open_options(), &dbname).unwrap();
+        assert_eq!("NOT_FOUND", get(&db, "foo"));
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_comparator_check() {
+        struct NewComparator;
+
+        impl Comparator for NewComparator {
+            fn compare(&self, a: &Slice, b: &Slice) -> i32 {
+                bytewise_comparator().compare(a, b)
+            }
+
+            fn name(&self) -> &'static str {
+                "leveldb.NewComparator"
+            }
+
+            fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &Slice) {
+                bytewise_comparator().find_shortest_separator(start, limit);
+            }
+
+            fn find_short_successor(&self, key: &mut Vec<u8>) {
+                bytewise_comparator().find_short_successor(key);
+            }
+        }
+
+        let dbname = test_db_name("comparator_check");
+        drop(DB::open(open_options(), &dbname).unwrap());
+
+        let options = Options {
+            comparator: Arc::new(NewComparator),
+            ..open_options()
+        };
+        let s = DB::open(options, &dbname).err().unwrap();
+        assert!(s.is_invalid_argument(), "{}", s);
+        assert!(s.to_string().contains("comparator"), "{}", s);
+
+        // The original comparator still works
+        drop(DB::open(open_options(), &dbname).unwrap());
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    fn total_table_files(db: &DB) -> usize {
+        (0..config::NUM_LEVELS)
+            .map(|level| db.test_num_level_files(level))
+            .sum()
+    }
+
+    #[test]
+    fn test_compact_mem_table() {
+        let dbname = test_db_name("compact_mem_table");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+        assert!(db.put(&wo, &"foo".into(), &"v1".into()).is_ok());
+        assert!(db.put(&wo, &"bar".into(), &"v2".into()).is_ok());
+        assert!(db.test_compact_mem_table().ok());
+        assert_eq!(1, total_table_files(&db));
+        assert_eq!("v1", get(&db, "foo"));
+        assert_eq!("v2", get(&db, "bar"));
+
+        // A deletion in a newer table hides the older value
+        assert!(db.delete(&wo, &"foo".into()).is_ok());
+        assert!(db.test_compact_mem_table().ok());
+        assert_eq!("NOT_FOUND", get(&db, "foo"));
+        assert_eq!("v2", get(&db, "bar"));
+        drop(db);
+
+        let db = DB::open(open_options(), &dbname).unwrap();
+        assert_eq!("NOT_FOUND", get(&db, "foo"));
+        assert_eq!("v2", get(&db, "bar"));
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_minor_compactions_during_writes() {
+        let dbname = test_db_name("minor_compactions");
+        let options = Options {
+            write_buffer_size: 100000,
+            ..open_options()
+        };
+        let db = DB::open(options.clone(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+        let value = "x".repeat(1000);
+        for i in 0..500 {
+            let key = format!("key{:06}", i);
+            assert!(db
+                .put(&wo, &key.as_str().into(), &value.as_str().into())
+                .is_ok());
+        }
+        db.test_wait_for_compactions();
+        assert!(total_table_files(&db) > 1);
+        for i in 0..500 {
+            assert_eq!(value, get(&db, &format!("key{:06}", i)));
+        }
+        drop(db);
+
+        let db = DB::open(options, &dbname).unwrap();
+        for i in 0..500 {
+            assert_eq!(value, get(&db, &format!("key{:06}", i)));
+        }
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_level0_compaction() {
+        let dbname = test_db_name("level0_compaction");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+
+        // Every table covers the same keys, so the later ones stay in level-0 until there are
+        // enough of them to be merged into level-1
+        for round in 0..8 {
+            for i in 0..100 {
+                let key = format!("key{:03}", i);
+                let value = format!("v{}-{}", round, i);
+                assert!(db
+                    .put(&wo, &key.as_str().into(), &value.as_str().into())
+                    .is_ok());
+            }
+            // Deletions must hide the older values once merged
+            assert!(db
+                .delete(&wo, &format!("key{:03}", round).as_str().into())
+                .is_ok());
+            assert!(db.test_compact_mem_table().ok());
+        }
+        db.test_wait_for_compactions();
+        assert!(db.test_num_level_files(0) < config::L0_COMPACTION_TRIGGER);
+        assert!(db.test_num_level_files(1) > 0);
+
+        let check = |db: &DB| {
+            for i in 0..100 {
+                let expected = if i == 7 {
+                    "NOT_FOUND".to_owned()
+                } else {
+                    format!("v7-{}", i)
+                };
+                assert_eq!(expected, get(db, &format!("key{:03}", i)));
+            }
+        };
+        check(&db);
+        drop(db);
+
+        let db = DB::open(open_options(), &dbname).unwrap();
+        check(&db);
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    fn get_at(db: &DB, key: &str, snapshot: &Arc<Snapshot>) -> String {
+        let options = ReadOptions {
+            snapshot: Some(Arc::clone(snapshot)),
+            ..Default::default()
+        };
+        match db.get(&options, &key.into()) {
+            Ok(value) => String::from_utf8(value).unwrap(),
+            Err(e) if e.is_not_found() => "NOT_FOUND".to_owned(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_get_snapshot() {
+        let dbname = test_db_name("get_snapshot");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+
+        // Try with both a short key and a long key
+        for key in ["foo".to_owned(), "x".repeat(200)] {
+            assert!(db.put(&wo, &key.as_str().into(), &"v1".into()).is_ok());
+            let s1 = db.get_snapshot();
+            assert!(db.put(&wo, &key.as_str().into(), &"v2".into()).is_ok());
+            assert_eq!("v2", get(&db, &key));
+            assert_eq!("v1", get_at(&db, &key, &s1));
+            assert!(db.test_compact_mem_table().ok());
+            assert_eq!("v2", get(&db, &key));
+            assert_eq!("v1", get_at(&db, &key, &s1));
+            db.release_snapshot(s1);
+        }
+
+        // A snapshot taken before a key existed does not see it
+        let s2 = db.get_snapshot();
+        assert!(db.put(&wo, &"bar".into(), &"v1".into()).is_ok());
+        assert_eq!("NOT_FOUND", get_at(&db, "bar", &s2));
+        assert!(db.delete(&wo, &"foo".into()).is_ok());
+        assert_eq!("v2", get_at(&db, "foo", &s2));
+        assert_eq!("NOT_FOUND", get(&db, "foo"));
+        db.release_snapshot(s2);
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_foreign_and_released_snapshots() {
+        let dbname = test_db_name("foreign_snapshot");
+        let other_dbname = test_db_name("foreign_snapshot_other");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let other = DB::open(open_options(), &other_dbname).unwrap();
+        assert!(db
+            .put(&WriteOptions::default(), &"foo".into(), &"v1".into())
+            .is_ok());
+
+        let check_invalid = |db: &DB, snapshot: &Arc<Snapshot>| {
+            let options = ReadOptions {
+                snapshot: Some(Arc::clone(snapshot)),
+                ..Default::default()
+            };
+            let e = db.get(&options, &"foo".into()).unwrap_err();
+            assert!(e.is_invalid_argument(), "{}", e);
+            let iter = db.new_iterator(&options);
+            assert!(!iter.valid());
+            assert!(iter.status().unwrap_err().is_invalid_argument());
+        };
+
+        // A snapshot can only be read through the DB that created it
+        let foreign = other.get_snapshot();
+        check_invalid(&db, &foreign);
+        other.release_snapshot(foreign);
+
+        // Clones of a released snapshot can no longer be read through
+        let snapshot = db.get_snapshot();
+        let clone = Arc::clone(&snapshot);
+        assert_eq!("v1", get_at(&db, "foo", &clone));
+        db.release_snapshot(snapshot);
+        check_invalid(&db, &clone);
+
+        drop(clone);
+        drop((db, other));
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+        assert!(destroy_db(&other_dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_survives_compaction() {
+        let dbname = test_db_name("snapshot_compaction");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+
+        let mut snapshot = None;
+        for round in 0..8 {
+            for i in 0..100 {
+                let key = format!("key{:03}", i);
+                let value = format!("v{}-{}", round, i);
+                assert!(db
+                    .put(&wo, &key.as_str().into(), &value.as_str().into())
+                    .is_ok());
+            }
+            if round == 0 {
+                snapshot = Some(db.get_snapshot());
+            }
+            assert!(db.test_compact_mem_table().ok());
+        }
+        db.test_wait_for_compactions();
+        assert!(db.test_num_level_files(0) < config::L0_COMPACTION_TRIGGER);
+
+        // The merged tables still hold the values the snapshot needs
+        let snapshot = snapshot.unwrap();
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            assert_eq!(format!("v0-{}", i), get_at(&db, &key, &snapshot));
+            assert_eq!(format!("v7-{}", i), get(&db, &key));
+        }
+        db.release_snapshot(snapshot);
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    fn iter_status(iter: &dyn Iter) -> String {
+        if iter.valid() {
+            format!(
+                "{}->{}",
+                String::from_utf8_lossy(&iter.key()),
+                String::from_utf8_lossy(&iter.value())
+            )
+        } else {
+            "(invalid)".to_owned()
+        }
+    }
+
+    // Returns all of the entries visible through "iter", first forwards and then backwards
+    fn contents(iter: &mut dyn Iter) -> (Vec<String>, Vec<String>) {
+        let mut forward = Vec::new();
+        iter.seek_to_first();
+        while iter.valid() {
+            forward.push(iter_status(iter));
+            iter.next();
+        }
+        let mut backward = Vec::new();
+        iter.seek_to_last();
+        while iter.valid() {
+            backward.push(iter_status(iter));
+            iter.prev();
+        }
+        backward.reverse();
+        (forward, backward)
+    }
+
+    #[test]
+    fn test_iter_empty() {
+        let dbname = test_db_name("iter_empty");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let mut iter = db.new_iterator(&ReadOptions::default());
+
+        iter.seek_to_first();
+        assert_eq!("(invalid)", iter_status(&*iter));
+        iter.seek_to_last();
+        assert_eq!("(invalid)", iter_status(&*iter));
+        iter.seek(&"foo".into());
+        assert_eq!("(invalid)", iter_status(&*iter));
+        assert!(iter.status().is_ok());
+        drop(iter);
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_iter_multi() {
+        let dbname = test_db_name("iter_multi");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+        assert!(db.put(&wo, &"a".into(), &"va".into()).is_ok());
+        assert!(db.put(&wo, &"b".into(), &"vb".into()).is_ok());
+        assert!(db.put(&wo, &"c".into(), &"vc".into()).is_ok());
+        let mut iter = db.new_iterator(&ReadOptions::default());
+
+        iter.seek_to_first();
+        assert_eq!("a->va", iter_status(&*iter));
+        iter.next();
+        assert_eq!("b->vb", iter_status(&*iter));
+        iter.next();
+        assert_eq!("c->vc", iter_status(&*iter));
+        iter.next();
+        assert_eq!("(invalid)", iter_status(&*iter));
+        iter.seek_to_first();
+        assert_eq!("a->va", iter_status(&*iter));
+        iter.prev();
+        assert_eq!("(invalid)", iter_status(&*iter));
+
+        iter.seek_to_last();
+        assert_eq!("c->vc", iter_status(&*iter));
+        iter.prev();
+        assert_eq!("b->vb", iter_status(&*iter));
+        iter.prev();
+        assert_eq!("a->va", iter_status(&*iter));
+        iter.prev();
+        assert_eq!("(invalid)", iter_status(&*iter));
+        iter.seek_to_last();
+        assert_eq!("c->vc", iter_status(&*iter));
+        iter.next();
+        assert_eq!("(invalid)", iter_status(&*iter));
+
+        iter.seek(&"".into());
+        assert_eq!("a->va", iter_status(&*iter));
+        iter.seek(&"a".into());
+        assert_eq!("a->va", iter_status(&*iter));
+        iter.seek(&"ax".into());
+        assert_eq!("b->vb", iter_status(&*iter));
+        iter.seek(&"b".into());
+        assert_eq!("b->vb", iter_status(&*iter));
+        iter.seek(&"z".into());
+        assert_eq!("(invalid)", iter_status(&*iter));
+
+        // Switch from reverse to forward
+        iter.seek_to_last();
+        iter.prev();
+        iter.prev();
+        iter.next();
+        assert_eq!("b->vb", iter_status(&*iter));
+
+        // Switch from forward to reverse
+        iter.seek_to_first();
+        iter.next();
+        iter.next();
+        iter.prev();
+        assert_eq!("b->vb", iter_status(&*iter));
+
+        // Make sure iter stays at snapshot
+        assert!(db.put(&wo, &"a".into(), &"va2".into()).is_ok());
+        assert!(db.put(&wo, &"a2".into(), &"va3".into()).is_ok());
+        assert!(db.put(&wo, &"b".into(), &"vb2".into()).is_ok());
+        assert!(db.put(&wo, &"c".into(), &"vc2".into()).is_ok());
+        assert!(db.delete(&wo, &"b".into()).is_ok());
+        let (forward, backward) = contents(&mut *iter);
+        assert_eq!(vec!["a->va", "b->vb", "c->vc"], forward);
+        assert_eq!(forward, backward);
+        drop(iter);
+
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        let (forward, backward) = contents(&mut *iter);
+        assert_eq!(vec!["a->va2", "a2->va3", "c->vc2"], forward);
+        assert_eq!(forward, backward);
+        drop(iter);
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_iter_across_tables() {
+        let dbname = test_db_name("iter_across_tables");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+
+        // Spread overwrites and deletions over the memtable and several tables
+        let mut expected = std::collections::BTreeMap::new();
+        for round in 0..6 {
+            for i in (round..60).step_by(round + 1) {
+                let key = format!("key{:02}", i);
+                if (i + round) % 4 == 0 {
+                    assert!(db.delete(&wo, &key.as_str().into()).is_ok());
+                    expected.remove(&key);
+                } else {
+                    let value = format!("v{}", round);
+                    assert!(db
+                        .put(&wo, &key.as_str().into(), &value.as_str().into())
+                        .is_ok());
+                    expected.insert(key, value);
+                }
+            }
+            if round < 5 {
+                assert!(db.test_compact_mem_table().ok());
+            }
+        }
+        assert!(total_table_files(&db) > 1);
+
+        let expected = expected
+            .iter()
+            .map(|(k, v)| format!("{}->{}", k, v))
+            .collect::<Vec<_>>();
+        let mut iter = db.new_iterator(&ReadOptions::default());
+        let (forward, backward) = contents(&mut *iter);
+        assert_eq!(expected, forward);
+        assert_eq!(expected, backward);
+        assert!(iter.status().is_ok());
+        drop(iter);
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_iter_with_snapshot() {
+        let dbname = test_db_name("iter_with_snapshot");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+        assert!(db.put(&wo, &"a".into(), &"v1".into()).is_ok());
+        assert!(db.put(&wo, &"b".into(), &"v1".into()).is_ok());
+        let snapshot = db.get_snapshot();
+        assert!(db.put(&wo, &"a".into(), &"v2".into()).is_ok());
+        assert!(db.delete(&wo, &"b".into()).is_ok());
+        assert!(db.put(&wo, &"c".into(), &"v2".into()).is_ok());
+        assert!(db.test_compact_mem_table().ok());
+
+        let options = ReadOptions {
+            snapshot: Some(Arc::clone(&snapshot)),
+            ..Default::default()
+        };
+        let mut iter = db.new_iterator(&options);
+        let (forward, backward) = contents(&mut *iter);
+        assert_eq!(vec!["a->v1", "b->v1"], forward);
+        assert_eq!(forward, backward);
+        drop(iter);
+        drop(options);
+        db.release_snapshot(snapshot);
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_seek_compaction() {
+        let dbname = test_db_name("seek_compaction");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        let wo = WriteOptions::default();
+
+        // A level-2 table, and a level-1 table that overlaps it without holding "m"
+        for key in ["a", "m", "z"] {
+            assert!(db.put(&wo, &key.into(), &"v".into()).is_ok());
+        }
+        assert!(db.test_compact_mem_table().ok());
+        for key in ["b", "y"] {
+            assert!(db.put(&wo, &key.into(), &"v".into()).is_ok());
+        }
+        assert!(db.test_compact_mem_table().ok());
+        assert_eq!(1, db.test_num_level_files(1));
+        assert_eq!(1, db.test_num_level_files(2));
+
+        // Every read of "m" seeks the level-1 table in vain, until it gets compacted
+        for _ in 0..100 {
+            assert_eq!("v", get(&db, "m"));
+        }
+        db.test_wait_for_compactions();
+        assert_eq!(0, db.test_num_level_files(1));
+        assert_eq!(1, db.test_num_level_files(2));
+        for key in ["a", "b", "m", "y", "z"] {
+            assert_eq!("v", get(&db, key));
+        }
+        drop(db);
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+    }
+
+    #[test]
+    fn test_destroy() {
+        let dbname = posix_db_name("destroy");
+        let db = DB::open(posix_options(), &dbname).unwrap();
+        assert!(db
+            .put(&WriteOptions::default(), &"a".into(), &"v".into())
+            .is_ok());
+        assert!(db.test_compact_mem_table().ok());
+
+        // The lock is held while the DB is open, so nothing is removed
+        assert!(destroy_db(&dbname, &Options::default()).is_err());
+        assert_eq!("v", get(&db, "a"));
+        drop(db);
+
+        assert!(destroy_db(&dbname, &Options::default()).is_ok());
+        assert!(!default_env().file_exists(&dbname));
+        let s = DB::open(Options::default(), &dbname).err().unwrap();
+        assert!(s.is_invalid_argument(), "{}", s);
+
+        // Destroying a missing DB is not an error
+        assert!(destroy_db(&dbname, &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn test_destroy_keeps_foreign_files() {
+        let dbname = test_db_name("destroy_foreign");
+        drop(DB::open(open_options(), &dbname).unwrap());
+        let foreign = format!("{}/notes.txt", dbname);
+        let env = test_env();
+        assert!(env::write_string_to_file(&*env, &"keep me".into(), &foreign).is_ok());
+
+        assert!(destroy_db(&dbname, &test_options()).is_ok());
+        assert_eq!(vec!["notes.txt"], env.get_children(&dbname).unwrap());
+        assert!(env.remove_file(&foreign).is_ok());
+    }
+}
@@ -0,0 +1,353 @@
+// leveldb/db/db_iter.{h,cc}
+
+use crate::{
+    comparator::Comparator,
+    db::{
+        db_impl::DBImpl,
+        dbformat::{
+            append_internal_key, config, extract_user_key, parse_internal_key, ParsedInternalKey,
+            SequenceNumber, ValueType, VALUE_TYPE_FOR_SEEK,
+        },
+    },
+    iter::{CleanupNode, Iter},
+    slice::Slice,
+    status::{Result, Status},
+    util::random::Random,
+};
+use std::sync::{Arc, Weak};
+
+// Return a new iterator that converts internal keys (yielded by "internal_iter") that were live
+// at the specified "sequence" number into appropriate user keys.
+// NOTE: C++ takes a DBImpl pointer; a weak reference is used since the iterator may outlive the DB
+pub(crate) fn new_db_iterator(
+    db: Weak<DBImpl>,
+    user_key_comparator: Arc<dyn Comparator>,
+    internal_iter: Box<dyn Iter>,
+    sequence: SequenceNumber,
+    seed: u32,
+) -> Box<dyn Iter> {
+    let mut iter = DBIter {
+        db,
+        user_comparator: user_key_comparator,
+        iter: internal_iter,
+        sequence,
+        status: Status::OK(),
+        saved_key: Vec::new(),
+        saved_value: Vec::new(),
+        direction: Direction::Forward,
+        valid: false,
+        rnd: Random::new(seed),
+        bytes_until_read_sampling: 0,
+        cleanup_head: CleanupNode::default(),
+    };
+    iter.bytes_until_read_sampling = iter.random_compaction_period();
+    Box::new(iter)
+}
+
+// Which direction is the iterator currently moving?
+// (1) When moving forward, the internal iterator is positioned at the exact entry that yields
+//     key(), value()
+// (2) When moving backwards, the internal iterator is positioned just before all entries whose
+//     user key == key().
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+// Memtables and sstables that make the DB representation contain (userkey,seq,type) => uservalue
+// entries. DBIter combines multiple entries for the same userkey found in the DB representation
+// into a single entry while accounting for sequence numbers, deletion markers, overwrites, etc.
+struct DBIter {
+    db: Weak<DBImpl>,
+    user_comparator: Arc<dyn Comparator>,
+    iter: Box<dyn Iter>,
+    sequence: SequenceNumber,
+    status: Status,
+    // == current key when direction == Reverse
+    saved_key: Vec<u8>,
+    // == current raw value when direction == Reverse
+    saved_value: Vec<u8>,
+    direction: Direction,
+    valid: bool,
+    rnd: Random,
+    bytes_until_read_sampling: usize,
+    cleanup_head: CleanupNode,
+}
+
+fn parse_key<'a>(k: &Slice<'a>, ikey: &mut ParsedInternalKey<'a>, status: &mut Status) -> bool {
+    if !parse_internal_key(k, ikey) {
+        *status = Status::corruption(&"corrupted internal key in DBIter".into(), &Slice::new());
+        false
+    } else {
+        true
+    }
+}
+
+impl DBIter {
+    // NOTE: C++ samples the read and parses the key in one ParseKey method; the parsing is done by
+    // the free parse_key function so that the parsed key only borrows iter, leaving the rest of
+    // the iterator free to be updated while it is in use.
+    fn sample_read(&mut self) {
+        let bytes_read = self.iter.key().size() + self.iter.value().size();
+        while self.bytes_until_read_sampling < bytes_read {
+            self.bytes_until_read_sampling += self.random_compaction_period();
+            if let Some(db) = self.db.upgrade() {
+                db.record_read_sample(&self.iter.key());
+            }
+        }
+        assert!(self.bytes_until_read_sampling >= bytes_read);
+        self.bytes_until_read_sampling -= bytes_read;
+    }
+
+    // NOTE: C++ takes the string holding the key to skip, which is always saved_key
+    fn find_next_user_entry(&mut self, mut skipping: bool) {
+        // Loop until we hit an acceptable entry to yield
+        assert!(self.iter.valid());
+        assert!(self.direction == Direction::Forward);
+        loop {
+            self.sample_read();
+            let mut ikey = ParsedInternalKey::default();
+            if parse_key(&self.iter.key(), &mut ikey, &mut self.status)
+                && ikey.sequence <= self.sequence
+            {
+                match ikey.value_type {
+                    ValueType::Deletion => {
+                        // Arrange to skip all upcoming entries for this key since they are hidden
+                        // by this deletion.
+                        self.saved_key = ikey.user_key.to_vec();
+                        skipping = true;
+                    }
+                    ValueType::Value => {
+                        if skipping
+                            && self
+                                .user_comparator
+                                .compare(&ikey.user_key, &Slice::from(&self.saved_key))
+                                <= 0
+                        {
+                            // Entry hidden
+                        } else {
+                            self.valid = true;
+                            self.saved_key.clear();
+                            return;
+                        }
+                    }
+                }
+            }
+            self.iter.next();
+            if !self.iter.valid() {
+                break;
+            }
+        }
+        self.saved_key.clear();
+        self.valid = false;
+    }
+
+    fn find_prev_user_entry(&mut self) {
+        assert!(self.direction == Direction::Reverse);
+
+        let mut value_type = ValueType::Deletion;
+        if self.iter.valid() {
+            loop {
+                self.sample_read();
+                let mut ikey = ParsedInternalKey::default();
+                if parse_key(&self.iter.key(), &mut ikey, &mut self.status)
+                    && ikey.sequence <= self.sequence
+                {
+                    if value_type != ValueType::Deletion
+                        && self
+                            .user_comparator
+                            .compare(&ikey.user_key, &Slice::from(&self.saved_key))
+                            < 0
+                    {
+                        // We encountered a non-deleted value in entries for previous keys,
+                        break;
+                    }
+                    value_type = ikey.value_type;
+                    if value_type == ValueType::Deletion {
+                        self.saved_key.clear();
+                        self.clear_saved_value();
+                    } else {
+                        let raw_value = self.iter.value();
+                        if self.saved_value.capacity() > raw_value.size() + 1048576 {
+                            self.saved_value = Vec::new();
+                        }
+                        self.saved_key = extract_user_key(&self.iter.key()).to_vec();
+                        self.saved_value.clear();
+                        self.saved_value.extend_from_slice(raw_value.as_bytes());
+                    }
+                }
+                self.iter.prev();
+                if !self.iter.valid() {
+                    break;
+                }
+            }
+        }
+
+        if value_type == ValueType::Deletion {
+            // End
+            self.valid = false;
+            self.saved_key.clear();
+            self.clear_saved_value();
+            self.direction = Direction::Forward;
+        } else {
+            self.valid = true;
+        }
+    }
+
+    fn clear_saved_value(&mut self) {
+        if self.saved_value.capacity() > 1048576 {
+            self.saved_value = Vec::new();
+        } else {
+            self.saved_value.clear();
+        }
+    }
+
+    // Picks the number of bytes that can be read until a compaction is scheduled.
+    fn random_compaction_period(&mut self) -> usize {
+        self.rnd.uniform(2 * config::READ_BYTES_PERIOD as u32) as usize
+    }
+}
+
+impl Iter for DBIter {
+    fn cleanup_head(&mut self) -> &mut CleanupNode {
+        &mut self.cleanup_head
+    }
+
+    fn valid(&self) -> bool {
+        self.valid
+    }
+
+    fn key(&self) -> Slice<'_> {
+        assert!(self.valid);
+        if self.direction == Direction::Forward {
+            extract_user_key(&self.iter.key())
+        } else {
+            Slice::from(&self.saved_key)
+        }
+    }
+
+    fn value(&self) -> Slice<'_> {
+        assert!(self.valid);
+        if self.direction == Direction::Forward {
+            self.iter.value()
+        } else {
+            Slice::from(&self.saved_value)
+        }
+    }
+
+    fn status(&self) -> Result<()> {
+        if self.status.ok() {
+            self.iter.status()
+        } else {
+            self.status.clone().into()
+        }
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid);
+
+        if self.direction == Direction::Reverse {
+            // Switch directions?
+            self.direction = Direction::Forward;
+            // iter is pointing just before the entries for key(), so advance into the range of
+            // entries for key() and then use the normal skipping code below.
+            if !self.iter.valid() {
+                self.iter.seek_to_first();
+            } else {
+                self.iter.next();
+            }
+            if !self.iter.valid() {
+                self.valid = false;
+                self.saved_key.clear();
+                return;
+            }
+            // saved_key already contains the key to skip past.
+        } else {
+            // Store in saved_key the current key so we skip it below.
+            self.saved_key = extract_user_key(&self.iter.key()).to_vec();
+
+            // iter is pointing to current key. We can now safely move to the next to avoid
+            // checking current key.
+            self.iter.next();
+            if !self.iter.valid() {
+                self.valid = false;
+                self.saved_key.clear();
+                return;
+            }
+        }
+
+        self.find_next_user_entry(true);
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid);
+
+        if self.direction == Direction::Forward {
+            // Switch directions?
+            // iter is pointing at the current entry. Scan backwards until the key changes so we
+            // can use the normal reverse scanning code.
+            assert!(self.iter.valid()); // Otherwise valid would have been false
+            self.saved_key = extract_user_key(&self.iter.key()).to_vec();
+            loop {
+                self.iter.prev();
+                if !self.iter.valid() {
+                    self.valid = false;
+                    self.saved_key.clear();
+                    self.clear_saved_value();
+                    return;
+                }
+                if self.user_comparator.compare(
+                    &extract_user_key(&self.iter.key()),
+                    &Slice::from(&self.saved_key),
+                ) < 0
+                {
+                    break;
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+
+        self.find_prev_user_entry();
+    }
+
+    fn seek(&mut self, target: &Slice) {
+        self.direction = Direction::Forward;
+        self.clear_saved_value();
+        self.saved_key.clear();
+        append_internal_key(
+            &mut self.saved_key,
+            &ParsedInternalKey::new(target, self.sequence, VALUE_TYPE_FOR_SEEK),
+        );
+        self.iter.seek(&Slice::from(&self.saved_key));
+        if self.iter.valid() {
+            self.find_next_user_entry(false);
+        } else {
+            self.valid = false;
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.direction = Direction::Forward;
+        self.clear_saved_value();
+        self.iter.seek_to_first();
+        if self.iter.valid() {
+            self.find_next_user_entry(false);
+        } else {
+            self.valid = false;
+        }
+    }
+
+    fn seek_to_last(&mut self) {
+        self.direction = Direction::Reverse;
+        self.clear_saved_value();
+        self.iter.seek_to_last();
+        self.find_prev_user_entry();
+    }
+}
+
+impl Drop for DBIter {
+    fn drop(&mut self) {
+        self.drop_func();
+    }
+}
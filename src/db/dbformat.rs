@@ -0,0 +1,478 @@
+// leveldb/db/dbformat.{h,cc}
+// leveldb/db/dbformat_test.cc
+
+use crate::comparator::Comparator;
+use crate::filter_policy::FilterPolicy;
+use crate::slice::Slice;
+use crate::util::coding::{decode_fixed64, put_fixed64};
+use crate::util::logging::escape_string;
+use std::sync::Arc;
+
+// Grouping of constants. We may want to make some of these parameters set via options.
+pub(crate) mod config {
+    pub(crate) const NUM_LEVELS: usize = 7;
+
+    // Level-0 compaction is started when we hit this many files.
+    pub(crate) const L0_COMPACTION_TRIGGER: usize = 4;
+
+    // Soft limit on number of level-0 files. We slow down writes at this point.
+    pub(crate) const L0_SLOWDOWN_WRITES_TRIGGER: usize = 8;
+
+    // Maximum number of level-0 files. We stop writes at this point.
+    pub(crate) const L0_STOP_WRITES_TRIGGER: usize = 12;
+
+    // Maximum level to which a new compacted memtable is pushed if it does not create overlap.
+    // We try to push to level 2 to avoid the relatively expensive level 0=>1 compactions and to
+    // avoid some expensive manifest file operations. We do not push all the way to the largest
+    // level since that can generate a lot of wasted disk space if the same key space is being
+    // repeatedly overwritten.
+    pub(crate) const MAX_MEM_COMPACT_LEVEL: usize = 2;
+
+    // Approximate gap in bytes between samples of data read during iteration.
+    pub(crate) const READ_BYTES_PERIOD: i32 = 1048576;
+}
+
+// Value types encoded as the last component of internal keys.
+// DO NOT CHANGE THESE ENUM VALUES: they are embedded in the on-disk data structures.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ValueType {
+    Deletion = 0x0,
+    Value = 0x1,
+}
+
+impl TryFrom<u8> for ValueType {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x0 => Ok(ValueType::Deletion),
+            0x1 => Ok(ValueType::Value),
+            _ => Err(()),
+        }
+    }
+}
+
+// VALUE_TYPE_FOR_SEEK defines the ValueType that should be passed when constructing a
+// ParsedInternalKey object for seeking to a particular sequence number (since we sort sequence
+// numbers in decreasing order and the value type is embedded as the low 8 bits in the sequence
+// number in internal keys, we need to use the highest-numbered ValueType, not the lowest).
+pub(crate) const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Value;
+
+pub(crate) type SequenceNumber = u64;
+
+// We leave eight bits empty at the bottom so a type and sequence# can be packed together into 64
+// bits.
+pub(crate) const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
+
+pub(crate) struct ParsedInternalKey<'a> {
+    pub(crate) user_key: Slice<'a>,
+    pub(crate) sequence: SequenceNumber,
+    pub(crate) value_type: ValueType,
+}
+
+impl<'a> ParsedInternalKey<'a> {
+    pub(crate) fn new(
+        user_key: &Slice<'a>,
+        sequence: SequenceNumber,
+        value_type: ValueType,
+    ) -> Self {
+        Self {
+            user_key: *user_key,
+            sequence,
+            value_type,
+        }
+    }
+
+    pub(crate) fn debug_string(&self) -> String {
+        format!(
+            "'{}' @ {} : {}",
+            escape_string(&self.user_key),
+            self.sequence,
+            self.value_type as u8
+        )
+    }
+}
+
+impl Default for ParsedInternalKey<'_> {
+    // Intentionally left uninitialized (for speed) in C++
+    fn default() -> Self {
+        Self::new(&Slice::new(), 0, ValueType::Deletion)
+    }
+}
+
+fn pack_sequence_and_type(seq: u64, t: ValueType) -> u64 {
+    assert!(seq <= MAX_SEQUENCE_NUMBER);
+    (seq << 8) | t as u64
+}
+
+// Append the serialization of "key" to *result.
+pub(crate) fn append_internal_key(result: &mut Vec<u8>, key: &ParsedInternalKey) {
+    result.extend_from_slice(key.user_key.as_bytes());
+    put_fixed64(result, pack_sequence_and_type(key.sequence, key.value_type));
+}
+
+// Attempt to parse an internal key from "internal_key". On success, stores the parsed data in
+// "*result", and returns true.
+//
+// On error, returns false, leaves "*result" in an undefined state.
+#[inline]
+pub(crate) fn parse_internal_key<'a>(
+    internal_key: &Slice<'a>,
+    result: &mut ParsedInternalKey<'a>,
+) -> bool {
+    let n = internal_key.size();
+    if n < 8 {
+        return false;
+    }
+    let num = decode_fixed64(&internal_key[n - 8..]);
+    let c = (num & 0xff) as u8;
+    result.sequence = num >> 8;
+    result.user_key = Slice::from(&internal_key.as_bytes()[..n - 8]);
+    match ValueType::try_from(c) {
+        Ok(value_type) => {
+            result.value_type = value_type;
+            true
+        }
+        Err(()) => false,
+    }
+}
+
+// Returns the user key portion of an internal key.
+#[inline]
+pub(crate) fn extract_user_key<'a>(internal_key: &Slice<'a>) -> Slice<'a> {
+    assert!(internal_key.size() >= 8);
+    Slice::from(&internal_key.as_bytes()[..internal_key.size() - 8])
+}
+
+// A comparator for internal keys that uses a specified comparator for the user key portion and
+// breaks ties by decreasing sequence number.
+#[derive(Clone)]
+pub(crate) struct InternalKeyComparator {
+    user_comparator: Arc<dyn Comparator>,
+}
+
+impl InternalKeyComparator {
+    pub(crate) fn new(c: Arc<dyn Comparator>) -> Self {
+        Self { user_comparator: c }
+    }
+
+    pub(crate) fn user_comparator(&self) -> &Arc<dyn Comparator> {
+        &self.user_comparator
+    }
+
+    pub(crate) fn compare_internal_keys(&self, a: &InternalKey, b: &InternalKey) -> i32 {
+        self.compare(&a.encode(), &b.encode())
+    }
+}
+
+impl Comparator for InternalKeyComparator {
+    fn compare(&self, akey: &Slice, bkey: &Slice) -> i32 {
+        // Order by:
+        //    increasing user key (according to user-supplied comparator)
+        //    decreasing sequence number
+        //    decreasing type (though sequence# should be enough to disambiguate)
+        let mut r = self
+            .user_comparator
+            .compare(&extract_user_key(akey), &extract_user_key(bkey));
+        if r == 0 {
+            let anum = decode_fixed64(&akey[akey.size() - 8..]);
+            let bnum = decode_fixed64(&bkey[bkey.size() - 8..]);
+            if anum > bnum {
+                r = -1;
+            } else if anum < bnum {
+                r = 1;
+            }
+        }
+        r
+    }
+
+    fn name(&self) -> &'static str {
+        "levelerdb.InternalKeyComparator"
+    }
+
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &Slice) {
+        // Attempt to shorten the user portion of the key
+        let user_start = extract_user_key(&Slice::from(&*start));
+        let user_limit = extract_user_key(limit);
+        let mut tmp = user_start.to_vec();
+        self.user_comparator
+            .find_shortest_separator(&mut tmp, &user_limit);
+        if tmp.len() < user_start.size()
+            && self
+                .user_comparator
+                .compare(&user_start, &Slice::from(&tmp))
+                < 0
+        {
+            // User key has become shorter physically, but larger logically.
+            // Tack on the earliest possible number to the shortened user key.
+            put_fixed64(
+                &mut tmp,
+                pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK),
+            );
+            assert!(self.compare(&Slice::from(&*start), &Slice::from(&tmp)) < 0);
+            assert!(self.compare(&Slice::from(&tmp), limit) < 0);
+            std::mem::swap(start, &mut tmp);
+        }
+    }
+
+    fn find_short_successor(&self, key: &mut Vec<u8>) {
+        let user_key = extract_user_key(&Slice::from(&*key));
+        let mut tmp = user_key.to_vec();
+        self.user_comparator.find_short_successor(&mut tmp);
+        if tmp.len() < user_key.size()
+            && self.user_comparator.compare(&user_key, &Slice::from(&tmp)) < 0
+        {
+            // User key has become shorter physically, but larger logically.
+            // Tack on the earliest possible number to the shortened user key.
+            put_fixed64(
+                &mut tmp,
+                pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK),
+            );
+            assert!(self.compare(&Slice::from(&*key), &Slice::from(&tmp)) < 0);
+            std::mem::swap(key, &mut tmp);
+        }
+    }
+}
+
+// Filter policy wrapper that converts from internal keys to user keys
+pub(crate) struct InternalFilterPolicy {
+    user_policy: Arc<dyn FilterPolicy>,
+}
+
+impl InternalFilterPolicy {
+    pub(crate) fn new(p: Arc<dyn FilterPolicy>) -> Self {
+        Self { user_policy: p }
+    }
+}
+
+impl FilterPolicy for InternalFilterPolicy {
+    fn name(&self) -> &'static str {
+        self.user_policy.name()
+    }
+
+    fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut Vec<u8>) {
+        // NOTE: C++ adjusts keys[] in place; the keys are borrowed immutably here so the user keys
+        // are collected instead
+        // TODO(sanjay): Suppress dups?
+        let mkey: Vec<Slice> = keys[..n].iter().map(extract_user_key).collect();
+        self.user_policy.create_filter(&mkey, n, dst);
+    }
+
+    fn key_may_match(&self, key: &Slice, f: &Slice) -> bool {
+        self.user_policy.key_may_match(&extract_user_key(key), f)
+    }
+}
+
+// Modules in this directory should keep internal keys wrapped inside the following struct instead
+// of plain strings so that we do not incorrectly use string comparisons instead of an
+// InternalKeyComparator.
+#[derive(Clone, Default)]
+pub(crate) struct InternalKey {
+    rep: Vec<u8>,
+}
+
+impl InternalKey {
+    pub(crate) fn new(user_key: &Slice, s: SequenceNumber, t: ValueType) -> Self {
+        let mut rep = Vec::new();
+        append_internal_key(&mut rep, &ParsedInternalKey::new(user_key, s, t));
+        Self { rep }
+    }
+
+    pub(crate) fn decode_from(&mut self, s: &Slice) -> bool {
+        self.rep = s.to_vec();
+        !self.rep.is_empty()
+    }
+
+    pub(crate) fn encode(&self) -> Slice<'_> {
+        assert!(!self.rep.is_empty());
+        Slice::from(&self.rep)
+    }
+
+    pub(crate) fn user_key(&self) -> Slice<'_> {
+        extract_user_key(&Slice::from(&self.rep))
+    }
+
+    pub(crate) fn debug_string(&self) -> String {
+        let mut parsed = ParsedInternalKey::default();
+        if parse_internal_key(&Slice::from(&self.rep), &mut parsed) {
+            return parsed.debug_string();
+        }
+        format!("(bad){}", escape_string(&Slice::from(&self.rep)))
+    }
+}
+
+// A helper struct useful for DB::get()
+pub(crate) struct LookupKey {
+    // We construct a byte vector of the form:
+    //    klength  varint32               <-- start
+    //    userkey  char[klength]          <-- kstart
+    //    tag      uint64
+    //                                    <-- end
+    // The vector is a suitable MemTable key.
+    // The suffix starting with "userkey" can be used as an InternalKey.
+    // NOTE: C++ uses an inline array for short keys and raw pointers into it
+    data: Vec<u8>,
+    kstart: usize,
+}
+
+impl LookupKey {
+    // Initialize for looking up user_key at a snapshot with the specified sequence number.
+    pub(crate) fn new(user_key: &Slice, s: SequenceNumber) -> Self {
+        let mut data = Vec::new();
+        crate::util::coding::put_varint32(&mut data, (user_key.size() + 8) as u32);
+        let kstart = data.len();
+        append_internal_key(
+            &mut data,
+            &ParsedInternalKey::new(user_key, s, VALUE_TYPE_FOR_SEEK),
+        );
+        Self { data, kstart }
+    }
+
+    // Return a key suitable for lookup in a MemTable.
+    pub(crate) fn memtable_key(&self) -> Slice<'_> {
+        Slice::from(&self.data)
+    }
+
+    // Return an internal key (suitable for passing to an internal iterator)
+    pub(crate) fn internal_key(&self) -> Slice<'_> {
+        Slice::from(&self.data[self.kstart..])
+    }
+
+    // Return the user key
+    pub(crate) fn user_key(&self) -> Slice<'_> {
+        Slice::from(&self.data[self.kstart..self.data.len() - 8])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::bytewise_comparator;
+
+    fn ikey(user_key: &[u8], seq: u64, vt: ValueType) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        append_internal_key(
+            &mut encoded,
+            &ParsedInternalKey::new(&user_key.into(), seq, vt),
+        );
+        encoded
+    }
+
+    fn internal_comparator() -> InternalKeyComparator {
+        InternalKeyComparator::new(Arc::new(bytewise_comparator()))
+    }
+
+    fn shorten(s: &[u8], l: &[u8]) -> Vec<u8> {
+        let mut result = s.to_vec();
+        internal_comparator().find_shortest_separator(&mut result, &l.into());
+        result
+    }
+
+    fn short_successor(s: &[u8]) -> Vec<u8> {
+        let mut result = s.to_vec();
+        internal_comparator().find_short_successor(&mut result);
+        result
+    }
+
+    fn test_key(key: &[u8], seq: u64, vt: ValueType) {
+        let encoded = ikey(key, seq, vt);
+
+        let input = Slice::from(&encoded);
+        let mut decoded = ParsedInternalKey::default();
+        assert!(parse_internal_key(&input, &mut decoded));
+        assert_eq!(key, decoded.user_key.as_bytes());
+        assert_eq!(seq, decoded.sequence);
+        assert_eq!(vt, decoded.value_type);
+
+        assert!(!parse_internal_key(&"bar".into(), &mut decoded));
+    }
+
+    #[test]
+    fn test_internal_key_encode_decode() {
+        let keys: [&[u8]; 4] = [b"", b"k", b"hello", b"longggggggggggggggggggggg"];
+        let seq = [
+            1,
+            2,
+            3,
+            (1u64 << 8) - 1,
+            1u64 << 8,
+            (1u64 << 8) + 1,
+            (1u64 << 16) - 1,
+            1u64 << 16,
+            (1u64 << 16) + 1,
+            (1u64 << 32) - 1,
+            1u64 << 32,
+            (1u64 << 32) + 1,
+        ];
+        for k in keys {
+            for s in seq {
+                test_key(k, s, ValueType::Value);
+                test_key(b"hello", 1, ValueType::Deletion);
+            }
+        }
+    }
+
+    #[test]
+    fn test_internal_key_decode_from_empty() {
+        let mut internal_key = InternalKey::default();
+        assert!(!internal_key.decode_from(&"".into()));
+    }
+
+    #[test]
+    fn test_internal_key_short_separator() {
+        use ValueType::{Deletion, Value};
+
+        // When user keys are same
+        assert_eq!(
+            ikey(b"foo", 100, Value),
+            shorten(&ikey(b"foo", 100, Value), &ikey(b"foo", 99, Value))
+        );
+        assert_eq!(
+            ikey(b"foo", 100, Value),
+            shorten(&ikey(b"foo", 100, Value), &ikey(b"foo", 101, Value))
+        );
+        assert_eq!(
+            ikey(b"foo", 100, Value),
+            shorten(&ikey(b"foo", 100, Value), &ikey(b"foo", 100, Value))
+        );
+        assert_eq!(
+            ikey(b"foo", 100, Value),
+            shorten(&ikey(b"foo", 100, Value), &ikey(b"foo", 100, Deletion))
+        );
+
+        // When user keys are misordered
+        assert_eq!(
+            ikey(b"foo", 100, Value),
+            shorten(&ikey(b"foo", 100, Value), &ikey(b"bar", 99, Value))
+        );
+
+        // When user keys are different, but correctly ordered
+        assert_eq!(
+            ikey(b"g", MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK),
+            shorten(&ikey(b"foo", 100, Value), &ikey(b"hello", 200, Value))
+        );
+
+        // When start user key is prefix of limit user key
+        assert_eq!(
+            ikey(b"foo", 100, Value),
+            shorten(&ikey(b"foo", 100, Value), &ikey(b"foobar", 200, Value))
+        );
+
+        // When limit user key is prefix of start user key
+        assert_eq!(
+            ikey(b"foobar", 100, Value),
+            shorten(&ikey(b"foobar", 100, Value), &ikey(b"foo", 200, Value))
+        );
+    }
+
+    #[test]
+    fn test_internal_key_shortest_successor() {
+        assert_eq!(
+            ikey(b"g", MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK),
+            short_successor(&ikey(b"foo", 100, ValueType::Value))
+        );
+        let encoded = ikey(b"\xff\xff", 100, ValueType::Value);
+        assert_eq!(encoded, short_successor(&encoded));
+    }
+}
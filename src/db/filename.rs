@@ -0,0 +1,245 @@
+// leveldb/db/filename.{h,cc}
+
+// File names used by DB code
+
+use crate::env::{self, Env};
+use crate::slice::Slice;
+use crate::status::Status;
+use crate::util::logging::consume_decimal_number;
+
+// NOTE: C++ suffixes each variant with "File", e.g. kLogFile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FileType {
+    Log,
+    DBLock,
+    Table,
+    Descriptor,
+    Current,
+    Temp,
+    // Either the current one, or an old one
+    InfoLog,
+}
+
+fn make_file_name(dbname: &str, number: u64, suffix: &str) -> String {
+    format!("{}/{:06}.{}", dbname, number, suffix)
+}
+
+// Return the name of the log file with the specified number in the db named by "dbname". The
+// result will be prefixed with "dbname".
+pub(crate) fn log_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "log")
+}
+
+// Return the name of the sstable with the specified number in the db named by "dbname". The
+// result will be prefixed with "dbname".
+pub(crate) fn table_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "ldb")
+}
+
+// Return the legacy file name for an sstable with the specified number in the db named by
+// "dbname". The result will be prefixed with "dbname".
+pub(crate) fn sst_table_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "sst")
+}
+
+// Return the name of the descriptor file for the db named by "dbname" and the specified
+// incarnation number. The result will be prefixed with "dbname".
+pub(crate) fn descriptor_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    format!("{}/MANIFEST-{:06}", dbname, number)
+}
+
+// Return the name of the current file. This file contains the name of the current manifest
+// file. The result will be prefixed with "dbname".
+pub(crate) fn current_file_name(dbname: &str) -> String {
+    format!("{}/CURRENT", dbname)
+}
+
+// Return the name of the lock file for the db named by "dbname". The result will be prefixed
+// with "dbname".
+pub(crate) fn lock_file_name(dbname: &str) -> String {
+    format!("{}/LOCK", dbname)
+}
+
+// Return the name of a temporary file owned by the db named "dbname". The result will be
+// prefixed with "dbname".
+pub(crate) fn temp_file_name(dbname: &str, number: u64) -> String {
+    assert!(number > 0);
+    make_file_name(dbname, number, "dbtmp")
+}
+
+// If filename is a leveldb file, store the type of the file in *file_type. The number encoded in
+// the filename is stored in *number. If the filename was successfully parsed, returns true.
+// Else return false.
+//
+// Owned filenames have the form:
+//    dbname/CURRENT
+//    dbname/LOCK
+//    dbname/LOG
+//    dbname/LOG.old
+//    dbname/MANIFEST-[0-9]+
+//    dbname/[0-9]+.(log|sst|ldb|dbtmp)
+pub(crate) fn parse_file_name(filename: &str, number: &mut u64, file_type: &mut FileType) -> bool {
+    if filename == "CURRENT" {
+        *number = 0;
+        *file_type = FileType::Current;
+    } else if filename == "LOCK" {
+        *number = 0;
+        *file_type = FileType::DBLock;
+    } else if filename == "LOG" || filename == "LOG.old" {
+        *number = 0;
+        *file_type = FileType::InfoLog;
+    } else if let Some(rest) = filename.strip_prefix("MANIFEST-") {
+        let mut rest = Slice::from(rest);
+        let mut num = 0;
+        if !consume_decimal_number(&mut rest, &mut num) {
+            return false;
+        }
+        if !rest.empty() {
+            return false;
+        }
+        *file_type = FileType::Descriptor;
+        *number = num;
+    } else {
+        // Avoid strtoull() to keep filename format independent of the current locale
+        let mut rest = Slice::from(filename);
+        let mut num = 0;
+        if !consume_decimal_number(&mut rest, &mut num) {
+            return false;
+        }
+        *file_type = match rest.as_bytes() {
+            b".log" => FileType::Log,
+            b".sst" | b".ldb" => FileType::Table,
+            b".dbtmp" => FileType::Temp,
+            _ => return false,
+        };
+        *number = num;
+    }
+    true
+}
+
+// Make the CURRENT file point to the descriptor file with the specified number.
+pub(crate) fn set_current_file(env: &dyn Env, dbname: &str, descriptor_number: u64) -> Status {
+    // Remove leading "dbname/" and add newline to manifest file name
+    let manifest = descriptor_file_name(dbname, descriptor_number);
+    let contents = &manifest[dbname.len() + 1..];
+    let tmp = temp_file_name(dbname, descriptor_number);
+    let mut s =
+        env::write_string_to_file_sync(env, &Slice::from(format!("{}\n", contents).as_str()), &tmp);
+    if s.is_ok() {
+        s = env.rename_file(&tmp, &current_file_name(dbname));
+    }
+    if s.is_err() {
+        let _ = env.remove_file(&tmp);
+    }
+    s.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut number = 0;
+        let mut file_type = FileType::Log;
+
+        // Successful parses
+        let cases = [
+            ("100.log", 100, FileType::Log),
+            ("0.log", 0, FileType::Log),
+            ("0.sst", 0, FileType::Table),
+            ("0.ldb", 0, FileType::Table),
+            ("CURRENT", 0, FileType::Current),
+            ("LOCK", 0, FileType::DBLock),
+            ("MANIFEST-2", 2, FileType::Descriptor),
+            ("MANIFEST-7", 7, FileType::Descriptor),
+            ("LOG", 0, FileType::InfoLog),
+            ("LOG.old", 0, FileType::InfoLog),
+            (
+                "18446744073709551615.log",
+                18446744073709551615,
+                FileType::Log,
+            ),
+        ];
+        for (fname, num, t) in cases {
+            assert!(
+                parse_file_name(fname, &mut number, &mut file_type),
+                "{}",
+                fname
+            );
+            assert_eq!(t, file_type, "{}", fname);
+            assert_eq!(num, number, "{}", fname);
+        }
+
+        // Errors
+        let errors = [
+            "",
+            "foo",
+            "foo-dx-100.log",
+            ".log",
+            "",
+            "manifest",
+            "CURREN",
+            "CURRENTX",
+            "MANIFES",
+            "MANIFEST",
+            "MANIFEST-",
+            "XMANIFEST-3",
+            "MANIFEST-3x",
+            "LOC",
+            "LOCKx",
+            "LO",
+            "LOGx",
+            "18446744073709551616.log",
+            "184467440737095516150.log",
+            "100",
+            "100.",
+            "100.lop",
+        ];
+        for fname in errors {
+            assert!(
+                !parse_file_name(fname, &mut number, &mut file_type),
+                "{}",
+                fname
+            );
+        }
+    }
+
+    #[test]
+    fn test_construction() {
+        let fname = current_file_name("foo");
+        assert_eq!("foo/CURRENT", fname);
+
+        let fname = lock_file_name("foo");
+        assert_eq!("foo/LOCK", fname);
+
+        let fname = descriptor_file_name("bar", 100);
+        assert_eq!("bar/MANIFEST-000100", fname);
+
+        let mut number = 0;
+        let mut file_type = FileType::Log;
+
+        let fname = log_file_name("foo", 192);
+        assert_eq!("foo/", &fname[..4]);
+        assert!(parse_file_name(&fname[4..], &mut number, &mut file_type));
+        assert_eq!(192, number);
+        assert_eq!(FileType::Log, file_type);
+
+        let fname = table_file_name("bar", 200);
+        assert_eq!("bar/", &fname[..4]);
+        assert!(parse_file_name(&fname[4..], &mut number, &mut file_type));
+        assert_eq!(200, number);
+        assert_eq!(FileType::Table, file_type);
+        assert_eq!("bar/000200.sst", sst_table_file_name("bar", 200));
+
+        let fname = temp_file_name("tmp", 999);
+        assert_eq!("tmp/000999.dbtmp", fname);
+        assert!(parse_file_name(&fname[4..], &mut number, &mut file_type));
+        assert_eq!(999, number);
+        assert_eq!(FileType::Temp, file_type);
+    }
+}
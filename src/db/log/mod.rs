@@ -0,0 +1,724 @@
+// leveldb/db/log_format.h
+// leveldb/db/log_test.cc
+
+// Log format information shared by reader and writer.
+// See ../doc/log_format.md for more detail.
+// NOTE: In the "log" namespace in C++
+
+#![allow(dead_code)]
+
+mod reader;
+mod writer;
+
+pub(crate) use reader::{Reader, Reporter};
+pub(crate) use writer::Writer;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RecordType {
+    // Zero is reserved for preallocated files
+    Zero = 0,
+
+    Full = 1,
+
+    // For fragments
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+pub(crate) const MAX_RECORD_TYPE: RecordType = RecordType::Last;
+
+pub(crate) const BLOCK_SIZE: usize = 32768;
+
+// Header is checksum (4 bytes), length (2 bytes), type (1 byte).
+pub(crate) const HEADER_SIZE: usize = 4 + 2 + 1;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{SequentialFile, WritableFile};
+    use crate::slice::Slice;
+    use crate::status::{Error, Result, Status};
+    use crate::util::{coding::encode_fixed32, crc32c, random::Random};
+    use std::sync::{Arc, Mutex};
+
+    // Construct a string of the specified length made out of the supplied partial string.
+    fn big_string(partial_string: &str, n: usize) -> String {
+        let mut result = String::new();
+        while result.len() < n {
+            result.push_str(partial_string);
+        }
+        result.truncate(n);
+        result
+    }
+
+    // Construct a string from a number
+    fn number_string(n: u32) -> String {
+        format!("{}.", n)
+    }
+
+    // Return a skewed potentially long string
+    fn random_skewed_string(i: u32, rnd: &mut Random) -> String {
+        let n = rnd.skewed(17) as usize;
+        big_string(&number_string(i), n)
+    }
+
+    type Contents = Arc<Mutex<Vec<u8>>>;
+
+    struct StringDest {
+        contents: Contents,
+    }
+
+    impl WritableFile for StringDest {
+        fn append(&mut self, slice: &Slice) -> Result<()> {
+            self.contents
+                .lock()
+                .unwrap()
+                .extend_from_slice(slice.as_bytes());
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn sync(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct SourceState {
+        contents: Vec<u8>,
+        force_error: bool,
+        returned_partial: bool,
+    }
+
+    struct StringSource {
+        state: Arc<Mutex<SourceState>>,
+    }
+
+    impl SequentialFile for StringSource {
+        fn read<'a>(&mut self, mut n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>> {
+            let mut state = self.state.lock().unwrap();
+            assert!(!state.returned_partial, "must not read() after eof/error");
+
+            if state.force_error {
+                state.force_error = false;
+                state.returned_partial = true;
+                return Err(Error::corruption(&"read error".into(), &Slice::new()));
+            }
+
+            if state.contents.len() < n {
+                n = state.contents.len();
+                state.returned_partial = true;
+            }
+            scratch[..n].copy_from_slice(&state.contents[..n]);
+            state.contents.drain(..n);
+            Ok(Slice::from(&scratch[..n]))
+        }
+
+        fn skip(&mut self, n: u64) -> Result<()> {
+            let mut state = self.state.lock().unwrap();
+            let n = n as usize;
+            if n > state.contents.len() {
+                state.contents.clear();
+                return Err(Error::not_found(
+                    &"in-memory file skipped past end".into(),
+                    &Slice::new(),
+                ));
+            }
+            state.contents.drain(..n);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct ReportState {
+        dropped_bytes: usize,
+        message: String,
+    }
+
+    struct ReportCollector {
+        state: Arc<Mutex<ReportState>>,
+    }
+
+    impl Reporter for ReportCollector {
+        fn corruption(&mut self, bytes: usize, status: &Status) {
+            let mut state = self.state.lock().unwrap();
+            state.dropped_bytes += bytes;
+            state.message.push_str(&status.to_string());
+        }
+    }
+
+    // Record metadata for testing initial offset functionality
+    const INITIAL_OFFSET_RECORD_SIZES: [usize; 6] = [
+        10000, // Two sizable records in first block
+        10000,
+        2 * BLOCK_SIZE - 1000, // Span three blocks
+        1,
+        13716,                    // Consume all but two bytes of block 3.
+        BLOCK_SIZE - HEADER_SIZE, // Consume the entirety of block 4.
+    ];
+
+    const INITIAL_OFFSET_LAST_RECORD_OFFSETS: [u64; 6] = [
+        0,
+        (HEADER_SIZE + 10000) as u64,
+        (2 * (HEADER_SIZE + 10000)) as u64,
+        (2 * (HEADER_SIZE + 10000) + (2 * BLOCK_SIZE - 1000) + 3 * HEADER_SIZE) as u64,
+        (2 * (HEADER_SIZE + 10000) + (2 * BLOCK_SIZE - 1000) + 3 * HEADER_SIZE + HEADER_SIZE + 1)
+            as u64,
+        (3 * BLOCK_SIZE) as u64,
+    ];
+
+    // Number of records
+    const NUM_INITIAL_OFFSET_RECORDS: usize = INITIAL_OFFSET_LAST_RECORD_OFFSETS.len();
+
+    struct LogTest {
+        dest: Contents,
+        source: Arc<Mutex<SourceState>>,
+        report: Arc<Mutex<ReportState>>,
+        reading: bool,
+        writer: Writer,
+        reader: Reader,
+    }
+
+    impl LogTest {
+        fn new() -> Self {
+            let dest = Contents::default();
+            let source = Arc::new(Mutex::new(SourceState::default()));
+            let report = Arc::new(Mutex::new(ReportState::default()));
+            let writer = Writer::new(Box::new(StringDest {
+                contents: Arc::clone(&dest),
+            }));
+            let reader = Self::new_reader(&source, &report, 0);
+            Self {
+                dest,
+                source,
+                report,
+                reading: false,
+                writer,
+                reader,
+            }
+        }
+
+        fn new_reader(
+            source: &Arc<Mutex<SourceState>>,
+            report: &Arc<Mutex<ReportState>>,
+            initial_offset: u64,
+        ) -> Reader {
+            Reader::new(
+                Box::new(StringSource {
+                    state: Arc::clone(source),
+                }),
+                Some(Box::new(ReportCollector {
+                    state: Arc::clone(report),
+                })),
+                true, // checksum
+                initial_offset,
+            )
+        }
+
+        fn reopen_for_append(&mut self) {
+            let dest_length = self.dest.lock().unwrap().len() as u64;
+            self.writer = Writer::with_dest_length(
+                Box::new(StringDest {
+                    contents: Arc::clone(&self.dest),
+                }),
+                dest_length,
+            );
+        }
+
+        fn write(&mut self, msg: &str) {
+            assert!(!self.reading, "write() after starting to read");
+            assert!(self.writer.add_record(&msg.into()).ok());
+        }
+
+        fn written_bytes(&self) -> usize {
+            self.dest.lock().unwrap().len()
+        }
+
+        fn start_reading(&mut self) {
+            if !self.reading {
+                self.reading = true;
+                self.source.lock().unwrap().contents = self.dest.lock().unwrap().clone();
+            }
+        }
+
+        fn read(&mut self) -> String {
+            self.start_reading();
+            let mut scratch = Vec::new();
+            match self.reader.read_record(&mut scratch) {
+                Some(record) => String::from_utf8(record.to_vec()).unwrap(),
+                None => "EOF".to_owned(),
+            }
+        }
+
+        fn increment_byte(&mut self, offset: usize, delta: u8) {
+            let mut dest = self.dest.lock().unwrap();
+            dest[offset] = dest[offset].wrapping_add(delta);
+        }
+
+        fn set_byte(&mut self, offset: usize, new_byte: u8) {
+            self.dest.lock().unwrap()[offset] = new_byte;
+        }
+
+        fn shrink_size(&mut self, bytes: usize) {
+            let mut dest = self.dest.lock().unwrap();
+            let len = dest.len();
+            dest.truncate(len - bytes);
+        }
+
+        fn fix_checksum(&mut self, header_offset: usize, len: usize) {
+            // Compute crc of type/len/data
+            let mut dest = self.dest.lock().unwrap();
+            let crc = crc32c::mask(crc32c::value(
+                &dest[header_offset + 6..header_offset + 7 + len],
+            ));
+            encode_fixed32(&mut dest[header_offset..], crc);
+        }
+
+        fn force_error(&mut self) {
+            self.source.lock().unwrap().force_error = true;
+        }
+
+        fn dropped_bytes(&self) -> usize {
+            self.report.lock().unwrap().dropped_bytes
+        }
+
+        fn report_message(&self) -> String {
+            self.report.lock().unwrap().message.clone()
+        }
+
+        // Returns OK iff recorded error message contains "msg"
+        fn match_error(&self, msg: &str) -> String {
+            let message = self.report_message();
+            if !message.contains(msg) {
+                message
+            } else {
+                "OK".to_owned()
+            }
+        }
+
+        fn write_initial_offset_log(&mut self) {
+            for (i, &size) in INITIAL_OFFSET_RECORD_SIZES.iter().enumerate() {
+                let record = String::from_utf8(vec![b'a' + i as u8; size]).unwrap();
+                self.write(&record);
+            }
+        }
+
+        fn start_reading_at(&mut self, initial_offset: u64) {
+            self.reader = Self::new_reader(&self.source, &self.report, initial_offset);
+        }
+
+        fn check_offset_past_end_returns_no_records(&mut self, offset_past_end: u64) {
+            self.write_initial_offset_log();
+            self.reading = true;
+            self.source.lock().unwrap().contents = self.dest.lock().unwrap().clone();
+            let mut offset_reader = Self::new_reader(
+                &self.source,
+                &self.report,
+                self.written_bytes() as u64 + offset_past_end,
+            );
+            let mut scratch = Vec::new();
+            assert!(offset_reader.read_record(&mut scratch).is_none());
+        }
+
+        fn check_initial_offset_record(
+            &mut self,
+            initial_offset: u64,
+            mut expected_record_offset: usize,
+        ) {
+            self.write_initial_offset_log();
+            self.reading = true;
+            self.source.lock().unwrap().contents = self.dest.lock().unwrap().clone();
+            let mut offset_reader = Self::new_reader(&self.source, &self.report, initial_offset);
+
+            // Read all records from expected_record_offset through the last one.
+            assert!(expected_record_offset < NUM_INITIAL_OFFSET_RECORDS);
+            while expected_record_offset < NUM_INITIAL_OFFSET_RECORDS {
+                let mut scratch = Vec::new();
+                let record = offset_reader.read_record(&mut scratch).unwrap();
+                assert_eq!(
+                    INITIAL_OFFSET_RECORD_SIZES[expected_record_offset],
+                    record.size()
+                );
+                assert_eq!(b'a' + expected_record_offset as u8, record[0]);
+                assert_eq!(
+                    INITIAL_OFFSET_LAST_RECORD_OFFSETS[expected_record_offset],
+                    offset_reader.last_record_offset()
+                );
+                expected_record_offset += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut t = LogTest::new();
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.write("bar");
+        t.write("");
+        t.write("xxxx");
+        assert_eq!("foo", t.read());
+        assert_eq!("bar", t.read());
+        assert_eq!("", t.read());
+        assert_eq!("xxxx", t.read());
+        assert_eq!("EOF", t.read());
+        assert_eq!("EOF", t.read()); // Make sure reads at eof work
+    }
+
+    #[test]
+    fn test_many_blocks() {
+        let mut t = LogTest::new();
+        for i in 0..100000 {
+            t.write(&number_string(i));
+        }
+        for i in 0..100000 {
+            assert_eq!(number_string(i), t.read());
+        }
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let mut t = LogTest::new();
+        t.write("small");
+        t.write(&big_string("medium", 50000));
+        t.write(&big_string("large", 100000));
+        assert_eq!("small", t.read());
+        assert_eq!(big_string("medium", 50000), t.read());
+        assert_eq!(big_string("large", 100000), t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_marginal_trailer() {
+        // Make a trailer that is exactly the same length as an empty record.
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE;
+        t.write(&big_string("foo", n));
+        assert_eq!(BLOCK_SIZE - HEADER_SIZE, t.written_bytes());
+        t.write("");
+        t.write("bar");
+        assert_eq!(big_string("foo", n), t.read());
+        assert_eq!("", t.read());
+        assert_eq!("bar", t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_short_trailer() {
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE + 4;
+        t.write(&big_string("foo", n));
+        assert_eq!(BLOCK_SIZE - HEADER_SIZE + 4, t.written_bytes());
+        t.write("");
+        t.write("bar");
+        assert_eq!(big_string("foo", n), t.read());
+        assert_eq!("", t.read());
+        assert_eq!("bar", t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_aligned_eof() {
+        let mut t = LogTest::new();
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE + 4;
+        t.write(&big_string("foo", n));
+        assert_eq!(BLOCK_SIZE - HEADER_SIZE + 4, t.written_bytes());
+        assert_eq!(big_string("foo", n), t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_open_for_append() {
+        let mut t = LogTest::new();
+        t.write("hello");
+        t.reopen_for_append();
+        t.write("world");
+        assert_eq!("hello", t.read());
+        assert_eq!("world", t.read());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_random_read() {
+        let mut t = LogTest::new();
+        const N: u32 = 500;
+        let mut write_rnd = Random::new(301);
+        for i in 0..N {
+            t.write(&random_skewed_string(i, &mut write_rnd));
+        }
+        let mut read_rnd = Random::new(301);
+        for i in 0..N {
+            assert_eq!(random_skewed_string(i, &mut read_rnd), t.read());
+        }
+        assert_eq!("EOF", t.read());
+    }
+
+    // Tests of all the error paths in log_reader.cc follow:
+
+    #[test]
+    fn test_read_error() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.force_error();
+        assert_eq!("EOF", t.read());
+        assert_eq!(BLOCK_SIZE, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("read error"));
+    }
+
+    #[test]
+    fn test_bad_record_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        // Type is stored in header[6]
+        t.increment_byte(6, 100);
+        t.fix_checksum(0, 3);
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("unknown record type"));
+    }
+
+    #[test]
+    fn test_truncated_trailing_record_is_ignored() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.shrink_size(4); // Drop all payload as well as a header byte
+        assert_eq!("EOF", t.read());
+        // Truncated last record is ignored, not treated as an error.
+        assert_eq!(0, t.dropped_bytes());
+        assert_eq!("", t.report_message());
+    }
+
+    #[test]
+    fn test_bad_length() {
+        let mut t = LogTest::new();
+        const PAYLOAD_SIZE: usize = BLOCK_SIZE - HEADER_SIZE;
+        t.write(&big_string("bar", PAYLOAD_SIZE));
+        t.write("foo");
+        // Least significant size byte is stored in header[4].
+        t.increment_byte(4, 1);
+        assert_eq!("foo", t.read());
+        assert_eq!(BLOCK_SIZE, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("bad record length"));
+    }
+
+    #[test]
+    fn test_bad_length_at_end_is_ignored() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.shrink_size(1);
+        assert_eq!("EOF", t.read());
+        assert_eq!(0, t.dropped_bytes());
+        assert_eq!("", t.report_message());
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.increment_byte(0, 10);
+        assert_eq!("EOF", t.read());
+        assert_eq!(10, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("checksum mismatch"));
+    }
+
+    #[test]
+    fn test_unexpected_middle_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.set_byte(6, RecordType::Middle as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("missing start"));
+    }
+
+    #[test]
+    fn test_unexpected_last_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.set_byte(6, RecordType::Last as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("missing start"));
+    }
+
+    #[test]
+    fn test_unexpected_full_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.write("bar");
+        t.set_byte(6, RecordType::First as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!("bar", t.read());
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("partial record without end"));
+    }
+
+    #[test]
+    fn test_unexpected_first_type() {
+        let mut t = LogTest::new();
+        t.write("foo");
+        t.write(&big_string("bar", 100000));
+        t.set_byte(6, RecordType::First as u8);
+        t.fix_checksum(0, 3);
+        assert_eq!(big_string("bar", 100000), t.read());
+        assert_eq!("EOF", t.read());
+        assert_eq!(3, t.dropped_bytes());
+        assert_eq!("OK", t.match_error("partial record without end"));
+    }
+
+    #[test]
+    fn test_missing_last_is_ignored() {
+        let mut t = LogTest::new();
+        t.write(&big_string("bar", BLOCK_SIZE));
+        // Remove the LAST block, including header.
+        t.shrink_size(14);
+        assert_eq!("EOF", t.read());
+        assert_eq!("", t.report_message());
+        assert_eq!(0, t.dropped_bytes());
+    }
+
+    #[test]
+    fn test_partial_last_is_ignored() {
+        let mut t = LogTest::new();
+        t.write(&big_string("bar", BLOCK_SIZE));
+        // Cause a bad record length in the LAST block.
+        t.shrink_size(1);
+        assert_eq!("EOF", t.read());
+        assert_eq!("", t.report_message());
+        assert_eq!(0, t.dropped_bytes());
+    }
+
+    #[test]
+    fn test_skip_into_multi_record() {
+        // Consider a fragmented record:
+        //    first(R1), middle(R1), last(R1), first(R2)
+        // If initial_offset points to a record after first(R1) but before first(R2) incomplete
+        // fragment errors are not actual errors, and must be suppressed until a new first or full
+        // record is encountered.
+        let mut t = LogTest::new();
+        t.write(&big_string("foo", 3 * BLOCK_SIZE));
+        t.write("correct");
+        t.start_reading_at(BLOCK_SIZE as u64);
+
+        assert_eq!("correct", t.read());
+        assert_eq!("", t.report_message());
+        assert_eq!(0, t.dropped_bytes());
+        assert_eq!("EOF", t.read());
+    }
+
+    #[test]
+    fn test_error_joins_records() {
+        // Consider two fragmented records:
+        //    first(R1) last(R1) first(R2) last(R2)
+        // where the middle two fragments disappear. We do not want first(R1),last(R2) to get
+        // joined and returned as a valid record.
+
+        // Write records that span two blocks
+        let mut t = LogTest::new();
+        t.write(&big_string("foo", BLOCK_SIZE));
+        t.write(&big_string("bar", BLOCK_SIZE));
+        t.write("correct");
+
+        // Wipe the middle block
+        for offset in BLOCK_SIZE..2 * BLOCK_SIZE {
+            t.set_byte(offset, b'x');
+        }
+
+        assert_eq!("correct", t.read());
+        assert_eq!("EOF", t.read());
+        let dropped = t.dropped_bytes();
+        assert!(dropped <= 2 * BLOCK_SIZE + 100);
+        assert!(dropped >= 2 * BLOCK_SIZE);
+    }
+
+    #[test]
+    fn test_read_start() {
+        LogTest::new().check_initial_offset_record(0, 0);
+    }
+
+    #[test]
+    fn test_read_second_one_off() {
+        LogTest::new().check_initial_offset_record(1, 1);
+    }
+
+    #[test]
+    fn test_read_second_ten_thousand() {
+        LogTest::new().check_initial_offset_record(10000, 1);
+    }
+
+    #[test]
+    fn test_read_second_start() {
+        LogTest::new().check_initial_offset_record(10007, 1);
+    }
+
+    #[test]
+    fn test_read_third_one_off() {
+        LogTest::new().check_initial_offset_record(10008, 2);
+    }
+
+    #[test]
+    fn test_read_third_start() {
+        LogTest::new().check_initial_offset_record(20014, 2);
+    }
+
+    #[test]
+    fn test_read_fourth_one_off() {
+        LogTest::new().check_initial_offset_record(20015, 3);
+    }
+
+    #[test]
+    fn test_read_fourth_first_block_trailer() {
+        LogTest::new().check_initial_offset_record(BLOCK_SIZE as u64 - 4, 3);
+    }
+
+    #[test]
+    fn test_read_fourth_middle_block() {
+        LogTest::new().check_initial_offset_record(BLOCK_SIZE as u64 + 1, 3);
+    }
+
+    #[test]
+    fn test_read_fourth_last_block() {
+        LogTest::new().check_initial_offset_record(2 * BLOCK_SIZE as u64 + 1, 3);
+    }
+
+    #[test]
+    fn test_read_fourth_start() {
+        LogTest::new().check_initial_offset_record(
+            (2 * (HEADER_SIZE + 1000) + (2 * BLOCK_SIZE - 1000) + 3 * HEADER_SIZE) as u64,
+            3,
+        );
+    }
+
+    #[test]
+    fn test_read_initial_offset_into_block_padding() {
+        LogTest::new().check_initial_offset_record(3 * BLOCK_SIZE as u64 - 3, 5);
+    }
+
+    #[test]
+    fn test_read_end() {
+        LogTest::new().check_offset_past_end_returns_no_records(0);
+    }
+
+    #[test]
+    fn test_read_past_end() {
+        LogTest::new().check_offset_past_end_returns_no_records(5);
+    }
+}
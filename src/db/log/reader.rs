@@ -0,0 +1,357 @@
+// leveldb/db/log_reader.{h,cc}
+
+use super::{RecordType, BLOCK_SIZE, HEADER_SIZE, MAX_RECORD_TYPE};
+use crate::env::SequentialFile;
+use crate::slice::Slice;
+use crate::status::Status;
+use crate::util::{coding::decode_fixed32, crc32c};
+use std::ops::Range;
+
+// Interface for reporting errors.
+pub(crate) trait Reporter {
+    // Some corruption was detected. "bytes" is the approximate number of bytes dropped due to
+    // the corruption.
+    fn corruption(&mut self, bytes: usize, status: &Status);
+}
+
+// Extend record types with the following special values
+const ZERO_TYPE: u32 = RecordType::Zero as u32;
+const FULL_TYPE: u32 = RecordType::Full as u32;
+const FIRST_TYPE: u32 = RecordType::First as u32;
+const MIDDLE_TYPE: u32 = RecordType::Middle as u32;
+const LAST_TYPE: u32 = RecordType::Last as u32;
+const EOF: u32 = MAX_RECORD_TYPE as u32 + 1;
+// Returned whenever we find an invalid physical record.
+// Currently there are three situations in which this happens:
+// * The record has an invalid CRC (read_physical_record reports a drop)
+// * The record is a 0-length record (No drop is reported)
+// * The record is below constructor's initial_offset (No drop is reported)
+const BAD_RECORD: u32 = MAX_RECORD_TYPE as u32 + 2;
+
+pub(crate) struct Reader {
+    file: Box<dyn SequentialFile>,
+    reporter: Option<Box<dyn Reporter>>,
+    checksum: bool,
+    backing_store: Vec<u8>,
+    // The unread part of backing_store
+    // NOTE: C++ keeps a Slice pointing into backing_store
+    buffer: Range<usize>,
+    // Last read() indicated EOF by returning < BLOCK_SIZE
+    eof: bool,
+
+    // Offset of the last record returned by read_record.
+    last_record_offset: u64,
+    // Offset of the first location past the end of buffer.
+    end_of_buffer_offset: u64,
+
+    // Offset at which to start looking for the first record to return
+    initial_offset: u64,
+
+    // True if we are resynchronizing after a seek (initial_offset > 0). In particular, a run of
+    // MIDDLE_TYPE and LAST_TYPE records can be silently skipped in this mode
+    resyncing: bool,
+}
+
+impl Reader {
+    // Create a reader that will return log records from "*file".
+    //
+    // If "reporter" is non-None, it is notified whenever some data is dropped due to a detected
+    // corruption.
+    //
+    // If "checksum" is true, verify checksums if available.
+    //
+    // The Reader will start reading at the first record located at physical position >=
+    // initial_offset within the file.
+    // NOTE: C++ keeps pointers to "*file" and "*reporter"; here the Reader owns both
+    pub(crate) fn new(
+        file: Box<dyn SequentialFile>,
+        reporter: Option<Box<dyn Reporter>>,
+        checksum: bool,
+        initial_offset: u64,
+    ) -> Self {
+        Self {
+            file,
+            reporter,
+            checksum,
+            backing_store: vec![0; BLOCK_SIZE],
+            buffer: 0..0,
+            eof: false,
+            last_record_offset: 0,
+            end_of_buffer_offset: 0,
+            initial_offset,
+            resyncing: initial_offset > 0,
+        }
+    }
+
+    // Read the next record. Returns None if we hit end of the input. May use "*scratch" as
+    // temporary storage. The returned record borrows both this reader and *scratch.
+    // NOTE: C++ returns a bool and fills in a *record out-parameter
+    pub(crate) fn read_record<'a>(&'a mut self, scratch: &'a mut Vec<u8>) -> Option<Slice<'a>> {
+        if self.last_record_offset < self.initial_offset && !self.skip_to_initial_block() {
+            return None;
+        }
+
+        scratch.clear();
+        let mut in_fragmented_record = false;
+        // Record offset of the logical record that we're reading
+        // 0 is a dummy value to make compilers happy
+        let mut prospective_record_offset = 0;
+
+        let mut fragment = 0..0;
+        loop {
+            let record_type = self.read_physical_record(&mut fragment);
+
+            // read_physical_record may have only had an empty trailer remaining in its internal
+            // buffer. Calculate the offset of the next physical record now that it has returned,
+            // properly accounting for its header size.
+            let physical_record_offset = self
+                .end_of_buffer_offset
+                .wrapping_sub((self.buffer.len() + HEADER_SIZE + fragment.len()) as u64);
+
+            if self.resyncing {
+                if record_type == MIDDLE_TYPE {
+                    continue;
+                } else if record_type == LAST_TYPE {
+                    self.resyncing = false;
+                    continue;
+                } else {
+                    self.resyncing = false;
+                }
+            }
+
+            match record_type {
+                FULL_TYPE => {
+                    if in_fragmented_record {
+                        // Handle bug in earlier versions of log::Writer where it could emit an
+                        // empty FIRST_TYPE record at the tail end of a block followed by a
+                        // FULL_TYPE or FIRST_TYPE record at the beginning of the next block.
+                        if !scratch.is_empty() {
+                            self.report_corruption(scratch.len(), "partial record without end(1)");
+                        }
+                    }
+                    prospective_record_offset = physical_record_offset;
+                    scratch.clear();
+                    self.last_record_offset = prospective_record_offset;
+                    return Some(Slice::from(&self.backing_store[fragment]));
+                }
+
+                FIRST_TYPE => {
+                    if in_fragmented_record {
+                        // Handle bug in earlier versions of log::Writer where it could emit an
+                        // empty FIRST_TYPE record at the tail end of a block followed by a
+                        // FULL_TYPE or FIRST_TYPE record at the beginning of the next block.
+                        if !scratch.is_empty() {
+                            self.report_corruption(scratch.len(), "partial record without end(2)");
+                        }
+                    }
+                    prospective_record_offset = physical_record_offset;
+                    scratch.clear();
+                    scratch.extend_from_slice(&self.backing_store[fragment.clone()]);
+                    in_fragmented_record = true;
+                }
+
+                MIDDLE_TYPE => {
+                    if !in_fragmented_record {
+                        self.report_corruption(
+                            fragment.len(),
+                            "missing start of fragmented record(1)",
+                        );
+                    } else {
+                        scratch.extend_from_slice(&self.backing_store[fragment.clone()]);
+                    }
+                }
+
+                LAST_TYPE => {
+                    if !in_fragmented_record {
+                        self.report_corruption(
+                            fragment.len(),
+                            "missing start of fragmented record(2)",
+                        );
+                    } else {
+                        scratch.extend_from_slice(&self.backing_store[fragment]);
+                        self.last_record_offset = prospective_record_offset;
+                        return Some(Slice::from(&scratch[..]));
+                    }
+                }
+
+                EOF => {
+                    if in_fragmented_record {
+                        // This can be caused by the writer dying immediately after writing a
+                        // physical record but before completing the next; don't treat it as a
+                        // corruption, just ignore the entire logical record.
+                        scratch.clear();
+                    }
+                    return None;
+                }
+
+                BAD_RECORD => {
+                    if in_fragmented_record {
+                        self.report_corruption(scratch.len(), "error in middle of record");
+                        in_fragmented_record = false;
+                        scratch.clear();
+                    }
+                }
+
+                _ => {
+                    let bytes = fragment.len()
+                        + if in_fragmented_record {
+                            scratch.len()
+                        } else {
+                            0
+                        };
+                    self.report_corruption(bytes, &format!("unknown record type {}", record_type));
+                    in_fragmented_record = false;
+                    scratch.clear();
+                }
+            }
+        }
+    }
+
+    // Returns the physical offset of the last record returned by read_record.
+    //
+    // Undefined before the first call to read_record.
+    pub(crate) fn last_record_offset(&self) -> u64 {
+        self.last_record_offset
+    }
+
+    // Skips all blocks that are completely before "initial_offset".
+    //
+    // Returns true on success. Handles reporting.
+    fn skip_to_initial_block(&mut self) -> bool {
+        let offset_in_block = (self.initial_offset % BLOCK_SIZE as u64) as usize;
+        let mut block_start_location = self.initial_offset - offset_in_block as u64;
+
+        // Don't search a block if we'd be in the trailer
+        if offset_in_block > BLOCK_SIZE - 6 {
+            block_start_location += BLOCK_SIZE as u64;
+        }
+
+        self.end_of_buffer_offset = block_start_location;
+
+        // Skip to start of first block that can contain the initial record
+        if block_start_location > 0 {
+            if let Err(e) = self.file.skip(block_start_location) {
+                self.report_drop(block_start_location, &e.into());
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // Return type, or one of the preceding special values
+    // NOTE: C++ returns the fragment as a Slice; here it is the range of backing_store it occupies
+    fn read_physical_record(&mut self, result: &mut Range<usize>) -> u32 {
+        loop {
+            if self.buffer.len() < HEADER_SIZE {
+                if !self.eof {
+                    // Last read was a full read, so this is a trailer to skip
+                    self.buffer = 0..0;
+                    let status = match self.file.read(BLOCK_SIZE, &mut self.backing_store) {
+                        // NOTE: C++ lets the file return data stored elsewhere; every
+                        // SequentialFile here reads into the front of scratch
+                        Ok(buffer) => {
+                            self.buffer = 0..buffer.size();
+                            Status::OK()
+                        }
+                        Err(status) => status.into(),
+                    };
+                    self.end_of_buffer_offset += self.buffer.len() as u64;
+                    if !status.ok() {
+                        self.buffer = 0..0;
+                        self.report_drop(BLOCK_SIZE as u64, &status);
+                        self.eof = true;
+                        return EOF;
+                    } else if self.buffer.len() < BLOCK_SIZE {
+                        self.eof = true;
+                    }
+                    continue;
+                } else {
+                    // Note that if buffer is non-empty, we have a truncated header at the end of
+                    // the file, which can be caused by the writer crashing in the middle of
+                    // writing the header. Instead of considering this an error, just report EOF.
+                    self.buffer = 0..0;
+                    return EOF;
+                }
+            }
+
+            // Parse the header
+            let header_start = self.buffer.start;
+            let header = &self.backing_store[self.buffer.clone()];
+            let a = header[4] as usize;
+            let b = header[5] as usize;
+            let record_type = header[6] as u32;
+            let length = a | (b << 8);
+            if HEADER_SIZE + length > self.buffer.len() {
+                let drop_size = self.buffer.len();
+                self.buffer = 0..0;
+                if !self.eof {
+                    self.report_corruption(drop_size, "bad record length");
+                    return BAD_RECORD;
+                }
+                // If the end of the file has been reached without reading |length| bytes of
+                // payload, assume the writer died in the middle of writing the record. Don't
+                // report a corruption.
+                return EOF;
+            }
+
+            if record_type == ZERO_TYPE && length == 0 {
+                // Skip zero length record without reporting any drops since such records are
+                // produced by the mmap based writing code in env_posix that preallocates file
+                // regions.
+                self.buffer = 0..0;
+                return BAD_RECORD;
+            }
+
+            // Check crc
+            if self.checksum {
+                let expected_crc = crc32c::unmask(decode_fixed32(header));
+                let actual_crc = crc32c::value(&header[6..HEADER_SIZE + length]);
+                if actual_crc != expected_crc {
+                    // Drop the rest of the buffer since "length" itself may have been corrupted
+                    // and if we trust it, we could find some fragment of a real log record that
+                    // just happens to look like a valid log record.
+                    let drop_size = self.buffer.len();
+                    self.buffer = 0..0;
+                    self.report_corruption(drop_size, "checksum mismatch");
+                    return BAD_RECORD;
+                }
+            }
+
+            self.buffer.start += HEADER_SIZE + length;
+
+            // Skip physical record that started before initial_offset
+            if self.end_of_buffer_offset - ((self.buffer.len() + HEADER_SIZE + length) as u64)
+                < self.initial_offset
+            {
+                *result = 0..0;
+                return BAD_RECORD;
+            }
+
+            *result = header_start + HEADER_SIZE..header_start + HEADER_SIZE + length;
+            return record_type;
+        }
+    }
+
+    // Reports dropped bytes to the reporter.
+    // buffer must be updated to remove the dropped bytes prior to invocation.
+    fn report_corruption(&mut self, bytes: usize, reason: &str) {
+        self.report_drop(
+            bytes as u64,
+            &Status::corruption(&reason.into(), &Slice::new()),
+        );
+    }
+
+    fn report_drop(&mut self, bytes: u64, reason: &Status) {
+        // NOTE: C++ relies on unsigned wraparound here as well
+        let start = self
+            .end_of_buffer_offset
+            .wrapping_sub(self.buffer.len() as u64)
+            .wrapping_sub(bytes);
+        if start >= self.initial_offset {
+            if let Some(reporter) = self.reporter.as_mut() {
+                reporter.corruption(bytes as usize, reason);
+            }
+        }
+    }
+}
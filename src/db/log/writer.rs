@@ -0,0 +1,126 @@
+// leveldb/db/log_writer.{h,cc}
+
+use super::{RecordType, BLOCK_SIZE, HEADER_SIZE, MAX_RECORD_TYPE};
+use crate::env::WritableFile;
+use crate::slice::Slice;
+use crate::status::Status;
+use crate::util::{coding::encode_fixed32, crc32c};
+
+fn init_type_crc(type_crc: &mut [u32]) {
+    for (i, crc) in type_crc[..=MAX_RECORD_TYPE as usize].iter_mut().enumerate() {
+        let t = i as u8;
+        *crc = crc32c::value(&[t]);
+    }
+}
+
+pub(crate) struct Writer {
+    dest: Box<dyn WritableFile>,
+    // Current offset in block
+    block_offset: usize,
+
+    // crc32c values for all supported record types. These are pre-computed to reduce the
+    // overhead of computing the crc of the record type stored in the header.
+    type_crc: [u32; MAX_RECORD_TYPE as usize + 1],
+}
+
+impl Writer {
+    // Create a writer that will append data to "*dest".
+    // "*dest" must be initially empty.
+    // NOTE: C++ keeps a pointer to "*dest", which must remain live while this Writer is in use;
+    // here the Writer takes ownership of it
+    pub(crate) fn new(dest: Box<dyn WritableFile>) -> Self {
+        Self::with_dest_length(dest, 0)
+    }
+
+    // Create a writer that will append data to "*dest".
+    // "*dest" must have initial length "dest_length".
+    pub(crate) fn with_dest_length(dest: Box<dyn WritableFile>, dest_length: u64) -> Self {
+        let mut type_crc = [0; MAX_RECORD_TYPE as usize + 1];
+        init_type_crc(&mut type_crc);
+        Self {
+            dest,
+            block_offset: (dest_length % BLOCK_SIZE as u64) as usize,
+            type_crc,
+        }
+    }
+
+    // The file being written to. Used to sync or close the log.
+    pub(crate) fn file(&mut self) -> &mut dyn WritableFile {
+        &mut *self.dest
+    }
+
+    pub(crate) fn add_record(&mut self, slice: &Slice) -> Status {
+        let mut ptr = slice.as_bytes();
+
+        // Fragment the record if necessary and emit it. Note that if slice is empty, we still
+        // want to iterate once to emit a single zero-length record.
+        let mut s;
+        let mut begin = true;
+        loop {
+            let leftover = BLOCK_SIZE - self.block_offset;
+            if leftover < HEADER_SIZE {
+                // Switch to a new block
+                if leftover > 0 {
+                    // Fill the trailer (literal below relies on HEADER_SIZE being 7)
+                    const _: () = assert!(HEADER_SIZE == 7);
+                    // NOTE: C++ ignores the status of this append as well
+                    let _ = self
+                        .dest
+                        .append(&Slice::from(&b"\x00\x00\x00\x00\x00\x00"[..leftover]));
+                }
+                self.block_offset = 0;
+            }
+
+            // Invariant: we never leave < HEADER_SIZE bytes in a block.
+            assert!(BLOCK_SIZE - self.block_offset >= HEADER_SIZE);
+
+            let avail = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let fragment_length = ptr.len().min(avail);
+
+            let end = ptr.len() == fragment_length;
+            let record_type = match (begin, end) {
+                (true, true) => RecordType::Full,
+                (true, false) => RecordType::First,
+                (false, true) => RecordType::Last,
+                (false, false) => RecordType::Middle,
+            };
+
+            s = self.emit_physical_record(record_type, &ptr[..fragment_length]);
+            ptr = &ptr[fragment_length..];
+            begin = false;
+            if !s.ok() || ptr.is_empty() {
+                break;
+            }
+        }
+        s
+    }
+
+    fn emit_physical_record(&mut self, t: RecordType, data: &[u8]) -> Status {
+        let length = data.len();
+        assert!(length <= 0xffff); // Must fit in two bytes
+        assert!(self.block_offset + HEADER_SIZE + length <= BLOCK_SIZE);
+
+        // Format the header
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[4] = (length & 0xff) as u8;
+        buf[5] = (length >> 8) as u8;
+        buf[6] = t as u8;
+
+        // Compute the crc of the record type and the payload.
+        let crc = crc32c::extend(self.type_crc[t as usize], data);
+        // Adjust for storage
+        let crc = crc32c::mask(crc);
+        encode_fixed32(&mut buf, crc);
+
+        // Write the header and the payload
+        let mut s = self.dest.append(&Slice::from(&buf[..]));
+        if s.is_ok() {
+            s = self.dest.append(&Slice::from(data));
+            if s.is_ok() {
+                s = self.dest.flush();
+            }
+        }
+        self.block_offset += HEADER_SIZE + length;
+        s.into()
+    }
+}
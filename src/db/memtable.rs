@@ -0,0 +1,354 @@
+// leveldb/db/memtable.{h,cc}
+
+use crate::comparator::Comparator;
+use crate::db::dbformat::{InternalKeyComparator, LookupKey, SequenceNumber, ValueType};
+use crate::db::skiplist::{self, SkipList, SkipListIterator};
+use crate::iter::{CleanupNode, Iter};
+use crate::slice::Slice;
+use crate::status::{Result, Status};
+use crate::util::arena::Arena;
+use crate::util::coding::{
+    decode_fixed64, encode_fixed64, encode_varint32, get_varint32_ptr, put_varint32, varint_length,
+};
+use std::os::raw::c_char;
+use std::slice;
+use std::sync::Arc;
+
+// Decode the length-prefixed slice that starts at "data", returning it along with a pointer just
+// past its end.
+// SAFETY: "data" must point at a well-formed length-prefixed slice (an entry in the arena or a key
+// built by encode_key) that outlives 'a.
+unsafe fn get_length_prefixed_slice<'a>(data: *const c_char) -> (Slice<'a>, *const c_char) {
+    // NOTE: C++ lets the varint parser look at up to 5 bytes; the varint is measured first here so
+    // no byte past the end of the entry is borrowed
+    let mut n = 1;
+    while *data.add(n - 1) as u8 & 128 != 0 {
+        n += 1;
+    }
+    let mut len = 0;
+    get_varint32_ptr(slice::from_raw_parts(data as *const u8, n), &mut len).unwrap();
+    let p = data.add(n);
+    let result = slice::from_raw_parts(p as *const u8, len as usize);
+    (Slice::from(result), p.add(len as usize))
+}
+
+// Encode a suitable internal key target for "target" and return it.
+// Uses *scratch as scratch space, and the returned pointer will point into this scratch space.
+fn encode_key(scratch: &mut Vec<u8>, target: &Slice) -> *const c_char {
+    scratch.clear();
+    put_varint32(scratch, target.size() as u32);
+    scratch.extend_from_slice(target.as_bytes());
+    scratch.as_ptr() as *const c_char
+}
+
+#[derive(Clone)]
+struct KeyComparator {
+    comparator: InternalKeyComparator,
+}
+
+impl skiplist::KeyComparator<*const c_char> for KeyComparator {
+    fn compare(&self, aptr: &*const c_char, bptr: &*const c_char) -> i32 {
+        // Internal keys are encoded as length-prefixed strings.
+        let (a, _) = unsafe { get_length_prefixed_slice(*aptr) };
+        let (b, _) = unsafe { get_length_prefixed_slice(*bptr) };
+        self.comparator.compare(&a, &b)
+    }
+}
+
+type Table = SkipList<*const c_char, KeyComparator>;
+
+pub(crate) struct MemTable {
+    comparator: KeyComparator,
+    table: Table,
+    // NOTE: Boxed so the table's pointer to it stays valid when the MemTable is moved
+    arena: Box<Arena>,
+}
+
+// SAFETY: Like in C++, reads are safe to perform concurrently while add() requires external
+// synchronization. Entries are never removed from the table and the arena is only freed when the
+// MemTable is dropped. approximate_memory_usage() only reads the arena's atomic counter, so it may
+// run alongside add().
+unsafe impl Send for MemTable {}
+unsafe impl Sync for MemTable {}
+
+impl MemTable {
+    // MemTables are reference counted.
+    // NOTE: C++ has the caller call Ref()/Unref() explicitly; an Arc is used instead
+    pub(crate) fn new(comparator: &InternalKeyComparator) -> Self {
+        let comparator = KeyComparator {
+            comparator: comparator.clone(),
+        };
+        let arena = Box::new(Arena::new());
+        let table = Table::new(comparator.clone(), &*arena);
+        Self {
+            comparator,
+            table,
+            arena,
+        }
+    }
+
+    // Returns an estimate of the number of bytes of data in use by this data structure. It is
+    // safe to call when MemTable is being modified.
+    pub(crate) fn approximate_memory_usage(&self) -> usize {
+        self.arena.memory_usage()
+    }
+
+    // Return an iterator that yields the contents of the memtable.
+    //
+    // The keys returned are internal keys encoded by append_internal_key in the db/dbformat
+    // module.
+    // NOTE: C++ requires the caller to keep the memtable live while the returned iterator is in
+    // use; here the iterator holds a reference to it
+    pub(crate) fn new_iterator(self: &Arc<Self>) -> Box<dyn Iter> {
+        Box::new(MemTableIterator {
+            iter: SkipListIterator::new(&self.table),
+            tmp: Vec::new(),
+            _mem: Arc::clone(self),
+            cleanup_head: CleanupNode::default(),
+        })
+    }
+
+    // Add an entry into memtable that maps key to value at the specified sequence number and with
+    // the specified type. Typically value will be empty if t == ValueType::Deletion.
+    // REQUIRES: external synchronization with any other calls to add()
+    pub(crate) fn add(&self, s: SequenceNumber, t: ValueType, key: &Slice, value: &Slice) {
+        // Format of an entry is concatenation of:
+        //  key_size     : varint32 of internal_key.size()
+        //  key bytes    : char[internal_key.size()]
+        //  tag          : uint64((sequence << 8) | type)
+        //  value_size   : varint32 of value.size()
+        //  value bytes  : char[value.size()]
+        let key_size = key.size();
+        let val_size = value.size();
+        let internal_key_size = key_size + 8;
+        let encoded_len = varint_length(internal_key_size as u64) as usize
+            + internal_key_size
+            + varint_length(val_size as u64) as usize
+            + val_size;
+        let ptr = self.arena.allocate(encoded_len);
+        let buf = unsafe { slice::from_raw_parts_mut(ptr as *mut u8, encoded_len) };
+        let mut p = encode_varint32(buf, internal_key_size as u32);
+        buf[p..p + key_size].copy_from_slice(key);
+        p += key_size;
+        encode_fixed64(&mut buf[p..], (s << 8) | t as u64);
+        p += 8;
+        p += encode_varint32(&mut buf[p..], val_size as u32);
+        buf[p..p + val_size].copy_from_slice(value);
+        assert_eq!(p + val_size, encoded_len);
+        self.table.insert(ptr as *const c_char);
+    }
+
+    // If memtable contains a value for key, store it in *value and return true.
+    // If memtable contains a deletion for key, store a NotFound() error in *status and return
+    // true.
+    // Else, return false.
+    pub(crate) fn get(&self, key: &LookupKey, value: &mut Vec<u8>, s: &mut Status) -> bool {
+        let memkey = key.memtable_key();
+        let mut iter = SkipListIterator::new(&self.table);
+        iter.seek(&memkey.as_ptr().cast());
+        if iter.valid() {
+            // entry format is:
+            //    klength  varint32
+            //    userkey  char[klength]
+            //    tag      uint64
+            //    vlength  varint32
+            //    value    char[vlength]
+            // Check that it belongs to same user key. We do not check the sequence number since
+            // the seek() call above should have skipped all entries with overly large sequence
+            // numbers.
+            let (internal_key, value_ptr) = unsafe { get_length_prefixed_slice(iter.key()) };
+            let key_length = internal_key.size();
+            let user_key = Slice::from(&internal_key[..key_length - 8]);
+            if self
+                .comparator
+                .comparator
+                .user_comparator()
+                .compare(&user_key, &key.user_key())
+                == 0
+            {
+                // Correct user key
+                let tag = decode_fixed64(&internal_key[key_length - 8..]);
+                match ValueType::try_from((tag & 0xff) as u8) {
+                    Ok(ValueType::Value) => {
+                        let (v, _) = unsafe { get_length_prefixed_slice(value_ptr) };
+                        value.clear();
+                        value.extend_from_slice(&v);
+                        return true;
+                    }
+                    Ok(ValueType::Deletion) => {
+                        *s = Status::not_found(&Slice::new(), &Slice::new());
+                        return true;
+                    }
+                    Err(()) => {}
+                }
+            }
+        }
+        false
+    }
+}
+
+struct MemTableIterator {
+    iter: SkipListIterator<*const c_char, KeyComparator>,
+    // For passing to encode_key
+    tmp: Vec<u8>,
+    // Keeps the table that iter points into alive
+    _mem: Arc<MemTable>,
+    cleanup_head: CleanupNode,
+}
+
+impl Iter for MemTableIterator {
+    fn cleanup_head(&mut self) -> &mut CleanupNode {
+        &mut self.cleanup_head
+    }
+
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+
+    fn seek(&mut self, k: &Slice) {
+        let target = encode_key(&mut self.tmp, k);
+        self.iter.seek(&target);
+    }
+
+    fn seek_to_first(&mut self) {
+        self.iter.seek_to_first();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+    }
+
+    fn next(&mut self) {
+        self.iter.next();
+    }
+
+    fn prev(&mut self) {
+        self.iter.prev();
+    }
+
+    fn key(&self) -> Slice<'_> {
+        // SAFETY: The entry lives in the arena of the memtable that _mem keeps alive
+        unsafe { get_length_prefixed_slice(self.iter.key()).0 }
+    }
+
+    fn value(&self) -> Slice<'_> {
+        unsafe {
+            let (_, value_ptr) = get_length_prefixed_slice(self.iter.key());
+            get_length_prefixed_slice(value_ptr).0
+        }
+    }
+
+    fn status(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MemTableIterator {
+    fn drop(&mut self) {
+        self.drop_func();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::bytewise_comparator;
+    use crate::db::dbformat::{parse_internal_key, ParsedInternalKey};
+
+    fn new_memtable() -> Arc<MemTable> {
+        let cmp = InternalKeyComparator::new(Arc::new(bytewise_comparator()));
+        Arc::new(MemTable::new(&cmp))
+    }
+
+    fn get(mem: &MemTable, key: &str, seq: SequenceNumber) -> String {
+        let mut value = Vec::new();
+        let mut s = Status::OK();
+        if !mem.get(&LookupKey::new(&key.into(), seq), &mut value, &mut s) {
+            "ABSENT".to_owned()
+        } else if s.is_not_found() {
+            "DELETED".to_owned()
+        } else {
+            String::from_utf8(value).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_add_get() {
+        let mem = new_memtable();
+        mem.add(1, ValueType::Value, &"foo".into(), &"v1".into());
+        mem.add(2, ValueType::Value, &"bar".into(), &"b1".into());
+        mem.add(3, ValueType::Deletion, &"foo".into(), &"".into());
+        mem.add(4, ValueType::Value, &"foo".into(), &"v2".into());
+
+        assert_eq!("ABSENT", get(&mem, "foo", 0));
+        assert_eq!("v1", get(&mem, "foo", 1));
+        assert_eq!("v1", get(&mem, "foo", 2));
+        assert_eq!("DELETED", get(&mem, "foo", 3));
+        assert_eq!("v2", get(&mem, "foo", 4));
+        assert_eq!("v2", get(&mem, "foo", 100));
+        assert_eq!("b1", get(&mem, "bar", 100));
+        assert_eq!("ABSENT", get(&mem, "baz", 100));
+        assert_eq!("ABSENT", get(&mem, "fo", 100));
+    }
+
+    #[test]
+    fn test_iterator() {
+        let mem = new_memtable();
+        mem.add(1, ValueType::Value, &"b".into(), &"vb".into());
+        mem.add(2, ValueType::Value, &"a".into(), &"va1".into());
+        mem.add(3, ValueType::Value, &"a".into(), &"va2".into());
+        mem.add(4, ValueType::Deletion, &"c".into(), &"".into());
+
+        let mut iter = mem.new_iterator();
+        // Newer entries for the same user key come first
+        let expected = [
+            ("a", 3, ValueType::Value, "va2"),
+            ("a", 2, ValueType::Value, "va1"),
+            ("b", 1, ValueType::Value, "vb"),
+            ("c", 4, ValueType::Deletion, ""),
+        ];
+        iter.seek_to_first();
+        for (user_key, seq, t, value) in expected {
+            assert!(iter.valid());
+            let mut parsed = ParsedInternalKey::default();
+            assert!(parse_internal_key(&iter.key(), &mut parsed));
+            assert_eq!(user_key.as_bytes(), parsed.user_key.as_bytes());
+            assert_eq!(seq, parsed.sequence);
+            assert_eq!(t, parsed.value_type);
+            assert_eq!(value.as_bytes(), iter.value().as_bytes());
+            iter.next();
+        }
+        assert!(!iter.valid());
+
+        iter.seek_to_last();
+        assert!(iter.valid());
+        iter.prev();
+        assert!(iter.valid());
+        assert_eq!(b"vb", iter.value().as_bytes());
+
+        iter.seek(&LookupKey::new(&"a".into(), 2).internal_key());
+        assert!(iter.valid());
+        assert_eq!(b"va1", iter.value().as_bytes());
+
+        // The iterator keeps the memtable alive
+        drop(mem);
+        iter.next();
+        assert_eq!(b"vb", iter.value().as_bytes());
+    }
+
+    #[test]
+    fn test_approximate_memory_usage() {
+        let mem = new_memtable();
+        let start = mem.approximate_memory_usage();
+        let value = "x".repeat(1000);
+        for i in 0..100 {
+            mem.add(
+                i + 1,
+                ValueType::Value,
+                &format!("key{}", i).as_str().into(),
+                &value.as_str().into(),
+            );
+        }
+        assert!(mem.approximate_memory_usage() >= start + 100 * 1000);
+    }
+}
@@ -0,0 +1,17 @@
+mod builder;
+mod db_impl;
+mod db_iter;
+pub(crate) mod dbformat;
+pub(crate) mod filename;
+pub(crate) mod log;
+pub(crate) mod memtable;
+mod repair;
+pub(crate) mod skiplist;
+pub(crate) mod snapshot;
+pub(crate) mod table_cache;
+pub(crate) mod version_edit;
+pub(crate) mod version_set;
+pub mod write_batch;
+
+pub use db_impl::{destroy_db, Snapshot, DB};
+pub use repair::repair_db;
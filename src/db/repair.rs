@@ -0,0 +1,671 @@
+// leveldb/db/repair.cc
+//
+// We recover the contents of the descriptor from the other files we find.
+// (1) Any log files are first converted to tables
+// (2) We scan every table to compute
+//     (a) smallest/largest for the table
+//     (b) largest sequence number in the table
+// (3) We generate descriptor contents:
+//      - log number is set to zero
+//      - next-file-number is set to 1 + largest file number we found
+//      - last-sequence-number is set to largest sequence# found across all tables (see 2c)
+//      - compaction pointers are cleared
+//      - every table file is added at level 0
+//
+// Possible optimization 1:
+//   (a) Compute total size and use to pick appropriate max-level M
+//   (b) Sort tables by largest sequence# in the table
+//   (c) For each table: if it overlaps earlier table, place in level-0, else place in level-M.
+// Possible optimization 2:
+//   Store per-table metadata (smallest, largest, largest-seq#, ...) in the table's meta section
+//   to speed up ScanTable.
+
+use crate::{
+    db::{
+        builder::build_table,
+        db_impl::sanitize_options,
+        dbformat::{parse_internal_key, InternalKeyComparator, ParsedInternalKey, SequenceNumber},
+        filename::{
+            descriptor_file_name, log_file_name, parse_file_name, set_current_file,
+            sst_table_file_name, table_file_name, temp_file_name, FileType,
+        },
+        log,
+        memtable::MemTable,
+        table_cache::TableCache,
+        version_edit::{FileMetaData, VersionEdit},
+        write_batch::{WriteBatch, WriteBatchInternal},
+    },
+    env::{self, Env, Logger},
+    iter::Iter,
+    options::{Options, ReadOptions},
+    slice::Slice,
+    status::{Result, Status},
+    table::TableBuilder,
+    util::logging::escape_string,
+};
+use std::sync::Arc;
+
+// If a DB cannot be opened, you may attempt to call this method to resurrect as much of the
+// contents of the database as possible. Some data may be lost, so be careful when calling this
+// function on a database that contains important information.
+pub fn repair_db(dbname: &str, options: &Options) -> Result<()> {
+    Repairer::new(dbname, options).run().into()
+}
+
+struct TableInfo {
+    meta: FileMetaData,
+    max_sequence: SequenceNumber,
+}
+
+struct Repairer {
+    dbname: String,
+    env: Arc<dyn Env>,
+    icmp: InternalKeyComparator,
+    options: Options,
+    table_cache: TableCache,
+    edit: VersionEdit,
+
+    manifests: Vec<String>,
+    table_numbers: Vec<u64>,
+    logs: Vec<u64>,
+    tables: Vec<TableInfo>,
+    next_file_number: u64,
+}
+
+impl Repairer {
+    fn new(dbname: &str, options: &Options) -> Self {
+        let icmp = InternalKeyComparator::new(Arc::clone(&options.comparator));
+        let options = sanitize_options(&icmp, options);
+        Self {
+            dbname: dbname.to_owned(),
+            env: Arc::clone(&options.env),
+            // TableCache can be small since we expect each table to be opened once.
+            table_cache: TableCache::new(dbname, &options, 10),
+            icmp,
+            options,
+            edit: VersionEdit::new(),
+            manifests: Vec::new(),
+            table_numbers: Vec::new(),
+            logs: Vec::new(),
+            tables: Vec::new(),
+            next_file_number: 1,
+        }
+    }
+
+    fn run(mut self) -> Status {
+        let mut status = self.find_files();
+        if status.ok() {
+            self.convert_log_files_to_tables();
+            self.extract_meta_data();
+            status = self.write_descriptor();
+        }
+        if status.ok() {
+            let bytes: u64 = self.tables.iter().map(|t| t.meta.file_size).sum();
+            env::log(
+                self.info_log(),
+                format_args!(
+                    "**** Repaired leveldb {}; recovered {} files; {} bytes. Some data may have \
+                     been lost. ****",
+                    self.dbname,
+                    self.tables.len(),
+                    bytes
+                ),
+            );
+        }
+        status
+    }
+
+    fn info_log(&self) -> Option<&dyn Logger> {
+        self.options.info_log.as_deref()
+    }
+
+    fn find_files(&mut self) -> Status {
+        let filenames = match self.env.get_children(&self.dbname) {
+            Ok(filenames) => filenames,
+            Err(s) => return s.into(),
+        };
+        if filenames.is_empty() {
+            return Status::io_error(&Slice::from(&self.dbname), &"repair found no files".into());
+        }
+
+        let mut number = 0;
+        let mut file_type = FileType::Log;
+        for filename in filenames {
+            if parse_file_name(&filename, &mut number, &mut file_type) {
+                if file_type == FileType::Descriptor {
+                    self.manifests.push(filename);
+                } else {
+                    if number + 1 > self.next_file_number {
+                        self.next_file_number = number + 1;
+                    }
+                    match file_type {
+                        FileType::Log => self.logs.push(number),
+                        FileType::Table => self.table_numbers.push(number),
+                        // Ignore other files
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Status::OK()
+    }
+
+    fn convert_log_files_to_tables(&mut self) {
+        for log in std::mem::take(&mut self.logs) {
+            let logname = log_file_name(&self.dbname, log);
+            let status = self.convert_log_to_table(log);
+            if !status.ok() {
+                env::log(
+                    self.info_log(),
+                    format_args!("Log #{}: ignoring conversion error: {}", log, status),
+                );
+            }
+            self.archive_file(&logname);
+        }
+    }
+
+    fn convert_log_to_table(&mut self, log: u64) -> Status {
+        #[derive(Clone)]
+        struct LogReporter {
+            info_log: Option<Arc<dyn Logger>>,
+            lognum: u64,
+        }
+
+        impl log::Reporter for LogReporter {
+            fn corruption(&mut self, bytes: usize, s: &Status) {
+                // We print error messages for corruption, but continue repairing.
+                env::log(
+                    self.info_log.as_deref(),
+                    format_args!("Log #{}: dropping {} bytes; {}", self.lognum, bytes, s),
+                );
+            }
+        }
+
+        // Open the log file
+        let logname = log_file_name(&self.dbname, log);
+        let lfile = match self.env.new_sequential_file(&logname) {
+            Ok(lfile) => lfile,
+            Err(s) => return s.into(),
+        };
+
+        // Create the log reader.
+        let mut reporter = LogReporter {
+            info_log: self.options.info_log.clone(),
+            lognum: log,
+        };
+        // We intentionally make log::Reader do checksumming so that corruptions cause entire
+        // commits to be skipped instead of propagating bad information (like overly large
+        // sequence numbers).
+        // NOTE: C++ passes false here despite the comment above; the records' crc32c checksums
+        // are what tell salvageable commits from corrupt ones, so they are verified
+        let mut reader = log::Reader::new(lfile, Some(Box::new(reporter.clone())), true, 0);
+
+        // Read all the records and add to a memtable
+        let mut scratch = Vec::new();
+        let mut batch = WriteBatch::new();
+        let mem = Arc::new(MemTable::new(&self.icmp));
+        let mut counter = 0;
+        while let Some(record) = reader.read_record(&mut scratch) {
+            if record.size() < 12 {
+                log::Reporter::corruption(
+                    &mut reporter,
+                    record.size(),
+                    &Status::corruption(&"log record too small".into(), &Slice::new()),
+                );
+                continue;
+            }
+            WriteBatchInternal::set_contents(&mut batch, &record);
+            let status = WriteBatchInternal::insert_into(&batch, &mem);
+            if status.ok() {
+                counter += WriteBatchInternal::count(&batch);
+            } else {
+                // Keep going with rest of file
+                env::log(
+                    self.info_log(),
+                    format_args!("Log #{}: ignoring {}", log, status),
+                );
+            }
+        }
+        drop(reader);
+
+        // Do not record a version edit for this conversion to a Table since extract_meta_data()
+        // will also generate edits.
+        let mut meta = FileMetaData::new();
+        meta.number = self.next_file_number;
+        self.next_file_number += 1;
+        let mut iter = mem.new_iterator();
+        let status = build_table(
+            &self.dbname,
+            &*self.env,
+            &self.options,
+            &self.table_cache,
+            &mut *iter,
+            &mut meta,
+        );
+        drop(iter);
+        if status.ok() && meta.file_size > 0 {
+            self.table_numbers.push(meta.number);
+        }
+        env::log(
+            self.info_log(),
+            format_args!(
+                "Log #{}: {} ops saved to Table #{} {}",
+                log, counter, meta.number, status
+            ),
+        );
+        status
+    }
+
+    fn extract_meta_data(&mut self) {
+        for number in std::mem::take(&mut self.table_numbers) {
+            self.scan_table(number);
+        }
+    }
+
+    fn new_table_iterator(&self, meta: &FileMetaData) -> Box<dyn Iter> {
+        // NOTE: C++ only verifies checksums when paranoid_checks is on, as compactions do. They
+        // are always verified here so that a corrupt block is reported and its table salvaged
+        // instead of its garbage being copied into the repaired DB.
+        let r = ReadOptions {
+            verify_checksums: true,
+            ..Default::default()
+        };
+        self.table_cache
+            .new_iterator(&r, meta.number, meta.file_size)
+    }
+
+    fn scan_table(&mut self, number: u64) {
+        let mut t = TableInfo {
+            meta: FileMetaData::new(),
+            max_sequence: 0,
+        };
+        t.meta.number = number;
+        let mut fname = table_file_name(&self.dbname, number);
+        let mut status = match self.env.get_file_size(&fname) {
+            Ok(size) => {
+                t.meta.file_size = size;
+                Status::OK()
+            }
+            Err(s) => {
+                // Try alternate file name.
+                fname = sst_table_file_name(&self.dbname, number);
+                match self.env.get_file_size(&fname) {
+                    Ok(size) => {
+                        t.meta.file_size = size;
+                        Status::OK()
+                    }
+                    Err(_) => s.into(),
+                }
+            }
+        };
+        if !status.ok() {
+            self.archive_file(&table_file_name(&self.dbname, number));
+            self.archive_file(&sst_table_file_name(&self.dbname, number));
+            env::log(
+                self.info_log(),
+                format_args!("Table #{}: dropped: {}", t.meta.number, status),
+            );
+            return;
+        }
+
+        // Extract metadata by scanning through table.
+        let mut counter = 0;
+        let mut iter = self.new_table_iterator(&t.meta);
+        let mut empty = true;
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key();
+            let mut parsed = ParsedInternalKey::default();
+            if !parse_internal_key(&key, &mut parsed) {
+                env::log(
+                    self.info_log(),
+                    format_args!(
+                        "Table #{}: unparsable key {}",
+                        t.meta.number,
+                        escape_string(&key)
+                    ),
+                );
+                iter.next();
+                continue;
+            }
+
+            counter += 1;
+            if empty {
+                empty = false;
+                t.meta.smallest.decode_from(&key);
+            }
+            t.meta.largest.decode_from(&key);
+            if parsed.sequence > t.max_sequence {
+                t.max_sequence = parsed.sequence;
+            }
+            iter.next();
+        }
+        if let Err(e) = iter.status() {
+            status = e.into();
+        }
+        drop(iter);
+        env::log(
+            self.info_log(),
+            format_args!("Table #{}: {} entries {}", t.meta.number, counter, status),
+        );
+
+        if status.ok() {
+            self.tables.push(t);
+        } else if status.is_corruption() {
+            // Salvage the entries that can still be read. repair_table() archives the input file.
+            self.repair_table(&fname, t);
+        } else {
+            // NOTE: C++ tries to repair the table after any error; only a corrupt table has
+            // anything to salvage, so the others are archived and dropped
+            self.archive_file(&fname);
+            env::log(
+                self.info_log(),
+                format_args!("Table #{}: dropped: {}", t.meta.number, status),
+            );
+        }
+    }
+
+    fn repair_table(&mut self, src: &str, mut t: TableInfo) {
+        // We will copy src contents to a new table and then rename the new table over the
+        // source.
+
+        // Create builder.
+        let copy = table_file_name(&self.dbname, self.next_file_number);
+        self.next_file_number += 1;
+        let mut file = match self.env.new_writable_file(&copy) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+
+        let mut counter = 0;
+        let mut s = {
+            let mut builder = TableBuilder::new(&self.options, &mut *file);
+
+            // Copy data.
+            let mut iter = self.new_table_iterator(&t.meta);
+            iter.seek_to_first();
+            while iter.valid() {
+                builder.add(&iter.key(), &iter.value());
+                counter += 1;
+                iter.next();
+            }
+            drop(iter);
+
+            self.archive_file(src);
+            if counter == 0 {
+                // Nothing to save
+                builder.abandon();
+                Status::OK()
+            } else {
+                let s = Status::from(builder.finish());
+                if s.ok() {
+                    t.meta.file_size = builder.file_size();
+                }
+                s
+            }
+        };
+
+        if s.ok() {
+            s = file.close().into();
+        }
+        drop(file);
+
+        if counter > 0 && s.ok() {
+            let orig = table_file_name(&self.dbname, t.meta.number);
+            s = self.env.rename_file(&copy, &orig).into();
+            if s.ok() {
+                env::log(
+                    self.info_log(),
+                    format_args!("Table #{}: {} entries repaired", t.meta.number, counter),
+                );
+                // The table cache may hold the corrupt table under the same number
+                self.table_cache.evict(t.meta.number);
+                self.tables.push(t);
+            }
+        }
+        if !s.ok() || counter == 0 {
+            let _ = self.env.remove_file(&copy);
+        }
+    }
+
+    fn write_descriptor(&mut self) -> Status {
+        let tmp = temp_file_name(&self.dbname, 1);
+        let file = match self.env.new_writable_file(&tmp) {
+            Ok(file) => file,
+            Err(s) => return s.into(),
+        };
+
+        let max_sequence = self
+            .tables
+            .iter()
+            .map(|t| t.max_sequence)
+            .max()
+            .unwrap_or(0);
+
+        self.edit
+            .set_comparator_name(self.icmp.user_comparator().name());
+        self.edit.set_log_number(0);
+        self.edit.set_next_file(self.next_file_number);
+        self.edit.set_last_sequence(max_sequence);
+
+        for t in &self.tables {
+            // TODO(opt): separate out into multiple levels
+            self.edit.add_file(
+                0,
+                t.meta.number,
+                t.meta.file_size,
+                &t.meta.smallest,
+                &t.meta.largest,
+            );
+        }
+
+        let mut status = {
+            let mut log = log::Writer::new(file);
+            let mut record = Vec::new();
+            self.edit.encode_to(&mut record);
+            let mut status = log.add_record(&Slice::from(&record));
+            if status.ok() {
+                status = log.file().close().into();
+            }
+            status
+        };
+
+        if !status.ok() {
+            let _ = self.env.remove_file(&tmp);
+        } else {
+            // Discard older manifests
+            for manifest in std::mem::take(&mut self.manifests) {
+                self.archive_file(&format!("{}/{}", self.dbname, manifest));
+            }
+
+            // Install new manifest
+            status = self
+                .env
+                .rename_file(&tmp, &descriptor_file_name(&self.dbname, 1))
+                .into();
+            if status.ok() {
+                status = set_current_file(&*self.env, &self.dbname, 1);
+            } else {
+                let _ = self.env.remove_file(&tmp);
+            }
+        }
+        status
+    }
+
+    fn archive_file(&self, fname: &str) {
+        // Move into another directory. E.g., for
+        //    dir/foo
+        // rename to
+        //    dir/lost/foo
+        let (dir, base) = match fname.rfind('/') {
+            Some(slash) => (&fname[..slash], &fname[slash + 1..]),
+            None => ("", fname),
+        };
+        let new_dir = format!("{}/lost", dir);
+        // Ignore error
+        let _ = self.env.create_dir(&new_dir);
+        let new_file = format!("{}/{}", new_dir, base);
+        let s = Status::from(self.env.rename_file(fname, &new_file));
+        env::log(
+            self.info_log(),
+            format_args!("Archiving {}: {}\n", fname, s),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{destroy_db, DB};
+    use crate::env::default_env;
+    use crate::options::WriteOptions;
+
+    fn test_db_name(name: &str) -> String {
+        let dir = default_env().get_test_directory().unwrap();
+        let dbname = format!("{}/repair_test_{}", dir, name);
+        assert!(destroy_db(&dbname, &Options::default()).is_ok());
+        dbname
+    }
+
+    fn open_options() -> Options {
+        Options {
+            create_if_missing: true,
+            ..Default::default()
+        }
+    }
+
+    fn key(i: usize) -> String {
+        format!("{:06}", i)
+    }
+
+    fn value(i: usize) -> String {
+        format!("{:0100}", i)
+    }
+
+    fn fill(db: &DB, range: std::ops::Range<usize>) {
+        for i in range {
+            db.put(
+                &WriteOptions::default(),
+                &(&key(i)).into(),
+                &(&value(i)).into(),
+            )
+            .unwrap();
+        }
+    }
+
+    fn get(db: &DB, k: &str) -> Option<String> {
+        match db.get(&ReadOptions::default(), &k.into()) {
+            Ok(value) => Some(String::from_utf8(value).unwrap()),
+            Err(e) if e.is_not_found() => None,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn files_of_type(dbname: &str, wanted: FileType) -> Vec<String> {
+        let mut number = 0;
+        let mut file_type = FileType::Log;
+        let mut files: Vec<_> = default_env()
+            .get_children(dbname)
+            .unwrap()
+            .into_iter()
+            .filter(|f| parse_file_name(f, &mut number, &mut file_type) && file_type == wanted)
+            .map(|f| format!("{}/{}", dbname, f))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_lost_manifest() {
+        let dbname = test_db_name("lost_manifest");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        // Some entries in a table and some only in the log
+        fill(&db, 0..100);
+        assert!(db.test_compact_mem_table().ok());
+        fill(&db, 100..200);
+        drop(db);
+
+        for manifest in files_of_type(&dbname, FileType::Descriptor) {
+            std::fs::remove_file(manifest).unwrap();
+        }
+        assert!(DB::open(Options::default(), &dbname).is_err());
+
+        repair_db(&dbname, &Options::default()).unwrap();
+        let db = DB::open(Options::default(), &dbname).unwrap();
+        for i in 0..200 {
+            assert_eq!(Some(value(i)), get(&db, &key(i)));
+        }
+
+        // New writes are numbered after the recovered ones
+        assert!(db
+            .put(&WriteOptions::default(), &(&key(0)).into(), &"new".into())
+            .is_ok());
+        assert_eq!(Some("new".to_owned()), get(&db, &key(0)));
+        drop(db);
+        assert!(destroy_db(&dbname, &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn test_corrupt_table() {
+        let dbname = test_db_name("corrupt_table");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        fill(&db, 0..1000);
+        assert!(db.test_compact_mem_table().ok());
+        drop(db);
+
+        // Corrupt the first data block of the table
+        let tables = files_of_type(&dbname, FileType::Table);
+        assert_eq!(1, tables.len());
+        let mut contents = std::fs::read(&tables[0]).unwrap();
+        contents[10] ^= 0x80;
+        std::fs::write(&tables[0], contents).unwrap();
+
+        repair_db(&dbname, &Options::default()).unwrap();
+
+        // The original table is kept in lost/ and the rest of its entries are salvaged
+        let lost = format!("{}/lost", dbname);
+        let table_name = tables[0].rsplit('/').next().unwrap();
+        assert!(std::fs::metadata(format!("{}/{}", lost, table_name)).is_ok());
+        let db = DB::open(Options::default(), &dbname).unwrap();
+        assert_eq!(None, get(&db, &key(0)));
+        assert_eq!(Some(value(999)), get(&db, &key(999)));
+        let found = (0..1000).filter(|&i| get(&db, &key(i)).is_some()).count();
+        assert!(found > 900 && found < 1000, "{}", found);
+        drop(db);
+        // The archived table is a DB file name, so destroying lost/ as a DB removes it
+        assert!(destroy_db(&lost, &Options::default()).is_ok());
+        assert!(destroy_db(&dbname, &Options::default()).is_ok());
+        assert!(!default_env().file_exists(&dbname));
+    }
+
+    #[test]
+    fn test_corrupt_log() {
+        let dbname = test_db_name("corrupt_log");
+        let db = DB::open(open_options(), &dbname).unwrap();
+        fill(&db, 0..1000);
+        drop(db);
+
+        // Corrupt the first record of the log. The reader drops the rest of its block, but the
+        // records in the following blocks are still converted.
+        let logs = files_of_type(&dbname, FileType::Log);
+        let log = logs.last().unwrap();
+        let mut contents = std::fs::read(log).unwrap();
+        contents[10] ^= 0x80;
+        std::fs::write(log, contents).unwrap();
+
+        repair_db(&dbname, &Options::default()).unwrap();
+        let db = DB::open(Options::default(), &dbname).unwrap();
+        assert_eq!(None, get(&db, &key(0)));
+        assert_eq!(Some(value(999)), get(&db, &key(999)));
+        let found = (0..1000).filter(|&i| get(&db, &key(i)).is_some()).count();
+        assert!(found > 500 && found < 1000, "{}", found);
+        drop(db);
+        assert!(destroy_db(&dbname, &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn test_missing_db() {
+        let dbname = test_db_name("missing");
+        assert!(repair_db(&dbname, &Options::default()).is_err());
+    }
+}
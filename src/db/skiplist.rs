@@ -0,0 +1,459 @@
+// leveldb/db/skiplist.h
+// leveldb/db/skiplist_test.cc
+
+// Thread safety
+// -------------
+//
+// Writes require external synchronization, most likely a mutex. Reads require a guarantee that
+// the SkipList will not be destroyed while the read is in progress. Apart from that, reads
+// progress without any internal locking or synchronization.
+//
+// Invariants:
+//
+// (1) Allocated nodes are never deleted until the SkipList is destroyed. This is trivially
+// guaranteed by the code since we never delete any skip list nodes.
+//
+// (2) The contents of a Node except for the next/prev pointers are immutable after the Node has
+// been linked into the SkipList. Only insert() modifies the list, and it is careful to initialize
+// a node and use release-stores to publish the nodes in one or more lists.
+//
+// ... prev vs. next pointer ordering ...
+
+use crate::util::{arena::Arena, random::Random};
+use std::cell::UnsafeCell;
+use std::mem::{align_of, size_of};
+use std::ptr::{self, null_mut};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+const MAX_HEIGHT: usize = 12;
+
+// Orders the keys stored in a SkipList.
+// NOTE: C++ takes the comparator as a template parameter that is called like a function
+pub(crate) trait KeyComparator<K> {
+    // Three-way comparison with the same meaning as Comparator::compare().
+    fn compare(&self, a: &K, b: &K) -> i32;
+}
+
+// Implementation details follow
+#[repr(C)]
+struct Node<K> {
+    key: K,
+    // Array of length equal to the node height. next[0] is lowest level link.
+    // NOTE: The array is over-allocated in the arena to hold the node's full height
+    next: [AtomicPtr<Node<K>>; 1],
+}
+
+impl<K> Node<K> {
+    // Accessors/mutators for links. Wrapped in methods so we can add the appropriate barriers as
+    // necessary.
+    // NOTE: These take raw pointers since the links live past the end of the declared array
+    unsafe fn link(node: *const Node<K>, n: usize) -> *const AtomicPtr<Node<K>> {
+        (ptr::addr_of!((*node).next) as *const AtomicPtr<Node<K>>).add(n)
+    }
+
+    unsafe fn next(node: *const Node<K>, n: usize) -> *mut Node<K> {
+        // Use an 'acquire load' so that we observe a fully initialized version of the returned
+        // Node.
+        (*Self::link(node, n)).load(Ordering::Acquire)
+    }
+
+    unsafe fn set_next(node: *const Node<K>, n: usize, x: *mut Node<K>) {
+        // Use a 'release store' so that anybody who reads through this pointer observes a fully
+        // initialized version of the inserted node.
+        (*Self::link(node, n)).store(x, Ordering::Release);
+    }
+
+    // No-barrier variants that can be safely used in a few locations.
+    unsafe fn no_barrier_next(node: *const Node<K>, n: usize) -> *mut Node<K> {
+        (*Self::link(node, n)).load(Ordering::Relaxed)
+    }
+
+    unsafe fn no_barrier_set_next(node: *const Node<K>, n: usize, x: *mut Node<K>) {
+        (*Self::link(node, n)).store(x, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct SkipList<K, C> {
+    // Immutable after construction
+    compare: C,
+    // Arena used for allocations of nodes
+    arena: *const Arena,
+
+    head: *mut Node<K>,
+
+    // Modified only by insert(). Read racily by readers, but stale values are ok.
+    // Height of the entire list
+    max_height: AtomicUsize,
+
+    // Read/written only by insert().
+    rnd: UnsafeCell<Random>,
+}
+
+impl<K: Copy, C: KeyComparator<K>> SkipList<K, C> {
+    // Create a new SkipList object that will use "cmp" for comparing keys, and will allocate
+    // memory using "*arena". Objects allocated in the arena must remain allocated for the
+    // lifetime of the skiplist object.
+    pub(crate) fn new(cmp: C, arena: *const Arena) -> Self {
+        let mut list = Self {
+            compare: cmp,
+            arena,
+            head: null_mut(),
+            max_height: AtomicUsize::new(1),
+            rnd: UnsafeCell::new(Random::new(0xdeadbeef)),
+        };
+        // NOTE: C++ initializes the head's key with 0; it is never read so it's left unset here
+        list.head = list.allocate_node(MAX_HEIGHT);
+        list
+    }
+
+    // Insert key into the list.
+    // REQUIRES: nothing that compares equal to key is currently in the list.
+    // REQUIRES: external synchronization with any other calls to insert().
+    pub(crate) fn insert(&self, key: K) {
+        let mut prev = [null_mut(); MAX_HEIGHT];
+        let x = self.find_greater_or_equal(&key, Some(&mut prev));
+
+        // Our data structure does not allow duplicate insertion
+        assert!(x.is_null() || !self.equal(&key, unsafe { &(*x).key }));
+
+        let height = self.random_height();
+        if height > self.get_max_height() {
+            for p in &mut prev[self.get_max_height()..height] {
+                *p = self.head;
+            }
+            // It is ok to mutate max_height without any synchronization with concurrent readers.
+            // A concurrent reader that observes the new value of max_height will see either the
+            // old value of new level pointers from head (null), or a new value set in the loop
+            // below. In the former case the reader will immediately drop to the next level since
+            // null sorts after all keys. In the latter case the reader will use the new node.
+            self.max_height.store(height, Ordering::Relaxed);
+        }
+
+        let x = self.new_node(key, height);
+        for (i, &prev) in prev[..height].iter().enumerate() {
+            unsafe {
+                // no_barrier_set_next() suffices since we will add a barrier when we publish a
+                // pointer to "x" in prev[i].
+                Node::no_barrier_set_next(x, i, Node::no_barrier_next(prev, i));
+                Node::set_next(prev, i, x);
+            }
+        }
+    }
+
+    // Returns true iff an entry that compares equal to key is in the list.
+    #[cfg(test)]
+    pub(crate) fn contains(&self, key: &K) -> bool {
+        let x = self.find_greater_or_equal(key, None);
+        !x.is_null() && self.equal(key, unsafe { &(*x).key })
+    }
+
+    fn get_max_height(&self) -> usize {
+        self.max_height.load(Ordering::Relaxed)
+    }
+
+    // Allocates a node of the given height with all links set to null. The key is left
+    // uninitialized.
+    fn allocate_node(&self, height: usize) -> *mut Node<K> {
+        assert!(align_of::<Node<K>>() <= 8);
+        let size = size_of::<Node<K>>() + size_of::<AtomicPtr<Node<K>>>() * (height - 1);
+        // SAFETY: The arena outlives the list, and writes to the list (and therefore the arena)
+        // are externally synchronized
+        let node = unsafe { (*self.arena).allocate_aligned(size) } as *mut Node<K>;
+        for i in 0..height {
+            unsafe {
+                ptr::write(
+                    Node::link(node, i) as *mut AtomicPtr<Node<K>>,
+                    AtomicPtr::new(null_mut()),
+                );
+            }
+        }
+        node
+    }
+
+    fn new_node(&self, key: K, height: usize) -> *mut Node<K> {
+        let node = self.allocate_node(height);
+        unsafe { ptr::write(ptr::addr_of_mut!((*node).key), key) };
+        node
+    }
+
+    fn random_height(&self) -> usize {
+        // Increase height with probability 1 in BRANCHING
+        const BRANCHING: u32 = 4;
+        // SAFETY: Only insert() calls this, which is externally synchronized
+        let rnd = unsafe { &mut *self.rnd.get() };
+        let mut height = 1;
+        while height < MAX_HEIGHT && rnd.one_in(BRANCHING) {
+            height += 1;
+        }
+        assert!(height > 0);
+        assert!(height <= MAX_HEIGHT);
+        height
+    }
+
+    fn equal(&self, a: &K, b: &K) -> bool {
+        self.compare.compare(a, b) == 0
+    }
+
+    // Return true if key is greater than the data stored in "n"
+    fn key_is_after_node(&self, key: &K, n: *mut Node<K>) -> bool {
+        // null n is considered infinite
+        !n.is_null() && self.compare.compare(unsafe { &(*n).key }, key) < 0
+    }
+
+    // Return the earliest node that comes at or after key.
+    // Return null if there is no such node.
+    //
+    // If prev is non-None, fills prev[level] with pointer to previous node at "level" for every
+    // level in [0..max_height-1].
+    fn find_greater_or_equal(
+        &self,
+        key: &K,
+        mut prev: Option<&mut [*mut Node<K>; MAX_HEIGHT]>,
+    ) -> *mut Node<K> {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            let next = unsafe { Node::next(x, level) };
+            if self.key_is_after_node(key, next) {
+                // Keep searching in this list
+                x = next;
+            } else {
+                if let Some(prev) = prev.as_mut() {
+                    prev[level] = x;
+                }
+                if level == 0 {
+                    return next;
+                } else {
+                    // Switch to next list
+                    level -= 1;
+                }
+            }
+        }
+    }
+
+    // Return the latest node with a key < key.
+    // Return head if there is no such node.
+    fn find_less_than(&self, key: &K) -> *mut Node<K> {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            assert!(x == self.head || self.compare.compare(unsafe { &(*x).key }, key) < 0);
+            let next = unsafe { Node::next(x, level) };
+            if next.is_null() || self.compare.compare(unsafe { &(*next).key }, key) >= 0 {
+                if level == 0 {
+                    return x;
+                } else {
+                    // Switch to next list
+                    level -= 1;
+                }
+            } else {
+                x = next;
+            }
+        }
+    }
+
+    // Return the last node in the list.
+    // Return head if list is empty.
+    fn find_last(&self) -> *mut Node<K> {
+        let mut x = self.head;
+        let mut level = self.get_max_height() - 1;
+        loop {
+            let next = unsafe { Node::next(x, level) };
+            if next.is_null() {
+                if level == 0 {
+                    return x;
+                } else {
+                    // Switch to next list
+                    level -= 1;
+                }
+            } else {
+                x = next;
+            }
+        }
+    }
+}
+
+// Iteration over the contents of a skip list
+// NOTE: C++ nests this class in SkipList
+pub(crate) struct SkipListIterator<K, C> {
+    list: *const SkipList<K, C>,
+    node: *mut Node<K>,
+}
+
+impl<K: Copy, C: KeyComparator<K>> SkipListIterator<K, C> {
+    // Initialize an iterator over the specified list.
+    // The returned iterator is not valid.
+    // NOTE: Like in C++, the list must outlive the iterator
+    pub(crate) fn new(list: &SkipList<K, C>) -> Self {
+        Self {
+            list,
+            node: null_mut(),
+        }
+    }
+
+    fn list(&self) -> &SkipList<K, C> {
+        unsafe { &*self.list }
+    }
+
+    // Returns true iff the iterator is positioned at a valid node.
+    pub(crate) fn valid(&self) -> bool {
+        !self.node.is_null()
+    }
+
+    // Returns the key at the current position.
+    // REQUIRES: valid()
+    pub(crate) fn key(&self) -> K {
+        assert!(self.valid());
+        unsafe { (*self.node).key }
+    }
+
+    // Advances to the next position.
+    // REQUIRES: valid()
+    pub(crate) fn next(&mut self) {
+        assert!(self.valid());
+        self.node = unsafe { Node::next(self.node, 0) };
+    }
+
+    // Advances to the previous position.
+    // REQUIRES: valid()
+    pub(crate) fn prev(&mut self) {
+        // Instead of using explicit "prev" links, we just search for the last node that falls
+        // before key.
+        assert!(self.valid());
+        let key = self.key();
+        self.node = self.list().find_less_than(&key);
+        if self.node == self.list().head {
+            self.node = null_mut();
+        }
+    }
+
+    // Advance to the first entry with a key >= target
+    pub(crate) fn seek(&mut self, target: &K) {
+        self.node = self.list().find_greater_or_equal(target, None);
+    }
+
+    // Position at the first entry in list.
+    // Final state of iterator is valid() iff list is not empty.
+    pub(crate) fn seek_to_first(&mut self) {
+        self.node = unsafe { Node::next(self.list().head, 0) };
+    }
+
+    // Position at the last entry in list.
+    // Final state of iterator is valid() iff list is not empty.
+    pub(crate) fn seek_to_last(&mut self) {
+        self.node = self.list().find_last();
+        if self.node == self.list().head {
+            self.node = null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    type Key = u64;
+
+    struct TestComparator;
+
+    impl KeyComparator<Key> for TestComparator {
+        fn compare(&self, a: &Key, b: &Key) -> i32 {
+            a.cmp(b) as i32
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let arena = Arena::new();
+        let list = SkipList::new(TestComparator, &arena);
+        assert!(!list.contains(&10));
+
+        let mut iter = SkipListIterator::new(&list);
+        assert!(!iter.valid());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek(&100);
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_insert_and_lookup() {
+        const N: u32 = 2000;
+        const R: u32 = 5000;
+        let mut rnd = Random::new(1000);
+        let mut keys = BTreeSet::new();
+        let arena = Arena::new();
+        let list = SkipList::new(TestComparator, &arena);
+        for _ in 0..N {
+            let key = (rnd.next() % R) as Key;
+            if keys.insert(key) {
+                list.insert(key);
+            }
+        }
+
+        for i in 0..R as Key {
+            if list.contains(&i) {
+                assert!(keys.contains(&i));
+            } else {
+                assert!(!keys.contains(&i));
+            }
+        }
+
+        // Simple iterator tests
+        {
+            let mut iter = SkipListIterator::new(&list);
+            assert!(!iter.valid());
+
+            iter.seek(&0);
+            assert!(iter.valid());
+            assert_eq!(*keys.first().unwrap(), iter.key());
+
+            iter.seek_to_first();
+            assert!(iter.valid());
+            assert_eq!(*keys.first().unwrap(), iter.key());
+
+            iter.seek_to_last();
+            assert!(iter.valid());
+            assert_eq!(*keys.last().unwrap(), iter.key());
+        }
+
+        // Forward iteration test
+        for i in 0..R as Key {
+            let mut iter = SkipListIterator::new(&list);
+            iter.seek(&i);
+
+            // Compare against model iterator
+            let mut model_iter = keys.range(i..);
+            for _ in 0..3 {
+                match model_iter.next() {
+                    Some(&key) => {
+                        assert!(iter.valid());
+                        assert_eq!(key, iter.key());
+                        iter.next();
+                    }
+                    None => {
+                        assert!(!iter.valid());
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Backward iteration test
+        {
+            let mut iter = SkipListIterator::new(&list);
+            iter.seek_to_last();
+
+            // Compare against model iterator
+            for &key in keys.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(key, iter.key());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+        }
+    }
+}
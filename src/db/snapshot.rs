@@ -0,0 +1,141 @@
+// leveldb/db/snapshot.h
+
+use crate::db::dbformat::SequenceNumber;
+use std::ptr::null_mut;
+
+// Snapshots are kept in a doubly-linked list in the DB.
+// Each SnapshotImpl corresponds to a particular sequence number.
+pub(crate) struct SnapshotImpl {
+    sequence_number: SequenceNumber,
+
+    // SnapshotImpl is kept in a doubly-linked circular list. The SnapshotList implementation
+    // operates on the next/previous fields directly.
+    prev: *mut SnapshotImpl,
+    next: *mut SnapshotImpl,
+
+    // NOTE: C++ points to the SnapshotList; the list's head is used here since it does not move
+    #[cfg(debug_assertions)]
+    list: *const SnapshotImpl,
+}
+
+impl SnapshotImpl {
+    pub(crate) fn sequence_number(&self) -> SequenceNumber {
+        self.sequence_number
+    }
+}
+
+pub(crate) struct SnapshotList {
+    // Dummy head of doubly-linked list of snapshots
+    // NOTE: Boxed so the circular list keeps a stable address when the list is moved
+    head: Box<SnapshotImpl>,
+}
+
+// SAFETY: The nodes are owned by the list and only followed through it, which requires external
+// synchronization for modification.
+unsafe impl Send for SnapshotList {}
+unsafe impl Sync for SnapshotList {}
+
+impl SnapshotList {
+    pub(crate) fn new() -> Self {
+        let mut head = Box::new(SnapshotImpl {
+            sequence_number: 0,
+            prev: null_mut(),
+            next: null_mut(),
+            #[cfg(debug_assertions)]
+            list: std::ptr::null(),
+        });
+        let head_ptr = &mut *head as *mut SnapshotImpl;
+        head.prev = head_ptr;
+        head.next = head_ptr;
+        Self { head }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        std::ptr::eq(self.head.next, &*self.head)
+    }
+
+    pub(crate) fn oldest(&self) -> &SnapshotImpl {
+        assert!(!self.is_empty());
+        unsafe { &*self.head.next }
+    }
+
+    pub(crate) fn newest(&self) -> &SnapshotImpl {
+        assert!(!self.is_empty());
+        unsafe { &*self.head.prev }
+    }
+
+    // Creates a SnapshotImpl and appends it to the end of the list.
+    // NOTE: C++ calls it "New"
+    pub(crate) fn new_snapshot(&mut self, sequence_number: SequenceNumber) -> *const SnapshotImpl {
+        assert!(self.is_empty() || self.newest().sequence_number <= sequence_number);
+
+        let head = &mut *self.head as *mut SnapshotImpl;
+        let snapshot = Box::into_raw(Box::new(SnapshotImpl {
+            sequence_number,
+            next: head,
+            prev: self.head.prev,
+            #[cfg(debug_assertions)]
+            list: head,
+        }));
+        unsafe {
+            (*(*snapshot).prev).next = snapshot;
+            (*(*snapshot).next).prev = snapshot;
+        }
+        snapshot
+    }
+
+    // Removes a SnapshotImpl from this list.
+    //
+    // The snapshot must have been created by calling new_snapshot() on this list.
+    pub(crate) fn delete(&mut self, snapshot: *const SnapshotImpl) {
+        let snapshot = snapshot as *mut SnapshotImpl;
+        unsafe {
+            #[cfg(debug_assertions)]
+            assert!(std::ptr::eq((*snapshot).list, &*self.head));
+            (*(*snapshot).prev).next = (*snapshot).next;
+            (*(*snapshot).next).prev = (*snapshot).prev;
+            drop(Box::from_raw(snapshot));
+        }
+    }
+}
+
+impl Drop for SnapshotList {
+    fn drop(&mut self) {
+        // NOTE: C++ requires every snapshot to be released before the DB is deleted; here the
+        // handles may outlive the DB, so the snapshots still in the list are freed with it
+        while !self.is_empty() {
+            let oldest = self.head.next;
+            self.delete(oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list() {
+        let mut list = SnapshotList::new();
+        assert!(list.is_empty());
+
+        let s1 = list.new_snapshot(1);
+        let s2 = list.new_snapshot(5);
+        let s3 = list.new_snapshot(5);
+        assert!(!list.is_empty());
+        assert_eq!(1, list.oldest().sequence_number());
+        assert_eq!(5, list.newest().sequence_number());
+
+        list.delete(s1);
+        assert_eq!(5, list.oldest().sequence_number());
+        list.delete(s3);
+        assert!(std::ptr::eq(s2, list.oldest()));
+        assert!(std::ptr::eq(s2, list.newest()));
+        list.delete(s2);
+        assert!(list.is_empty());
+
+        // Snapshots still in the list are freed with it
+        list.new_snapshot(7);
+        list.new_snapshot(8);
+    }
+}
@@ -0,0 +1,212 @@
+// leveldb/db/table_cache.{h,cc}
+//
+// Thread-safe (provides internal synchronization)
+
+use crate::{
+    db::filename::{sst_table_file_name, table_file_name},
+    env::Env,
+    iter::{new_error_iter, Iter},
+    options::{Options, ReadOptions},
+    slice::Slice,
+    status::{Result, Status},
+    table::Table,
+    util::{
+        cache::{new_lru_cache, Cache, Handle},
+        coding::encode_fixed64,
+    },
+};
+use std::cell::Cell;
+use std::sync::Arc;
+
+// NOTE: C++ builds the key in a stack buffer; the caller keeps the buffer alive for the slice
+fn encode_key(buf: &mut [u8; 8], file_number: u64) -> Slice<'_> {
+    encode_fixed64(buf, file_number);
+    Slice::from(&buf[..])
+}
+
+// NOTE: C++ caches a TableAndFile pair; the Table owns its RandomAccessFile here, so the file is
+// closed once the table is evicted and no handle or iterator still uses it
+pub(crate) struct TableCache {
+    env: Arc<dyn Env>,
+    dbname: String,
+    options: Options,
+    cache: Arc<dyn Cache<Table>>,
+}
+
+impl TableCache {
+    pub(crate) fn new(dbname: &str, options: &Options, entries: usize) -> Self {
+        Self {
+            env: Arc::clone(&options.env),
+            dbname: dbname.to_owned(),
+            options: options.clone(),
+            cache: new_lru_cache(entries),
+        }
+    }
+
+    // Return an iterator for the specified file number (the corresponding file length must be
+    // exactly "file_size" bytes). The iterator holds the table's cache entry until it is dropped.
+    // NOTE: C++ can also return the underlying Table through an out parameter
+    pub(crate) fn new_iterator(
+        &self,
+        options: &ReadOptions,
+        file_number: u64,
+        file_size: u64,
+    ) -> Box<dyn Iter> {
+        let handle = match self.find_table(file_number, file_size) {
+            Ok(handle) => handle,
+            Err(s) => return new_error_iter(s),
+        };
+
+        let mut result = handle.value().new_iterator(options);
+        let handle = Cell::new(Some(handle));
+        result.register_cleanup(
+            Box::new(move |_, _| drop(handle.take())),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        result
+    }
+
+    // If a seek to internal key "k" in specified file finds an entry, call
+    // handle_result(found_key, found_value).
+    pub(crate) fn get(
+        &self,
+        options: &ReadOptions,
+        file_number: u64,
+        file_size: u64,
+        k: &Slice,
+        handle_result: &mut dyn FnMut(&Slice, &Slice),
+    ) -> Status {
+        match self.find_table(file_number, file_size) {
+            Ok(handle) => handle.internal_get(options, k, handle_result),
+            Err(s) => s.into(),
+        }
+    }
+
+    // Evict any entry for the specified file number
+    pub(crate) fn evict(&self, file_number: u64) {
+        let mut buf = [0u8; 8];
+        self.cache.erase(&encode_key(&mut buf, file_number));
+    }
+
+    // NOTE: C++ returns the handle through an out parameter
+    fn find_table(&self, file_number: u64, file_size: u64) -> Result<Handle<Table>> {
+        let mut buf = [0u8; 8];
+        let key = encode_key(&mut buf, file_number);
+        if let Some(handle) = self.cache.lookup(&key) {
+            return Ok(handle);
+        }
+
+        let fname = table_file_name(&self.dbname, file_number);
+        let file = match self.env.new_random_access_file(&fname) {
+            Ok(file) => file,
+            Err(s) => {
+                let old_fname = sst_table_file_name(&self.dbname, file_number);
+                match self.env.new_random_access_file(&old_fname) {
+                    Ok(file) => file,
+                    Err(_) => return Err(s),
+                }
+            }
+        };
+        // We do not cache error results so that if the error is transient, or somebody repairs
+        // the file, we recover automatically.
+        let table = Table::open(&self.options, file, file_size)?;
+        Ok(self.cache.insert(&key, Arc::new(table), 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::helpers::memenv::new_mem_env;
+    use crate::table::TableBuilder;
+
+    const DBNAME: &str = "/table_cache_test";
+
+    // Writes a single-entry table for "file_number" and returns its size.
+    fn build(env: &dyn Env, options: &Options, file_number: u64) -> u64 {
+        let fname = table_file_name(DBNAME, file_number);
+        let mut file = env.new_writable_file(&fname).unwrap();
+        let mut builder = TableBuilder::new(options, &mut *file);
+        builder.add(
+            &"key".into(),
+            &format!("value{}", file_number).as_str().into(),
+        );
+        assert!(builder.finish().is_ok());
+        let size = builder.file_size();
+        drop(builder);
+        assert!(file.close().is_ok());
+        size
+    }
+
+    fn get(cache: &TableCache, file_number: u64, file_size: u64) -> String {
+        let mut result = String::new();
+        let s = cache.get(
+            &ReadOptions::default(),
+            file_number,
+            file_size,
+            &"key".into(),
+            &mut |_, v| result = String::from_utf8_lossy(v).into_owned(),
+        );
+        if s.ok() {
+            result
+        } else {
+            s.to_string()
+        }
+    }
+
+    #[test]
+    fn test_cached_table_survives_file_removal() {
+        let env = new_mem_env(default_env());
+        let options = Options {
+            env: Arc::clone(&env),
+            ..Default::default()
+        };
+        let size = build(&*env, &options, 1);
+        let cache = TableCache::new(DBNAME, &options, 100);
+
+        assert_eq!("value1", get(&cache, 1, size));
+        let mut iter = cache.new_iterator(&ReadOptions::default(), 1, size);
+
+        // The open table is served from the cache once its file is gone
+        assert!(env.remove_file(&table_file_name(DBNAME, 1)).is_ok());
+        assert_eq!("value1", get(&cache, 1, size));
+
+        // Evicting closes the cached table, but the iterator still holds it
+        cache.evict(1);
+        assert!(!get(&cache, 1, size).starts_with("value"));
+        iter.seek_to_first();
+        assert!(iter.valid());
+        assert_eq!(b"value1", &*iter.value());
+    }
+
+    #[test]
+    fn test_zero_capacity_does_not_cache() {
+        let env = new_mem_env(default_env());
+        let options = Options {
+            env: Arc::clone(&env),
+            ..Default::default()
+        };
+        let size = build(&*env, &options, 2);
+        let cache = TableCache::new(DBNAME, &options, 0);
+
+        assert_eq!("value2", get(&cache, 2, size));
+        assert!(env.remove_file(&table_file_name(DBNAME, 2)).is_ok());
+        assert!(!get(&cache, 2, size).starts_with("value"));
+    }
+
+    #[test]
+    fn test_missing_file_is_not_cached() {
+        let env = new_mem_env(default_env());
+        let options = Options {
+            env: Arc::clone(&env),
+            ..Default::default()
+        };
+        let cache = TableCache::new(DBNAME, &options, 100);
+
+        assert!(!get(&cache, 3, 100).starts_with("value"));
+        let size = build(&*env, &options, 3);
+        assert_eq!("value3", get(&cache, 3, size));
+    }
+}
@@ -0,0 +1,451 @@
+// leveldb/db/version_edit.{h,cc}
+// leveldb/db/version_edit_test.cc
+
+use crate::{
+    db::dbformat::{config, InternalKey, SequenceNumber},
+    slice::Slice,
+    status::Status,
+    util::coding::{
+        get_length_prefixed_slice, get_varint32, get_varint64, put_length_prefixed_slice,
+        put_varint32, put_varint64,
+    },
+};
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+pub(crate) struct FileMetaData {
+    // NOTE: C++ keeps a manual reference count; files are shared between versions through Arcs
+
+    // Seeks allowed until compaction
+    // NOTE: Atomic since the file is shared by every version that contains it
+    pub(crate) allowed_seeks: AtomicI32,
+    pub(crate) number: u64,
+    // File size in bytes
+    pub(crate) file_size: u64,
+    // Smallest internal key served by table
+    pub(crate) smallest: InternalKey,
+    // Largest internal key served by table
+    pub(crate) largest: InternalKey,
+}
+
+impl FileMetaData {
+    pub(crate) fn new() -> Self {
+        Self {
+            allowed_seeks: AtomicI32::new(1 << 30),
+            number: 0,
+            file_size: 0,
+            smallest: InternalKey::default(),
+            largest: InternalKey::default(),
+        }
+    }
+}
+
+impl Default for FileMetaData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for FileMetaData {
+    fn clone(&self) -> Self {
+        Self {
+            allowed_seeks: AtomicI32::new(self.allowed_seeks.load(Ordering::Relaxed)),
+            number: self.number,
+            file_size: self.file_size,
+            smallest: self.smallest.clone(),
+            largest: self.largest.clone(),
+        }
+    }
+}
+
+// Tag numbers for serialized VersionEdit. These numbers are written to disk and should not be
+// changed.
+const COMPARATOR: u32 = 1;
+const LOG_NUMBER: u32 = 2;
+const NEXT_FILE_NUMBER: u32 = 3;
+const LAST_SEQUENCE: u32 = 4;
+const COMPACT_POINTER: u32 = 5;
+const DELETED_FILE: u32 = 6;
+const NEW_FILE: u32 = 7;
+// 8 was used for large value refs
+const PREV_LOG_NUMBER: u32 = 9;
+
+#[derive(Default)]
+pub(crate) struct VersionEdit {
+    pub(super) comparator: String,
+    pub(super) log_number: u64,
+    pub(super) prev_log_number: u64,
+    pub(super) next_file_number: u64,
+    pub(super) last_sequence: SequenceNumber,
+    pub(super) has_comparator: bool,
+    pub(super) has_log_number: bool,
+    pub(super) has_prev_log_number: bool,
+    pub(super) has_next_file_number: bool,
+    pub(super) has_last_sequence: bool,
+
+    pub(super) compact_pointers: Vec<(usize, InternalKey)>,
+    pub(super) deleted_files: BTreeSet<(usize, u64)>,
+    pub(super) new_files: Vec<(usize, FileMetaData)>,
+}
+
+impl VersionEdit {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub(crate) fn set_comparator_name(&mut self, name: &str) {
+        self.has_comparator = true;
+        self.comparator = name.to_owned();
+    }
+
+    pub(crate) fn set_log_number(&mut self, num: u64) {
+        self.has_log_number = true;
+        self.log_number = num;
+    }
+
+    pub(crate) fn set_prev_log_number(&mut self, num: u64) {
+        self.has_prev_log_number = true;
+        self.prev_log_number = num;
+    }
+
+    pub(crate) fn set_next_file(&mut self, num: u64) {
+        self.has_next_file_number = true;
+        self.next_file_number = num;
+    }
+
+    pub(crate) fn set_last_sequence(&mut self, seq: SequenceNumber) {
+        self.has_last_sequence = true;
+        self.last_sequence = seq;
+    }
+
+    pub(crate) fn set_compact_pointer(&mut self, level: usize, key: &InternalKey) {
+        self.compact_pointers.push((level, key.clone()));
+    }
+
+    // Add the specified file at the specified number.
+    // REQUIRES: This version has not been saved (see VersionSet::save_to)
+    // REQUIRES: "smallest" and "largest" are smallest and largest keys in file
+    pub(crate) fn add_file(
+        &mut self,
+        level: usize,
+        file: u64,
+        file_size: u64,
+        smallest: &InternalKey,
+        largest: &InternalKey,
+    ) {
+        let f = FileMetaData {
+            number: file,
+            file_size,
+            smallest: smallest.clone(),
+            largest: largest.clone(),
+            ..FileMetaData::new()
+        };
+        self.new_files.push((level, f));
+    }
+
+    // Delete the specified "file" from the specified "level".
+    pub(crate) fn remove_file(&mut self, level: usize, file: u64) {
+        self.deleted_files.insert((level, file));
+    }
+
+    pub(crate) fn encode_to(&self, dst: &mut Vec<u8>) {
+        if self.has_comparator {
+            put_varint32(dst, COMPARATOR);
+            put_length_prefixed_slice(dst, &Slice::from(self.comparator.as_str()));
+        }
+        if self.has_log_number {
+            put_varint32(dst, LOG_NUMBER);
+            put_varint64(dst, self.log_number);
+        }
+        if self.has_prev_log_number {
+            put_varint32(dst, PREV_LOG_NUMBER);
+            put_varint64(dst, self.prev_log_number);
+        }
+        if self.has_next_file_number {
+            put_varint32(dst, NEXT_FILE_NUMBER);
+            put_varint64(dst, self.next_file_number);
+        }
+        if self.has_last_sequence {
+            put_varint32(dst, LAST_SEQUENCE);
+            put_varint64(dst, self.last_sequence);
+        }
+
+        for (level, key) in &self.compact_pointers {
+            put_varint32(dst, COMPACT_POINTER);
+            put_varint32(dst, *level as u32); // level
+            put_length_prefixed_slice(dst, &key.encode());
+        }
+
+        for (level, number) in &self.deleted_files {
+            put_varint32(dst, DELETED_FILE);
+            put_varint32(dst, *level as u32); // level
+            put_varint64(dst, *number);
+        }
+
+        for (level, f) in &self.new_files {
+            put_varint32(dst, NEW_FILE);
+            put_varint32(dst, *level as u32); // level
+            put_varint64(dst, f.number);
+            put_varint64(dst, f.file_size);
+            put_length_prefixed_slice(dst, &f.smallest.encode());
+            put_length_prefixed_slice(dst, &f.largest.encode());
+        }
+    }
+
+    pub(crate) fn decode_from(&mut self, src: &Slice) -> Status {
+        self.clear();
+        let mut input = *src;
+        let mut msg = None;
+        let mut tag = 0;
+
+        while msg.is_none() && get_varint32(&mut input, &mut tag) {
+            match tag {
+                COMPARATOR => {
+                    let mut str = Slice::new();
+                    if get_length_prefixed_slice(&mut input, &mut str) {
+                        self.comparator = String::from_utf8_lossy(str.as_bytes()).into_owned();
+                        self.has_comparator = true;
+                    } else {
+                        msg = Some("comparator name");
+                    }
+                }
+
+                LOG_NUMBER => {
+                    if get_varint64(&mut input, &mut self.log_number) {
+                        self.has_log_number = true;
+                    } else {
+                        msg = Some("log number");
+                    }
+                }
+
+                PREV_LOG_NUMBER => {
+                    if get_varint64(&mut input, &mut self.prev_log_number) {
+                        self.has_prev_log_number = true;
+                    } else {
+                        msg = Some("previous log number");
+                    }
+                }
+
+                NEXT_FILE_NUMBER => {
+                    if get_varint64(&mut input, &mut self.next_file_number) {
+                        self.has_next_file_number = true;
+                    } else {
+                        msg = Some("next file number");
+                    }
+                }
+
+                LAST_SEQUENCE => {
+                    if get_varint64(&mut input, &mut self.last_sequence) {
+                        self.has_last_sequence = true;
+                    } else {
+                        msg = Some("last sequence number");
+                    }
+                }
+
+                COMPACT_POINTER => {
+                    let mut level = 0;
+                    let mut key = InternalKey::default();
+                    if get_level(&mut input, &mut level) && get_internal_key(&mut input, &mut key) {
+                        self.compact_pointers.push((level, key));
+                    } else {
+                        msg = Some("compaction pointer");
+                    }
+                }
+
+                DELETED_FILE => {
+                    let mut level = 0;
+                    let mut number = 0;
+                    if get_level(&mut input, &mut level) && get_varint64(&mut input, &mut number) {
+                        self.deleted_files.insert((level, number));
+                    } else {
+                        msg = Some("deleted file");
+                    }
+                }
+
+                NEW_FILE => {
+                    let mut level = 0;
+                    let mut f = FileMetaData::new();
+                    if get_level(&mut input, &mut level)
+                        && get_varint64(&mut input, &mut f.number)
+                        && get_varint64(&mut input, &mut f.file_size)
+                        && get_internal_key(&mut input, &mut f.smallest)
+                        && get_internal_key(&mut input, &mut f.largest)
+                    {
+                        self.new_files.push((level, f));
+                    } else {
+                        msg = Some("new-file entry");
+                    }
+                }
+
+                _ => msg = Some("unknown tag"),
+            }
+        }
+
+        if msg.is_none() && !input.empty() {
+            msg = Some("invalid tag");
+        }
+
+        match msg {
+            Some(msg) => Status::corruption(&"VersionEdit".into(), &msg.into()),
+            None => Status::OK(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn debug_string(&self) -> String {
+        let mut r = String::from("VersionEdit {");
+        if self.has_comparator {
+            r.push_str("\n  Comparator: ");
+            r.push_str(&self.comparator);
+        }
+        if self.has_log_number {
+            r.push_str(&format!("\n  LogNumber: {}", self.log_number));
+        }
+        if self.has_prev_log_number {
+            r.push_str(&format!("\n  PrevLogNumber: {}", self.prev_log_number));
+        }
+        if self.has_next_file_number {
+            r.push_str(&format!("\n  NextFile: {}", self.next_file_number));
+        }
+        if self.has_last_sequence {
+            r.push_str(&format!("\n  LastSeq: {}", self.last_sequence));
+        }
+        for (level, key) in &self.compact_pointers {
+            r.push_str(&format!(
+                "\n  CompactPointer: {} {}",
+                level,
+                key.debug_string()
+            ));
+        }
+        for (level, number) in &self.deleted_files {
+            r.push_str(&format!("\n  RemoveFile: {} {}", level, number));
+        }
+        for (level, f) in &self.new_files {
+            r.push_str(&format!(
+                "\n  AddFile: {} {} {} {} .. {}",
+                level,
+                f.number,
+                f.file_size,
+                f.smallest.debug_string(),
+                f.largest.debug_string()
+            ));
+        }
+        r.push_str("\n}\n");
+        r
+    }
+}
+
+fn get_internal_key(input: &mut Slice, dst: &mut InternalKey) -> bool {
+    let mut str = Slice::new();
+    if get_length_prefixed_slice(input, &mut str) {
+        dst.decode_from(&str)
+    } else {
+        false
+    }
+}
+
+fn get_level(input: &mut Slice, level: &mut usize) -> bool {
+    let mut v = 0;
+    if get_varint32(input, &mut v) && (v as usize) < config::NUM_LEVELS {
+        *level = v as usize;
+        true
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::dbformat::ValueType;
+
+    fn encode_decode(edit: &VersionEdit) {
+        let mut encoded = Vec::new();
+        let mut encoded2 = Vec::new();
+        edit.encode_to(&mut encoded);
+        let mut parsed = VersionEdit::new();
+        let s = parsed.decode_from(&Slice::from(&encoded));
+        assert!(s.ok(), "{}", s);
+        parsed.encode_to(&mut encoded2);
+        assert_eq!(encoded, encoded2);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        const BIG: u64 = 1 << 50;
+
+        let mut edit = VersionEdit::new();
+        for i in 0..4 {
+            encode_decode(&edit);
+            edit.add_file(
+                3,
+                BIG + 300 + i,
+                BIG + 400 + i,
+                &InternalKey::new(&"foo".into(), BIG + 500 + i, ValueType::Value),
+                &InternalKey::new(&"zoo".into(), BIG + 600 + i, ValueType::Deletion),
+            );
+            edit.remove_file(4, BIG + 700 + i);
+            edit.set_compact_pointer(
+                i as usize,
+                &InternalKey::new(&"x".into(), BIG + 900 + i, ValueType::Value),
+            );
+        }
+
+        edit.set_comparator_name("foo");
+        edit.set_log_number(BIG + 100);
+        edit.set_next_file(BIG + 200);
+        edit.set_last_sequence(BIG + 1000);
+        encode_decode(&edit);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut edit = VersionEdit::new();
+        edit.set_log_number(5);
+        edit.add_file(
+            1,
+            7,
+            100,
+            &InternalKey::new(&"a".into(), 1, ValueType::Value),
+            &InternalKey::new(&"b".into(), 2, ValueType::Value),
+        );
+        let mut encoded = Vec::new();
+        edit.encode_to(&mut encoded);
+
+        // Truncated records are rejected
+        let mut parsed = VersionEdit::new();
+        let s = parsed.decode_from(&Slice::from(&encoded[..encoded.len() - 1]));
+        assert!(s.is_corruption(), "{}", s);
+        assert!(s.to_string().contains("new-file entry"), "{}", s);
+
+        // So are unknown tags and levels past the last one
+        assert!(parsed.decode_from(&Slice::from("\x08\x01")).is_corruption());
+        assert!(parsed
+            .decode_from(&Slice::from("\x06\x07\x01"))
+            .is_corruption());
+    }
+
+    #[test]
+    fn test_debug_string() {
+        let mut edit = VersionEdit::new();
+        edit.set_comparator_name("leveldb.BytewiseComparator");
+        edit.set_log_number(3);
+        edit.remove_file(0, 4);
+        edit.add_file(
+            1,
+            5,
+            100,
+            &InternalKey::new(&"a".into(), 1, ValueType::Value),
+            &InternalKey::new(&"b".into(), 2, ValueType::Deletion),
+        );
+        assert_eq!(
+            "VersionEdit {\n  Comparator: leveldb.BytewiseComparator\n  LogNumber: 3\n  \
+             RemoveFile: 0 4\n  AddFile: 1 5 100 'a' @ 1 : 1 .. 'b' @ 2 : 0\n}\n",
+            edit.debug_string()
+        );
+    }
+}
@@ -0,0 +1,2200 @@
+// leveldb/db/version_set.{h,cc}
+// leveldb/db/version_set_test.cc
+//
+// The representation of a DBImpl consists of a set of Versions. The newest version is called
+// "current". Older versions may be kept around to provide a consistent view to live iterators.
+//
+// Each Version keeps track of a set of Table files per level. The entire set of versions is
+// maintained in a VersionSet.
+//
+// Version,VersionSet are thread-compatible, but require external synchronization on all
+// accesses.
+
+use crate::{
+    comparator::Comparator,
+    db::{
+        dbformat::{
+            config, parse_internal_key, InternalKey, InternalKeyComparator, LookupKey,
+            ParsedInternalKey, ValueType, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK,
+        },
+        filename::{descriptor_file_name, parse_file_name, set_current_file, FileType},
+        log,
+        table_cache::TableCache,
+        version_edit::{FileMetaData, VersionEdit},
+    },
+    env::{self, Env, Logger},
+    iter::{new_error_iter, CleanupNode, Iter},
+    options::{Options, ReadOptions},
+    slice::Slice,
+    status::{Error, Result, Status},
+    table::{merger::new_merging_iterator, two_level_iterator::new_two_level_iterator},
+    util::coding::{decode_fixed64, encode_fixed64},
+};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use std::sync::atomic;
+use std::sync::{Arc, Mutex, Weak};
+
+// NOTE: C++ passes the Options to the helpers below; only max_file_size is used
+fn target_file_size(max_file_size: usize) -> u64 {
+    max_file_size as u64
+}
+
+// Maximum bytes of overlaps in grandparent (i.e., level+2) before we stop building a single file
+// in a level->level+1 compaction.
+fn max_grand_parent_overlap_bytes(max_file_size: usize) -> u64 {
+    10 * target_file_size(max_file_size)
+}
+
+// Maximum number of bytes in all compacted files. We avoid expanding the lower level file set of
+// a compaction if it would make the total compaction cover more than this many bytes.
+fn expanded_compaction_byte_size_limit(max_file_size: usize) -> u64 {
+    25 * target_file_size(max_file_size)
+}
+
+fn max_bytes_for_level(mut level: usize) -> f64 {
+    // Note: the result for level zero is not really used since we set the level-0 compaction
+    // threshold based on number of files.
+
+    // Result for both level-0 and level-1
+    let mut result = 10. * 1048576.0;
+    while level > 1 {
+        result *= 10.;
+        level -= 1;
+    }
+    result
+}
+
+fn max_file_size_for_level(max_file_size: usize, _level: usize) -> u64 {
+    // We could vary per level to reduce number of files?
+    target_file_size(max_file_size)
+}
+
+fn total_file_size(files: &[Arc<FileMetaData>]) -> u64 {
+    files.iter().map(|f| f.file_size).sum()
+}
+
+// Return the smallest index i such that files[i].largest >= key.
+// Return files.len() if there is no such file.
+// REQUIRES: "files" contains a sorted list of non-overlapping files.
+pub(crate) fn find_file(
+    icmp: &InternalKeyComparator,
+    files: &[Arc<FileMetaData>],
+    key: &Slice,
+) -> usize {
+    let mut left = 0;
+    let mut right = files.len();
+    while left < right {
+        let mid = (left + right) / 2;
+        let f = &files[mid];
+        if icmp.compare(&f.largest.encode(), key) < 0 {
+            // Key at "mid.largest" is < "target". Therefore all files at or before "mid" are
+            // uninteresting.
+            left = mid + 1;
+        } else {
+            // Key at "mid.largest" is >= "target". Therefore all files after "mid" are
+            // uninteresting.
+            right = mid;
+        }
+    }
+    right
+}
+
+fn after_file(ucmp: &dyn Comparator, user_key: Option<&Slice>, f: &FileMetaData) -> bool {
+    // None user_key occurs before all keys and is therefore never after *f
+    user_key.is_some_and(|user_key| ucmp.compare(user_key, &f.largest.user_key()) > 0)
+}
+
+fn before_file(ucmp: &dyn Comparator, user_key: Option<&Slice>, f: &FileMetaData) -> bool {
+    // None user_key occurs after all keys and is therefore never before *f
+    user_key.is_some_and(|user_key| ucmp.compare(user_key, &f.smallest.user_key()) < 0)
+}
+
+// Returns true iff some file in "files" overlaps the user key range
+// [*smallest_user_key,*largest_user_key].
+// smallest_user_key==None represents a key smaller than all keys in the DB.
+// largest_user_key==None represents a key largest than all keys in the DB.
+// REQUIRES: If disjoint_sorted_files, files[] contains disjoint ranges in sorted order.
+pub(crate) fn some_file_overlaps_range(
+    icmp: &InternalKeyComparator,
+    disjoint_sorted_files: bool,
+    files: &[Arc<FileMetaData>],
+    smallest_user_key: Option<&Slice>,
+    largest_user_key: Option<&Slice>,
+) -> bool {
+    let ucmp = &**icmp.user_comparator();
+    if !disjoint_sorted_files {
+        // Need to check against all files
+        return files.iter().any(|f| {
+            !(after_file(ucmp, smallest_user_key, f) || before_file(ucmp, largest_user_key, f))
+        });
+    }
+
+    // Binary search over file list
+    let mut index = 0;
+    if let Some(smallest_user_key) = smallest_user_key {
+        // Find the earliest possible internal key for smallest_user_key
+        let small_key =
+            InternalKey::new(smallest_user_key, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK);
+        index = find_file(icmp, files, &small_key.encode());
+    }
+
+    if index >= files.len() {
+        // beginning of range is after all files, so no overlap.
+        return false;
+    }
+
+    !before_file(ucmp, largest_user_key, &files[index])
+}
+
+// An internal iterator. For a given version/level pair, yields information about the files in
+// the level. For a given entry, key() is the largest key that occurs in the file, and value() is
+// a 16-byte value containing the file number and file size, both encoded using encode_fixed64.
+// NOTE: C++ borrows the version's file list; the list is copied here so the iterator can outlive
+// the borrow
+struct LevelFileNumIterator {
+    icmp: InternalKeyComparator,
+    flist: Vec<Arc<FileMetaData>>,
+    index: usize,
+    // Backing store for value(). Holds the file number and size.
+    // NOTE: C++ fills it in value() through a mutable member; it is filled whenever the iterator
+    // moves instead
+    value_buf: [u8; 16],
+    cleanup_head: CleanupNode,
+}
+
+impl LevelFileNumIterator {
+    fn new(icmp: &InternalKeyComparator, flist: &[Arc<FileMetaData>]) -> Self {
+        Self {
+            icmp: icmp.clone(),
+            flist: flist.to_vec(),
+            // Marks as invalid
+            index: flist.len(),
+            value_buf: [0; 16],
+            cleanup_head: CleanupNode::default(),
+        }
+    }
+
+    fn fill_value_buf(&mut self) {
+        if let Some(f) = self.flist.get(self.index) {
+            encode_fixed64(&mut self.value_buf, f.number);
+            encode_fixed64(&mut self.value_buf[8..], f.file_size);
+        }
+    }
+}
+
+impl Iter for LevelFileNumIterator {
+    fn cleanup_head(&mut self) -> &mut CleanupNode {
+        &mut self.cleanup_head
+    }
+
+    fn valid(&self) -> bool {
+        self.index < self.flist.len()
+    }
+
+    fn seek(&mut self, target: &Slice) {
+        self.index = find_file(&self.icmp, &self.flist, target);
+        self.fill_value_buf();
+    }
+
+    fn seek_to_first(&mut self) {
+        self.index = 0;
+        self.fill_value_buf();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.index = self.flist.len().saturating_sub(1);
+        self.fill_value_buf();
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.index += 1;
+        self.fill_value_buf();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        if self.index == 0 {
+            // Marks as invalid
+            self.index = self.flist.len();
+        } else {
+            self.index -= 1;
+        }
+        self.fill_value_buf();
+    }
+
+    fn key(&self) -> Slice<'_> {
+        assert!(self.valid());
+        self.flist[self.index].largest.encode()
+    }
+
+    fn value(&self) -> Slice<'_> {
+        assert!(self.valid());
+        Slice::from(&self.value_buf[..])
+    }
+
+    fn status(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LevelFileNumIterator {
+    fn drop(&mut self) {
+        self.drop_func();
+    }
+}
+
+fn get_file_iterator(
+    cache: &TableCache,
+    options: &ReadOptions,
+    file_value: &Slice,
+) -> Box<dyn Iter> {
+    if file_value.size() != 16 {
+        new_error_iter(Error::corruption(
+            &"FileReader invoked with unexpected value".into(),
+            &Slice::new(),
+        ))
+    } else {
+        cache.new_iterator(
+            options,
+            decode_fixed64(file_value),
+            decode_fixed64(&file_value[8..]),
+        )
+    }
+}
+
+// Outcome of looking up a user key in a single table
+// NOTE: C++ keeps this in the Saver struct passed to SaveValue()
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SaverState {
+    NotFound,
+    Found,
+    Deleted,
+    Corrupt,
+}
+
+fn save_value(
+    ucmp: &dyn Comparator,
+    user_key: &Slice,
+    state: &mut SaverState,
+    value: &mut Vec<u8>,
+    ikey: &Slice,
+    v: &Slice,
+) {
+    let mut parsed_key = ParsedInternalKey::default();
+    if !parse_internal_key(ikey, &mut parsed_key) {
+        *state = SaverState::Corrupt;
+    } else if ucmp.compare(&parsed_key.user_key, user_key) == 0 {
+        *state = if parsed_key.value_type == ValueType::Value {
+            SaverState::Found
+        } else {
+            SaverState::Deleted
+        };
+        if *state == SaverState::Found {
+            value.clear();
+            value.extend_from_slice(v.as_bytes());
+        }
+    }
+}
+
+pub(crate) struct Version {
+    // NOTE: C++ reaches these through the VersionSet to which this Version belongs
+    icmp: InternalKeyComparator,
+    max_file_size: usize,
+    table_cache: Arc<TableCache>,
+
+    // List of files per level
+    pub(super) files: [Vec<Arc<FileMetaData>>; config::NUM_LEVELS],
+
+    // Level that should be compacted next and its compaction score. Score < 1 means compaction
+    // is not strictly needed. These fields are initialized by finalize().
+    pub(super) compaction_score: f64,
+    pub(super) compaction_level: i32,
+
+    // Next file to compact based on seek stats, along with its level.
+    // NOTE: C++ updates these under the DB mutex; a Version is shared through Arcs here, so they
+    // have their own lock
+    file_to_compact: Mutex<Option<(Arc<FileMetaData>, usize)>>,
+}
+
+// NOTE: C++ declares this inside of Version
+#[derive(Default)]
+pub(crate) struct GetStats {
+    pub(crate) seek_file: Option<Arc<FileMetaData>>,
+    pub(crate) seek_file_level: usize,
+}
+
+impl Version {
+    fn new(vset: &VersionSet) -> Self {
+        Self {
+            icmp: vset.icmp.clone(),
+            max_file_size: vset.max_file_size,
+            table_cache: Arc::clone(&vset.table_cache),
+            files: Default::default(),
+            compaction_score: -1.,
+            compaction_level: -1,
+            file_to_compact: Mutex::new(None),
+        }
+    }
+
+    // Append to *iters a sequence of iterators that will yield the contents of this Version when
+    // merged together.
+    // REQUIRES: This version has been saved (see VersionSet::save_to)
+    pub(crate) fn add_iterators(&self, options: &ReadOptions, iters: &mut Vec<Box<dyn Iter>>) {
+        // Merge all level zero files together since they may overlap
+        for f in &self.files[0] {
+            iters.push(
+                self.table_cache
+                    .new_iterator(options, f.number, f.file_size),
+            );
+        }
+
+        // For levels > 0, we can use a concatenating iterator that sequentially walks through the
+        // non-overlapping files in the level, opening them lazily.
+        for level in 1..config::NUM_LEVELS {
+            if !self.files[level].is_empty() {
+                iters.push(self.new_concatenating_iterator(options, level));
+            }
+        }
+    }
+
+    fn new_concatenating_iterator(&self, options: &ReadOptions, level: usize) -> Box<dyn Iter> {
+        let table_cache = Arc::clone(&self.table_cache);
+        new_two_level_iterator(
+            Box::new(LevelFileNumIterator::new(&self.icmp, &self.files[level])),
+            Box::new(move |options, file_value| {
+                get_file_iterator(&table_cache, options, file_value)
+            }),
+            options,
+        )
+    }
+
+    // Lookup the value for key. If found, store it in *value and return OK. Else return a non-OK
+    // status. Fills *stats.
+    // REQUIRES: lock is not held
+    pub(crate) fn get(
+        &self,
+        options: &ReadOptions,
+        k: &LookupKey,
+        value: &mut Vec<u8>,
+        stats: &mut GetStats,
+    ) -> Status {
+        stats.seek_file = None;
+        stats.seek_file_level = 0;
+
+        let ikey = k.internal_key();
+        let user_key = k.user_key();
+        let ucmp = self.icmp.user_comparator();
+        let mut s = Status::OK();
+        let mut found = false;
+        let mut last_file_read: Option<(Arc<FileMetaData>, usize)> = None;
+
+        self.for_each_overlapping(&user_key, &ikey, &mut |level, f| {
+            if stats.seek_file.is_none() {
+                if let Some((last_file, last_level)) = last_file_read.take() {
+                    // We have had more than one seek for this read. Charge the 1st file.
+                    stats.seek_file = Some(last_file);
+                    stats.seek_file_level = last_level;
+                }
+            }
+            last_file_read = Some((Arc::clone(f), level));
+
+            let mut state = SaverState::NotFound;
+            s = self.table_cache.get(
+                options,
+                f.number,
+                f.file_size,
+                &ikey,
+                &mut |found_key, v| save_value(&**ucmp, &user_key, &mut state, value, found_key, v),
+            );
+            if !s.ok() {
+                found = true;
+                return false;
+            }
+            match state {
+                // Keep searching in other files
+                SaverState::NotFound => true,
+                SaverState::Found => {
+                    found = true;
+                    false
+                }
+                SaverState::Deleted => false,
+                SaverState::Corrupt => {
+                    s = Status::corruption(&"corrupted key for ".into(), &user_key);
+                    found = true;
+                    false
+                }
+            }
+        });
+
+        if found {
+            s
+        } else {
+            Status::not_found(&Slice::new(), &Slice::new())
+        }
+    }
+
+    // Adds "stats" into the current state. Returns true if a new compaction may need to be
+    // triggered, false otherwise.
+    // REQUIRES: lock is held
+    pub(crate) fn update_stats(&self, stats: &GetStats) -> bool {
+        if let Some(f) = &stats.seek_file {
+            let allowed_seeks = f.allowed_seeks.fetch_sub(1, atomic::Ordering::Relaxed) - 1;
+            let mut file_to_compact = self.file_to_compact.lock().unwrap();
+            if allowed_seeks <= 0 && file_to_compact.is_none() {
+                *file_to_compact = Some((Arc::clone(f), stats.seek_file_level));
+                return true;
+            }
+        }
+        false
+    }
+
+    // Record a sample of bytes read at the specified internal key. Samples are taken
+    // approximately once every config::READ_BYTES_PERIOD bytes. Returns true if a new compaction
+    // may need to be triggered.
+    // REQUIRES: lock is held
+    pub(crate) fn record_read_sample(&self, internal_key: &Slice) -> bool {
+        let mut ikey = ParsedInternalKey::default();
+        if !parse_internal_key(internal_key, &mut ikey) {
+            return false;
+        }
+
+        let mut stats = GetStats::default();
+        let mut matches = 0;
+        self.for_each_overlapping(&ikey.user_key, internal_key, &mut |level, f| {
+            matches += 1;
+            if matches == 1 {
+                // Remember first match.
+                stats.seek_file = Some(Arc::clone(f));
+                stats.seek_file_level = level;
+            }
+            // We can stop iterating once we have a second match.
+            matches < 2
+        });
+
+        // Must have at least two matches since we want to merge across files. But what if we
+        // have a single file that contains many overwrites and deletions? Should we have another
+        // mechanism for finding such files?
+        if matches >= 2 {
+            // 1MB cost is about 1 seek (see comment in Builder::apply).
+            return self.update_stats(&stats);
+        }
+        false
+    }
+
+    // Call func(level, f) for every file that overlaps user_key in order from newest to oldest.
+    // If an invocation of func returns false, makes no more calls.
+    //
+    // REQUIRES: user portion of internal_key == user_key.
+    // NOTE: C++ passes a "void* arg" along with a function pointer; a closure is used instead
+    fn for_each_overlapping(
+        &self,
+        user_key: &Slice,
+        internal_key: &Slice,
+        func: &mut dyn FnMut(usize, &Arc<FileMetaData>) -> bool,
+    ) {
+        let ucmp = self.icmp.user_comparator();
+
+        // Search level-0 in order from newest to oldest.
+        let mut tmp: Vec<&Arc<FileMetaData>> = self.files[0]
+            .iter()
+            .filter(|f| {
+                ucmp.compare(user_key, &f.smallest.user_key()) >= 0
+                    && ucmp.compare(user_key, &f.largest.user_key()) <= 0
+            })
+            .collect();
+        if !tmp.is_empty() {
+            // Newest first
+            tmp.sort_by_key(|f| std::cmp::Reverse(f.number));
+            for f in tmp {
+                if !func(0, f) {
+                    return;
+                }
+            }
+        }
+
+        // Search other levels.
+        for level in 1..config::NUM_LEVELS {
+            let num_files = self.files[level].len();
+            if num_files == 0 {
+                continue;
+            }
+
+            // Binary search to find earliest index whose largest key >= internal_key.
+            let index = find_file(&self.icmp, &self.files[level], internal_key);
+            if index < num_files {
+                let f = &self.files[level][index];
+                if ucmp.compare(user_key, &f.smallest.user_key()) < 0 {
+                    // All of "f" is past any data for user_key
+                } else if !func(level, f) {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Returns true iff some file in the specified level overlaps some part of
+    // [*smallest_user_key,*largest_user_key].
+    // smallest_user_key==None represents a key smaller than all the DB's keys.
+    // largest_user_key==None represents a key largest than all the DB's keys.
+    pub(crate) fn overlap_in_level(
+        &self,
+        level: usize,
+        smallest_user_key: Option<&Slice>,
+        largest_user_key: Option<&Slice>,
+    ) -> bool {
+        some_file_overlaps_range(
+            &self.icmp,
+            level > 0,
+            &self.files[level],
+            smallest_user_key,
+            largest_user_key,
+        )
+    }
+
+    // Return the level at which we should place a new memtable compaction result that covers
+    // the range [smallest_user_key,largest_user_key].
+    pub(crate) fn pick_level_for_mem_table_output(
+        &self,
+        smallest_user_key: &Slice,
+        largest_user_key: &Slice,
+    ) -> usize {
+        let mut level = 0;
+        if !self.overlap_in_level(0, Some(smallest_user_key), Some(largest_user_key)) {
+            // Push to next level if there is no overlap in next level, and the #bytes
+            // overlapping in the level after that are limited.
+            let start =
+                InternalKey::new(smallest_user_key, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK);
+            let limit = InternalKey::new(
+                largest_user_key,
+                0,
+                crate::db::dbformat::ValueType::Deletion,
+            );
+            let mut overlaps = Vec::new();
+            while level < config::MAX_MEM_COMPACT_LEVEL {
+                if self.overlap_in_level(level + 1, Some(smallest_user_key), Some(largest_user_key))
+                {
+                    break;
+                }
+                if level + 2 < config::NUM_LEVELS {
+                    // Check that file does not overlap too many grandparent bytes.
+                    self.get_overlapping_inputs(
+                        level + 2,
+                        Some(&start),
+                        Some(&limit),
+                        &mut overlaps,
+                    );
+                    let sum = total_file_size(&overlaps);
+                    if sum > max_grand_parent_overlap_bytes(self.max_file_size) {
+                        break;
+                    }
+                }
+                level += 1;
+            }
+        }
+        level
+    }
+
+    // Store in "*inputs" all files in "level" that overlap [begin,end]
+    // begin==None means before all keys, end==None means after all keys.
+    pub(crate) fn get_overlapping_inputs(
+        &self,
+        level: usize,
+        begin: Option<&InternalKey>,
+        end: Option<&InternalKey>,
+        inputs: &mut Vec<Arc<FileMetaData>>,
+    ) {
+        assert!(level < config::NUM_LEVELS);
+        inputs.clear();
+        let mut user_begin = begin.map(|begin| begin.user_key());
+        let mut user_end = end.map(|end| end.user_key());
+        let user_cmp = self.icmp.user_comparator();
+        let mut i = 0;
+        while i < self.files[level].len() {
+            let f = &self.files[level][i];
+            i += 1;
+            let file_start = f.smallest.user_key();
+            let file_limit = f.largest.user_key();
+            if user_begin.is_some_and(|user_begin| user_cmp.compare(&file_limit, &user_begin) < 0) {
+                // "f" is completely before specified range; skip it
+            } else if user_end.is_some_and(|user_end| user_cmp.compare(&file_start, &user_end) > 0)
+            {
+                // "f" is completely after specified range; skip it
+            } else {
+                inputs.push(Arc::clone(f));
+                if level == 0 {
+                    // Level-0 files may overlap each other. So check if the newly added file has
+                    // expanded the range. If so, restart search.
+                    if user_begin
+                        .is_some_and(|user_begin| user_cmp.compare(&file_start, &user_begin) < 0)
+                    {
+                        user_begin = Some(file_start);
+                        inputs.clear();
+                        i = 0;
+                    } else if user_end
+                        .is_some_and(|user_end| user_cmp.compare(&file_limit, &user_end) > 0)
+                    {
+                        user_end = Some(file_limit);
+                        inputs.clear();
+                        i = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    // Return a human readable string that describes this version's contents.
+    #[cfg(test)]
+    pub(crate) fn debug_string(&self) -> String {
+        let mut r = String::new();
+        for (level, files) in self.files.iter().enumerate() {
+            // E.g.,
+            //   --- level 1 ---
+            //   17:123['a' .. 'd']
+            //   20:43['e' .. 'g']
+            r.push_str(&format!("--- level {} ---\n", level));
+            for f in files {
+                r.push_str(&format!(
+                    " {}:{}[{} .. {}]\n",
+                    f.number,
+                    f.file_size,
+                    f.smallest.debug_string(),
+                    f.largest.debug_string()
+                ));
+            }
+        }
+        r
+    }
+}
+
+// A MANIFEST record waiting to be written by the caller of VersionSet::prepare_log_and_apply().
+// Owns the descriptor log until it is handed back to VersionSet::finish_log_and_apply().
+pub(crate) struct ManifestWrite {
+    // The version installed once the record is written
+    v: Version,
+    descriptor_log: Option<log::Writer>,
+    // Non-empty iff a new descriptor file was created for this record
+    new_manifest_file: String,
+    record: Vec<u8>,
+    status: Status,
+    env: Arc<dyn Env>,
+    dbname: String,
+    manifest_file_number: u64,
+    info_log: Option<Arc<dyn Logger>>,
+}
+
+impl ManifestWrite {
+    // Append the record to the MANIFEST and sync it. If a new descriptor file was created, also
+    // install it by writing a new CURRENT file that points to it.
+    pub(crate) fn write(&mut self) {
+        // Write new record to MANIFEST log
+        if self.status.ok() {
+            let descriptor_log = self.descriptor_log.as_mut().unwrap();
+            self.status = descriptor_log.add_record(&Slice::from(&self.record));
+            if self.status.ok() {
+                self.status = descriptor_log.file().sync().into();
+            }
+            if !self.status.ok() {
+                env::log(
+                    self.info_log.as_deref(),
+                    format_args!("MANIFEST write: {}\n", self.status),
+                );
+            }
+        }
+
+        // If we just created a new descriptor file, install it by writing a new CURRENT file
+        // that points to it.
+        if self.status.ok() && !self.new_manifest_file.is_empty() {
+            self.status = set_current_file(&*self.env, &self.dbname, self.manifest_file_number);
+        }
+    }
+}
+
+pub(crate) struct VersionSet {
+    env: Arc<dyn Env>,
+    dbname: String,
+    // NOTE: C++ keeps a pointer to the DB's options; only the fields used here are copied
+    info_log: Option<Arc<dyn Logger>>,
+    reuse_logs: bool,
+    max_file_size: usize,
+    paranoid_checks: bool,
+    table_cache: Arc<TableCache>,
+    icmp: InternalKeyComparator,
+    next_file_number: u64,
+    manifest_file_number: u64,
+    last_sequence: u64,
+    log_number: u64,
+    // 0 or backing store for memtable being compacted
+    prev_log_number: u64,
+
+    // Opened lazily
+    // NOTE: C++ also keeps the descriptor file, which is owned by the log writer here
+    descriptor_log: Option<log::Writer>,
+    // NOTE: C++ links every live version into a list headed by a dummy version. Weak references
+    // to the versions are kept here instead, and versions that have been dropped are pruned.
+    versions: Vec<Weak<Version>>,
+    // == versions.last()
+    current: Arc<Version>,
+
+    // Per-level key at which the next compaction at that level should start. Either an empty
+    // string, or a valid InternalKey.
+    compact_pointer: [Vec<u8>; config::NUM_LEVELS],
+}
+
+impl VersionSet {
+    pub(crate) fn new(
+        dbname: &str,
+        options: &Options,
+        table_cache: &Arc<TableCache>,
+        cmp: &InternalKeyComparator,
+    ) -> Self {
+        // NOTE: C++ appends a new empty Version here; it is built directly since the set it would
+        // be built from doesn't exist yet
+        let current = Arc::new(Version {
+            icmp: cmp.clone(),
+            max_file_size: options.max_file_size,
+            table_cache: Arc::clone(table_cache),
+            files: Default::default(),
+            compaction_score: -1.,
+            compaction_level: -1,
+            file_to_compact: Mutex::new(None),
+        });
+        Self {
+            env: Arc::clone(&options.env),
+            dbname: dbname.to_owned(),
+            info_log: options.info_log.clone(),
+            reuse_logs: options.reuse_logs,
+            max_file_size: options.max_file_size,
+            paranoid_checks: options.paranoid_checks,
+            table_cache: Arc::clone(table_cache),
+            icmp: cmp.clone(),
+            next_file_number: 2,
+            manifest_file_number: 0, // Filled by recover()
+            last_sequence: 0,
+            log_number: 0,
+            prev_log_number: 0,
+            descriptor_log: None,
+            versions: vec![Arc::downgrade(&current)],
+            current,
+            compact_pointer: Default::default(),
+        }
+    }
+
+    // Apply *edit to the current version to form a new descriptor that is both saved to
+    // persistent state and installed as the new current version. The MANIFEST record is written
+    // by ManifestWrite::write(), which the caller runs without holding the DB mutex, and the new
+    // version is installed by finish_log_and_apply().
+    // REQUIRES: the ManifestWrite of any earlier call has been passed to finish_log_and_apply()
+    // NOTE: C++ does all of this in LogAndApply(), which unlocks the DB mutex it is passed around
+    // the write. The mutex owns the whole VersionSet here, so the call is split around the write.
+    pub(crate) fn prepare_log_and_apply(&mut self, edit: &mut VersionEdit) -> ManifestWrite {
+        if edit.has_log_number {
+            assert!(edit.log_number >= self.log_number);
+            assert!(edit.log_number < self.next_file_number);
+        } else {
+            edit.set_log_number(self.log_number);
+        }
+
+        if !edit.has_prev_log_number {
+            edit.set_prev_log_number(self.prev_log_number);
+        }
+
+        edit.set_next_file(self.next_file_number);
+        edit.set_last_sequence(self.last_sequence);
+
+        let mut v = Version::new(self);
+        {
+            let mut builder = Builder::new(&self.icmp, Arc::clone(&self.current));
+            builder.apply(edit, &mut self.compact_pointer);
+            builder.save_to(&mut v);
+        }
+        Self::finalize(&mut v);
+
+        // Initialize new descriptor log file if necessary by creating a temporary file that
+        // contains a snapshot of the current version.
+        let mut new_manifest_file = String::new();
+        let mut s = Status::OK();
+        if self.descriptor_log.is_none() {
+            // No reason to write this without the DB mutex since we only hit this path in the
+            // first call (when opening the database).
+            new_manifest_file = descriptor_file_name(&self.dbname, self.manifest_file_number);
+            match self.env.new_writable_file(&new_manifest_file) {
+                Ok(descriptor_file) => {
+                    let mut descriptor_log = log::Writer::new(descriptor_file);
+                    s = self.write_snapshot(&mut descriptor_log);
+                    self.descriptor_log = Some(descriptor_log);
+                }
+                Err(status) => s = status.into(),
+            }
+        }
+
+        let mut record = Vec::new();
+        edit.encode_to(&mut record);
+        ManifestWrite {
+            v,
+            descriptor_log: self.descriptor_log.take(),
+            new_manifest_file,
+            record,
+            status: s,
+            env: Arc::clone(&self.env),
+            dbname: self.dbname.clone(),
+            manifest_file_number: self.manifest_file_number,
+            info_log: self.info_log.clone(),
+        }
+    }
+
+    // Install the version prepared by prepare_log_and_apply() for "edit" if its MANIFEST record
+    // was written, and return the status of the write.
+    pub(crate) fn finish_log_and_apply(
+        &mut self,
+        edit: &VersionEdit,
+        manifest_write: ManifestWrite,
+    ) -> Status {
+        let ManifestWrite {
+            v,
+            descriptor_log,
+            new_manifest_file,
+            status: s,
+            ..
+        } = manifest_write;
+        self.descriptor_log = descriptor_log;
+
+        // Install the new version
+        if s.ok() {
+            self.append_version(v);
+            self.log_number = edit.log_number;
+            self.prev_log_number = edit.prev_log_number;
+        } else if !new_manifest_file.is_empty() {
+            self.descriptor_log = None;
+            let _ = self.env.remove_file(&new_manifest_file);
+        }
+
+        s
+    }
+
+    // Recover the last saved descriptor from persistent storage.
+    pub(crate) fn recover(&mut self, save_manifest: &mut bool) -> Status {
+        #[derive(Clone)]
+        struct LogReporter {
+            status: Rc<RefCell<Status>>,
+        }
+
+        impl log::Reporter for LogReporter {
+            fn corruption(&mut self, _bytes: usize, s: &Status) {
+                let mut status = self.status.borrow_mut();
+                if status.ok() {
+                    *status = s.clone();
+                }
+            }
+        }
+
+        // Read "CURRENT" file, which contains a pointer to the current manifest file
+        let mut current = match env::read_file_to_string(
+            &*self.env,
+            &crate::db::filename::current_file_name(&self.dbname),
+        ) {
+            Ok(current) => current,
+            Err(s) => return s.into(),
+        };
+        if current.last() != Some(&b'\n') {
+            return Status::corruption(
+                &"CURRENT file does not end with newline".into(),
+                &Slice::new(),
+            );
+        }
+        current.pop();
+        let current = String::from_utf8_lossy(&current).into_owned();
+
+        let dscname = format!("{}/{}", self.dbname, current);
+        let file = match self.env.new_sequential_file(&dscname) {
+            Ok(file) => file,
+            Err(s) => {
+                if s.is_not_found() {
+                    return Status::corruption(
+                        &"CURRENT points to a non-existent file".into(),
+                        &s.to_string().as_str().into(),
+                    );
+                }
+                return s.into();
+            }
+        };
+
+        let mut have_log_number = false;
+        let mut have_prev_log_number = false;
+        let mut have_next_file = false;
+        let mut have_last_sequence = false;
+        let mut next_file = 0;
+        let mut last_sequence = 0;
+        let mut log_number = 0;
+        let mut prev_log_number = 0;
+        let mut builder = Builder::new(&self.icmp, Arc::clone(&self.current));
+        let mut read_records = 0;
+
+        let status = Rc::new(RefCell::new(Status::OK()));
+        {
+            let reporter = LogReporter {
+                status: Rc::clone(&status),
+            };
+            let mut reader = log::Reader::new(file, Some(Box::new(reporter)), true, 0);
+            let mut scratch = Vec::new();
+            while let Some(record) = reader
+                .read_record(&mut scratch)
+                .filter(|_| status.borrow().ok())
+            {
+                read_records += 1;
+                let mut edit = VersionEdit::new();
+                let mut s = edit.decode_from(&record);
+                if s.ok() && edit.has_comparator {
+                    let name = self.icmp.user_comparator().name();
+                    if edit.comparator != name {
+                        s = Status::invalid_argument(
+                            &format!("{} does not match existing comparator ", edit.comparator)
+                                .as_str()
+                                .into(),
+                            &name.into(),
+                        );
+                    }
+                }
+
+                if s.ok() {
+                    builder.apply(&edit, &mut self.compact_pointer);
+                }
+
+                if edit.has_log_number {
+                    log_number = edit.log_number;
+                    have_log_number = true;
+                }
+
+                if edit.has_prev_log_number {
+                    prev_log_number = edit.prev_log_number;
+                    have_prev_log_number = true;
+                }
+
+                if edit.has_next_file_number {
+                    next_file = edit.next_file_number;
+                    have_next_file = true;
+                }
+
+                if edit.has_last_sequence {
+                    last_sequence = edit.last_sequence;
+                    have_last_sequence = true;
+                }
+
+                if !s.ok() {
+                    *status.borrow_mut() = s;
+                }
+            }
+        }
+        let mut s = status.take();
+
+        if s.ok() {
+            if !have_next_file {
+                s = Status::corruption(
+                    &"no meta-nextfile entry in descriptor".into(),
+                    &Slice::new(),
+                );
+            } else if !have_log_number {
+                s = Status::corruption(
+                    &"no meta-lognumber entry in descriptor".into(),
+                    &Slice::new(),
+                );
+            } else if !have_last_sequence {
+                s = Status::corruption(
+                    &"no last-sequence-number entry in descriptor".into(),
+                    &Slice::new(),
+                );
+            }
+
+            if !have_prev_log_number {
+                prev_log_number = 0;
+            }
+
+            self.mark_file_number_used(prev_log_number);
+            self.mark_file_number_used(log_number);
+        }
+
+        if s.ok() {
+            let mut v = Version::new(self);
+            builder.save_to(&mut v);
+            // Install recovered version
+            Self::finalize(&mut v);
+            self.append_version(v);
+            self.manifest_file_number = next_file;
+            self.next_file_number = next_file + 1;
+            self.last_sequence = last_sequence;
+            self.log_number = log_number;
+            self.prev_log_number = prev_log_number;
+
+            // See if we can reuse the existing MANIFEST file.
+            if !self.reuse_manifest(&dscname, &current) {
+                *save_manifest = true;
+            }
+        } else {
+            env::log(
+                self.info_log.as_deref(),
+                format_args!(
+                    "Error recovering version set with {} records: {}",
+                    read_records, s
+                ),
+            );
+        }
+
+        s
+    }
+
+    fn reuse_manifest(&mut self, dscname: &str, dscbase: &str) -> bool {
+        if !self.reuse_logs {
+            return false;
+        }
+        let mut manifest_type = FileType::Descriptor;
+        let mut manifest_number = 0;
+        if !parse_file_name(dscbase, &mut manifest_number, &mut manifest_type)
+            || manifest_type != FileType::Descriptor
+        {
+            return false;
+        }
+        let manifest_size = match self.env.get_file_size(dscname) {
+            Ok(manifest_size) => manifest_size,
+            Err(_) => return false,
+        };
+        // Make new compacted MANIFEST if old one is too big
+        if manifest_size >= target_file_size(self.max_file_size) {
+            return false;
+        }
+
+        assert!(self.descriptor_log.is_none());
+        let descriptor_file = match self.env.new_appendable_file(dscname) {
+            Ok(descriptor_file) => descriptor_file,
+            Err(r) => {
+                env::log(
+                    self.info_log.as_deref(),
+                    format_args!("Reuse MANIFEST: {}\n", r),
+                );
+                return false;
+            }
+        };
+
+        env::log(
+            self.info_log.as_deref(),
+            format_args!("Reusing MANIFEST {}\n", dscname),
+        );
+        self.descriptor_log = Some(log::Writer::with_dest_length(
+            descriptor_file,
+            manifest_size,
+        ));
+        self.manifest_file_number = manifest_number;
+        true
+    }
+
+    // Return the current version.
+    pub(crate) fn current(&self) -> &Arc<Version> {
+        &self.current
+    }
+
+    // Return the current manifest file number
+    pub(crate) fn manifest_file_number(&self) -> u64 {
+        self.manifest_file_number
+    }
+
+    // Allocate and return a new file number
+    pub(crate) fn new_file_number(&mut self) -> u64 {
+        let n = self.next_file_number;
+        self.next_file_number += 1;
+        n
+    }
+
+    // Arrange to reuse "file_number" unless a newer file number has already been allocated.
+    // REQUIRES: "file_number" was returned by a call to new_file_number().
+    pub(crate) fn reuse_file_number(&mut self, file_number: u64) {
+        if self.next_file_number == file_number + 1 {
+            self.next_file_number = file_number;
+        }
+    }
+
+    // Return the number of Table files at the specified level.
+    pub(crate) fn num_level_files(&self, level: usize) -> usize {
+        assert!(level < config::NUM_LEVELS);
+        self.current.files[level].len()
+    }
+
+    // Return the combined file size of all files at the specified level.
+    #[cfg(test)]
+    pub(crate) fn num_level_bytes(&self, level: usize) -> u64 {
+        assert!(level < config::NUM_LEVELS);
+        total_file_size(&self.current.files[level])
+    }
+
+    // Return the last sequence number.
+    pub(crate) fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    // Set the last sequence number to s.
+    pub(crate) fn set_last_sequence(&mut self, s: u64) {
+        assert!(s >= self.last_sequence);
+        self.last_sequence = s;
+    }
+
+    // Mark the specified file number as used.
+    pub(crate) fn mark_file_number_used(&mut self, number: u64) {
+        if self.next_file_number <= number {
+            self.next_file_number = number + 1;
+        }
+    }
+
+    // Return the current log file number.
+    pub(crate) fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    // Return the log file number for the log file that is currently being compacted, or zero if
+    // there is no such log file.
+    pub(crate) fn prev_log_number(&self) -> u64 {
+        self.prev_log_number
+    }
+
+    // Pick level and inputs for a new compaction.
+    // Returns None if there is no compaction to be done.
+    // Otherwise returns a compaction that describes the compaction.
+    pub(crate) fn pick_compaction(&mut self) -> Option<Compaction> {
+        // We prefer compactions triggered by too much data in a level over the compactions
+        // triggered by seeks.
+        let size_compaction = self.current.compaction_score >= 1.;
+        let seek_compaction = self.current.file_to_compact.lock().unwrap().clone();
+        let level;
+        let mut c;
+        if size_compaction {
+            level = self.current.compaction_level as usize;
+            assert!(level + 1 < config::NUM_LEVELS);
+            c = Compaction::new(&self.icmp, self.max_file_size, level);
+
+            // Pick the first file that comes after compact_pointer[level]
+            for f in &self.current.files[level] {
+                if self.compact_pointer[level].is_empty()
+                    || self.icmp.compare(
+                        &f.largest.encode(),
+                        &Slice::from(&self.compact_pointer[level]),
+                    ) > 0
+                {
+                    c.inputs[0].push(Arc::clone(f));
+                    break;
+                }
+            }
+            if c.inputs[0].is_empty() {
+                // Wrap-around to the beginning of the key space
+                c.inputs[0].push(Arc::clone(&self.current.files[level][0]));
+            }
+        } else if let Some((f, file_level)) = seek_compaction {
+            level = file_level;
+            c = Compaction::new(&self.icmp, self.max_file_size, level);
+            c.inputs[0].push(f);
+        } else {
+            return None;
+        }
+
+        c.input_version = Some(Arc::clone(&self.current));
+
+        // Files in level 0 may overlap each other, so pick up all overlapping ones
+        if level == 0 {
+            let (smallest, largest) = self.get_range(&c.inputs[0]);
+            // Note that the next call will discard the file we placed in c.inputs[0] earlier and
+            // replace it with an overlapping set which will include the picked file.
+            self.current.get_overlapping_inputs(
+                0,
+                Some(&smallest),
+                Some(&largest),
+                &mut c.inputs[0],
+            );
+            assert!(!c.inputs[0].is_empty());
+        }
+
+        self.setup_other_inputs(&mut c);
+
+        Some(c)
+    }
+
+    // Create an iterator that reads over the compaction inputs for "*c".
+    pub(crate) fn make_input_iterator(&self, c: &Compaction) -> Box<dyn Iter> {
+        let options = ReadOptions {
+            verify_checksums: self.paranoid_checks,
+            fill_cache: false,
+            ..Default::default()
+        };
+
+        // Level-0 files have to be merged together. For other levels, we will make a
+        // concatenating iterator per level.
+        // TODO(opt): use concatenating iterator for level-0 if there is no overlap
+        let mut list: Vec<Box<dyn Iter>> = Vec::new();
+        for which in 0..2 {
+            if !c.inputs[which].is_empty() {
+                if c.level + which == 0 {
+                    for f in &c.inputs[which] {
+                        list.push(
+                            self.table_cache
+                                .new_iterator(&options, f.number, f.file_size),
+                        );
+                    }
+                } else {
+                    // Create concatenating iterator for the files from this level
+                    let table_cache = Arc::clone(&self.table_cache);
+                    list.push(new_two_level_iterator(
+                        Box::new(LevelFileNumIterator::new(&self.icmp, &c.inputs[which])),
+                        Box::new(move |options, file_value| {
+                            get_file_iterator(&table_cache, options, file_value)
+                        }),
+                        &options,
+                    ));
+                }
+            }
+        }
+        new_merging_iterator(Arc::new(self.icmp.clone()), list)
+    }
+
+    // Returns true iff some level needs a compaction.
+    pub(crate) fn needs_compaction(&self) -> bool {
+        self.current.compaction_score >= 1.
+            || self.current.file_to_compact.lock().unwrap().is_some()
+    }
+
+    // Stores the minimal range that covers all entries in inputs as (smallest, largest).
+    // REQUIRES: inputs is not empty
+    // NOTE: C++ stores the range in out parameters
+    fn get_range(&self, inputs: &[Arc<FileMetaData>]) -> (InternalKey, InternalKey) {
+        assert!(!inputs.is_empty());
+        let mut smallest = inputs[0].smallest.clone();
+        let mut largest = inputs[0].largest.clone();
+        for f in &inputs[1..] {
+            if self.icmp.compare_internal_keys(&f.smallest, &smallest) < 0 {
+                smallest = f.smallest.clone();
+            }
+            if self.icmp.compare_internal_keys(&f.largest, &largest) > 0 {
+                largest = f.largest.clone();
+            }
+        }
+        (smallest, largest)
+    }
+
+    // Stores the minimal range that covers all entries in inputs1 and inputs2 as
+    // (smallest, largest).
+    // REQUIRES: inputs is not empty
+    fn get_range2(
+        &self,
+        inputs1: &[Arc<FileMetaData>],
+        inputs2: &[Arc<FileMetaData>],
+    ) -> (InternalKey, InternalKey) {
+        let all = [inputs1, inputs2].concat();
+        self.get_range(&all)
+    }
+
+    fn setup_other_inputs(&mut self, c: &mut Compaction) {
+        let level = c.level;
+
+        add_boundary_inputs(&self.icmp, &self.current.files[level], &mut c.inputs[0]);
+        let (smallest, mut largest) = self.get_range(&c.inputs[0]);
+
+        self.current.get_overlapping_inputs(
+            level + 1,
+            Some(&smallest),
+            Some(&largest),
+            &mut c.inputs[1],
+        );
+        add_boundary_inputs(&self.icmp, &self.current.files[level + 1], &mut c.inputs[1]);
+
+        // Get entire range covered by compaction
+        let (mut all_start, mut all_limit) = self.get_range2(&c.inputs[0], &c.inputs[1]);
+
+        // See if we can grow the number of inputs in "level" without changing the number of
+        // "level+1" files we pick up.
+        if !c.inputs[1].is_empty() {
+            let mut expanded0 = Vec::new();
+            self.current.get_overlapping_inputs(
+                level,
+                Some(&all_start),
+                Some(&all_limit),
+                &mut expanded0,
+            );
+            add_boundary_inputs(&self.icmp, &self.current.files[level], &mut expanded0);
+            let inputs0_size = total_file_size(&c.inputs[0]);
+            let inputs1_size = total_file_size(&c.inputs[1]);
+            let expanded0_size = total_file_size(&expanded0);
+            if expanded0.len() > c.inputs[0].len()
+                && inputs1_size + expanded0_size
+                    < expanded_compaction_byte_size_limit(self.max_file_size)
+            {
+                let (new_start, new_limit) = self.get_range(&expanded0);
+                let mut expanded1 = Vec::new();
+                self.current.get_overlapping_inputs(
+                    level + 1,
+                    Some(&new_start),
+                    Some(&new_limit),
+                    &mut expanded1,
+                );
+                add_boundary_inputs(&self.icmp, &self.current.files[level + 1], &mut expanded1);
+                if expanded1.len() == c.inputs[1].len() {
+                    env::log(
+                        self.info_log.as_deref(),
+                        format_args!(
+                            "Expanding@{} {}+{} ({}+{} bytes) to {}+{} ({}+{} bytes)\n",
+                            level,
+                            c.inputs[0].len(),
+                            c.inputs[1].len(),
+                            inputs0_size,
+                            inputs1_size,
+                            expanded0.len(),
+                            expanded1.len(),
+                            expanded0_size,
+                            inputs1_size
+                        ),
+                    );
+                    largest = new_limit;
+                    c.inputs[0] = expanded0;
+                    c.inputs[1] = expanded1;
+                    (all_start, all_limit) = self.get_range2(&c.inputs[0], &c.inputs[1]);
+                }
+            }
+        }
+
+        // Compute the set of grandparent files that overlap this compaction
+        // (parent == level+1; grandparent == level+2)
+        if level + 2 < config::NUM_LEVELS {
+            self.current.get_overlapping_inputs(
+                level + 2,
+                Some(&all_start),
+                Some(&all_limit),
+                &mut c.grandparents,
+            );
+        }
+
+        // Update the place where we will do the next compaction for this level. We update this
+        // immediately instead of waiting for the VersionEdit to be applied so that if the
+        // compaction fails, we will try a different key range next time.
+        self.compact_pointer[level] = largest.encode().to_vec();
+        c.edit.set_compact_pointer(level, &largest);
+    }
+
+    // Add all files listed in any live version to *live.
+    // May also mutate some internal state.
+    pub(crate) fn add_live_files(&mut self, live: &mut BTreeSet<u64>) {
+        self.versions.retain(|v| v.strong_count() > 0);
+        for v in self.versions.iter().filter_map(Weak::upgrade) {
+            for files in &v.files {
+                live.extend(files.iter().map(|f| f.number));
+            }
+        }
+    }
+
+    // Return a human-readable short (single-line) summary of the number of files per level.
+    // NOTE: C++ formats into a caller-provided buffer
+    pub(crate) fn level_summary(&self) -> String {
+        // Update code if NUM_LEVELS changes
+        const _: () = assert!(config::NUM_LEVELS == 7);
+        let files = &self.current.files;
+        format!(
+            "files[ {} {} {} {} {} {} {} ]",
+            files[0].len(),
+            files[1].len(),
+            files[2].len(),
+            files[3].len(),
+            files[4].len(),
+            files[5].len(),
+            files[6].len()
+        )
+    }
+
+    fn finalize(v: &mut Version) {
+        // Precomputed best level for next compaction
+        let mut best_level = -1;
+        let mut best_score = -1.;
+
+        for level in 0..config::NUM_LEVELS - 1 {
+            let score = if level == 0 {
+                // We treat level-0 specially by bounding the number of files instead of number of
+                // bytes for two reasons:
+                //
+                // (1) With larger write-buffer sizes, it is nice not to do too many level-0
+                // compactions.
+                //
+                // (2) The files in level-0 are merged on every read and therefore we wish to
+                // avoid too many files when the individual file size is small (perhaps because of
+                // a small write-buffer setting, or very high compression ratios, or lots of
+                // overwrites/deletions).
+                v.files[level].len() as f64 / config::L0_COMPACTION_TRIGGER as f64
+            } else {
+                // Compute the ratio of current size to size limit.
+                let level_bytes = total_file_size(&v.files[level]);
+                level_bytes as f64 / max_bytes_for_level(level)
+            };
+
+            if score > best_score {
+                best_level = level as i32;
+                best_score = score;
+            }
+        }
+
+        v.compaction_level = best_level;
+        v.compaction_score = best_score;
+    }
+
+    // Save current contents to *log
+    fn write_snapshot(&self, log: &mut log::Writer) -> Status {
+        // TODO: Break up into multiple records to reduce memory usage on recovery?
+
+        // Save metadata
+        let mut edit = VersionEdit::new();
+        edit.set_comparator_name(self.icmp.user_comparator().name());
+
+        // Save compaction pointers
+        for (level, compact_pointer) in self.compact_pointer.iter().enumerate() {
+            if !compact_pointer.is_empty() {
+                let mut key = InternalKey::default();
+                key.decode_from(&Slice::from(compact_pointer));
+                edit.set_compact_pointer(level, &key);
+            }
+        }
+
+        // Save files
+        for (level, files) in self.current.files.iter().enumerate() {
+            for f in files {
+                edit.add_file(level, f.number, f.file_size, &f.smallest, &f.largest);
+            }
+        }
+
+        let mut record = Vec::new();
+        edit.encode_to(&mut record);
+        log.add_record(&Slice::from(&record))
+    }
+
+    fn append_version(&mut self, v: Version) {
+        // Make "v" current
+        let v = Arc::new(v);
+        self.versions.retain(|v| v.strong_count() > 0);
+        self.versions.push(Arc::downgrade(&v));
+        self.current = v;
+    }
+}
+
+// Helper to sort by v.files[file_number].smallest
+fn by_smallest_key(
+    internal_comparator: &InternalKeyComparator,
+    f1: &FileMetaData,
+    f2: &FileMetaData,
+) -> Ordering {
+    let r = internal_comparator.compare_internal_keys(&f1.smallest, &f2.smallest);
+    if r != 0 {
+        r.cmp(&0)
+    } else {
+        // Break ties by file number
+        f1.number.cmp(&f2.number)
+    }
+}
+
+#[derive(Default)]
+struct LevelState {
+    deleted_files: BTreeSet<u64>,
+    // NOTE: C++ keeps a std::set ordered by smallest key; the files are sorted in save_to()
+    added_files: Vec<Arc<FileMetaData>>,
+}
+
+// A helper class so we can efficiently apply a whole sequence of edits to a particular state
+// without creating intermediate Versions that contain full copies of the intermediate state.
+struct Builder {
+    icmp: InternalKeyComparator,
+    base: Arc<Version>,
+    levels: [LevelState; config::NUM_LEVELS],
+}
+
+impl Builder {
+    // Initialize a builder with the files from *base and other info from the VersionSet.
+    fn new(icmp: &InternalKeyComparator, base: Arc<Version>) -> Self {
+        Self {
+            icmp: icmp.clone(),
+            base,
+            levels: Default::default(),
+        }
+    }
+
+    // Apply all of the edits in *edit to the current state.
+    // NOTE: C++ updates the VersionSet's compaction pointers through its pointer to the set
+    fn apply(&mut self, edit: &VersionEdit, compact_pointer: &mut [Vec<u8>]) {
+        // Update compaction pointers
+        for (level, key) in &edit.compact_pointers {
+            compact_pointer[*level] = key.encode().to_vec();
+        }
+
+        // Delete files
+        for (level, number) in &edit.deleted_files {
+            self.levels[*level].deleted_files.insert(*number);
+        }
+
+        // Add new files
+        for (level, f) in &edit.new_files {
+            let f = f.clone();
+
+            // We arrange to automatically compact this file after a certain number of seeks.
+            // Let's assume:
+            //   (1) One seek costs 10ms
+            //   (2) Writing or reading 1MB costs 10ms (100MB/s)
+            //   (3) A compaction of 1MB does 25MB of IO:
+            //         1MB read from this level
+            //         10-12MB read from next level (boundaries may be misaligned)
+            //         10-12MB written to next level
+            // This implies that 25 seeks cost the same as the compaction of 1MB of data. I.e.,
+            // one seek costs approximately the same as the compaction of 40KB of data. We are a
+            // little conservative and allow approximately one seek for every 16KB of data before
+            // triggering a compaction.
+            let allowed_seeks = (f.file_size / 16384).min(i32::MAX as u64) as i32;
+            f.allowed_seeks
+                .store(allowed_seeks.max(100), atomic::Ordering::Relaxed);
+
+            self.levels[*level].deleted_files.remove(&f.number);
+            self.levels[*level].added_files.push(Arc::new(f));
+        }
+    }
+
+    // Save the current state in *v.
+    fn save_to(&self, v: &mut Version) {
+        for level in 0..config::NUM_LEVELS {
+            // Merge the set of added files with the set of pre-existing files. Drop any deleted
+            // files. Store the result in *v.
+            let base_files = &self.base.files[level];
+            let mut base_iter = 0;
+            let mut added_files = self.levels[level].added_files.clone();
+            added_files.sort_by(|f1, f2| by_smallest_key(&self.icmp, f1, f2));
+            v.files[level].reserve(base_files.len() + added_files.len());
+            for added_file in &added_files {
+                // Add all smaller files listed in base
+                let bpos = base_iter
+                    + base_files[base_iter..].partition_point(|f| {
+                        by_smallest_key(&self.icmp, added_file, f) != Ordering::Less
+                    });
+                while base_iter != bpos {
+                    self.maybe_add_file(v, level, &base_files[base_iter]);
+                    base_iter += 1;
+                }
+
+                self.maybe_add_file(v, level, added_file);
+            }
+
+            // Add remaining base files
+            for f in &base_files[base_iter..] {
+                self.maybe_add_file(v, level, f);
+            }
+
+            // Make sure there is no overlap in levels > 0
+            if cfg!(debug_assertions) && level > 0 {
+                for files in v.files[level].windows(2) {
+                    let prev_end = &files[0].largest;
+                    let this_begin = &files[1].smallest;
+                    if self.icmp.compare_internal_keys(prev_end, this_begin) >= 0 {
+                        panic!(
+                            "overlapping ranges in same level {} vs. {}",
+                            prev_end.debug_string(),
+                            this_begin.debug_string()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn maybe_add_file(&self, v: &mut Version, level: usize, f: &Arc<FileMetaData>) {
+        if self.levels[level].deleted_files.contains(&f.number) {
+            // File is deleted: do nothing
+        } else {
+            let files = &mut v.files[level];
+            if level > 0 {
+                if let Some(last) = files.last() {
+                    // Must not overlap
+                    assert!(self.icmp.compare_internal_keys(&last.largest, &f.smallest) < 0);
+                }
+            }
+            files.push(Arc::clone(f));
+        }
+    }
+}
+
+// Finds the largest key in a vector of files. Returns None if files is empty.
+fn find_largest_key(
+    icmp: &InternalKeyComparator,
+    files: &[Arc<FileMetaData>],
+) -> Option<InternalKey> {
+    let mut largest_key = &files.first()?.largest;
+    for f in &files[1..] {
+        if icmp.compare_internal_keys(&f.largest, largest_key) > 0 {
+            largest_key = &f.largest;
+        }
+    }
+    Some(largest_key.clone())
+}
+
+// Finds minimum file b2=(l2, u2) in level file for which l2 > u1 and
+// user_key(l2) = user_key(u1)
+fn find_smallest_boundary_file(
+    icmp: &InternalKeyComparator,
+    level_files: &[Arc<FileMetaData>],
+    largest_key: &InternalKey,
+) -> Option<Arc<FileMetaData>> {
+    let user_cmp = icmp.user_comparator();
+    let mut smallest_boundary_file: Option<&Arc<FileMetaData>> = None;
+    for f in level_files {
+        if icmp.compare_internal_keys(&f.smallest, largest_key) > 0
+            && user_cmp.compare(&f.smallest.user_key(), &largest_key.user_key()) == 0
+            && smallest_boundary_file
+                .is_none_or(|b| icmp.compare_internal_keys(&f.smallest, &b.smallest) < 0)
+        {
+            smallest_boundary_file = Some(f);
+        }
+    }
+    smallest_boundary_file.cloned()
+}
+
+// Extracts the largest file b1 from |compaction_files| and then searches for a b2 in
+// |level_files| for which user_key(u1) = user_key(l2). If it finds such a file b2 (known as a
+// boundary file) it adds it to |compaction_files| and then searches again using this new upper
+// bound.
+//
+// If there are two blocks, b1=(l1, u1) and b2=(l2, u2) and user_key(u1) = user_key(l2), and if we
+// compact b1 but not b2 then a subsequent get operation will yield an incorrect result because
+// it will return the record from b2 in level i rather than from b1 because it searches level by
+// level for records matching the supplied user key.
+//
+// parameters:
+//   in     level_files:      List of files to search for boundary files.
+//   in/out compaction_files: List of files to extend by adding boundary files.
+fn add_boundary_inputs(
+    icmp: &InternalKeyComparator,
+    level_files: &[Arc<FileMetaData>],
+    compaction_files: &mut Vec<Arc<FileMetaData>>,
+) {
+    // Quick return if compaction_files is empty.
+    let Some(mut largest_key) = find_largest_key(icmp, compaction_files) else {
+        return;
+    };
+
+    // If a boundary file was found advance largest_key, otherwise we're done.
+    while let Some(smallest_boundary_file) =
+        find_smallest_boundary_file(icmp, level_files, &largest_key)
+    {
+        largest_key = smallest_boundary_file.largest.clone();
+        compaction_files.push(smallest_boundary_file);
+    }
+}
+
+// A Compaction encapsulates information about a compaction.
+pub(crate) struct Compaction {
+    level: usize,
+    max_output_file_size: u64,
+    // NOTE: C++ reaches these through the input version's VersionSet
+    icmp: InternalKeyComparator,
+    max_file_size: usize,
+    input_version: Option<Arc<Version>>,
+    edit: VersionEdit,
+
+    // Each compaction reads inputs from "level" and "level+1"
+    // The two sets of inputs
+    inputs: [Vec<Arc<FileMetaData>>; 2],
+
+    // State used to check for number of overlapping grandparent files
+    // (parent == level + 1, grandparent == level + 2)
+    grandparents: Vec<Arc<FileMetaData>>,
+    // Index in grandparents
+    grandparent_index: usize,
+    // Some output key has been seen
+    seen_key: bool,
+    // Bytes of overlap between current output and grandparent files
+    overlapped_bytes: u64,
+
+    // State for implementing is_base_level_for_key
+
+    // level_ptrs holds indices into input_version.files: our state is that we are positioned at
+    // one of the file ranges for each higher level than the ones involved in this compaction
+    // (i.e. for all L >= level + 2).
+    level_ptrs: [usize; config::NUM_LEVELS],
+}
+
+impl Compaction {
+    fn new(icmp: &InternalKeyComparator, max_file_size: usize, level: usize) -> Self {
+        Self {
+            level,
+            max_output_file_size: max_file_size_for_level(max_file_size, level),
+            icmp: icmp.clone(),
+            max_file_size,
+            input_version: None,
+            edit: VersionEdit::new(),
+            inputs: Default::default(),
+            grandparents: Vec::new(),
+            grandparent_index: 0,
+            seen_key: false,
+            overlapped_bytes: 0,
+            level_ptrs: [0; config::NUM_LEVELS],
+        }
+    }
+
+    // Return the level that is being compacted. Inputs from "level" and "level+1" will be
+    // merged to produce a set of "level+1" files.
+    pub(crate) fn level(&self) -> usize {
+        self.level
+    }
+
+    // Return the object that holds the edits to the descriptor done by this compaction.
+    pub(crate) fn edit(&mut self) -> &mut VersionEdit {
+        &mut self.edit
+    }
+
+    // "which" must be either 0 or 1
+    pub(crate) fn num_input_files(&self, which: usize) -> usize {
+        self.inputs[which].len()
+    }
+
+    // Return the ith input file at "level()+which" ("which" must be 0 or 1).
+    pub(crate) fn input(&self, which: usize, i: usize) -> &Arc<FileMetaData> {
+        &self.inputs[which][i]
+    }
+
+    // Maximum size of files to build during this compaction.
+    pub(crate) fn max_output_file_size(&self) -> u64 {
+        self.max_output_file_size
+    }
+
+    // Is this a trivial compaction that can be implemented by just moving a single input file to
+    // the next level (no merging or splitting)
+    pub(crate) fn is_trivial_move(&self) -> bool {
+        // Avoid a move if there is lots of overlapping grandparent data. Otherwise, the move could
+        // create a parent file that will require a very expensive merge later on.
+        self.num_input_files(0) == 1
+            && self.num_input_files(1) == 0
+            && total_file_size(&self.grandparents)
+                <= max_grand_parent_overlap_bytes(self.max_file_size)
+    }
+
+    // Add all inputs to this compaction as delete operations to its edit.
+    // NOTE: C++ takes the edit to add to; it is always the compaction's own edit
+    pub(crate) fn add_input_deletions(&mut self) {
+        for which in 0..2 {
+            for f in &self.inputs[which] {
+                self.edit.remove_file(self.level + which, f.number);
+            }
+        }
+    }
+
+    // Returns true if the information we have available guarantees that the compaction is
+    // producing data in "level+1" for which no data exists in levels greater than "level+1".
+    pub(crate) fn is_base_level_for_key(&mut self, user_key: &Slice) -> bool {
+        // Maybe use binary search to find right entry instead of linear search?
+        let user_cmp = self.icmp.user_comparator();
+        let input_version = self.input_version.as_ref().unwrap();
+        for lvl in self.level + 2..config::NUM_LEVELS {
+            let files = &input_version.files[lvl];
+            while self.level_ptrs[lvl] < files.len() {
+                let f = &files[self.level_ptrs[lvl]];
+                if user_cmp.compare(user_key, &f.largest.user_key()) <= 0 {
+                    // We've advanced far enough
+                    if user_cmp.compare(user_key, &f.smallest.user_key()) >= 0 {
+                        // Key falls in this file's range, so definitely not base level
+                        return false;
+                    }
+                    break;
+                }
+                self.level_ptrs[lvl] += 1;
+            }
+        }
+        true
+    }
+
+    // Returns true iff we should stop building the current output before processing
+    // "internal_key".
+    pub(crate) fn should_stop_before(&mut self, internal_key: &Slice) -> bool {
+        // Scan to find earliest grandparent file that contains key.
+        while self.grandparent_index < self.grandparents.len()
+            && self.icmp.compare(
+                internal_key,
+                &self.grandparents[self.grandparent_index].largest.encode(),
+            ) > 0
+        {
+            if self.seen_key {
+                self.overlapped_bytes += self.grandparents[self.grandparent_index].file_size;
+            }
+            self.grandparent_index += 1;
+        }
+        self.seen_key = true;
+
+        if self.overlapped_bytes > max_grand_parent_overlap_bytes(self.max_file_size) {
+            // Too much overlap for current output; start new output
+            self.overlapped_bytes = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Release the input version for the compaction, once the compaction is successful.
+    pub(crate) fn release_inputs(&mut self) {
+        self.input_version = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comparator::bytewise_comparator,
+        db::dbformat::{SequenceNumber, ValueType},
+        db::filename::current_file_name,
+        helpers::memenv::new_mem_env,
+    };
+
+    struct FindFileTest {
+        disjoint_sorted_files: bool,
+        files: Vec<Arc<FileMetaData>>,
+        icmp: InternalKeyComparator,
+    }
+
+    impl FindFileTest {
+        fn new() -> Self {
+            Self {
+                disjoint_sorted_files: true,
+                files: Vec::new(),
+                icmp: InternalKeyComparator::new(Arc::new(bytewise_comparator())),
+            }
+        }
+
+        fn add(&mut self, smallest: &str, largest: &str) {
+            self.add_with_seq(smallest, largest, 100, 100);
+        }
+
+        fn add_with_seq(
+            &mut self,
+            smallest: &str,
+            largest: &str,
+            smallest_seq: SequenceNumber,
+            largest_seq: SequenceNumber,
+        ) {
+            self.files.push(Arc::new(FileMetaData {
+                number: self.files.len() as u64 + 1,
+                smallest: InternalKey::new(&smallest.into(), smallest_seq, ValueType::Value),
+                largest: InternalKey::new(&largest.into(), largest_seq, ValueType::Value),
+                ..FileMetaData::new()
+            }));
+        }
+
+        fn find(&self, key: &str) -> usize {
+            let target = InternalKey::new(&key.into(), 100, ValueType::Value);
+            find_file(&self.icmp, &self.files, &target.encode())
+        }
+
+        fn overlaps(&self, smallest: Option<&str>, largest: Option<&str>) -> bool {
+            let s = smallest.map(Slice::from);
+            let l = largest.map(Slice::from);
+            some_file_overlaps_range(
+                &self.icmp,
+                self.disjoint_sorted_files,
+                &self.files,
+                s.as_ref(),
+                l.as_ref(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_find_file_empty() {
+        let t = FindFileTest::new();
+        assert_eq!(0, t.find("foo"));
+        assert!(!t.overlaps(Some("a"), Some("z")));
+        assert!(!t.overlaps(None, Some("z")));
+        assert!(!t.overlaps(Some("a"), None));
+        assert!(!t.overlaps(None, None));
+    }
+
+    #[test]
+    fn test_find_file_single() {
+        let mut t = FindFileTest::new();
+        t.add("p", "q");
+        assert_eq!(0, t.find("a"));
+        assert_eq!(0, t.find("p"));
+        assert_eq!(0, t.find("p1"));
+        assert_eq!(0, t.find("q"));
+        assert_eq!(1, t.find("q1"));
+        assert_eq!(1, t.find("z"));
+
+        assert!(!t.overlaps(Some("a"), Some("b")));
+        assert!(!t.overlaps(Some("z1"), Some("z2")));
+        assert!(t.overlaps(Some("a"), Some("p")));
+        assert!(t.overlaps(Some("a"), Some("q")));
+        assert!(t.overlaps(Some("a"), Some("z")));
+        assert!(t.overlaps(Some("p"), Some("p1")));
+        assert!(t.overlaps(Some("p"), Some("q")));
+        assert!(t.overlaps(Some("p"), Some("z")));
+        assert!(t.overlaps(Some("p1"), Some("p2")));
+        assert!(t.overlaps(Some("p1"), Some("z")));
+        assert!(t.overlaps(Some("q"), Some("q")));
+        assert!(t.overlaps(Some("q"), Some("q1")));
+
+        assert!(!t.overlaps(None, Some("j")));
+        assert!(!t.overlaps(Some("r"), None));
+        assert!(t.overlaps(None, Some("p")));
+        assert!(t.overlaps(None, Some("p1")));
+        assert!(t.overlaps(Some("q"), None));
+        assert!(t.overlaps(None, None));
+    }
+
+    #[test]
+    fn test_find_file_multiple() {
+        let mut t = FindFileTest::new();
+        t.add("150", "200");
+        t.add("200", "250");
+        t.add("300", "350");
+        t.add("400", "450");
+        assert_eq!(0, t.find("100"));
+        assert_eq!(0, t.find("150"));
+        assert_eq!(0, t.find("151"));
+        assert_eq!(0, t.find("199"));
+        assert_eq!(0, t.find("200"));
+        assert_eq!(1, t.find("201"));
+        assert_eq!(1, t.find("249"));
+        assert_eq!(1, t.find("250"));
+        assert_eq!(2, t.find("251"));
+        assert_eq!(2, t.find("299"));
+        assert_eq!(2, t.find("300"));
+        assert_eq!(2, t.find("349"));
+        assert_eq!(2, t.find("350"));
+        assert_eq!(3, t.find("351"));
+        assert_eq!(3, t.find("400"));
+        assert_eq!(3, t.find("450"));
+        assert_eq!(4, t.find("451"));
+
+        assert!(!t.overlaps(Some("100"), Some("149")));
+        assert!(!t.overlaps(Some("251"), Some("299")));
+        assert!(!t.overlaps(Some("451"), Some("500")));
+        assert!(!t.overlaps(Some("351"), Some("399")));
+
+        assert!(t.overlaps(Some("100"), Some("150")));
+        assert!(t.overlaps(Some("100"), Some("200")));
+        assert!(t.overlaps(Some("100"), Some("300")));
+        assert!(t.overlaps(Some("100"), Some("400")));
+        assert!(t.overlaps(Some("100"), Some("500")));
+        assert!(t.overlaps(Some("375"), Some("400")));
+        assert!(t.overlaps(Some("450"), Some("450")));
+        assert!(t.overlaps(Some("450"), Some("500")));
+    }
+
+    #[test]
+    fn test_find_file_multiple_null_boundaries() {
+        let mut t = FindFileTest::new();
+        t.add("150", "200");
+        t.add("200", "250");
+        t.add("300", "350");
+        t.add("400", "450");
+        assert!(!t.overlaps(None, Some("149")));
+        assert!(!t.overlaps(Some("451"), None));
+        assert!(t.overlaps(None, None));
+        assert!(t.overlaps(None, Some("150")));
+        assert!(t.overlaps(None, Some("199")));
+        assert!(t.overlaps(None, Some("200")));
+        assert!(t.overlaps(None, Some("201")));
+        assert!(t.overlaps(None, Some("400")));
+        assert!(t.overlaps(None, Some("800")));
+        assert!(t.overlaps(Some("100"), None));
+        assert!(t.overlaps(Some("200"), None));
+        assert!(t.overlaps(Some("449"), None));
+        assert!(t.overlaps(Some("450"), None));
+    }
+
+    #[test]
+    fn test_find_file_overlap_sequence_checks() {
+        let mut t = FindFileTest::new();
+        t.add_with_seq("200", "200", 5000, 3000);
+        assert!(!t.overlaps(Some("199"), Some("199")));
+        assert!(!t.overlaps(Some("201"), Some("300")));
+        assert!(t.overlaps(Some("200"), Some("200")));
+        assert!(t.overlaps(Some("190"), Some("200")));
+        assert!(t.overlaps(Some("200"), Some("210")));
+    }
+
+    #[test]
+    fn test_find_file_overlapping_files() {
+        let mut t = FindFileTest::new();
+        t.add("150", "600");
+        t.add("400", "500");
+        t.disjoint_sorted_files = false;
+        assert!(!t.overlaps(Some("100"), Some("149")));
+        assert!(!t.overlaps(Some("601"), Some("700")));
+        assert!(t.overlaps(Some("100"), Some("150")));
+        assert!(t.overlaps(Some("100"), Some("200")));
+        assert!(t.overlaps(Some("100"), Some("300")));
+        assert!(t.overlaps(Some("100"), Some("400")));
+        assert!(t.overlaps(Some("100"), Some("500")));
+        assert!(t.overlaps(Some("375"), Some("400")));
+        assert!(t.overlaps(Some("450"), Some("450")));
+        assert!(t.overlaps(Some("450"), Some("500")));
+        assert!(t.overlaps(Some("450"), Some("700")));
+        assert!(t.overlaps(Some("600"), Some("700")));
+    }
+
+    fn ikey(user_key: &str, seq: SequenceNumber) -> InternalKey {
+        InternalKey::new(&user_key.into(), seq, ValueType::Value)
+    }
+
+    fn new_db(env: &Arc<dyn Env>, options: &Options, dbname: &str) {
+        let mut new_db = VersionEdit::new();
+        new_db.set_comparator_name(options.comparator.name());
+        new_db.set_log_number(0);
+        new_db.set_next_file(2);
+        new_db.set_last_sequence(0);
+        let file = env
+            .new_writable_file(&descriptor_file_name(dbname, 1))
+            .unwrap();
+        let mut log = log::Writer::new(file);
+        let mut record = Vec::new();
+        new_db.encode_to(&mut record);
+        assert!(log.add_record(&Slice::from(&record)).ok());
+        assert!(set_current_file(&**env, dbname, 1).ok());
+    }
+
+    fn recover(options: &Options, dbname: &str) -> Result<VersionSet> {
+        let icmp = InternalKeyComparator::new(Arc::clone(&options.comparator));
+        let table_cache = Arc::new(TableCache::new(dbname, options, 100));
+        let mut vset = VersionSet::new(dbname, options, &table_cache, &icmp);
+        let mut save_manifest = false;
+        Result::from(vset.recover(&mut save_manifest))?;
+        Ok(vset)
+    }
+
+    // The VersionSet isn't shared here, so the MANIFEST is written in place
+    fn log_and_apply(vset: &mut VersionSet, edit: &mut VersionEdit) -> Status {
+        let mut manifest_write = vset.prepare_log_and_apply(edit);
+        manifest_write.write();
+        vset.finish_log_and_apply(edit, manifest_write)
+    }
+
+    #[test]
+    fn test_log_and_apply_and_recover() {
+        let env = new_mem_env(crate::env::default_env());
+        let options = Options {
+            env: Arc::clone(&env),
+            ..Default::default()
+        };
+        new_db(&env, &options, "/db");
+
+        let mut vset = recover(&options, "/db").unwrap();
+        // Without reuse_logs the next log_and_apply() writes a new manifest
+        assert_eq!(2, vset.manifest_file_number());
+
+        let mut edit = VersionEdit::new();
+        edit.add_file(0, 10, 100, &ikey("a", 1), &ikey("c", 2));
+        edit.add_file(1, 11, 200, &ikey("a", 3), &ikey("b", 4));
+        edit.add_file(1, 12, 300, &ikey("d", 5), &ikey("e", 6));
+        edit.set_compact_pointer(1, &ikey("b", 4));
+        vset.mark_file_number_used(12);
+        vset.set_last_sequence(6);
+        assert!(log_and_apply(&mut vset, &mut edit).ok());
+        let old_version = Arc::clone(vset.current());
+
+        let mut edit = VersionEdit::new();
+        edit.remove_file(1, 11);
+        edit.add_file(2, 13, 400, &ikey("a", 7), &ikey("a", 7));
+        vset.mark_file_number_used(13);
+        assert!(log_and_apply(&mut vset, &mut edit).ok());
+        assert_eq!("files[ 1 1 1 0 0 0 0 ]", vset.level_summary());
+        assert_eq!(300, vset.num_level_bytes(1));
+
+        // Files of versions that are still referenced stay live
+        let mut live = BTreeSet::new();
+        vset.add_live_files(&mut live);
+        assert_eq!(vec![10, 11, 12, 13], live.into_iter().collect::<Vec<_>>());
+        drop(old_version);
+        let mut live = BTreeSet::new();
+        vset.add_live_files(&mut live);
+        assert_eq!(vec![10, 12, 13], live.into_iter().collect::<Vec<_>>());
+
+        let debug_string = vset.current().debug_string();
+        drop(vset);
+
+        // CURRENT points at the new manifest, which holds the same state
+        let current = env::read_file_to_string(&*env, &current_file_name("/db")).unwrap();
+        assert_eq!(b"MANIFEST-000002\n", &current[..]);
+        let mut vset = recover(&options, "/db").unwrap();
+        assert_eq!(debug_string, vset.current().debug_string());
+        assert_eq!(6, vset.last_sequence());
+        assert_eq!(14, vset.manifest_file_number());
+        assert_eq!(15, vset.new_file_number());
+        assert_eq!(
+            ikey("b", 4).encode().as_bytes(),
+            &vset.compact_pointer[1][..]
+        );
+        // A single level-0 file outweighs the few bytes in the other levels
+        assert_eq!(0, vset.current().compaction_level);
+        assert_eq!(0.25, vset.current().compaction_score);
+    }
+
+    #[test]
+    fn test_recover_comparator_mismatch() {
+        struct NewComparator;
+
+        impl Comparator for NewComparator {
+            fn compare(&self, a: &Slice, b: &Slice) -> i32 {
+                bytewise_comparator().compare(a, b)
+            }
+
+            fn name(&self) -> &'static str {
+                "leveldb.NewComparator"
+            }
+
+            fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &Slice) {
+                bytewise_comparator().find_shortest_separator(start, limit);
+            }
+
+            fn find_short_successor(&self, key: &mut Vec<u8>) {
+                bytewise_comparator().find_short_successor(key);
+            }
+        }
+
+        let env = new_mem_env(crate::env::default_env());
+        let options = Options {
+            env: Arc::clone(&env),
+            ..Default::default()
+        };
+        new_db(&env, &options, "/db");
+
+        let options = Options {
+            env: Arc::clone(&env),
+            comparator: Arc::new(NewComparator),
+            ..Default::default()
+        };
+        let s = recover(&options, "/db").err().unwrap();
+        assert!(s.is_invalid_argument(), "{}", s);
+        assert!(s.to_string().contains("comparator"), "{}", s);
+    }
+
+    #[test]
+    fn test_recover_missing_fields() {
+        let env = new_mem_env(crate::env::default_env());
+        let options = Options {
+            env: Arc::clone(&env),
+            ..Default::default()
+        };
+
+        // A descriptor without a next file number
+        let mut edit = VersionEdit::new();
+        edit.set_log_number(0);
+        edit.set_last_sequence(0);
+        let file = env
+            .new_writable_file(&descriptor_file_name("/db", 1))
+            .unwrap();
+        let mut log = log::Writer::new(file);
+        let mut record = Vec::new();
+        edit.encode_to(&mut record);
+        assert!(log.add_record(&Slice::from(&record)).ok());
+        assert!(set_current_file(&*env, "/db", 1).ok());
+        let s = recover(&options, "/db").err().unwrap();
+        assert!(s.is_corruption(), "{}", s);
+
+        // CURRENT pointing at a missing file
+        assert!(env.remove_file(&descriptor_file_name("/db", 1)).is_ok());
+        let s = recover(&options, "/db").err().unwrap();
+        assert!(s.is_corruption(), "{}", s);
+        assert!(s.to_string().contains("non-existent"), "{}", s);
+    }
+}
@@ -0,0 +1,358 @@
+// leveldb/include/leveldb/write_batch.h
+// leveldb/db/write_batch_internal.h
+// leveldb/db/write_batch.cc
+// leveldb/db/write_batch_test.cc
+
+// WriteBatch holds a collection of updates to apply atomically to a DB.
+//
+// The updates are applied in the order in which they are added to the WriteBatch. For example,
+// the value of "key" will be "v3" after the following batch is written:
+//
+//    batch.put("key", "v1");
+//    batch.delete("key");
+//    batch.put("key", "v2");
+//    batch.put("key", "v3");
+//
+// Multiple threads can invoke const methods on a WriteBatch without external synchronization,
+// but if any of the threads may call a non-const method, all threads accessing the same
+// WriteBatch must use external synchronization.
+
+// WriteBatch::rep :=
+//    sequence: fixed64
+//    count: fixed32
+//    data: record[count]
+// record :=
+//    ValueType::Value varstring varstring         |
+//    ValueType::Deletion varstring
+// varstring :=
+//    len: varint32
+//    data: uint8[len]
+
+use crate::db::dbformat::{SequenceNumber, ValueType};
+use crate::db::memtable::MemTable;
+use crate::slice::Slice;
+use crate::status::{Error, Result, Status};
+use crate::util::coding::{
+    decode_fixed32, decode_fixed64, encode_fixed32, encode_fixed64, get_length_prefixed_slice,
+    put_length_prefixed_slice,
+};
+
+// WriteBatch header has an 8-byte sequence number followed by a 4-byte count.
+const HEADER: usize = 12;
+
+// Support for iterating over the contents of a batch.
+// NOTE: C++ declares this as WriteBatch::Handler
+pub trait Handler {
+    fn put(&mut self, key: &Slice, value: &Slice);
+    fn delete(&mut self, key: &Slice);
+}
+
+#[derive(Clone)]
+pub struct WriteBatch {
+    // See comment at the top of the file for the format of rep
+    // NOTE: C++ uses std::string
+    rep: Vec<u8>,
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        Self {
+            rep: vec![0; HEADER],
+        }
+    }
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Store the mapping "key->value" in the database.
+    pub fn put(&mut self, key: &Slice, value: &Slice) {
+        WriteBatchInternal::set_count(self, WriteBatchInternal::count(self) + 1);
+        self.rep.push(ValueType::Value as u8);
+        put_length_prefixed_slice(&mut self.rep, key);
+        put_length_prefixed_slice(&mut self.rep, value);
+    }
+
+    // If the database contains a mapping for "key", erase it. Else do nothing.
+    pub fn delete(&mut self, key: &Slice) {
+        WriteBatchInternal::set_count(self, WriteBatchInternal::count(self) + 1);
+        self.rep.push(ValueType::Deletion as u8);
+        put_length_prefixed_slice(&mut self.rep, key);
+    }
+
+    // Clear all updates buffered in this batch.
+    pub fn clear(&mut self) {
+        self.rep.clear();
+        self.rep.resize(HEADER, 0);
+    }
+
+    // The size of the database changes caused by this batch.
+    //
+    // This number is tied to implementation details, and may change across releases. It is
+    // intended for LevelDB usage metrics.
+    pub fn approximate_size(&self) -> usize {
+        self.rep.len()
+    }
+
+    // Copies the operations in "source" to this batch.
+    //
+    // This runs in O(source size) time. However, the constant factor is better than calling
+    // iterate() over the source batch with a Handler that replicates the operations into this
+    // batch.
+    pub fn append(&mut self, source: &WriteBatch) {
+        WriteBatchInternal::append(self, source);
+    }
+
+    // Support for iterating over the contents of a batch.
+    pub fn iterate(&self, handler: &mut dyn Handler) -> Result<()> {
+        let mut input = Slice::from(&self.rep);
+        if input.size() < HEADER {
+            return Err(Error::corruption(
+                &"malformed WriteBatch (too small)".into(),
+                &Slice::new(),
+            ));
+        }
+
+        input.remove_prefix(HEADER);
+        let mut key = Slice::new();
+        let mut value = Slice::new();
+        let mut found = 0;
+        while !input.empty() {
+            found += 1;
+            let tag = input[0];
+            input.remove_prefix(1);
+            match ValueType::try_from(tag) {
+                Ok(ValueType::Value) => {
+                    if get_length_prefixed_slice(&mut input, &mut key)
+                        && get_length_prefixed_slice(&mut input, &mut value)
+                    {
+                        handler.put(&key, &value);
+                    } else {
+                        return Err(Error::corruption(
+                            &"bad WriteBatch Put".into(),
+                            &Slice::new(),
+                        ));
+                    }
+                }
+                Ok(ValueType::Deletion) => {
+                    if get_length_prefixed_slice(&mut input, &mut key) {
+                        handler.delete(&key);
+                    } else {
+                        return Err(Error::corruption(
+                            &"bad WriteBatch Delete".into(),
+                            &Slice::new(),
+                        ));
+                    }
+                }
+                Err(()) => {
+                    return Err(Error::corruption(
+                        &"unknown WriteBatch tag".into(),
+                        &Slice::new(),
+                    ));
+                }
+            }
+        }
+        if found != WriteBatchInternal::count(self) {
+            Err(Error::corruption(
+                &"WriteBatch has wrong count".into(),
+                &Slice::new(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// WriteBatchInternal provides static methods for manipulating a WriteBatch that we don't want in
+// the public WriteBatch interface.
+pub(crate) struct WriteBatchInternal;
+
+impl WriteBatchInternal {
+    // Return the number of entries in the batch.
+    pub(crate) fn count(b: &WriteBatch) -> u32 {
+        decode_fixed32(&b.rep[8..])
+    }
+
+    // Set the count for the number of entries in the batch.
+    pub(crate) fn set_count(b: &mut WriteBatch, n: u32) {
+        encode_fixed32(&mut b.rep[8..], n);
+    }
+
+    // Return the sequence number for the start of this batch.
+    pub(crate) fn sequence(b: &WriteBatch) -> SequenceNumber {
+        decode_fixed64(&b.rep)
+    }
+
+    // Store the specified number as the sequence number for the start of this batch.
+    pub(crate) fn set_sequence(b: &mut WriteBatch, seq: SequenceNumber) {
+        encode_fixed64(&mut b.rep, seq);
+    }
+
+    pub(crate) fn contents(batch: &WriteBatch) -> Slice<'_> {
+        Slice::from(&batch.rep)
+    }
+
+    pub(crate) fn set_contents(b: &mut WriteBatch, contents: &Slice) {
+        assert!(contents.size() >= HEADER);
+        b.rep.clear();
+        b.rep.extend_from_slice(contents.as_bytes());
+    }
+
+    pub(crate) fn insert_into(b: &WriteBatch, memtable: &MemTable) -> Status {
+        let mut inserter = MemTableInserter {
+            sequence: WriteBatchInternal::sequence(b),
+            mem: memtable,
+        };
+        b.iterate(&mut inserter).into()
+    }
+
+    pub(crate) fn append(dst: &mut WriteBatch, src: &WriteBatch) {
+        WriteBatchInternal::set_count(
+            dst,
+            WriteBatchInternal::count(dst) + WriteBatchInternal::count(src),
+        );
+        assert!(src.rep.len() >= HEADER);
+        dst.rep.extend_from_slice(&src.rep[HEADER..]);
+    }
+}
+
+struct MemTableInserter<'a> {
+    sequence: SequenceNumber,
+    mem: &'a MemTable,
+}
+
+impl Handler for MemTableInserter<'_> {
+    fn put(&mut self, key: &Slice, value: &Slice) {
+        self.mem.add(self.sequence, ValueType::Value, key, value);
+        self.sequence += 1;
+    }
+
+    fn delete(&mut self, key: &Slice) {
+        self.mem
+            .add(self.sequence, ValueType::Deletion, key, &Slice::new());
+        self.sequence += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::bytewise_comparator;
+    use crate::db::dbformat::{parse_internal_key, InternalKeyComparator, ParsedInternalKey};
+    use std::sync::Arc;
+
+    fn print_contents(b: &WriteBatch) -> String {
+        let cmp = InternalKeyComparator::new(Arc::new(bytewise_comparator()));
+        let mem = Arc::new(MemTable::new(&cmp));
+        let mut state = String::new();
+        let s = WriteBatchInternal::insert_into(b, &mem);
+        let mut count = 0;
+        let mut iter = mem.new_iterator();
+        iter.seek_to_first();
+        while iter.valid() {
+            let mut ikey = ParsedInternalKey::default();
+            assert!(parse_internal_key(&iter.key(), &mut ikey));
+            match ikey.value_type {
+                ValueType::Value => {
+                    state.push_str("Put(");
+                    state.push_str(&String::from_utf8_lossy(&ikey.user_key));
+                    state.push_str(", ");
+                    state.push_str(&String::from_utf8_lossy(&iter.value()));
+                    state.push(')');
+                    count += 1;
+                }
+                ValueType::Deletion => {
+                    state.push_str("Delete(");
+                    state.push_str(&String::from_utf8_lossy(&ikey.user_key));
+                    state.push(')');
+                    count += 1;
+                }
+            }
+            state.push('@');
+            state.push_str(&ikey.sequence.to_string());
+            iter.next();
+        }
+        if !s.ok() {
+            state.push_str("ParseError()");
+        } else if count != WriteBatchInternal::count(b) {
+            state.push_str("CountMismatch()");
+        }
+        state
+    }
+
+    #[test]
+    fn test_empty() {
+        let batch = WriteBatch::new();
+        assert_eq!("", print_contents(&batch));
+        assert_eq!(0, WriteBatchInternal::count(&batch));
+    }
+
+    #[test]
+    fn test_multiple() {
+        let mut batch = WriteBatch::new();
+        batch.put(&"foo".into(), &"bar".into());
+        batch.delete(&"box".into());
+        batch.put(&"baz".into(), &"boo".into());
+        WriteBatchInternal::set_sequence(&mut batch, 100);
+        assert_eq!(100, WriteBatchInternal::sequence(&batch));
+        assert_eq!(3, WriteBatchInternal::count(&batch));
+        assert_eq!(
+            "Put(baz, boo)@102Delete(box)@101Put(foo, bar)@100",
+            print_contents(&batch)
+        );
+    }
+
+    #[test]
+    fn test_corruption() {
+        let mut batch = WriteBatch::new();
+        batch.put(&"foo".into(), &"bar".into());
+        batch.delete(&"box".into());
+        WriteBatchInternal::set_sequence(&mut batch, 200);
+        let contents = WriteBatchInternal::contents(&batch).as_bytes().to_vec();
+        WriteBatchInternal::set_contents(&mut batch, &Slice::from(&contents[..contents.len() - 1]));
+        assert_eq!("Put(foo, bar)@200ParseError()", print_contents(&batch));
+    }
+
+    #[test]
+    fn test_append() {
+        let mut b1 = WriteBatch::new();
+        let mut b2 = WriteBatch::new();
+        WriteBatchInternal::set_sequence(&mut b1, 200);
+        WriteBatchInternal::set_sequence(&mut b2, 300);
+        b1.append(&b2);
+        assert_eq!("", print_contents(&b1));
+        b2.put(&"a".into(), &"va".into());
+        b1.append(&b2);
+        assert_eq!("Put(a, va)@200", print_contents(&b1));
+        b2.clear();
+        b2.put(&"b".into(), &"vb".into());
+        b1.append(&b2);
+        assert_eq!("Put(a, va)@200Put(b, vb)@201", print_contents(&b1));
+        b2.delete(&"foo".into());
+        b1.append(&b2);
+        assert_eq!(
+            "Put(a, va)@200Put(b, vb)@202Put(b, vb)@201Delete(foo)@203",
+            print_contents(&b1)
+        );
+    }
+
+    #[test]
+    fn test_approximate_size() {
+        let mut batch = WriteBatch::new();
+        let empty_size = batch.approximate_size();
+
+        batch.put(&"foo".into(), &"bar".into());
+        let one_key_size = batch.approximate_size();
+        assert!(empty_size < one_key_size);
+
+        batch.put(&"baz".into(), &"boo".into());
+        let two_keys_size = batch.approximate_size();
+        assert!(one_key_size < two_keys_size);
+
+        batch.delete(&"box".into());
+        let post_delete_size = batch.approximate_size();
+        assert!(two_keys_size < post_delete_size);
+    }
+}
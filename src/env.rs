@@ -1,10 +1,14 @@
 // leveldb/include/leveldb/env.h
+// leveldb/util/env.cc
 
 // TODO: Check types of the methods
 // TODO: Change all *File to impls in functino args
 
-use crate::{slice::Slice, status::Status};
-use std::ffi::c_void;
+use crate::{
+    slice::Slice,
+    status::{Error, Result},
+};
+use std::sync::{Arc, OnceLock};
 
 // TODO: Following block is in C++
 // #if defined(_WIN32)
@@ -14,186 +18,343 @@ use std::ffi::c_void;
 // #endif
 // #endif
 
-struct FileLock();
-struct RandomAccessFile();
-struct WritableFile();
+// Identifies a locked file.
+// NOTE: C++ uses an empty class that implementations derive from. Here the lock is released when
+// the value is dropped, so implementors should release the underlying lock in their Drop impl.
+pub trait FileLock: Send + Sync {}
 
-pub trait Env {
+// NOTE: C++ returns a Status and stores results through pointer out-params. Here every fallible
+// method returns a Result, with Result<()> for methods that only report success.
+// NOTE: An Env is shared by every thread using the DB (including the background thread), so
+// methods take &self and implementations must do their own synchronization.
+pub trait Env: Send + Sync {
     // Create an object that sequentially reads the file with the specified name.
-    // On success, stores a pointer to the new file in *result TODO(check source comment) and
-    // returns OK.
-    // On failure, stores 'None' in *result and returns non-OK. If the file doesn't exist, returns
-    // a non-OK status. Implementations should return a NotFound status when the file doesn't
-    // exist.
+    // On success, returns the new file. On failure, returns an error. If the file doesn't
+    // exist, returns an error. Implementations should return a NotFound error when the
+    // file doesn't exist.
     //
     // The returned file will only be accessed by one thread at a time.
-    // TODO: Check type of 'result'
-    fn new_sequential_file(
-        &mut self,
-        fname: &str,
-        result: *mut *mut dyn SequentialFile,
-    ) -> Status;
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>>;
 
     // Create an object supporting random-access from the file with the specified name. On
-    // success, stores a pointer to the new file in *result and returns OK. On failure, stores
-    // 'None' in *result and returns non-OK. If the file doesn't exist, returns a non-OK status.
-    // Implementations should return a NotFound status when the file doesn't exist.
+    // success, returns the new file. On failure, returns an error. If the file doesn't
+    // exist, returns an error. Implementations should return a NotFound error when the
+    // file doesn't exist.
     //
     // The returned file may be concurrently accessed by multiple threads.
-    fn new_random_access_file(
-        &mut self,
-        fname: &str,
-        result: *mut *mut RandomAccessFile,
-    ) -> Status;
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>>;
 
     // Create an object that writes to a new file with the specified name. Deletes any existing
-    // file with the same name and creates a new file. On success, stores a pointer to the new
-    // file in *result and returns OK. On failure, stores 'None' in *result and returns non-OK.
+    // file with the same name and creates a new file. On success, returns the new file. On
+    // failure, returns an error.
     //
     // The returned file will only be accessed by one thread at a time.
-    fn new_writable_file(
-        &mut self,
-        result: *mut *mut WritableFile,
-    ) -> Status;
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>>;
 
     // Create an object that either appends to an existing file, or writes to a file (if the file
-    // doesn't exist to begine with). On success, stores a pointer to the new file in *result and
-    // returns OK. On failure, stores 'None' in *result and returns non-OK.
+    // doesn't exist to begine with). On success, returns the new file. On failure, returns a
+    // non-OK status.
     //
     // The returned file will only be accessed by one thread at a time.
     //
     // May return an IsNotSupportedError error if this Env doesn't allow appending to an existing
     // file. Users of Env (including the leveldb implementation) must be prepared to deal with an
     // Env that doesn't support appending.
-    fn new_appendable_file(
-        &mut self,
-        result: *mut *mut WritableFile,
-    ) -> Status {
-        unimplemented!()
+    fn new_appendable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        Err(Error::not_supported(
+            &"new_appendable_file".into(),
+            &fname.into(),
+        ))
     }
 
     // Returns true iff the named file exists.
-    fn file_exists(&mut self, fname: &str) -> bool;
+    fn file_exists(&self, fname: &str) -> bool;
 
-    // Store in *result the names of the children of the specified dicrectory.
+    // Return the names of the children of the specified dicrectory.
     // The names are relative to "dir".
-    // The original contents of *results are dropped.
-    // TODO: Check param types
-    fn get_children(&mut self, dir: &str, result: &mut Vec<String>) -> Status;
+    fn get_children(&self, dir: &str) -> Result<Vec<String>>;
 
     // Delete the named file.
     //
-    // NOTE: The comments below are for deprecated methods in C++
-    // The default implementation calls DeleteFile, to support legacy Env implementations. Updated
-    // Env implementations must override RemoveFile and ignore the existence of DeleteFile.
-    // Updated code calling into the Env API must call remove file instead of DeleteFile.
-    //
-    // A future release will remove DeleteDir and the default implementation of RemoveDir.
-    fn remove_file(&mut self, fname: &str) -> Status {
-        unimplemented!()
-    }
+    // NOTE: The C++ default implementation calls the deprecated DeleteFile to support legacy Env
+    // implementations; there are no legacy implementations here so this is required.
+    fn remove_file(&self, fname: &str) -> Result<()>;
 
     // NOTE: C++ has deprecated 'DeleteFile' method
 
     // Create the specified directory
-    fn create_dir(&mut self, dirname: &str) -> Status;
+    fn create_dir(&self, dirname: &str) -> Result<()>;
 
     // Delete the specified directory.
     //
     // NOTE: C++ has deprecation comments similar to those for the 'remove_file' method
-    fn remove_dir(&mut self, dirname: &str) -> Status {
-        unimplemented!()
-    }
+    fn remove_dir(&self, dirname: &str) -> Result<()>;
 
     // NOTE: C++ has deprecated 'DeleteDir' method
 
-    // Store the size of fname in *file_size.
-    fn get_file_size(&mut self, fname: &str, file_size: &mut u64) -> Status {
-        unimplemented!()
-    }
+    // Return the size of fname.
+    fn get_file_size(&self, fname: &str) -> Result<u64>;
 
     // Rename file src to target.
-    fn rename_file(&mut self, src: &str, target: &str) -> Status;
+    fn rename_file(&self, src: &str, target: &str) -> Result<()>;
 
     // Lock the specified file. Used to prevent concurrent access to the same db by multiple
-    // processes. On failure, stores 'None' in *lock and returns non-OK.
+    // processes. On failure, returns an error.
     //
-    // On success, stores a pointer to the object that represents the acquired lock in *lock and
-    // returns OK. The caller should call unlock_file(*lock) to release the lock. If the process
-    // exits, the lock will be automatically released.
+    // On success, returns the object that represents the acquired lock. The caller should call
+    // unlock_file(lock) to release the lock. If the process exits, the lock will be automatically
+    // released.
     //
     // If somebody else already holds the lock, finished immediately with a failure. I.e., this
     // call doesn't wait for existing locks to go away.
     //
     // May create the named file if it doesn't alreay exist.
-    // TODO: Check types
-    fn lock_file(&mut self, fname: &str, lock: &mut &mut FileLock);
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>>;
 
     // Release the lock acquired by a previous successful call to lock_file.
     // REQUIRES: lock was returned by a successful lock_file() call.
     // REQUIRES: lock hasn't already be unlocked.
-    fn unlock_file(&mut self, lock: &mut FileLock);
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()>;
 
     // Arrange to run "function(arg)" once in a background thread.
     //
     // 'function' may run in an unspecified thread. Multiple functions added to the same Env may
     // run concurrently in different threads. I.e., the caller may not assume that background work
     // items are serialized.
-    fn schedule(&mut self, function: Box<dyn Fn(*mut c_void)>, arg: *mut c_void);
+    // NOTE: C++ takes a function pointer and a void* argument; a closure captures both here
+    fn schedule(&self, function: Box<dyn FnOnce() + Send>);
 
     // Start a new thread, invoking 'function(arg)' within the new thread. When 'function(arg)'
     // returns, the thread will be destroyed.
-    fn start_thread(&mut self, function: Box<dyn Fn(*mut c_void)>, arg: *mut c_void);
+    fn start_thread(&self, function: Box<dyn FnOnce() + Send>);
 
-    // *path is set to a temporary directory that can be used for testing. It may or may not have
-    // just been created. The directory may or may not differ between runs of the same process,
-    // but subsequent calls will return the same directory.
-    fn get_test_directory(&mut self, path: &mut String) -> Status;
+    // Returns a temporary directory that can be used for testing. It may or may not have just
+    // been created. The directory may or may not differ between runs of the same process, but
+    // subsequent calls will return the same directory.
+    fn get_test_directory(&self) -> Result<String>;
 
     // Create and return a new log file for storing informational messages.
-    fn new_logger(&mut self, fname: &str, result: &mut &mut Logger);
+    fn new_logger(&self, fname: &str) -> Result<Arc<dyn Logger>>;
 
     // Returns the number of micro-seconds since some fixed point in time. Only useful for
     // computing deltas of time.
-    fn now_micros(&mut self) -> u64;
+    fn now_micros(&self) -> u64;
 
     // Sleep/delay the thread for the prescribed number of micro-seconds.
-    fn sleep_for_microseconds(micros: i32);
+    fn sleep_for_microseconds(&self, micros: i32);
 }
 
 // A file abstraction for reading sequentially through a file
-pub trait SequentialFile {
-    // Read
+pub trait SequentialFile: Send {
+    // Read up to "n" bytes from the file. "scratch[0..n-1]" may be written by this routine.
+    // Returns the data that was read (including if fewer than "n" bytes were successfully read).
+    // May return a slice pointing at data in "scratch[0..n-1]", so "scratch[0..n-1]" must be live
+    // when the result is used. If an error was encountered, returns an error.
+    //
+    // REQUIRES: External synchronization
+    fn read<'a>(&mut self, n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>>;
+
+    // Skip "n" bytes from the file. This is guaranteed to be no slower that reading the same
+    // data, but may be faster.
+    //
+    // If end of file is reached, skipping will stop at the end of the file, and Skip will return
+    // OK.
+    //
+    // REQUIRES: External synchronization
+    fn skip(&mut self, n: u64) -> Result<()>;
+}
+
+// A file abstraction for randomly reading the contents of a file.
+pub trait RandomAccessFile: Send + Sync {
+    // Read up to "n" bytes from the file starting at "offset". "scratch[0..n-1]" may be written
+    // by this routine. Returns the data that was read (including if fewer than "n" bytes were
+    // successfully read). May return a slice pointing at data in "scratch[0..n-1]", so
+    // "scratch[0..n-1]" must be live when the result is used. If an error was encountered,
+    // returns an error.
+    //
+    // Safe for concurrent use by multiple threads.
+    // NOTE: C++ may also return a slice into a memory-mapped region owned by the file; every
+    // implementation here reads into scratch, so the result borrows only scratch
+    fn read<'a>(&self, offset: u64, n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>>;
+}
+
+// A file abstraction for sequential writing. The implementation must provide buffering since
+// callers may append small fragments at a time to the file.
+pub trait WritableFile: Send {
+    fn append(&mut self, data: &Slice) -> Result<()>;
+    fn close(&mut self) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+    fn sync(&mut self) -> Result<()>;
+}
+
+// An interface for writing log messages.
+// NOTE: C++ Loggers are shared between threads and synchronize internally, so logv takes &self
+pub trait Logger: Send + Sync {
+    // Write an entry to the log file with the specified format.
+    // NOTE: C++ takes a format string and a va_list
+    fn logv(&self, args: std::fmt::Arguments);
+}
+
+// Log the specified data to *info_log if info_log is non-None.
+pub fn log(info_log: Option<&dyn Logger>, args: std::fmt::Arguments) {
+    if let Some(info_log) = info_log {
+        info_log.logv(args);
+    }
 }
 
-// TODO: See C++ implementation for details
+fn do_write_string_to_file(
+    env: &dyn Env,
+    data: &Slice,
+    fname: &str,
+    should_sync: bool,
+) -> Result<()> {
+    let mut file = env.new_writable_file(fname)?;
+    let mut s = file.append(data);
+    if s.is_ok() && should_sync {
+        s = file.sync();
+    }
+    if s.is_ok() {
+        s = file.close();
+    }
+    drop(file); // Will auto-close if we did not close above
+    if s.is_err() {
+        let _ = env.remove_file(fname);
+    }
+    s
+}
+
+// A utility routine: write "data" to the named file.
+pub fn write_string_to_file(env: &dyn Env, data: &Slice, fname: &str) -> Result<()> {
+    do_write_string_to_file(env, data, fname, false)
+}
+
+// A utility routine: write "data" to the named file and Sync() it.
+pub fn write_string_to_file_sync(env: &dyn Env, data: &Slice, fname: &str) -> Result<()> {
+    do_write_string_to_file(env, data, fname, true)
+}
+
+// A utility routine: read contents of named file
+pub fn read_file_to_string(env: &dyn Env, fname: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut file = env.new_sequential_file(fname)?;
+    const BUFFER_SIZE: usize = 8192;
+    let mut space = vec![0u8; BUFFER_SIZE];
+    loop {
+        let fragment = file.read(BUFFER_SIZE, &mut space)?;
+        data.extend_from_slice(fragment.as_bytes());
+        if fragment.empty() {
+            break;
+        }
+    }
+    Ok(data)
+}
+
+// An implementation of Env that forwards all calls to another Env. May be useful to clients who
+// wish to override just part of the functionality of another Env.
 pub struct EnvWrapper {
-    target: Box<dyn Env>,
+    target: Arc<dyn Env>,
 }
 
 impl EnvWrapper {
-    pub fn new(t: impl Env) -> Self { Self { target: Box::new(t) } }
+    // Initialize an EnvWrapper that delegates all calls to *t.
+    pub fn new(t: Arc<dyn Env>) -> Self {
+        Self { target: t }
+    }
 
     // Return the target to which this env forwards all calls.
-    // TODO: Figure out what to return
-    pub fn target(&self) -> &Env { return &*self.target }
+    pub fn target(&self) -> &dyn Env {
+        &*self.target
+    }
 }
 
 impl Env for EnvWrapper {
-    //fn
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        self.target.new_sequential_file(fname)
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        self.target.new_random_access_file(fname)
+    }
+
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        self.target.new_writable_file(fname)
+    }
+
+    fn new_appendable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        self.target.new_appendable_file(fname)
+    }
+
+    fn file_exists(&self, fname: &str) -> bool {
+        self.target.file_exists(fname)
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        self.target.get_children(dir)
+    }
+
+    fn remove_file(&self, fname: &str) -> Result<()> {
+        self.target.remove_file(fname)
+    }
+
+    fn create_dir(&self, dirname: &str) -> Result<()> {
+        self.target.create_dir(dirname)
+    }
+
+    fn remove_dir(&self, dirname: &str) -> Result<()> {
+        self.target.remove_dir(dirname)
+    }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> {
+        self.target.get_file_size(fname)
+    }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> {
+        self.target.rename_file(src, target)
+    }
+
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> {
+        self.target.lock_file(fname)
+    }
+
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()> {
+        self.target.unlock_file(lock)
+    }
+
+    fn schedule(&self, function: Box<dyn FnOnce() + Send>) {
+        self.target.schedule(function)
+    }
+
+    fn start_thread(&self, function: Box<dyn FnOnce() + Send>) {
+        self.target.start_thread(function)
+    }
+
+    fn get_test_directory(&self) -> Result<String> {
+        self.target.get_test_directory()
+    }
+
+    fn new_logger(&self, fname: &str) -> Result<Arc<dyn Logger>> {
+        self.target.new_logger(fname)
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.target.now_micros()
+    }
+
+    fn sleep_for_microseconds(&self, micros: i32) {
+        self.target.sleep_for_microseconds(micros)
+    }
 }
 
 // Return a default environment suitable for the current operating system. Sophisticated users
 // may wish to provide their own Env implementation instead of relying on this default
 // environment.
 //
-// The result of Default() belongs to leveldb and must never be deleted.
+// The result of default_env() belongs to leveldb and is shared by every caller; it is never
+// destroyed.
+//
 // NOTE: C++ has this as a static method in Env but since this doesn't work with Rust traits, it's
-// implemented as a separate function
-//pub fn default() -> Box<dyn Env>; // TODO
-
-// TODO: Possibly use Default trait and different impl based on cfg targets
-
-pub trait Logger {
-    // TODO
+// implemented as a separate function.
+pub fn default_env() -> Arc<dyn Env> {
+    static DEFAULT_ENV: OnceLock<Arc<dyn Env>> = OnceLock::new();
+    Arc::clone(DEFAULT_ENV.get_or_init(|| Arc::new(crate::util::env_posix::PosixEnv::new())))
 }
@@ -2,7 +2,8 @@
 
 use crate::slice::Slice;
 
-pub trait FilterPolicy {
+// NOTE: Filter policies are shared between tables that may be read from multiple threads
+pub trait FilterPolicy: Send + Sync {
     // Return the name of this policy. Note that if the filter encoding changes in an incompatible
     // way, the name returned by this method must be changed. Otherwise, old incompatible filters
     // may be passed to methods of this type.
@@ -15,7 +16,7 @@ pub trait FilterPolicy {
     // Warning: don't change the initial contents of *dst. Instead, append the newly constructed
     // filter to *dst.
     // NOTE: C++ takes 'n' as an int
-    fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut String);
+    fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut Vec<u8>);
 
     // 'filter' contains the data appended by a preceding call to `create_filter` on this trait.
     // This method must return true if the key was in the list of keys passed to `create_filter`.
@@ -0,0 +1,558 @@
+// leveldb/helpers/memenv/memenv.h
+// leveldb/helpers/memenv/memenv.cc
+
+use crate::{
+    env::{Env, EnvWrapper, FileLock, Logger, RandomAccessFile, SequentialFile, WritableFile},
+    slice::Slice,
+    status::{Error, Result},
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const BLOCK_SIZE: usize = 8 * 1024;
+
+struct FileStateInner {
+    blocks: Vec<Box<[u8; BLOCK_SIZE]>>,
+    size: u64,
+}
+
+// NOTE: C++ reference counts FileStates by hand; Arcs are used here, so a file stays readable by
+// open handles after it has been removed from the file system map.
+struct FileState {
+    blocks_mutex: Mutex<FileStateInner>,
+}
+
+impl FileState {
+    fn new() -> Self {
+        Self {
+            blocks_mutex: Mutex::new(FileStateInner {
+                blocks: Vec::new(),
+                size: 0,
+            }),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.blocks_mutex.lock().unwrap().size
+    }
+
+    fn read<'a>(&self, offset: u64, mut n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>> {
+        let inner = self.blocks_mutex.lock().unwrap();
+        if offset > inner.size {
+            return Err(Error::io_error(
+                &"Offset greater than file size.".into(),
+                &Slice::new(),
+            ));
+        }
+        let available = inner.size - offset;
+        if n as u64 > available {
+            n = available as usize;
+        }
+        if n == 0 {
+            return Ok(Slice::new());
+        }
+
+        assert!(offset / (BLOCK_SIZE as u64) <= usize::MAX as u64);
+        let mut block = (offset / BLOCK_SIZE as u64) as usize;
+        let mut block_offset = (offset % BLOCK_SIZE as u64) as usize;
+        let mut bytes_to_copy = n;
+        let mut dst = 0;
+
+        while bytes_to_copy > 0 {
+            let avail = (BLOCK_SIZE - block_offset).min(bytes_to_copy);
+            scratch[dst..dst + avail]
+                .copy_from_slice(&inner.blocks[block][block_offset..block_offset + avail]);
+
+            bytes_to_copy -= avail;
+            dst += avail;
+            block += 1;
+            block_offset = 0;
+        }
+
+        Ok(Slice::from(&scratch[..n]))
+    }
+
+    fn append(&self, data: &Slice) -> Result<()> {
+        let mut src = data.as_bytes();
+
+        let mut inner = self.blocks_mutex.lock().unwrap();
+        while !src.is_empty() {
+            let offset = (inner.size % BLOCK_SIZE as u64) as usize;
+
+            let avail = if offset != 0 {
+                // There is some room in the last block.
+                BLOCK_SIZE - offset
+            } else {
+                // No room in the last block; push new one.
+                inner.blocks.push(Box::new([0; BLOCK_SIZE]));
+                BLOCK_SIZE
+            };
+
+            let avail = avail.min(src.len());
+            inner.blocks.last_mut().unwrap()[offset..offset + avail].copy_from_slice(&src[..avail]);
+            src = &src[avail..];
+            inner.size += avail as u64;
+        }
+
+        Ok(())
+    }
+}
+
+struct SequentialFileImpl {
+    file: Arc<FileState>,
+    pos: u64,
+}
+
+impl SequentialFile for SequentialFileImpl {
+    fn read<'a>(&mut self, n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>> {
+        let result = self.file.read(self.pos, n, scratch)?;
+        self.pos += result.size() as u64;
+        Ok(result)
+    }
+
+    fn skip(&mut self, mut n: u64) -> Result<()> {
+        let size = self.file.size();
+        if self.pos > size {
+            return Err(Error::io_error(
+                &"pos_ > file_->Size()".into(),
+                &Slice::new(),
+            ));
+        }
+        let available = size - self.pos;
+        if n > available {
+            n = available;
+        }
+        self.pos += n;
+        Ok(())
+    }
+}
+
+struct RandomAccessFileImpl {
+    file: Arc<FileState>,
+}
+
+impl RandomAccessFile for RandomAccessFileImpl {
+    fn read<'a>(&self, offset: u64, n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>> {
+        self.file.read(offset, n, scratch)
+    }
+}
+
+struct WritableFileImpl {
+    file: Arc<FileState>,
+}
+
+impl WritableFile for WritableFileImpl {
+    fn append(&mut self, data: &Slice) -> Result<()> {
+        self.file.append(data)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct NoOpLogger;
+
+impl Logger for NoOpLogger {
+    fn logv(&self, _args: std::fmt::Arguments) {}
+}
+
+struct MemFileLock;
+
+impl FileLock for MemFileLock {}
+
+// Map from filenames to FileState objects, representing a simple file system.
+type FileSystem = HashMap<String, Arc<FileState>>;
+
+fn file_not_found(fname: &str) -> Error {
+    // NOTE: C++ returns an IOError; NotFound is returned here as the Env trait asks of
+    // implementations
+    Error::not_found(&fname.into(), &"File not found".into())
+}
+
+struct InMemoryEnv {
+    // Non-file calls are forwarded to the base Env
+    base: EnvWrapper,
+    file_map: Mutex<FileSystem>,
+}
+
+impl Env for InMemoryEnv {
+    // Partial implementation of the Env interface.
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        let file_map = self.file_map.lock().unwrap();
+        match file_map.get(fname) {
+            Some(file) => Ok(Box::new(SequentialFileImpl {
+                file: Arc::clone(file),
+                pos: 0,
+            })),
+            None => Err(file_not_found(fname)),
+        }
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        let file_map = self.file_map.lock().unwrap();
+        match file_map.get(fname) {
+            Some(file) => Ok(Box::new(RandomAccessFileImpl {
+                file: Arc::clone(file),
+            })),
+            None => Err(file_not_found(fname)),
+        }
+    }
+
+    // NOTE: C++ truncates an existing FileState in place, so files already open on "fname" see
+    // the new contents. The file is replaced here instead, so they keep reading the old contents
+    // like they would from a file that was replaced on disk.
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        let file = Arc::new(FileState::new());
+        self.file_map
+            .lock()
+            .unwrap()
+            .insert(fname.to_owned(), Arc::clone(&file));
+        Ok(Box::new(WritableFileImpl { file }))
+    }
+
+    fn new_appendable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        let mut file_map = self.file_map.lock().unwrap();
+        let file = file_map
+            .entry(fname.to_owned())
+            .or_insert_with(|| Arc::new(FileState::new()));
+        Ok(Box::new(WritableFileImpl {
+            file: Arc::clone(file),
+        }))
+    }
+
+    fn file_exists(&self, fname: &str) -> bool {
+        self.file_map.lock().unwrap().contains_key(fname)
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        let file_map = self.file_map.lock().unwrap();
+        let mut result = Vec::new();
+        for filename in file_map.keys() {
+            if filename.len() > dir.len()
+                && filename.as_bytes()[dir.len()] == b'/'
+                && filename.starts_with(dir)
+            {
+                result.push(filename[dir.len() + 1..].to_owned());
+            }
+        }
+        Ok(result)
+    }
+
+    fn remove_file(&self, fname: &str) -> Result<()> {
+        match self.file_map.lock().unwrap().remove(fname) {
+            Some(_) => Ok(()),
+            None => Err(file_not_found(fname)),
+        }
+    }
+
+    fn create_dir(&self, _dirname: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn remove_dir(&self, _dirname: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> {
+        match self.file_map.lock().unwrap().get(fname) {
+            Some(file) => Ok(file.size()),
+            None => Err(file_not_found(fname)),
+        }
+    }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> {
+        let mut file_map = self.file_map.lock().unwrap();
+        match file_map.remove(src) {
+            Some(file) => {
+                file_map.insert(target.to_owned(), file);
+                Ok(())
+            }
+            None => Err(file_not_found(src)),
+        }
+    }
+
+    fn lock_file(&self, _fname: &str) -> Result<Box<dyn FileLock>> {
+        Ok(Box::new(MemFileLock))
+    }
+
+    fn unlock_file(&self, _lock: Box<dyn FileLock>) -> Result<()> {
+        Ok(())
+    }
+
+    fn schedule(&self, function: Box<dyn FnOnce() + Send>) {
+        self.base.schedule(function)
+    }
+
+    fn start_thread(&self, function: Box<dyn FnOnce() + Send>) {
+        self.base.start_thread(function)
+    }
+
+    fn get_test_directory(&self) -> Result<String> {
+        Ok("/test".to_owned())
+    }
+
+    fn new_logger(&self, _fname: &str) -> Result<Arc<dyn Logger>> {
+        Ok(Arc::new(NoOpLogger))
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.base.now_micros()
+    }
+
+    fn sleep_for_microseconds(&self, micros: i32) {
+        self.base.sleep_for_microseconds(micros)
+    }
+}
+
+// Returns a new environment that stores its data in memory and delegates all non-file-storage
+// tasks to base_env.
+// NOTE: C++ returns a raw pointer owned by the caller. An Arc is returned so the env can be put in
+// the Options of a DB and still be used directly.
+pub fn new_mem_env(base_env: Arc<dyn Env>) -> Arc<dyn Env> {
+    Arc::new(InMemoryEnv {
+        base: EnvWrapper::new(base_env),
+        file_map: Mutex::new(HashMap::new()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::{self, default_env},
+        options::{Options, ReadOptions, WriteOptions},
+        DB,
+    };
+
+    fn new_env() -> Arc<dyn Env> {
+        new_mem_env(default_env())
+    }
+
+    #[test]
+    fn test_basics() {
+        let env = new_env();
+
+        assert!(env.create_dir("/dir").is_ok());
+
+        assert!(!env.file_exists("/dir/non_existent"));
+        assert!(env.get_file_size("/dir/non_existent").is_err());
+
+        assert!(env.get_children("/dir").unwrap().is_empty());
+
+        // Create a file.
+        let mut writable_file = env.new_writable_file("/dir/f").unwrap();
+        assert_eq!(0, env.get_file_size("/dir/f").unwrap());
+        drop(writable_file);
+
+        // Check that the file exists.
+        assert!(env.file_exists("/dir/f"));
+        assert_eq!(0, env.get_file_size("/dir/f").unwrap());
+        assert_eq!(vec!["f"], env.get_children("/dir").unwrap());
+
+        // Write to the file.
+        writable_file = env.new_writable_file("/dir/f").unwrap();
+        assert!(writable_file.append(&"abc".into()).is_ok());
+        drop(writable_file);
+
+        // Check that append works.
+        writable_file = env.new_appendable_file("/dir/f").unwrap();
+        assert_eq!(3, env.get_file_size("/dir/f").unwrap());
+        assert!(writable_file.append(&"hello".into()).is_ok());
+        drop(writable_file);
+
+        // Check for expected size.
+        assert_eq!(8, env.get_file_size("/dir/f").unwrap());
+
+        // Check that renaming works.
+        assert!(env.rename_file("/dir/non_existent", "/dir/g").is_err());
+        assert!(env.rename_file("/dir/f", "/dir/g").is_ok());
+        assert!(!env.file_exists("/dir/f"));
+        assert!(env.file_exists("/dir/g"));
+        assert_eq!(8, env.get_file_size("/dir/g").unwrap());
+
+        // Check that opening non-existent file fails.
+        assert!(env.new_sequential_file("/dir/non_existent").is_err());
+        assert!(env.new_random_access_file("/dir/non_existent").is_err());
+
+        // Check that deleting works.
+        assert!(env.remove_file("/dir/non_existent").is_err());
+        assert!(env.remove_file("/dir/g").is_ok());
+        assert!(!env.file_exists("/dir/g"));
+        assert!(env.get_children("/dir").unwrap().is_empty());
+        assert!(env.remove_dir("/dir").is_ok());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let env = new_env();
+        let mut scratch = [0u8; 100];
+
+        assert!(env.create_dir("/dir").is_ok());
+
+        let mut writable_file = env.new_writable_file("/dir/f").unwrap();
+        assert!(writable_file.append(&"hello ".into()).is_ok());
+        assert!(writable_file.append(&"world".into()).is_ok());
+        drop(writable_file);
+
+        // Read sequentially.
+        let mut seq_file = env.new_sequential_file("/dir/f").unwrap();
+        let result = seq_file.read(5, &mut scratch).unwrap(); // Read "hello".
+        assert_eq!(b"hello", result.as_bytes());
+        assert!(seq_file.skip(1).is_ok());
+        let result = seq_file.read(1000, &mut scratch).unwrap(); // Read "world".
+        assert_eq!(b"world", result.as_bytes());
+        let result = seq_file.read(1000, &mut scratch).unwrap(); // Try reading past EOF.
+        assert_eq!(0, result.size());
+        assert!(seq_file.skip(100).is_ok()); // Try to skip past end of file.
+        let result = seq_file.read(1000, &mut scratch).unwrap();
+        assert_eq!(0, result.size());
+
+        // Random reads.
+        let rand_file = env.new_random_access_file("/dir/f").unwrap();
+        let result = rand_file.read(6, 5, &mut scratch).unwrap(); // Read "world".
+        assert_eq!(b"world", result.as_bytes());
+        let result = rand_file.read(0, 5, &mut scratch).unwrap(); // Read "hello".
+        assert_eq!(b"hello", result.as_bytes());
+        let result = rand_file.read(10, 100, &mut scratch).unwrap(); // Read "d".
+        assert_eq!(b"d", result.as_bytes());
+
+        // Too high offset.
+        assert!(rand_file.read(1000, 5, &mut scratch).is_err());
+    }
+
+    #[test]
+    fn test_locks() {
+        let env = new_env();
+
+        // These are no-ops, but we test they return success.
+        let lock = env.lock_file("some file").unwrap();
+        assert!(env.unlock_file(lock).is_ok());
+    }
+
+    #[test]
+    fn test_misc() {
+        let env = new_env();
+
+        let test_dir = env.get_test_directory().unwrap();
+        assert!(!test_dir.is_empty());
+
+        let mut writable_file = env.new_writable_file("/a/b").unwrap();
+
+        // These are no-ops, but we test they return success.
+        assert!(writable_file.sync().is_ok());
+        assert!(writable_file.flush().is_ok());
+        assert!(writable_file.close().is_ok());
+
+        let logger = env.new_logger("/a/LOG").unwrap();
+        env::log(Some(&*logger), format_args!("ignored"));
+        assert!(!env.file_exists("/a/LOG"));
+    }
+
+    #[test]
+    fn test_large_write() {
+        let env = new_env();
+        const WRITE_SIZE: usize = 300 * 1024;
+        let write_data: Vec<u8> = (0..WRITE_SIZE).map(|i| i as u8).collect();
+
+        let mut writable_file = env.new_writable_file("/dir/f").unwrap();
+        assert!(writable_file.append(&"foo".into()).is_ok());
+        assert!(writable_file.append(&Slice::from(&write_data[..])).is_ok());
+        drop(writable_file);
+
+        let mut seq_file = env.new_sequential_file("/dir/f").unwrap();
+        let mut scratch = vec![0u8; WRITE_SIZE];
+        let result = seq_file.read(3, &mut scratch).unwrap(); // Read "foo".
+        assert_eq!(b"foo", result.as_bytes());
+
+        let mut read = 0;
+        let mut read_data = Vec::new();
+        while read < WRITE_SIZE {
+            let result = seq_file.read(WRITE_SIZE - read, &mut scratch).unwrap();
+            read_data.extend_from_slice(result.as_bytes());
+            read += result.size();
+        }
+        assert_eq!(write_data, read_data);
+    }
+
+    #[test]
+    fn test_overwrite_open_file() {
+        let env = new_env();
+        const WRITE1_DATA: &str = "Write #1 data";
+        const FILE_DATA_LEN: usize = WRITE1_DATA.len();
+        let test_file_name = format!("{}/leveldb-TestFile.dat", env.get_test_directory().unwrap());
+
+        assert!(env::write_string_to_file(&*env, &WRITE1_DATA.into(), &test_file_name).is_ok());
+
+        let rand_file = env.new_random_access_file(&test_file_name).unwrap();
+
+        const WRITE2_DATA: &str = "Write #2 data";
+        assert!(env::write_string_to_file(&*env, &WRITE2_DATA.into(), &test_file_name).is_ok());
+
+        // Files that were already open keep reading the data they were opened on, while newly
+        // opened files read the new data.
+        let mut scratch = [0u8; FILE_DATA_LEN];
+        let result = rand_file.read(0, FILE_DATA_LEN, &mut scratch).unwrap();
+        assert_eq!(WRITE1_DATA.as_bytes(), result.as_bytes());
+
+        let rand_file = env.new_random_access_file(&test_file_name).unwrap();
+        let result = rand_file.read(0, FILE_DATA_LEN, &mut scratch).unwrap();
+        assert_eq!(WRITE2_DATA.as_bytes(), result.as_bytes());
+    }
+
+    #[test]
+    fn test_removed_file_stays_readable() {
+        let env = new_env();
+        assert!(env::write_string_to_file(&*env, &"data".into(), "/dir/f").is_ok());
+        let rand_file = env.new_random_access_file("/dir/f").unwrap();
+        assert!(env.remove_file("/dir/f").is_ok());
+
+        let mut scratch = [0u8; 4];
+        let result = rand_file.read(0, 4, &mut scratch).unwrap();
+        assert_eq!(b"data", result.as_bytes());
+    }
+
+    #[test]
+    fn test_db() {
+        let env = new_env();
+        let keys = ["aaa", "bbb", "ccc"];
+        let vals = ["foo", "bar", "baz"];
+
+        let options = || Options {
+            create_if_missing: true,
+            env: Arc::clone(&env),
+            ..Default::default()
+        };
+        let db = DB::open(options(), "/dir/db").unwrap();
+        for (key, val) in keys.iter().zip(vals) {
+            assert!(db
+                .put(&WriteOptions::default(), &(*key).into(), &val.into())
+                .is_ok());
+        }
+
+        let check = |db: &DB| {
+            for (key, val) in keys.iter().zip(vals) {
+                let res = db.get(&ReadOptions::default(), &(*key).into()).unwrap();
+                assert_eq!(val.as_bytes(), &res[..]);
+            }
+        };
+        check(&db);
+        drop(db);
+
+        // Nothing was written to the real file system
+        assert!(!default_env().file_exists("/dir/db/CURRENT"));
+        assert!(env.file_exists("/dir/db/CURRENT"));
+
+        // The data survives reopening the database in the same env
+        let db = DB::open(options(), "/dir/db").unwrap();
+        check(&db);
+    }
+}
@@ -0,0 +1,3 @@
+// leveldb/helpers
+
+pub mod memenv;
@@ -1,6 +1,9 @@
 // TODO: Files?
 
-use crate::{slice::Slice, status::Status};
+use crate::{
+    slice::Slice,
+    status::{Error, Result},
+};
 use std::ffi::c_void;
 use std::ptr::null_mut;
 
@@ -10,7 +13,7 @@ pub type CleanupFunction = Box<dyn Fn(*mut c_void, *mut c_void)>;
 pub trait Iter {
     // NOTE: C++ has this as a private member (not a method); implementors should return a
     // reference to their own CleanupNode
-    fn cleanup_head<'a>(&'a mut self) -> &'a mut CleanupNode;
+    fn cleanup_head(&mut self) -> &mut CleanupNode;
     // An iterator is either positioned at a key/value pair, or not valid. This method returns
     // true iff the iterator is valid.
     fn valid(&self) -> bool;
@@ -34,22 +37,41 @@ pub trait Iter {
     // Return the key for the current entry. The underlying storage for the returned slice is
     // valid only until the next modification of the iterator.
     // REQUIRES: valid()
-    fn key(&self) -> Slice;
+    fn key(&self) -> Slice<'_>;
     // Return the value for the current entry. The underlying storage for the returned slice is
     // valid only until the next modification of the iterator.
     // REQUIRES: valid()
-    fn value(&self) -> Slice;
-    // If an error has occurred, return it. Else return an ok status.
-    fn status(&self) -> Status;
+    fn value(&self) -> Slice<'_>;
+    // If an error has occurred, return it. Else return Ok.
+    fn status(&self) -> Result<()>;
     // Clients are allowed to register function/arg1/arg2 triples that will be invoked when this
     // iterator is destroyed.
     //
     // Note that unlike all of the preceding methods, this method is not abstract and threfore
     // clients should not override it.
-    fn register_cleanup(&mut self, function: CleanupFunction, arg1: *mut c_void, arg2: *mut c_void);
+    fn register_cleanup(
+        &mut self,
+        function: CleanupFunction,
+        arg1: *mut c_void,
+        arg2: *mut c_void,
+    ) {
+        let cleanup_head = self.cleanup_head();
+        let node = if cleanup_head.is_empty() {
+            cleanup_head
+        } else {
+            let node = Box::new(CleanupNode {
+                next: cleanup_head.next.take(),
+                ..Default::default()
+            });
+            &mut **cleanup_head.next.insert(node)
+        };
+        node.function = Some(function);
+        node.arg1 = arg1;
+        node.arg2 = arg2;
+    }
     // The default function to be run on drop
     fn drop_func(&mut self) {
-        let mut cleanup_head = self.cleanup_head();
+        let cleanup_head = self.cleanup_head();
         if !cleanup_head.is_empty() {
             cleanup_head.run();
             let mut node = cleanup_head.next.take();
@@ -74,7 +96,7 @@ pub struct CleanupNode {
 impl CleanupNode {
     // True if the node is not used. Only head nodes might be usused.
     fn is_empty(&self) -> bool {
-        self.function.is_some()
+        self.function.is_none()
     }
 
     // Invokes the cleanup function.
@@ -99,7 +121,7 @@ impl Default for CleanupNode {
 }
 
 pub struct EmptyIterator {
-    status: Status,
+    status: Result<()>,
     cleanup_head: CleanupNode,
 }
 
@@ -126,44 +148,29 @@ impl Iter for EmptyIterator {
         unimplemented!()
     }
 
-    fn key(&self) -> Slice {
+    fn key(&self) -> Slice<'_> {
         unimplemented!()
     }
 
-    fn value(&self) -> Slice {
+    fn value(&self) -> Slice<'_> {
         unimplemented!()
     }
 
-    fn status(&self) -> Status {
+    fn status(&self) -> Result<()> {
         self.status.clone()
     }
-
-    // TODO: Change?
-    fn register_cleanup(
-        &mut self,
-        function: CleanupFunction,
-        arg1: *mut c_void,
-        arg2: *mut c_void,
-    ) {
-        self.cleanup_head = CleanupNode {
-            function: Some(function),
-            arg1,
-            arg2,
-            next: None,
-        };
-    }
 }
 
-fn new_empty_iterator() -> Box<dyn Iter> {
+pub fn new_empty_iterator() -> Box<dyn Iter> {
     Box::new(EmptyIterator {
-        status: Status::OK(),
+        status: Ok(()),
         cleanup_head: Default::default(),
     })
 }
 
-fn new_error_iter(status: Status) -> Box<dyn Iter> {
+pub fn new_error_iter(error: Error) -> Box<dyn Iter> {
     Box::new(EmptyIterator {
-        status,
+        status: Err(error),
         cleanup_head: Default::default(),
     })
 }
@@ -1,8 +1,14 @@
+pub mod db;
 pub mod env;
 pub mod filter_policy;
+pub mod helpers;
 pub mod iter;
 pub mod options;
 pub mod slice;
+#[path = "../snappy/mod.rs"]
+pub mod snappy;
 pub mod table;
 mod util;
+pub use db::{destroy_db, repair_db, write_batch, Snapshot, DB};
+pub use util::status::{Error, Result};
 pub use util::{cache, comparator, status};
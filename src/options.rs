@@ -5,17 +5,19 @@
 
 use crate::{
     cache::Cache,
-    comparator::{Comparator, bytewise_comparator},
-    env::{Env, Logger},
-    filter_plicy::FilterPolicy,
+    comparator::{bytewise_comparator, Comparator},
+    db::Snapshot,
+    env::{self, Env, Logger},
+    filter_policy::FilterPolicy,
+    table::Block,
 };
-
-pub trait Snapshot {}
+use std::sync::Arc;
 
 // DB contents are stored in a set of blocks, each of which holds a sequence of key,value pairs.
 // Each block may be compressed before stored in a file. The file enum descrives which compression
 // method (if any) is used to compress a block.
 #[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     // NOTE: do not change the value of existing entries, as these are part of the persistent
     // format on disk.
@@ -24,6 +26,7 @@ pub enum CompressionType {
 }
 
 // Options to control the behavior of a database (passed to DB::Open)
+#[derive(Clone)]
 pub struct Options {
     // -------------------
     // Parameters that affect behavior
@@ -35,11 +38,11 @@ pub struct Options {
     // orders keys *exactly* the same as the comparator provided to the previous open calls on the
     // same DB.
     // NOTE: C++ uses pointer. This may be important since the default Options uses the
-    // bytewise_comparator(), which returns a (raw) pointer to a static piece of memory.
-    // TODO: Possibly take reference since this is a const pointer in C++
-    pub comparator: Box<dyn Comparator>,
-    
-    // If true, an error is raised if the database already exists.
+    // bytewise_comparator(), which returns a (raw) pointer to a static piece of memory. An Arc is
+    // used here since the comparator is shared with the internal structures of an open DB.
+    pub comparator: Arc<dyn Comparator>,
+
+    // If true, the database will be created if it is missing.
     pub create_if_missing: bool,
 
     // If true, an error is raised if the database already exists.
@@ -54,13 +57,14 @@ pub struct Options {
     // Use the specified object to interact with the environment, e.g., to read/write files,
     // schedule background work, etc.
     // Default: Env::default().
-    pub env: Box<dyn Env>,
+    // NOTE: An Arc is used since the env is shared with the DB's background work.
+    pub env: Arc<dyn Env>,
 
     // Any internal progress/error information generated by the DB will be written to info_log if
     // it is non-null, or to a file stored in the same directory as the DB contents if info_log is
     // None.
     // NOTE: C++ uses nullptr
-    pub info_log: Option<Box<dyn Logger>>,
+    pub info_log: Option<Arc<dyn Logger>>,
 
     // -------------------
     // Parameters that affect performance
@@ -84,8 +88,8 @@ pub struct Options {
     //
     // If non-null, use the specified cache for blocks.
     // If null, leveldb will automatically create and use an 8MB internal cache.
-    // NOTE: C++ uses nullptr
-    pub block_cache: Option<Box<dyn Cache>>,
+    // NOTE: C++ uses nullptr. An Arc is used since the cache is shared with the open tables.
+    pub block_cache: Option<Arc<dyn Cache<Block>>>,
 
     // Approximate size of user data packed per block. Note that the block size specified here
     // corresponds to uncompressed data. The actual size of the unit read from disk may be smaller
@@ -117,7 +121,7 @@ pub struct Options {
     // incomprehensible, the SnappyCompression implementation will efficiently detect that and
     // will switch to uncompressed mode.
     pub compression: CompressionType,
-    
+
     // EXPERIMENTAL: If true, append to existing MANIFEST and log files when a database is opened.
     // This can significantly speed up open.
     //
@@ -126,8 +130,8 @@ pub struct Options {
 
     // If non-null, use the specified filter policy to reduce disk reads. Many applications will
     // benefit from passing the result of new_bloom_filter_policy() here.
-    // NOTE: C++ uses nullptr
-    pub filter_policy: Option<Box<dyn FilterPolicy>>,
+    // NOTE: C++ uses nullptr. An Arc is used since the policy is shared with the open tables.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
 }
 
 impl Default for Options {
@@ -136,12 +140,12 @@ impl Default for Options {
         // and create a new() method that makes comparator and env the values below.
         Self {
             // FILE: leveldb/util/options.cc
-            comparator: Box::new(bytewise_comparator()), // TODO
+            comparator: Arc::new(bytewise_comparator()),
             create_if_missing: false,
-            error_is_exists: false,
+            error_if_exists: false,
             paranoid_checks: false,
             // FILE: leveldb/util/options.cc
-            env: Box::new(Env::default()),
+            env: env::default_env(),
             info_log: None,
             write_buffer_size: 4 * 1024 * 1024,
             max_open_files: 1000,
@@ -157,6 +161,7 @@ impl Default for Options {
 }
 
 // Options that control read operations.
+#[derive(Clone)]
 pub struct ReadOptions {
     // If true, all data read from underlying storage will be verifiec against corresponding
     // checksums.
@@ -169,8 +174,9 @@ pub struct ReadOptions {
     // If "snapshot" is non-null, read as of the supplied snapshot (which must belong to the DB
     // that is being read and which must not have been released). If "snapshot" is null, use an
     // implicit snapshot of the state at the beginning of this read operation.
-    // NOTE: C++ uses nullptr
-    pub snapshot: Option<Box<dyn Snapshot>>,
+    // NOTE: C++ uses a nullable const pointer; an Arc lets the same snapshot be shared by several
+    // ReadOptions
+    pub snapshot: Option<Arc<Snapshot>>,
 }
 
 impl Default for ReadOptions {
@@ -184,6 +190,7 @@ impl Default for ReadOptions {
 }
 
 // Options that control write operations.
+#[derive(Default)]
 pub struct WriteOptions {
     // If true, the write will be flushed from the operating system buffer cache (by calling
     // WriteableFile::sync()) before the write is considered complete. If this flag is true,
@@ -198,9 +205,3 @@ pub struct WriteOptions {
     // call followed by "fsync()".
     pub sync: bool,
 }
-
-impl Default for WriteOptions {
-    fn default() -> Self {
-        Self { sync: false }
-    }
-}
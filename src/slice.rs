@@ -1,155 +1,135 @@
 // leveldb/include/leveldb/slice.h
 
-use std::alloc;
 use std::cmp::Ordering;
-use std::ffi::c_void;
-use std::mem;
-use std::ops::Index;
-use std::os::raw::{c_char, c_int};
-
-extern "C" {
-    pub fn memcmp(p1: *const c_void, p2: *const c_void, n: usize) -> c_int;
-}
-
-unsafe fn memcmp_rs<T, U>(p1: *const T, p2: *const U, n: usize) -> i32 {
-    memcmp(p1 as _, p2 as _, n) as i32
+use std::ops::Deref;
+
+// Slice is a borrowed view of a sequence of bytes.
+// NOTE: C++ stores a raw pointer and a length, so a Slice can outlive the data it refers to; here
+// it wraps a &[u8] and the borrow checker ties its lifetime to the underlying data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Slice<'a> {
+    data: &'a [u8],
 }
 
-// TODO: Possibly impose lifetime using PhantomData
-#[derive(Clone, Copy)]
-pub struct Slice {
-    data: *const c_char,
-    size: usize,
-}
-
-impl Slice {
+impl<'a> Slice<'a> {
+    // Create an empty slice.
     pub fn new() -> Self {
-        Default::default()
-    }
-
-    // NOTE: Possibly rename
-    pub fn from_raw(data: *const c_char, size: usize) -> Self {
-        Self { data, size }
+        Self { data: &[] }
     }
 
-    pub fn data(&self) -> *const c_char {
+    // Return the referenced bytes.
+    // NOTE: Unlike deref, the returned slice borrows the underlying data rather than the Slice
+    pub fn as_bytes(&self) -> &'a [u8] {
         self.data
     }
 
+    // Return the length (in bytes) of the referenced data.
     pub fn size(&self) -> usize {
-        self.size
+        self.data.len()
     }
 
+    // Return true iff the length of the referenced data is zero.
     pub fn empty(&self) -> bool {
-        self.size == 0
+        self.data.is_empty()
     }
 
+    // Change this slice to refer to an empty array.
     pub fn clear(&mut self) {
-        self.data = "".as_ptr().cast();
-        self.size = 0;
+        self.data = &[];
     }
 
+    // Drop the first "n" bytes from this slice.
     pub fn remove_prefix(&mut self, n: usize) {
         assert!(n <= self.size(), "remove index out of bounds");
-        unsafe {
-            self.data = self.data.add(n);
-        }
-        self.size -= n;
+        self.data = &self.data[n..];
     }
 
+    // Three-way comparison. Returns value:
+    //   <  0 iff "*this" <  "b",
+    //   == 0 iff "*this" == "b",
+    //   >  0 iff "*this" >  "b"
     pub fn compare(&self, b: &Slice) -> i32 {
-        let min_len = if self.size < b.size() {
-            self.size
-        } else {
-            b.size()
-        };
-        let mut r = unsafe { memcmp_rs(self.data, b.data(), min_len) };
-        if r == 0 {
-            if self.size < b.size() {
-                if self.size < b.size() {
-                    r = -1
-                } else if self.size > b.size() {
-                    r = 1;
-                }
-            }
+        match self.data.cmp(b.data) {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
         }
-        r
     }
 
+    // Return true iff "x" is a prefix of "*this".
     pub fn starts_with(&self, x: &Slice) -> bool {
-        unsafe { self.size >= x.size() && memcmp_rs(self.data, x.data(), x.size()) == 0 }
+        self.data.starts_with(x.data)
     }
 }
 
-impl Default for Slice {
-    fn default() -> Self {
-        Self::from_raw("".as_ptr().cast(), 0)
+impl Deref for Slice<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
     }
 }
 
-impl From<&str> for Slice {
-    fn from(s: &str) -> Self {
-        Self::from_raw(s.as_ptr().cast(), s.len())
+impl<'a> From<&'a str> for Slice<'a> {
+    fn from(s: &'a str) -> Self {
+        Self { data: s.as_bytes() }
     }
 }
 
-impl From<&String> for Slice {
-    fn from(s: &String) -> Self {
-        Self::from_raw(s.as_ptr().cast(), s.len())
+impl<'a> From<&'a [u8]> for Slice<'a> {
+    fn from(s: &'a [u8]) -> Self {
+        Self { data: s }
     }
 }
 
-impl ToString for Slice {
-    fn to_string(&self) -> String {
-        unsafe {
-            let l = self.size;
-            // NOTE: Possibly use std::process::die
-            let layout =
-                alloc::Layout::from_size_align(l, mem::align_of::<u8>()).expect("bad layout");
-            let buf = alloc::alloc(layout);
-            buf.copy_from(self.data as *const u8, l);
-            String::from_raw_parts(buf, l, l)
-        }
+impl<'a> From<&'a Vec<u8>> for Slice<'a> {
+    fn from(s: &'a Vec<u8>) -> Self {
+        Self { data: s }
     }
 }
 
-impl Index<usize> for Slice {
-    type Output = u8;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        assert!(index < self.size(), "index out of bounds");
-        //unsafe { &(*self.data.add(index) as u8) }
-        unsafe {
-            self.data
-                .add(index)
-                .cast::<u8>()
-                .as_ref()
-                .expect("null pointer")
-        }
+impl<'a> From<&'a String> for Slice<'a> {
+    fn from(s: &'a String) -> Self {
+        Self { data: s.as_bytes() }
     }
 }
 
-impl PartialEq for Slice {
-    fn eq(&self, b: &Slice) -> bool {
-        self.compare(b) == 0
+impl PartialOrd for Slice<'_> {
+    fn partial_cmp(&self, b: &Self) -> Option<Ordering> {
+        Some(self.cmp(b))
     }
 }
 
-impl Eq for Slice {}
-
-impl PartialOrd for Slice {
-    fn partial_cmp(&self, b: &Slice) -> Option<Ordering> {
-        match self.compare(b) {
-            x if x < 0 => Some(Ordering::Less),
-            0 => Some(Ordering::Equal),
-            x if x > 0 => Some(Ordering::Greater),
-            _ => unreachable!(),
-        }
+// Slices are ordered byte-wise, like memcmp.
+impl Ord for Slice<'_> {
+    fn cmp(&self, b: &Self) -> Ordering {
+        self.data.cmp(b.data)
     }
 }
 
-impl Ord for Slice {
-    fn cmp(&self, b: &Slice) -> Ordering {
-        self.partial_cmp(b).unwrap()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let a = Slice::from("abc");
+        assert_eq!(0, a.compare(&"abc".into()));
+        assert!(a.compare(&"abd".into()) < 0);
+        assert!(a.compare(&"ab".into()) > 0);
+        assert!(a < Slice::from("abcd"));
+        // Bytes compare as unsigned, like memcmp
+        assert!(Slice::from(&[0x7fu8][..]) < Slice::from(&[0x80u8][..]));
+    }
+
+    #[test]
+    fn test_remove_prefix() {
+        let mut s = Slice::from("hello");
+        assert!(s.starts_with(&"he".into()));
+        s.remove_prefix(2);
+        assert_eq!(b"llo", &*s);
+        assert_eq!(b'l', s[0]);
+        s.clear();
+        assert!(s.empty());
     }
 }
@@ -1,35 +1,552 @@
 // leveldb/table/block.{h,cc}
 
-use crate::{comparator, table::format, util::{coding, logging}};
-use std::os::raw::c_char;
+use crate::{
+    comparator::Comparator,
+    iter::{new_empty_iterator, new_error_iter, CleanupNode, Iter},
+    slice::Slice,
+    status::{Error, Result, Status},
+    table::format::BlockContents,
+    util::coding::{decode_fixed32, get_varint32_ptr},
+};
+use std::mem::size_of;
+use std::sync::Arc;
 
-pub struct BlockContents {
-}
+const U32_SIZE: usize = size_of::<u32>();
 
+// NOTE: Public so users can name the values of a block cache; it is opaque outside the crate
 pub struct Block {
-    data: *const c_char,
+    // NOTE: C++ uses a bool to tell whether the block owns data and deletes it when the block is
+    // destroyed; the block always owns its data here
+    data: Vec<u8>,
     size: usize,
-    restart_offset: u32, // Offset in "data" of restart array
-    owned: bool, // Block owns "data"
+    // Offset in data of restart array
+    restart_offset: u32,
 }
 
 impl Block {
-    // Initialize the block with the specified contents
-    pub fn new(contents: &BlockContents) -> Self {
+    // Initialize the block with the specified contents.
+    pub(crate) fn new(contents: BlockContents) -> Self {
+        let mut block = Self {
+            size: contents.data.len(),
+            data: contents.data,
+            restart_offset: 0,
+        };
+        if block.size < U32_SIZE {
+            // Error marker
+            block.size = 0;
+        } else {
+            let max_restarts_allowed = (block.size - U32_SIZE) / U32_SIZE;
+            if block.num_restarts() as usize > max_restarts_allowed {
+                // The size is too small for num_restarts()
+                block.size = 0;
+            } else {
+                block.restart_offset =
+                    (block.size - (1 + block.num_restarts() as usize) * U32_SIZE) as u32;
+            }
+        }
+        block
     }
 
-    pub fn size(&self) -> usize { self.size }
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
 
-    pub fn new_iterator(&mut self, comparator: &Comparator) -> Box<dyn Iter> {
+    // NOTE: C++ requires the caller to keep the block live while the returned iterator is in use;
+    // here the iterator holds a reference to it
+    pub(crate) fn new_iterator(self: &Arc<Self>, comparator: Arc<dyn Comparator>) -> Box<dyn Iter> {
+        if self.size < U32_SIZE {
+            return new_error_iter(Error::corruption(
+                &"bad block contents".into(),
+                &Slice::new(),
+            ));
+        }
+        let num_restarts = self.num_restarts();
+        if num_restarts == 0 {
+            new_empty_iterator()
+        } else {
+            Box::new(BlockIter {
+                comparator,
+                block: Arc::clone(self),
+                restarts: self.restart_offset,
+                num_restarts,
+                current: self.restart_offset,
+                restart_index: num_restarts,
+                key: Vec::new(),
+                value_offset: 0,
+                value_size: 0,
+                status: Status::OK(),
+                cleanup_head: CleanupNode::default(),
+            })
+        }
     }
 
     #[inline]
     fn num_restarts(&self) -> u32 {
-        assert!(self.size >= size_of::<u32>());
-        coding::decode_fixed32();
+        assert!(self.size >= U32_SIZE);
+        decode_fixed32(&self.data[self.size - U32_SIZE..])
+    }
+}
+
+// Helper routine: decode the next block entry starting at the front of "p", storing the number
+// of shared key bytes, non_shared key bytes, and the length of the value in "*shared",
+// "*non_shared", and "*value_length", respectively. Will not look past the end of "p".
+//
+// If any errors are detected, returns None. Otherwise, returns the rest of "p" starting at the
+// key delta (just past the three decoded values).
+#[inline]
+fn decode_entry<'a>(
+    mut p: &'a [u8],
+    shared: &mut u32,
+    non_shared: &mut u32,
+    value_length: &mut u32,
+) -> Option<&'a [u8]> {
+    if p.len() < 3 {
+        return None;
+    }
+    *shared = p[0] as u32;
+    *non_shared = p[1] as u32;
+    *value_length = p[2] as u32;
+    if (*shared | *non_shared | *value_length) < 128 {
+        // Fast path: all three values are encoded in one byte each
+        p = &p[3..];
+    } else {
+        p = get_varint32_ptr(p, shared)?;
+        p = get_varint32_ptr(p, non_shared)?;
+        p = get_varint32_ptr(p, value_length)?;
     }
+
+    if p.len() < (*non_shared + *value_length) as usize {
+        return None;
+    }
+    Some(p)
 }
 
 // NOTE: C++ calls it "Iter" and is privately declared in "Block"
 struct BlockIter {
+    comparator: Arc<dyn Comparator>,
+    // Underlying block contents
+    // NOTE: C++ keeps a pointer to the data; holding the block also keeps the data alive
+    block: Arc<Block>,
+    // Offset of restart array (list of fixed32)
+    restarts: u32,
+    // Number of u32 entries in restart array
+    num_restarts: u32,
+
+    // current is offset in data of current entry. >= restarts if !valid()
+    current: u32,
+    // Index of restart block in which current falls
+    restart_index: u32,
+    key: Vec<u8>,
+    // Offset in data and size of the current value
+    // NOTE: C++ keeps the value as a Slice into data
+    value_offset: u32,
+    value_size: u32,
+    status: Status,
+
+    cleanup_head: CleanupNode,
+}
+
+impl BlockIter {
+    #[inline]
+    fn compare(&self, a: &Slice, b: &Slice) -> i32 {
+        self.comparator.compare(a, b)
+    }
+
+    // Return the offset in data just past the end of the current entry.
+    #[inline]
+    fn next_entry_offset(&self) -> u32 {
+        self.value_offset + self.value_size
+    }
+
+    fn get_restart_point(&self, index: u32) -> u32 {
+        assert!(index < self.num_restarts);
+        decode_fixed32(&self.block.data[self.restarts as usize + index as usize * U32_SIZE..])
+    }
+
+    fn seek_to_restart_point(&mut self, index: u32) {
+        self.key.clear();
+        self.restart_index = index;
+        // current will be fixed by parse_next_key()
+
+        // parse_next_key() starts at the end of value, so set value accordingly
+        self.value_offset = self.get_restart_point(index);
+        self.value_size = 0;
+    }
+
+    fn corruption_error(&mut self) {
+        self.current = self.restarts;
+        self.restart_index = self.num_restarts;
+        self.status = Status::corruption(&"bad entry in block".into(), &Slice::new());
+        self.key.clear();
+        self.value_size = 0;
+    }
+
+    fn parse_next_key(&mut self) -> bool {
+        self.current = self.next_entry_offset();
+        if self.current >= self.restarts {
+            // No more entries to return. Mark as invalid.
+            self.current = self.restarts;
+            self.restart_index = self.num_restarts;
+            return false;
+        }
+        let p = &self.block.data[self.current as usize..self.restarts as usize];
+
+        // Decode next entry
+        let (mut shared, mut non_shared, mut value_length) = (0, 0, 0);
+        match decode_entry(p, &mut shared, &mut non_shared, &mut value_length) {
+            Some(delta) if self.key.len() >= shared as usize => {
+                self.key.truncate(shared as usize);
+                self.key.extend_from_slice(&delta[..non_shared as usize]);
+                self.value_offset = self.current + (p.len() - delta.len()) as u32 + non_shared;
+                self.value_size = value_length;
+                while self.restart_index + 1 < self.num_restarts
+                    && self.get_restart_point(self.restart_index + 1) < self.current
+                {
+                    self.restart_index += 1;
+                }
+                true
+            }
+            _ => {
+                self.corruption_error();
+                false
+            }
+        }
+    }
+}
+
+impl Iter for BlockIter {
+    fn cleanup_head(&mut self) -> &mut CleanupNode {
+        &mut self.cleanup_head
+    }
+
+    fn valid(&self) -> bool {
+        self.current < self.restarts
+    }
+
+    fn status(&self) -> Result<()> {
+        self.status.clone().into()
+    }
+
+    fn key(&self) -> Slice<'_> {
+        assert!(self.valid());
+        Slice::from(&self.key)
+    }
+
+    fn value(&self) -> Slice<'_> {
+        assert!(self.valid());
+        let start = self.value_offset as usize;
+        Slice::from(&self.block.data[start..start + self.value_size as usize])
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.parse_next_key();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+
+        // Scan backwards to a restart point before current
+        let original = self.current;
+        while self.get_restart_point(self.restart_index) >= original {
+            if self.restart_index == 0 {
+                // No more entries
+                self.current = self.restarts;
+                self.restart_index = self.num_restarts;
+                return;
+            }
+            self.restart_index -= 1;
+        }
+
+        self.seek_to_restart_point(self.restart_index);
+        // Loop until end of current entry hits the start of original entry
+        while self.parse_next_key() && self.next_entry_offset() < original {}
+    }
+
+    fn seek(&mut self, target: &Slice) {
+        // Binary search in restart array to find the last restart point with a key < target
+        let mut left = 0;
+        let mut right = self.num_restarts - 1;
+        let mut current_key_compare = 0;
+
+        if self.valid() {
+            // If we're already scanning, use the current position as a starting point. This is
+            // beneficial if the key we're seeking to is ahead of the current position.
+            current_key_compare = self.compare(&Slice::from(&self.key), target);
+            if current_key_compare < 0 {
+                // key is smaller than target
+                left = self.restart_index;
+            } else if current_key_compare > 0 {
+                right = self.restart_index;
+            } else {
+                // We're seeking to the key we're already at.
+                return;
+            }
+        }
+
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            let region_offset = self.get_restart_point(mid);
+            let (mut shared, mut non_shared, mut value_length) = (0, 0, 0);
+            // A corrupt restart point may lie past the restart array; treat it as an empty entry
+            let region = self
+                .block
+                .data
+                .get(region_offset as usize..self.restarts as usize)
+                .unwrap_or_default();
+            let key_ptr = decode_entry(region, &mut shared, &mut non_shared, &mut value_length);
+            let key_ptr = match key_ptr {
+                Some(key_ptr) if shared == 0 => key_ptr,
+                _ => {
+                    self.corruption_error();
+                    return;
+                }
+            };
+            let mid_key = Slice::from(&key_ptr[..non_shared as usize]);
+            if self.compare(&mid_key, target) < 0 {
+                // Key at "mid" is smaller than "target". Therefore all blocks before "mid" are
+                // uninteresting.
+                left = mid;
+            } else {
+                // Key at "mid" is >= "target". Therefore all blocks at or after "mid" are
+                // uninteresting.
+                right = mid - 1;
+            }
+        }
+
+        // We might be able to use our current position within the restart block. This is true if
+        // we determined the key we desire is in the current block and is after than the current
+        // key.
+        assert!(current_key_compare == 0 || self.valid());
+        let skip_seek = left == self.restart_index && current_key_compare < 0;
+        if !skip_seek {
+            self.seek_to_restart_point(left);
+        }
+        // Linear search (within restart block) for first key >= target
+        loop {
+            if !self.parse_next_key() {
+                return;
+            }
+            if self.compare(&Slice::from(&self.key), target) >= 0 {
+                return;
+            }
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.seek_to_restart_point(0);
+        self.parse_next_key();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.seek_to_restart_point(self.num_restarts - 1);
+        while self.parse_next_key() && self.next_entry_offset() < self.restarts {
+            // Keep skipping
+        }
+    }
+}
+
+impl Drop for BlockIter {
+    fn drop(&mut self) {
+        self.drop_func();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::bytewise_comparator;
+    use crate::options::Options;
+    use crate::table::block_builder::BlockBuilder;
+    use crate::util::{random::Random, testutil};
+
+    fn new_block(data: Vec<u8>) -> Arc<Block> {
+        Arc::new(Block::new(BlockContents {
+            data,
+            cachable: false,
+        }))
+    }
+
+    fn build_block<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        restart_interval: i32,
+        entries: &[(K, V)],
+    ) -> Arc<Block> {
+        let options = Options {
+            block_restart_interval: restart_interval,
+            ..Default::default()
+        };
+        let mut builder = BlockBuilder::new(&options);
+        for (k, v) in entries {
+            builder.add(&k.as_ref().into(), &v.as_ref().into());
+        }
+        new_block(builder.finish().as_bytes().to_vec())
+    }
+
+    fn new_iter(block: &Arc<Block>) -> Box<dyn Iter> {
+        block.new_iterator(Arc::new(bytewise_comparator()))
+    }
+
+    fn entries(n: usize) -> Vec<(String, String)> {
+        let mut rnd = Random::new(301);
+        (0..n)
+            .map(|i| {
+                let value = (0..rnd.uniform(20)).map(|_| 'v').collect();
+                (format!("key{:06}", i * 2), value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_empty() {
+        let block = build_block::<&str, &str>(16, &[]);
+        let mut iter = new_iter(&block);
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+        iter.seek(&"foo".into());
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn test_iterate() {
+        for restart_interval in [1, 2, 16, 1024] {
+            let entries = entries(200);
+            let block = build_block(restart_interval, &entries);
+            let mut iter = new_iter(&block);
+
+            iter.seek_to_first();
+            for (k, v) in &entries {
+                assert!(iter.valid());
+                assert_eq!(k.as_bytes(), iter.key().as_bytes());
+                assert_eq!(v.as_bytes(), iter.value().as_bytes());
+                iter.next();
+            }
+            assert!(!iter.valid());
+
+            iter.seek_to_last();
+            for (k, v) in entries.iter().rev() {
+                assert!(iter.valid());
+                assert_eq!(k.as_bytes(), iter.key().as_bytes());
+                assert_eq!(v.as_bytes(), iter.value().as_bytes());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+            assert!(iter.status().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_seek() {
+        for restart_interval in [1, 3, 16] {
+            let entries = entries(100);
+            let block = build_block(restart_interval, &entries);
+            let mut iter = new_iter(&block);
+
+            for (i, (k, _)) in entries.iter().enumerate() {
+                // Exact match
+                iter.seek(&k.into());
+                assert!(iter.valid());
+                assert_eq!(k.as_bytes(), iter.key().as_bytes());
+
+                // Keys are even numbers, so an odd key lands on the following entry
+                iter.seek(&format!("key{:06}", i * 2 + 1).as_str().into());
+                if i + 1 < entries.len() {
+                    assert!(iter.valid());
+                    assert_eq!(entries[i + 1].0.as_bytes(), iter.key().as_bytes());
+                    iter.prev();
+                    assert_eq!(k.as_bytes(), iter.key().as_bytes());
+                } else {
+                    assert!(!iter.valid());
+                }
+            }
+
+            // Seeking backwards from the current position
+            iter.seek(&entries[50].0.as_str().into());
+            iter.seek(&"a".into());
+            assert!(iter.valid());
+            assert_eq!(entries[0].0.as_bytes(), iter.key().as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_too_small() {
+        for data in [vec![], vec![0, 0, 0]] {
+            let block = new_block(data);
+            assert_eq!(0, block.size());
+            let mut iter = new_iter(&block);
+            iter.seek_to_first();
+            assert!(!iter.valid());
+            assert!(iter.status().unwrap_err().is_corruption());
+        }
+    }
+
+    #[test]
+    fn test_bad_num_restarts() {
+        let mut data = build_block(16, &entries(10)).as_bytes_for_test();
+        let len = data.len();
+        data[len - 4..].copy_from_slice(&1000u32.to_le_bytes());
+        let block = new_block(data);
+        assert_eq!(0, block.size());
+        let iter = new_iter(&block);
+        assert!(iter.status().unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn test_bad_entry() {
+        let entries = entries(10);
+        let mut data = build_block(16, &entries).as_bytes_for_test();
+        // Claim the first entry shares bytes with a nonexistent previous key
+        data[0] = 5;
+        let block = new_block(data);
+        let mut iter = new_iter(&block);
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        assert!(iter.status().unwrap_err().is_corruption());
+
+        // Truncate the value of the only entry so it runs into the restart array
+        let mut data = build_block(16, &entries[..1]).as_bytes_for_test();
+        data[2] = 100;
+        let block = new_block(data);
+        let mut iter = new_iter(&block);
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        assert!(iter.status().unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        // Shared key prefixes are byte ranges, so they may split multi-byte characters
+        let mut rnd = Random::new(301);
+        for _ in 0..50 {
+            let entries = testutil::random_entries(&mut rnd, 200, 12, 30);
+            let restart_interval = 1 + rnd.uniform(16) as i32;
+            let block = build_block(restart_interval, &entries);
+            let mut iter = new_iter(&block);
+
+            iter.seek_to_first();
+            for (k, v) in &entries {
+                assert!(iter.valid());
+                assert_eq!(&k[..], &*iter.key());
+                assert_eq!(&v[..], &*iter.value());
+                iter.next();
+            }
+            assert!(!iter.valid());
+
+            for (k, v) in &entries {
+                iter.seek(&k.into());
+                assert!(iter.valid());
+                assert_eq!(&k[..], &*iter.key());
+                assert_eq!(&v[..], &*iter.value());
+            }
+            assert!(iter.status().is_ok());
+        }
+    }
+
+    impl Block {
+        fn as_bytes_for_test(&self) -> Vec<u8> {
+            self.data[..self.size].to_vec()
+        }
+    }
 }
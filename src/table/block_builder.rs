@@ -22,43 +22,53 @@
 //     num_restarts: u32
 // restarts[i] contains the offset within the block of the ith restart point.
 
-use crate::{
-    slice::Slice,
-    options::Options,
-    util::coding,
-};
-use std::slice;
+use crate::{comparator::Comparator, options::Options, slice::Slice, util::coding};
+use std::sync::Arc;
 
-pub(crate) struct BlockBuilder<'a> {
-    // NOTE: C++ uses const pointer
-    options: &'a Options,
-    buffer: String, // Destination buffer
-    restarts: Vec<u32>, // Restart points
+pub(crate) struct BlockBuilder {
+    // NOTE: C++ keeps a const pointer to the options; only the fields used are copied so that
+    // TableBuilder can own its builders (see set_block_restart_interval)
+    comparator: Arc<dyn Comparator>,
+    block_restart_interval: i32,
+    // Destination buffer
+    buffer: Vec<u8>,
+    // Restart points
+    restarts: Vec<u32>,
+    // Number of entries emitted since restart
     // NOTE: C++ uses int, possibly use different type
-    counter: i32, // Number of entries emitted since restart
-    finished: bool, // Has finish() been called?
-    last_key: String,
+    counter: i32,
+    // Has finish() been called?
+    finished: bool,
+    last_key: Vec<u8>,
 }
 
-impl<'a> BlockBuiler<'a> {
+impl BlockBuilder {
     // NOTE: C++ uses a const pointer
-    fn new(options: &Options) -> Self {
+    pub(crate) fn new(options: &Options) -> Self {
         assert!(options.block_restart_interval >= 1);
-        let restarts = vec![0]; // First restart point is at offset 0.
         Self {
-            options,
-            buffer: String::new(),
-            restarts,
+            comparator: Arc::clone(&options.comparator),
+            block_restart_interval: options.block_restart_interval,
+            buffer: Vec::new(),
+            // First restart point is at offset 0
+            restarts: vec![0],
             counter: 0,
             finished: false,
-            last_key: String::new(),
+            last_key: Vec::new(),
         }
     }
 
+    // NOTE: C++ changes the options the builder points to instead
+    pub(crate) fn set_block_restart_interval(&mut self, block_restart_interval: i32) {
+        assert!(block_restart_interval >= 1);
+        self.block_restart_interval = block_restart_interval;
+    }
+
     // Reset the contents as if the BlockBuilder was just constructed.
-    fn reset(&mut self) {
+    pub(crate) fn reset(&mut self) {
         self.buffer.clear();
         self.restarts.clear();
+        // First restart point is at offset 0
         self.restarts.push(0);
         self.counter = 0;
         self.finished = false;
@@ -67,14 +77,16 @@ impl<'a> BlockBuiler<'a> {
 
     // REQUIRES: finish() has not been called since last call to reset().
     // REQUIRES: key is larger than any previously added key.
-    fn add(&mut self, key: &Slice, value: &Slice) {
-        let last_key_pirce = self.last_key;
+    pub(crate) fn add(&mut self, key: &Slice, value: &Slice) {
+        let last_key_piece = Slice::from(&self.last_key);
         assert!(!self.finished);
-        assert!(self.counter <= self.options.block_restart_interval);
-        assert!(self.buffer.is_empty() // No values yet?
-            || self.options.comparator.compare(&key, &last_key_piece) > 0);
+        assert!(self.counter <= self.block_restart_interval);
+        assert!(
+            // No values yet?
+            self.buffer.is_empty() || self.comparator.compare(key, &last_key_piece) > 0
+        );
         let mut shared = 0usize;
-        if self.counter < self.options.block_restart_interval {
+        if self.counter < self.block_restart_interval {
             // See how much sharing to do with previous string
             let min_length = last_key_piece.size().min(key.size());
             while shared < min_length && last_key_piece[shared] == key[shared] {
@@ -87,30 +99,26 @@ impl<'a> BlockBuiler<'a> {
         }
         let non_shared = key.size() - shared;
 
-        // Add "<shared><non_shared><value_size>" to self.buffer.
+        // Add "<shared><non_shared><value_size>" to buffer
         coding::put_varint32(&mut self.buffer, shared as u32);
         coding::put_varint32(&mut self.buffer, non_shared as u32);
         coding::put_varint32(&mut self.buffer, value.size() as u32);
 
-        unsafe {
-            let buffer = self.buffer.as_mut_vec();
-            // Add string delta to self.buffer followed by value.
-            buffer.extend_from_slice(slice::from_raw_parts(key.data().add(shared), non_shared));
-            buffer.extend_from_slice(slice::from_raw_parts(value.data(), value.size()));
+        // Add string delta to buffer followed by value
+        self.buffer.extend_from_slice(&key.as_bytes()[shared..]);
+        self.buffer.extend_from_slice(value.as_bytes());
 
-            // Update state
-            let last_key = self.last_key.as_mut_vec();
-            last_key.resize(shared, 0u8);
-            last_key.extend_from_slice(slice::from_raw_parts(key.data().add(shared), non_shared));
-        }
-        assert_eq!(Slice::from(&self.last_key) == key);
+        // Update state
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key.as_bytes()[shared..]);
+        assert!(Slice::from(&self.last_key) == *key);
         self.counter += 1;
     }
 
     // Finish building the block and return a slice that refers to the block contents. The
-    // retuned slice will remain valid for the lifetime of this builder or until reset() is
+    // returned slice will remain valid for the lifetime of this builder or until reset() is
     // called.
-    fn finish(&mut self) -> Slice {
+    pub(crate) fn finish(&mut self) -> Slice<'_> {
         // Append restart array
         for i in 0..self.restarts.len() {
             coding::put_fixed32(&mut self.buffer, self.restarts[i]);
@@ -121,15 +129,15 @@ impl<'a> BlockBuiler<'a> {
     }
 
     // Returns an estimate of the current (uncompressed) size of the block we are building.
-    fn current_size_estimate(&self) -> usize {
+    pub(crate) fn current_size_estimate(&self) -> usize {
         const U32_SIZE: usize = std::mem::size_of::<u32>();
-        self.buffer.len() + // Raw data buffer
-            self.restarts.len() * U32_SIZE + // Restart array
-            U32_SIZE // Restart array length
+        self.buffer.len() // Raw data buffer
+            + self.restarts.len() * U32_SIZE // Restart array
+            + U32_SIZE // Restart array length
     }
 
-    // Return true iff no entries have been added since the last reset().
-    fn empty(&self) -> bool {
+    // Return true iff no entries have been added since the last reset()
+    pub(crate) fn empty(&self) -> bool {
         self.buffer.is_empty()
     }
 }
@@ -1,54 +1,49 @@
 // leveldb/table/filter_block.{h,cc}
+// leveldb/table/filter_block_test.cc
 
-use crate::{
-    coding,
-    filter_policy::FilterPolicy,
-    slice::Slice,
-};
-use std::ffi::c_char;
-use std::slice;
+// A filter block is stored near the end of a Table file. It contains filters (e.g., bloom
+// filters) for all data blocks in the table combined into a single filter block.
+
+use crate::{filter_policy::FilterPolicy, slice::Slice, util::coding};
+use std::sync::Arc;
 
-// TODO: Below
 // See doc/table_format.md for an explanation of the filter block format.
 
-// Generate new filter every 2kb of data
+// Generate new filter every 2KB of data
 const FILTER_BASE_LG: usize = 11;
 const FILTER_BASE: usize = 1 << FILTER_BASE_LG;
 
-// A FilterBlockBuilder is used to construt all of the filters for a particular Table. It
+// A FilterBlockBuilder is used to construct all of the filters for a particular Table. It
 // generates a single string which is stored as a special block in the Table.
 //
 // The sequence of calls to FilterBlockBuilder must match the regexp:
-//      (StartBlock AddKey*)* Finish
+//      (start_block add_key*)* finish
 pub(crate) struct FilterBlockBuilder {
-    // NOTE: C++ uses const*, possibly use reference
-    // NOTE: doing this as a nasty psuedo-work-around so that policy can be called without unsafe.
-    // The C++ code never checks for nullptr so I won't either.
-    // TODO: Change drop() method when changing this
-    policy: Box<dyn FilterPolicy>,
-    keys: String, // Flattened key contents
-    start: Vec<usize>, // Starting index in self.keys of each key
-    result: String, // Filter data computed so far
-    tmp_keys: Vec<Slice>, // policy.create_filter() argument
+    // NOTE: C++ uses a const pointer
+    policy: Arc<dyn FilterPolicy>,
+    // Flattened key contents
+    keys: Vec<u8>,
+    // Starting index in keys of each key
+    start: Vec<usize>,
+    // Filter data computed so far
+    result: Vec<u8>,
+    // NOTE: C++ keeps the policy.create_filter() argument in a tmp_keys member; those slices
+    // borrow keys, so generate_filter() builds them on each call instead
     filter_offsets: Vec<u32>,
 }
 
 impl FilterBlockBuilder {
-    // TODO: policy
-    pub fn new(policy: &dyn FilterPolicy>) -> Self {
+    pub(crate) fn new(policy: Arc<dyn FilterPolicy>) -> Self {
         Self {
-            policy: unsafe {
-                Box::from_raw(policy as *const dyn FilterPolicy as *mut dyn FilterPolicy)
-            },
-            keys: String::new(),
+            policy,
+            keys: Vec::new(),
             start: Vec::new(),
-            result: String::new(),
-            tmp_keys: Vec::new(),
+            result: Vec::new(),
             filter_offsets: Vec::new(),
         }
     }
 
-    pub fn start_block(&mut self, block_offset: u64) {
+    pub(crate) fn start_block(&mut self, block_offset: u64) {
         let filter_index = block_offset / FILTER_BASE as u64;
         assert!(filter_index >= self.filter_offsets.len() as u64);
         while filter_index > self.filter_offsets.len() as u64 {
@@ -56,26 +51,25 @@ impl FilterBlockBuilder {
         }
     }
 
-    pub fn add_key(&mut self, key: &Slice) {
-        let k = key;
+    pub(crate) fn add_key(&mut self, key: &Slice) {
         self.start.push(self.keys.len());
-        unsafe { self.keys.as_mut_vec().extend_from_slice(slice::from_raw(k.data(), k.size())) };
+        self.keys.extend_from_slice(key.as_bytes());
     }
 
-    pub fn finish(&mut self) -> Slice {
-        if self.start.is_empty() {
+    pub(crate) fn finish(&mut self) -> Slice<'_> {
+        if !self.start.is_empty() {
             self.generate_filter();
         }
 
-        // Append array of per-filter offsets.
+        // Append array of per-filter offsets
         let array_offset = self.result.len() as u32;
         for i in 0..self.filter_offsets.len() {
-            let fo = self.filter_offsets[i] as u32;
-            coding::put_fixed32(&mut self.result, fo);
+            coding::put_fixed32(&mut self.result, self.filter_offsets[i]);
         }
 
         coding::put_fixed32(&mut self.result, array_offset);
-        self.result.push(FILTER_BASE_LG); // Save encoding parameter in result
+        // Save encoding parameter in result
+        self.result.push(FILTER_BASE_LG as u8);
         Slice::from(&self.result)
     }
 
@@ -83,112 +77,113 @@ impl FilterBlockBuilder {
         let num_keys = self.start.len();
         if num_keys == 0 {
             // Fast path if there are no keys for this filter
-            self.filter_offsets.push(self.result.len());
+            self.filter_offsets.push(self.result.len() as u32);
             return;
         }
 
         // Make list of keys from flattened key structure
-        self.start.push(self.keys.size()); // Simplify length computation
-        self.tmp_keys.resize(num_keys, Slice::new());
-        for i in 0..num_keys {
-            let base = unsafe { self.keys.as_ptr().cast() }
-            let length = self.start[i + 1] - self.start[0];
-            self.tmp_keys[i] = Slice::from_raw(base, length);
-        }
-
-        // Generate filter for current set of keys and append to self.result.
-        self.filter_offsets.push(self.result.len());
-        let sl = &self.tmp_keys[0] as *const [Slice; 1];
-        unsafe { self.policy.create_filter(&*sl, num_keys, &mut self.result) };
+        // Simplify length computation
+        self.start.push(self.keys.len());
+        let keys = &self.keys;
+        let tmp_keys: Vec<Slice> = self
+            .start
+            .windows(2)
+            .map(|w| Slice::from(&keys[w[0]..w[1]]))
+            .collect();
+
+        // Generate filter for current set of keys and append to result.
+        self.filter_offsets.push(self.result.len() as u32);
+        self.policy
+            .create_filter(&tmp_keys, num_keys, &mut self.result);
 
-        self.tmp_keys.clear();
         self.keys.clear();
         self.start.clear();
     }
 }
 
-impl Drop for FilterBlockBuiler {
-    fn drop(&mut self) {
-        // TODO: Remove when changing self.policy
-        std::mem::forget(self.policy);
-    }
-}
-
 pub(crate) struct FilterBlockReader {
-    // NOTE: C++ uses const*, possibly use reference
-    policy: Box<dyn FilterPolicy>,
-    data: *const c_char, // Pointer to filter data (at block-start)
-    offset: *const c_char, // Pointer to beginning of offset array (at block-end)
-    num: usize, // Number of entries in offset array
-    base_lg: usize // Encoding parameter (see FILTER_BASE_LG)
+    // NOTE: C++ uses a const pointer
+    policy: Arc<dyn FilterPolicy>,
+    // Filter data (at block-start) followed by the offset array (at block-end)
+    // NOTE: C++ keeps pointers into contents that the table owns; the reader owns them here
+    data: Vec<u8>,
+    // Offset in data of the beginning of the offset array
+    offset: usize,
+    // Number of entries in offset array
+    num: usize,
+    // Encoding parameter (see FILTER_BASE_LG in the .rs file)
+    base_lg: usize,
 }
 
 impl FilterBlockReader {
-    pub fn new(policy: Box<dyn FilterPolicy>, contents: &Slice) -> Self {
+    pub(crate) fn new(policy: Arc<dyn FilterPolicy>, contents: Vec<u8>) -> Self {
         let mut reader = Self {
             policy,
-            data: 0 as *const _,
-            offset: 0 as *const _,
+            data: Vec::new(),
+            offset: 0,
             num: 0,
             base_lg: 0,
         };
-        let n = contents.size();
+        let n = contents.len();
         // 1 byte for base_lg and 4 for start of offset array
         if n < 5 {
             return reader;
         }
-        reader.base_lg = contents[n - 1];
-        unsafe {
-            let last_word = coding::decode_fixed32(contents.data().add(n - 5));
-            if last_word > n - 5 {
-                return reader;
-            }
-            reader.data = contents.data();
-            reader.offset = reader.data.add(last_word as usize);
-            reader.num = (n - 5 - last_word) / 4;
+        reader.base_lg = contents[n - 1] as usize;
+        let last_word = coding::decode_fixed32(&contents[n - 5..]) as usize;
+        if last_word > n - 5 {
+            return reader;
         }
+        reader.data = contents;
+        reader.offset = last_word;
+        reader.num = (n - 5 - last_word) / 4;
         reader
     }
 
-    pub fn key_may_match(&mut self, block_offset: u64, key: &Slice) -> bool {
-        let index = block_offset >> self.base_lg as u64;
+    pub(crate) fn key_may_match(&self, block_offset: u64, key: &Slice) -> bool {
+        let index = block_offset >> self.base_lg;
         if index < self.num as u64 {
-            unsafe {
-                let start = coding::decode_fixed32(self.offset.add(index as usize * 4)) as usize;
-                let limit = coding::decode_fixed32(self.offset.add(index as usize * 4 + 4)) as usize;
-                if start <= limit && limit <= self.offset as usize - self.data as usize {
-                    return policy.key_may_match(key, Slice::from_raw(self.data.add(start), limit - start);
-                } else if start == limit {
-                    // Empty filters do not match any keys
-                    return false;
-                }
+            let index = index as usize;
+            let offset = &self.data[self.offset..];
+            let start = coding::decode_fixed32(&offset[index * 4..]) as usize;
+            let limit = coding::decode_fixed32(&offset[index * 4 + 4..]) as usize;
+            if start <= limit && limit <= self.offset {
+                let filter = Slice::from(&self.data[start..limit]);
+                return self.policy.key_may_match(key, &filter);
+            } else if start == limit {
+                // Empty filters do not match any keys
+                return false;
             }
         }
-        return true; // Errors are treated as potential matches
+        // Errors are treated as potential matches
+        true
     }
 }
 
-#[cfg(tests)]
+#[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{filter_policy::, util::{hash, logging}};
+    use crate::util::{hash, logging, random::Random, testutil};
 
     struct TestHashFilter;
 
     impl FilterPolicy for TestHashFilter {
-        fn name(&self) -> &'static str { "TestHashFilter" }
+        fn name(&self) -> &'static str {
+            "TestHashFilter"
+        }
 
-        fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut String) {
-            for i in 0..n {
-                coding::put_fixed32(dst, hash::hash(keys[i].data(), keys[i].size(), 1));
+        fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut Vec<u8>) {
+            for key in &keys[..n] {
+                let h = hash::hash(key, 1);
+                coding::put_fixed32(dst, h);
             }
         }
 
         fn key_may_match(&self, key: &Slice, filter: &Slice) -> bool {
-            let h = hash::hash(key.data(), key.size(), 1);
+            let h = hash::hash(key, 1);
             let mut i = 0;
-            while i + 4 < filter.size() {
-                if h == coding::decode_fixed32(unsafe { filter.data().add(i) }) {
+            while i + 4 <= filter.size() {
+                if h == coding::decode_fixed32(&filter[i..]) {
                     return true;
                 }
                 i += 4;
@@ -197,86 +192,135 @@ mod tests {
         }
     }
 
-    const POLICY: TestHashFilter = TestHashFilter;
+    fn policy() -> Arc<dyn FilterPolicy> {
+        Arc::new(TestHashFilter)
+    }
 
     #[test]
     fn test_empty_builder() {
-        let mut builder = FilterBlockBuilder(&POLICY);
+        let mut builder = FilterBlockBuilder::new(policy());
         let block = builder.finish();
-        assert_eq!("\\x00\\x00\\x00\\x00\\x0b", logging::escape_string(block));
-        let mut reader = FilterBlockReader::new(&POLICY, &block);
-        assert!(reader.key_may_match(0, Slice::from("foo")));
-        assert!(reader.key_may_match(100_000, Slice::from("foo")));
+        assert_eq!("\\x00\\x00\\x00\\x00\\x0b", logging::escape_string(&block));
+        let reader = FilterBlockReader::new(policy(), block.to_vec());
+        assert!(reader.key_may_match(0, &"foo".into()));
+        assert!(reader.key_may_match(100000, &"foo".into()));
     }
 
     #[test]
     fn test_single_chunk() {
-        let mut builder = FilterBlockBuilder(&POLICY);
+        let mut builder = FilterBlockBuilder::new(policy());
         builder.start_block(100);
-        builder.add_key(Slice::from("foo"));
-        builder.add_key(Slice::from("bar"));
-        builder.add_key(Slice::from("box"));
+        builder.add_key(&"foo".into());
+        builder.add_key(&"bar".into());
+        builder.add_key(&"box".into());
         builder.start_block(200);
-        builder.add_key(Slice::from("box"));
+        builder.add_key(&"box".into());
         builder.start_block(300);
-        builder.add_key(Slice::from("hello"));
+        builder.add_key(&"hello".into());
         let block = builder.finish();
-        let mut reader = FilterBlockReader::new(&POLICY, &block);
-        assert!(reader.key_may_match(100, "foo"));
-        assert!(reader.key_may_match(100, "bar"));
-        assert!(reader.key_may_match(100, "box"));
-        assert!(reader.key_may_match(100, "hello"));
-        assert!(reader.key_may_match(100, "foo"));
-        assert!(!reader.key_may_match(100, "missing"));
-        assert!(!reader.key_may_match(100, "other"));
+        let reader = FilterBlockReader::new(policy(), block.to_vec());
+        assert!(reader.key_may_match(100, &"foo".into()));
+        assert!(reader.key_may_match(100, &"bar".into()));
+        assert!(reader.key_may_match(100, &"box".into()));
+        assert!(reader.key_may_match(100, &"hello".into()));
+        assert!(reader.key_may_match(100, &"foo".into()));
+        assert!(!reader.key_may_match(100, &"missing".into()));
+        assert!(!reader.key_may_match(100, &"other".into()));
     }
 
     #[test]
     fn test_multi_chunk() {
-        let mut builder = FilterBlockBuilder(&POLICY);
+        let mut builder = FilterBlockBuilder::new(policy());
 
         // First filter
         builder.start_block(0);
-        builder.add_key("foo");
+        builder.add_key(&"foo".into());
         builder.start_block(2000);
-        builder.add_key("bar");
+        builder.add_key(&"bar".into());
 
         // Second filter
         builder.start_block(3100);
-        builder.add_key("box");
+        builder.add_key(&"box".into());
 
         // Third filter is empty
 
         // Last filter
         builder.start_block(9000);
-        builder.add_key("box");
-        builder.add_key("hello");
+        builder.add_key(&"box".into());
+        builder.add_key(&"hello".into());
 
         let block = builder.finish();
-        let mut reader = FilterBlockReader::new(&POLICY, &block);
+        let reader = FilterBlockReader::new(policy(), block.to_vec());
 
         // Check first filter
-        assert!(reader.key_may_match(0, "foo"));
-        assert!(reader.key_may_match(2000, "bar"));
-        assert!(!reader.key_may_match(0, "box"));
-        assert!(!reader.key_may_match(0, "hello"));
+        assert!(reader.key_may_match(0, &"foo".into()));
+        assert!(reader.key_may_match(2000, &"bar".into()));
+        assert!(!reader.key_may_match(0, &"box".into()));
+        assert!(!reader.key_may_match(0, &"hello".into()));
 
         // Check second filter
-        assert!(reader.key_may_match(3100, "box"));
-        assert!(!reader.key_may_match(3100, "foo"));
-        assert!(!reader.key_may_match(3100, "bar"));
-        assert!(!reader.key_may_match(3100, "hello"));
+        assert!(reader.key_may_match(3100, &"box".into()));
+        assert!(!reader.key_may_match(3100, &"foo".into()));
+        assert!(!reader.key_may_match(3100, &"bar".into()));
+        assert!(!reader.key_may_match(3100, &"hello".into()));
 
         // Check third filter (empty)
-        assert!(!reader.key_may_match(4100, "foo"));
-        assert!(!reader.key_may_match(4100, "bar"));
-        assert!(!reader.key_may_match(4100, "box"));
-        assert!(!reader.key_may_match(4100, "hello"));
+        assert!(!reader.key_may_match(4100, &"foo".into()));
+        assert!(!reader.key_may_match(4100, &"bar".into()));
+        assert!(!reader.key_may_match(4100, &"box".into()));
+        assert!(!reader.key_may_match(4100, &"hello".into()));
 
         // Check last filter
-        assert!(reader.key_may_match(9000, "box"));
-        assert!(reader.key_may_match(9000, "hello"));
-        assert!(!reader.key_may_match(9000, "foo"));
-        assert!(!reader.key_may_match(9000, "bar"));
+        assert!(reader.key_may_match(9000, &"box".into()));
+        assert!(reader.key_may_match(9000, &"hello".into()));
+        assert!(!reader.key_may_match(9000, &"foo".into()));
+        assert!(!reader.key_may_match(9000, &"bar".into()));
+    }
+
+    #[test]
+    fn test_binary_keys() {
+        // Keys are hashed as raw bytes, so keys that aren't valid UTF-8 must still match
+        let mut rnd = Random::new(301);
+        // 0xfe and 0xff never appear in UTF-8, and the distinct prefixes keep the sets disjoint
+        let key = |rnd: &mut Random, prefix: u8| {
+            let len = rnd.uniform(16) as usize;
+            let mut k = vec![prefix];
+            k.extend(testutil::random_bytes(rnd, len));
+            k
+        };
+        let blocks: Vec<Vec<Vec<u8>>> = (0..4)
+            .map(|_| (0..20).map(|_| key(&mut rnd, 0xff)).collect())
+            .collect();
+        let missing: Vec<Vec<u8>> = (0..20).map(|_| key(&mut rnd, 0xfe)).collect();
+
+        // The policy on its own
+        let keys: Vec<Slice> = blocks[0].iter().map(Slice::from).collect();
+        let mut filter = Vec::new();
+        policy().create_filter(&keys, keys.len(), &mut filter);
+        for k in &blocks[0] {
+            assert!(policy().key_may_match(&k.into(), &Slice::from(&filter)));
+        }
+        for k in &missing {
+            assert!(!policy().key_may_match(&k.into(), &Slice::from(&filter)));
+        }
+
+        // One filter per block
+        let mut builder = FilterBlockBuilder::new(policy());
+        for (i, keys) in blocks.iter().enumerate() {
+            builder.start_block(i as u64 * 3000);
+            for k in keys {
+                builder.add_key(&k.into());
+            }
+        }
+        let block = builder.finish();
+        let reader = FilterBlockReader::new(policy(), block.to_vec());
+        for (i, keys) in blocks.iter().enumerate() {
+            for k in keys {
+                assert!(reader.key_may_match(i as u64 * 3000, &k.into()));
+            }
+            for k in &missing {
+                assert!(!reader.key_may_match(i as u64 * 3000, &k.into()));
+            }
+        }
     }
 }
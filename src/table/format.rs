@@ -1,3 +1,284 @@
 // leveldb/table/format.{h,cc}
 
-use crate::{slice, status, table_builder};
+use crate::env::RandomAccessFile;
+use crate::options::{CompressionType, ReadOptions};
+use crate::slice::Slice;
+use crate::snappy;
+use crate::status::Status;
+use crate::util::coding::{decode_fixed32, get_varint64, put_fixed32, put_varint64};
+use crate::util::crc32c;
+
+// BlockHandle is a pointer to the extent of a file that stores a data block or a meta block.
+#[derive(Clone, Copy)]
+pub(crate) struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+impl BlockHandle {
+    // Maximum encoding length of a BlockHandle
+    pub(crate) const MAX_ENCODED_LENGTH: usize = 10 + 10;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            offset: !0,
+            size: !0,
+        }
+    }
+
+    // The offset of the block in the file.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    // The size of the stored block
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn set_size(&mut self, size: u64) {
+        self.size = size;
+    }
+
+    pub(crate) fn encode_to(&self, dst: &mut Vec<u8>) {
+        // Sanity check that all fields have been set
+        assert_ne!(self.offset, !0);
+        assert_ne!(self.size, !0);
+        put_varint64(dst, self.offset);
+        put_varint64(dst, self.size);
+    }
+
+    pub(crate) fn decode_from(&mut self, input: &mut Slice) -> Status {
+        if get_varint64(input, &mut self.offset) && get_varint64(input, &mut self.size) {
+            Status::OK()
+        } else {
+            Status::corruption(&"bad block handle".into(), &Slice::new())
+        }
+    }
+}
+
+impl Default for BlockHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Footer encapsulates the fixed information stored at the tail end of every table file.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Footer {
+    metaindex_handle: BlockHandle,
+    index_handle: BlockHandle,
+}
+
+impl Footer {
+    // Encoded length of a Footer. Note that the serialization of a Footer will always occupy
+    // exactly this many bytes. It consists of two block handles and a magic number.
+    pub(crate) const ENCODED_LENGTH: usize = 2 * BlockHandle::MAX_ENCODED_LENGTH + 8;
+
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    // The block handle for the metaindex block of the table
+    pub(crate) fn metaindex_handle(&self) -> &BlockHandle {
+        &self.metaindex_handle
+    }
+
+    pub(crate) fn set_metaindex_handle(&mut self, h: &BlockHandle) {
+        self.metaindex_handle = *h;
+    }
+
+    // The block handle for the index block of the table
+    pub(crate) fn index_handle(&self) -> &BlockHandle {
+        &self.index_handle
+    }
+
+    pub(crate) fn set_index_handle(&mut self, h: &BlockHandle) {
+        self.index_handle = *h;
+    }
+
+    pub(crate) fn encode_to(&self, dst: &mut Vec<u8>) {
+        let original_size = dst.len();
+        self.metaindex_handle.encode_to(dst);
+        self.index_handle.encode_to(dst);
+        // Padding
+        dst.resize(original_size + 2 * BlockHandle::MAX_ENCODED_LENGTH, 0);
+        put_fixed32(dst, (TABLE_MAGIC_NUMBER & 0xffffffff) as u32);
+        put_fixed32(dst, (TABLE_MAGIC_NUMBER >> 32) as u32);
+        assert_eq!(dst.len(), original_size + Self::ENCODED_LENGTH);
+    }
+
+    pub(crate) fn decode_from(&mut self, input: &mut Slice) -> Status {
+        if input.size() < Self::ENCODED_LENGTH {
+            return Status::corruption(&"not an sstable (footer too short)".into(), &Slice::new());
+        }
+
+        let magic_ptr = &input.as_bytes()[Self::ENCODED_LENGTH - 8..];
+        let magic_lo = decode_fixed32(magic_ptr);
+        let magic_hi = decode_fixed32(&magic_ptr[4..]);
+        let magic = ((magic_hi as u64) << 32) | (magic_lo as u64);
+        if magic != TABLE_MAGIC_NUMBER {
+            return Status::corruption(&"not an sstable (bad magic number)".into(), &Slice::new());
+        }
+
+        let mut result = self.metaindex_handle.decode_from(input);
+        if result.ok() {
+            result = self.index_handle.decode_from(input);
+        }
+        if result.ok() {
+            // We skip over any leftover data (just padding for now) in "input"
+            *input = Slice::from(&magic_ptr[8..]);
+        }
+        result
+    }
+}
+
+// TABLE_MAGIC_NUMBER was picked by running
+//    echo http://code.google.com/p/leveldb/ | sha1sum
+// and taking the leading 64 bits.
+pub(crate) const TABLE_MAGIC_NUMBER: u64 = 0xdb4775248b80fb57;
+
+// 1-byte type + 32-bit crc
+pub(crate) const BLOCK_TRAILER_SIZE: usize = 5;
+
+#[derive(Default)]
+pub(crate) struct BlockContents {
+    // Actual contents of data
+    // NOTE: C++ holds a Slice plus a heap_allocated flag telling the owner to delete[] it; the
+    // contents are always owned here
+    pub(crate) data: Vec<u8>,
+    // True iff data can be cached
+    pub(crate) cachable: bool,
+}
+
+// Read the block identified by "handle" from "file". On failure return non-OK. On success fill
+// *result and return OK.
+pub(crate) fn read_block(
+    file: &dyn RandomAccessFile,
+    options: &ReadOptions,
+    handle: &BlockHandle,
+    result: &mut BlockContents,
+) -> Status {
+    result.data = Vec::new();
+    result.cachable = false;
+
+    // Read the block contents as well as the type/crc footer.
+    // See table_builder.rs for the code that built this structure.
+    let n = handle.size() as usize;
+    let mut buf = vec![0u8; n + BLOCK_TRAILER_SIZE];
+    let contents = match file.read(handle.offset(), n + BLOCK_TRAILER_SIZE, &mut buf) {
+        Ok(contents) => contents,
+        Err(s) => return s.into(),
+    };
+    if contents.size() != n + BLOCK_TRAILER_SIZE {
+        return Status::corruption(&"truncated block read".into(), &Slice::new());
+    }
+
+    // Check the crc of the type and the block contents
+    if options.verify_checksums {
+        let crc = crc32c::unmask(decode_fixed32(&contents[n + 1..]));
+        let actual = crc32c::value(&contents[..n + 1]);
+        if actual != crc {
+            return Status::corruption(&"block checksum mismatch".into(), &Slice::new());
+        }
+    }
+
+    match contents[n] {
+        t if t == CompressionType::NoCompression as u8 => {
+            // NOTE: C++ uses the data in place if the file returned a pointer to data it owns;
+            // every RandomAccessFile here reads into buf, so it becomes the contents
+            buf.truncate(n);
+            result.data = buf;
+            result.cachable = true;
+
+            // Ok
+        }
+        t if t == CompressionType::SnappyCompression as u8 => {
+            // NOTE: C++ goes through port::Snappy_GetUncompressedLength and port::Snappy_Uncompress
+            let compressed = &contents.as_bytes()[..n];
+            let mut ulength = 0;
+            if !snappy::get_uncompressed_length(compressed, &mut ulength) {
+                return Status::corruption(
+                    &"corrupted snappy compressed block length".into(),
+                    &Slice::new(),
+                );
+            }
+            let mut ubuf = Vec::new();
+            if !snappy::uncompress(compressed, &mut ubuf) {
+                return Status::corruption(
+                    &"corrupted snappy compressed block contents".into(),
+                    &Slice::new(),
+                );
+            }
+            result.data = ubuf;
+            result.cachable = true;
+        }
+        _ => return Status::corruption(&"bad block type".into(), &Slice::new()),
+    }
+
+    Status::OK()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_handle() {
+        let mut handle = BlockHandle::new();
+        handle.set_offset(1 << 40);
+        handle.set_size(300);
+        let mut encoded = Vec::new();
+        handle.encode_to(&mut encoded);
+
+        let mut input = Slice::from(&encoded);
+        let mut decoded = BlockHandle::new();
+        assert!(decoded.decode_from(&mut input).ok());
+        assert!(input.empty());
+        assert_eq!(1 << 40, decoded.offset());
+        assert_eq!(300, decoded.size());
+
+        let mut input = Slice::from(&encoded[..encoded.len() - 1]);
+        assert!(decoded.decode_from(&mut input).is_corruption());
+    }
+
+    #[test]
+    fn test_footer() {
+        let mut metaindex_handle = BlockHandle::new();
+        metaindex_handle.set_offset(100);
+        metaindex_handle.set_size(20);
+        let mut index_handle = BlockHandle::new();
+        index_handle.set_offset(120);
+        index_handle.set_size(3000);
+        let mut footer = Footer::new();
+        footer.set_metaindex_handle(&metaindex_handle);
+        footer.set_index_handle(&index_handle);
+
+        let mut encoded = Vec::new();
+        footer.encode_to(&mut encoded);
+        assert_eq!(Footer::ENCODED_LENGTH, encoded.len());
+
+        let mut input = Slice::from(&encoded);
+        let mut decoded = Footer::new();
+        assert!(decoded.decode_from(&mut input).ok());
+        assert!(input.empty());
+        assert_eq!(100, decoded.metaindex_handle().offset());
+        assert_eq!(20, decoded.metaindex_handle().size());
+        assert_eq!(120, decoded.index_handle().offset());
+        assert_eq!(3000, decoded.index_handle().size());
+
+        // Bad magic number
+        let mut bytes = encoded;
+        bytes[Footer::ENCODED_LENGTH - 1] ^= 1;
+        let mut input = Slice::from(&bytes);
+        assert!(decoded.decode_from(&mut input).is_corruption());
+
+        // Too short
+        let mut input = Slice::from(&bytes[1..]);
+        assert!(decoded.decode_from(&mut input).is_corruption());
+    }
+}
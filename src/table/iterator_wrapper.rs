@@ -0,0 +1,86 @@
+// leveldb/table/iterator_wrapper.h
+
+use crate::{iter::Iter, slice::Slice, status::Result};
+
+// An internal wrapper class with an interface similar to Iter that caches the valid() result
+// for an underlying iterator. This can help avoid virtual function calls.
+// NOTE: C++ also caches key(); a cached Slice would borrow from the wrapped iterator, so key() is
+// forwarded instead
+pub(crate) struct IteratorWrapper {
+    iter: Option<Box<dyn Iter>>,
+    valid: bool,
+}
+
+impl IteratorWrapper {
+    pub(crate) fn new(iter: Option<Box<dyn Iter>>) -> Self {
+        let mut wrapper = Self {
+            iter: None,
+            valid: false,
+        };
+        wrapper.set(iter);
+        wrapper
+    }
+
+    pub(crate) fn iter(&self) -> Option<&dyn Iter> {
+        self.iter.as_deref()
+    }
+
+    // Takes ownership of "iter" and will drop it when destroyed, or when set() is invoked again.
+    pub(crate) fn set(&mut self, iter: Option<Box<dyn Iter>>) {
+        self.iter = iter;
+        if self.iter.is_none() {
+            self.valid = false;
+        } else {
+            self.update();
+        }
+    }
+
+    // Iterator interface methods
+    pub(crate) fn valid(&self) -> bool {
+        self.valid
+    }
+
+    pub(crate) fn key(&self) -> Slice<'_> {
+        assert!(self.valid());
+        self.iter.as_ref().unwrap().key()
+    }
+
+    pub(crate) fn value(&self) -> Slice<'_> {
+        assert!(self.valid());
+        self.iter.as_ref().unwrap().value()
+    }
+
+    // Methods below require iter() != None
+    pub(crate) fn status(&self) -> Result<()> {
+        self.iter.as_ref().unwrap().status()
+    }
+
+    pub(crate) fn next(&mut self) {
+        self.iter.as_mut().unwrap().next();
+        self.update();
+    }
+
+    pub(crate) fn prev(&mut self) {
+        self.iter.as_mut().unwrap().prev();
+        self.update();
+    }
+
+    pub(crate) fn seek(&mut self, k: &Slice) {
+        self.iter.as_mut().unwrap().seek(k);
+        self.update();
+    }
+
+    pub(crate) fn seek_to_first(&mut self) {
+        self.iter.as_mut().unwrap().seek_to_first();
+        self.update();
+    }
+
+    pub(crate) fn seek_to_last(&mut self) {
+        self.iter.as_mut().unwrap().seek_to_last();
+        self.update();
+    }
+
+    fn update(&mut self) {
+        self.valid = self.iter.as_ref().unwrap().valid();
+    }
+}
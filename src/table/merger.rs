@@ -0,0 +1,458 @@
+// leveldb/table/merger.{h,cc}
+
+use crate::{
+    comparator::Comparator,
+    iter::{new_empty_iterator, CleanupNode, Iter},
+    slice::Slice,
+    status::Result,
+    table::iterator_wrapper::IteratorWrapper,
+};
+use std::sync::Arc;
+
+// Return an iterator that provided the union of the data in children[0,n-1]. Takes ownership of
+// the child iterators and will drop them when the result iterator is dropped.
+//
+// The result does no duplicate suppression. I.e., if a particular key is present in K child
+// iterators, it will be yielded K times.
+pub(crate) fn new_merging_iterator(
+    comparator: Arc<dyn Comparator>,
+    mut children: Vec<Box<dyn Iter>>,
+) -> Box<dyn Iter> {
+    match children.len() {
+        0 => new_empty_iterator(),
+        1 => children.pop().unwrap(),
+        _ => Box::new(MergingIterator {
+            comparator,
+            children: children
+                .into_iter()
+                .map(|child| IteratorWrapper::new(Some(child)))
+                .collect(),
+            current: None,
+            direction: Direction::Forward,
+            cleanup_head: CleanupNode::default(),
+        }),
+    }
+}
+
+// Which direction is the iterator moving?
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+struct MergingIterator {
+    // We might want to use a heap in case there are lots of children. For now we use a simple
+    // array since we expect a very small number of children in leveldb.
+    comparator: Arc<dyn Comparator>,
+    children: Vec<IteratorWrapper>,
+    // Index of the child the iterator is positioned at
+    // NOTE: C++ keeps a pointer to the child
+    current: Option<usize>,
+    direction: Direction,
+    cleanup_head: CleanupNode,
+}
+
+impl MergingIterator {
+    fn find_smallest(&mut self) {
+        let mut smallest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate() {
+            if child.valid() {
+                match smallest {
+                    Some(s)
+                        if self
+                            .comparator
+                            .compare(&child.key(), &self.children[s].key())
+                            >= 0 => {}
+                    _ => smallest = Some(i),
+                }
+            }
+        }
+        self.current = smallest;
+    }
+
+    fn find_largest(&mut self) {
+        let mut largest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate().rev() {
+            if child.valid() {
+                match largest {
+                    Some(l)
+                        if self
+                            .comparator
+                            .compare(&child.key(), &self.children[l].key())
+                            <= 0 => {}
+                    _ => largest = Some(i),
+                }
+            }
+        }
+        self.current = largest;
+    }
+}
+
+impl Iter for MergingIterator {
+    fn cleanup_head(&mut self) -> &mut CleanupNode {
+        &mut self.cleanup_head
+    }
+
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn seek_to_first(&mut self) {
+        for child in &mut self.children {
+            child.seek_to_first();
+        }
+        self.find_smallest();
+        self.direction = Direction::Forward;
+    }
+
+    fn seek_to_last(&mut self) {
+        for child in &mut self.children {
+            child.seek_to_last();
+        }
+        self.find_largest();
+        self.direction = Direction::Reverse;
+    }
+
+    fn seek(&mut self, target: &Slice) {
+        for child in &mut self.children {
+            child.seek(target);
+        }
+        self.find_smallest();
+        self.direction = Direction::Forward;
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        let current = self.current.unwrap();
+
+        // Ensure that all children are positioned after key(). If we are moving in the forward
+        // direction, it is already true for all of the non-current children since current is the
+        // smallest child and key() == current.key(). Otherwise, we explicitly position the
+        // non-current children.
+        if self.direction != Direction::Forward {
+            // NOTE: The children are split around current so that its key can be borrowed while
+            // the others are repositioned
+            let (before, rest) = self.children.split_at_mut(current);
+            let (current_child, after) = rest.split_first_mut().unwrap();
+            let key = current_child.key();
+            for child in before.iter_mut().chain(after) {
+                child.seek(&key);
+                if child.valid() && self.comparator.compare(&key, &child.key()) == 0 {
+                    child.next();
+                }
+            }
+            self.direction = Direction::Forward;
+        }
+
+        self.children[current].next();
+        self.find_smallest();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        let current = self.current.unwrap();
+
+        // Ensure that all children are positioned before key(). If we are moving in the reverse
+        // direction, it is already true for all of the non-current children since current is the
+        // largest child and key() == current.key(). Otherwise, we explicitly position the
+        // non-current children.
+        if self.direction != Direction::Reverse {
+            // NOTE: Split around current like in next()
+            let (before, rest) = self.children.split_at_mut(current);
+            let (current_child, after) = rest.split_first_mut().unwrap();
+            let key = current_child.key();
+            for child in before.iter_mut().chain(after) {
+                child.seek(&key);
+                if child.valid() {
+                    // Child is at first entry >= key(). Step back one to be < key()
+                    child.prev();
+                } else {
+                    // Child has no entries >= key(). Position at last entry.
+                    child.seek_to_last();
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+
+        self.children[current].prev();
+        self.find_largest();
+    }
+
+    fn key(&self) -> Slice<'_> {
+        assert!(self.valid());
+        self.children[self.current.unwrap()].key()
+    }
+
+    fn value(&self) -> Slice<'_> {
+        assert!(self.valid());
+        self.children[self.current.unwrap()].value()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.children.iter().try_for_each(IteratorWrapper::status)
+    }
+}
+
+impl Drop for MergingIterator {
+    fn drop(&mut self) {
+        self.drop_func();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::bytewise_comparator;
+    use crate::iter::new_error_iter;
+    use crate::status::Error;
+    use crate::util::random::Random;
+
+    // An iterator over a sorted list of keys whose values are "<tag>:<key>"
+    struct VecIter {
+        entries: Vec<(String, String)>,
+        // entries.len() when not valid
+        index: usize,
+        cleanup_head: CleanupNode,
+    }
+
+    fn new_vec_iter(tag: &str, mut keys: Vec<String>) -> Box<dyn Iter> {
+        keys.sort();
+        let entries = keys
+            .into_iter()
+            .map(|k| {
+                let v = format!("{}:{}", tag, k);
+                (k, v)
+            })
+            .collect::<Vec<_>>();
+        Box::new(VecIter {
+            index: entries.len(),
+            entries,
+            cleanup_head: CleanupNode::default(),
+        })
+    }
+
+    impl Iter for VecIter {
+        fn cleanup_head(&mut self) -> &mut CleanupNode {
+            &mut self.cleanup_head
+        }
+
+        fn valid(&self) -> bool {
+            self.index < self.entries.len()
+        }
+
+        fn seek_to_first(&mut self) {
+            self.index = 0;
+        }
+
+        fn seek_to_last(&mut self) {
+            self.index = self.entries.len().saturating_sub(1);
+        }
+
+        fn seek(&mut self, target: &Slice) {
+            self.index = self
+                .entries
+                .partition_point(|(k, _)| Slice::from(k).compare(target) < 0);
+        }
+
+        fn next(&mut self) {
+            assert!(self.valid());
+            self.index += 1;
+        }
+
+        fn prev(&mut self) {
+            assert!(self.valid());
+            self.index = self.index.checked_sub(1).unwrap_or(self.entries.len());
+        }
+
+        fn key(&self) -> Slice<'_> {
+            Slice::from(&self.entries[self.index].0)
+        }
+
+        fn value(&self) -> Slice<'_> {
+            Slice::from(&self.entries[self.index].1)
+        }
+
+        fn status(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Drop for VecIter {
+        fn drop(&mut self) {
+            self.drop_func();
+        }
+    }
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|k| k.to_string()).collect()
+    }
+
+    fn new_iter(children: Vec<Box<dyn Iter>>) -> Box<dyn Iter> {
+        new_merging_iterator(Arc::new(bytewise_comparator()), children)
+    }
+
+    fn entry(iter: &dyn Iter) -> String {
+        if iter.valid() {
+            format!(
+                "{}->{}",
+                String::from_utf8_lossy(&iter.key()),
+                String::from_utf8_lossy(&iter.value())
+            )
+        } else {
+            "END".to_owned()
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut iter = new_iter(Vec::new());
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+
+        let mut iter = new_iter(vec![new_vec_iter("a", Vec::new()), new_empty_iterator()]);
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek(&"foo".into());
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn test_single_child() {
+        let mut iter = new_iter(vec![new_vec_iter("a", keys(&["k1", "k2"]))]);
+        iter.seek_to_last();
+        assert_eq!("k2->a:k2", entry(&*iter));
+        iter.prev();
+        assert_eq!("k1->a:k1", entry(&*iter));
+        iter.prev();
+        assert_eq!("END", entry(&*iter));
+    }
+
+    #[test]
+    fn test_both_directions() {
+        let mut iter = new_iter(vec![
+            new_vec_iter("a", keys(&["a", "c", "e"])),
+            new_vec_iter("b", keys(&["b", "c", "f"])),
+            new_vec_iter("c", keys(&["d"])),
+        ]);
+
+        iter.seek_to_first();
+        let mut forward = Vec::new();
+        while iter.valid() {
+            forward.push(entry(&*iter));
+            iter.next();
+        }
+        // Duplicates are not suppressed
+        assert_eq!(
+            vec!["a->a:a", "b->b:b", "c->a:c", "c->b:c", "d->c:d", "e->a:e", "f->b:f"],
+            forward
+        );
+
+        iter.seek_to_last();
+        let mut backward = Vec::new();
+        while iter.valid() {
+            backward.push(entry(&*iter));
+            iter.prev();
+        }
+        assert_eq!(7, backward.len());
+        assert_eq!(
+            vec!["f", "e", "d", "c", "c", "b", "a"],
+            backward.iter().map(|e| &e[..1]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_switch_direction() {
+        let mut iter = new_iter(vec![
+            new_vec_iter("a", keys(&["a", "d", "g"])),
+            new_vec_iter("b", keys(&["b", "e", "h"])),
+            new_vec_iter("c", keys(&["c", "f"])),
+        ]);
+
+        iter.seek(&"d".into());
+        assert_eq!("d->a:d", entry(&*iter));
+        iter.next();
+        assert_eq!("e->b:e", entry(&*iter));
+        iter.prev();
+        assert_eq!("d->a:d", entry(&*iter));
+        iter.prev();
+        assert_eq!("c->c:c", entry(&*iter));
+        iter.next();
+        assert_eq!("d->a:d", entry(&*iter));
+        iter.next();
+        assert_eq!("e->b:e", entry(&*iter));
+
+        // Switch direction at the ends
+        iter.seek_to_last();
+        assert_eq!("h->b:h", entry(&*iter));
+        iter.prev();
+        assert_eq!("g->a:g", entry(&*iter));
+        iter.next();
+        assert_eq!("h->b:h", entry(&*iter));
+        iter.next();
+        assert_eq!("END", entry(&*iter));
+
+        iter.seek_to_first();
+        iter.next();
+        iter.prev();
+        assert_eq!("a->a:a", entry(&*iter));
+        iter.prev();
+        assert_eq!("END", entry(&*iter));
+    }
+
+    #[test]
+    fn test_status() {
+        let iter = new_iter(vec![
+            new_vec_iter("a", keys(&["a"])),
+            new_error_iter(Error::corruption(&"first".into(), &Slice::new())),
+            new_error_iter(Error::io_error(&"second".into(), &Slice::new())),
+        ]);
+        let e = iter.status().unwrap_err();
+        assert!(e.is_corruption(), "{}", e);
+        assert_eq!("Corruption: first", e.to_string());
+    }
+
+    #[test]
+    fn test_randomized() {
+        let mut rnd = Random::new(301);
+        for _ in 0..50 {
+            let num_children = 1 + rnd.uniform(5) as usize;
+            let mut children = Vec::new();
+            let mut expected = Vec::new();
+            for c in 0..num_children {
+                let tag = format!("{}", c);
+                // Keys are unique across children, like the internal keys merged by the DB
+                let child_keys = (0..rnd.uniform(20))
+                    .map(|_| format!("{:03}{}", rnd.uniform(100), tag))
+                    .collect::<Vec<_>>();
+                expected.extend(child_keys.iter().cloned());
+                children.push(new_vec_iter(&tag, child_keys));
+            }
+            expected.sort();
+
+            let mut iter = new_iter(children);
+            iter.seek_to_first();
+            let mut index = 0;
+            for _ in 0..200 {
+                // Walk in a random direction, checking the key against the expected position
+                if index < expected.len() {
+                    assert!(iter.valid());
+                    assert_eq!(expected[index].as_bytes(), &*iter.key());
+                } else {
+                    assert!(!iter.valid());
+                    break;
+                }
+                if index > 0 && rnd.one_in(3) {
+                    iter.prev();
+                    index -= 1;
+                } else {
+                    iter.next();
+                    index += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+pub(crate) mod block;
+pub(crate) mod block_builder;
+pub(crate) mod filter_block;
+pub(crate) mod format;
+pub(crate) mod iterator_wrapper;
+pub(crate) mod merger;
+#[allow(clippy::module_inception)]
+mod table;
+mod table_builder;
+pub(crate) mod two_level_iterator;
+
+pub use block::Block;
+pub use table::Table;
+pub use table_builder::TableBuilder;
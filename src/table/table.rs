@@ -0,0 +1,979 @@
+// leveldb/include/leveldb/table.h
+// leveldb/table/table.cc
+
+use crate::{
+    cache::Cache,
+    comparator::{bytewise_comparator, Comparator},
+    env::RandomAccessFile,
+    filter_policy::FilterPolicy,
+    iter::{new_empty_iterator, new_error_iter, Iter},
+    options::{Options, ReadOptions},
+    slice::Slice,
+    status::{Error, Result, Status},
+    table::{
+        block::Block,
+        filter_block::FilterBlockReader,
+        format::{read_block, BlockContents, BlockHandle, Footer},
+        two_level_iterator::new_two_level_iterator,
+    },
+    util::coding::encode_fixed64,
+};
+use std::cell::Cell;
+use std::sync::Arc;
+
+// A Table is a sorted map from strings to strings. Tables are immutable and persistent. A Table
+// may be safely accessed from multiple threads without external synchronization.
+pub struct Table {
+    rep: Rep,
+}
+
+struct Rep {
+    // NOTE: C++ keeps a copy of the options; only the fields used by the table are kept
+    comparator: Arc<dyn Comparator>,
+    filter_policy: Option<Arc<dyn FilterPolicy>>,
+    block_cache: Option<Arc<dyn Cache<Block>>>,
+    paranoid_checks: bool,
+    file: Box<dyn RandomAccessFile>,
+    cache_id: u64,
+    // NOTE: C++ also keeps the filter block data to delete it later; the reader owns it here
+    filter: Option<FilterBlockReader>,
+
+    // Handle to metaindex_block: saved from footer
+    metaindex_handle: BlockHandle,
+    index_block: Arc<Block>,
+}
+
+impl Table {
+    // Attempt to open the table that is stored in bytes [0..file_size) of "file", and read the
+    // metadata entries necessary to allow retrieving data from the table.
+    //
+    // If successful, returns the newly opened table. The client should drop the table when no
+    // longer needed. If there was an error while initializing the table, returns the error.
+    //
+    // NOTE: C++ stores the table in *table and does not take ownership of "*file"; here the table
+    // owns the file
+    pub fn open(options: &Options, file: Box<dyn RandomAccessFile>, size: u64) -> Result<Table> {
+        if size < Footer::ENCODED_LENGTH as u64 {
+            return Err(Error::corruption(
+                &"file is too short to be an sstable".into(),
+                &Slice::new(),
+            ));
+        }
+
+        let mut footer_space = [0u8; Footer::ENCODED_LENGTH];
+        let mut footer_input = file.read(
+            size - Footer::ENCODED_LENGTH as u64,
+            Footer::ENCODED_LENGTH,
+            &mut footer_space,
+        )?;
+
+        let mut footer = Footer::new();
+        Result::from(footer.decode_from(&mut footer_input))?;
+
+        // Read the index block
+        let mut index_block_contents = BlockContents::default();
+        let opt = ReadOptions {
+            verify_checksums: options.paranoid_checks,
+            ..Default::default()
+        };
+        Result::from(read_block(
+            &*file,
+            &opt,
+            footer.index_handle(),
+            &mut index_block_contents,
+        ))?;
+
+        // We've successfully read the footer and the index block: we're ready to serve requests.
+        let index_block = Arc::new(Block::new(index_block_contents));
+        let mut table = Table {
+            rep: Rep {
+                comparator: Arc::clone(&options.comparator),
+                filter_policy: options.filter_policy.clone(),
+                block_cache: options.block_cache.clone(),
+                paranoid_checks: options.paranoid_checks,
+                file,
+                cache_id: options.block_cache.as_ref().map_or(0, |c| c.new_id()),
+                filter: None,
+                metaindex_handle: *footer.metaindex_handle(),
+                index_block,
+            },
+        };
+        table.read_meta(&footer);
+        Ok(table)
+    }
+
+    // Returns a new iterator over the table contents. The result of new_iterator() is initially
+    // invalid (caller must call one of the seek methods on the iterator before using it).
+    // NOTE: The iterator keeps a reference to the table
+    pub fn new_iterator(self: &Arc<Self>, options: &ReadOptions) -> Box<dyn Iter> {
+        let table = Arc::clone(self);
+        new_two_level_iterator(
+            self.rep
+                .index_block
+                .new_iterator(Arc::clone(&self.rep.comparator)),
+            Box::new(move |options, index_value| table.block_reader(options, index_value)),
+            options,
+        )
+    }
+
+    // Given a key, return an approximate byte offset in the file where the data for that key
+    // begins (or would begin if the key were present in the file). The returned value is in
+    // terms of file bytes, and so includes effects like compression of the underlying data.
+    // E.g., the approximate offset of the last key in the table will be close to the file
+    // length.
+    pub fn approximate_offset_of(&self, key: &Slice) -> u64 {
+        let mut index_iter = self
+            .rep
+            .index_block
+            .new_iterator(Arc::clone(&self.rep.comparator));
+        index_iter.seek(key);
+        if index_iter.valid() {
+            let mut handle = BlockHandle::new();
+            let mut input = index_iter.value();
+            let s = handle.decode_from(&mut input);
+            if s.ok() {
+                handle.offset()
+            } else {
+                // Strange: we can't decode the block handle in the index block. We'll just
+                // return the offset of the metaindex block, which is close to the whole file size
+                // for this case.
+                self.rep.metaindex_handle.offset()
+            }
+        } else {
+            // key is past the last key in the file. Approximate the offset by returning the
+            // offset of the metaindex block (which is right near the end of the file).
+            self.rep.metaindex_handle.offset()
+        }
+    }
+
+    // Calls handle_result with the entry found after a call to seek(key). May not make such a
+    // call if filter policy says that key is not present.
+    // NOTE: C++ passes a "void* arg" and a function pointer; a closure is used instead
+    pub(crate) fn internal_get(
+        &self,
+        options: &ReadOptions,
+        k: &Slice,
+        handle_result: &mut dyn FnMut(&Slice, &Slice),
+    ) -> Status {
+        let mut s = Status::OK();
+        let mut iiter = self
+            .rep
+            .index_block
+            .new_iterator(Arc::clone(&self.rep.comparator));
+        iiter.seek(k);
+        if iiter.valid() {
+            let handle_value = iiter.value();
+            let mut handle = BlockHandle::new();
+            let filtered_out = match &self.rep.filter {
+                Some(filter) => {
+                    handle.decode_from(&mut handle_value.clone()).ok()
+                        && !filter.key_may_match(handle.offset(), k)
+                }
+                None => false,
+            };
+            if filtered_out {
+                // Not found
+            } else {
+                let mut block_iter = self.block_reader(options, &handle_value);
+                block_iter.seek(k);
+                if block_iter.valid() {
+                    handle_result(&block_iter.key(), &block_iter.value());
+                }
+                s = block_iter.status().into();
+            }
+        }
+        if s.ok() {
+            s = iiter.status().into();
+        }
+        s
+    }
+
+    fn read_meta(&mut self, footer: &Footer) {
+        let policy = match &self.rep.filter_policy {
+            Some(policy) => Arc::clone(policy),
+            // Do not need any metadata
+            None => return,
+        };
+
+        // TODO(sanjay): Skip this if footer.metaindex_handle() size indicates it is an empty
+        // block.
+        let opt = ReadOptions {
+            verify_checksums: self.rep.paranoid_checks,
+            ..Default::default()
+        };
+        let mut contents = BlockContents::default();
+        if !read_block(
+            &*self.rep.file,
+            &opt,
+            footer.metaindex_handle(),
+            &mut contents,
+        )
+        .ok()
+        {
+            // Do not propagate errors since meta info is not needed for operation
+            return;
+        }
+        let meta = Arc::new(Block::new(contents));
+
+        let mut iter = meta.new_iterator(Arc::new(bytewise_comparator()));
+        let key = format!("filter.{}", policy.name());
+        iter.seek(&key.as_str().into());
+        if iter.valid() && iter.key() == Slice::from(key.as_str()) {
+            self.read_filter(policy, &iter.value());
+        }
+    }
+
+    fn read_filter(&mut self, policy: Arc<dyn FilterPolicy>, filter_handle_value: &Slice) {
+        let mut v = *filter_handle_value;
+        let mut filter_handle = BlockHandle::new();
+        if !filter_handle.decode_from(&mut v).ok() {
+            return;
+        }
+
+        // We might want to unify with read_block() if we start requiring checksum verification
+        // in Table::open.
+        let opt = ReadOptions {
+            verify_checksums: self.rep.paranoid_checks,
+            ..Default::default()
+        };
+        let mut block = BlockContents::default();
+        if !read_block(&*self.rep.file, &opt, &filter_handle, &mut block).ok() {
+            return;
+        }
+        self.rep.filter = Some(FilterBlockReader::new(policy, block.data));
+    }
+
+    // Convert an index iterator value (i.e., an encoded BlockHandle) into an iterator over the
+    // contents of the corresponding block.
+    fn block_reader(&self, options: &ReadOptions, index_value: &Slice) -> Box<dyn Iter> {
+        let block_cache = &self.rep.block_cache;
+        let mut block = None;
+        let mut cache_handle = None;
+
+        let mut handle = BlockHandle::new();
+        let mut input = *index_value;
+        let mut s = handle.decode_from(&mut input);
+        // We intentionally allow extra stuff in index_value so that we can add more features in
+        // the future.
+
+        if s.ok() {
+            let mut contents = BlockContents::default();
+            if let Some(block_cache) = block_cache {
+                let mut cache_key_buffer = [0u8; 16];
+                encode_fixed64(&mut cache_key_buffer, self.rep.cache_id);
+                encode_fixed64(&mut cache_key_buffer[8..], handle.offset());
+                let key = Slice::from(&cache_key_buffer[..]);
+                cache_handle = block_cache.lookup(&key);
+                if let Some(h) = &cache_handle {
+                    block = Some(Arc::clone(h.value()));
+                } else {
+                    s = read_block(&*self.rep.file, options, &handle, &mut contents);
+                    if s.ok() {
+                        let cachable = contents.cachable;
+                        let b = Arc::new(Block::new(contents));
+                        if cachable && options.fill_cache {
+                            cache_handle = Some(block_cache.insert(&key, Arc::clone(&b), b.size()));
+                        }
+                        block = Some(b);
+                    }
+                }
+            } else {
+                s = read_block(&*self.rep.file, options, &handle, &mut contents);
+                if s.ok() {
+                    block = Some(Arc::new(Block::new(contents)));
+                }
+            }
+        }
+
+        match block {
+            Some(block) => {
+                let mut iter = block.new_iterator(Arc::clone(&self.rep.comparator));
+                // NOTE: C++ deletes the block in a cleanup function when it isn't cached; here
+                // the iterator holds a reference to the block instead
+                if let Some(h) = cache_handle {
+                    let handle = Cell::new(Some(h));
+                    iter.register_cleanup(
+                        Box::new(move |_, _| drop(handle.take())),
+                        std::ptr::null_mut(),
+                        std::ptr::null_mut(),
+                    );
+                }
+                iter
+            }
+            None => match Result::from(s) {
+                Ok(()) => new_empty_iterator(),
+                Err(e) => new_error_iter(e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Handle;
+    use crate::db::dbformat::InternalKeyComparator;
+    use crate::env::WritableFile;
+    use crate::options::CompressionType;
+    use crate::table::format::TABLE_MAGIC_NUMBER;
+    use crate::table::TableBuilder;
+    use crate::util::{coding, hash, random::Random, testutil};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // NOTE: C++ calls it StringSource in table_test.cc
+    struct StringSource {
+        contents: Vec<u8>,
+    }
+
+    impl RandomAccessFile for StringSource {
+        fn read<'a>(&self, offset: u64, n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>> {
+            if offset >= self.contents.len() as u64 {
+                return Err(Error::invalid_argument(
+                    &"invalid Read offset".into(),
+                    &Slice::new(),
+                ));
+            }
+            let offset = offset as usize;
+            let n = n.min(self.contents.len() - offset);
+            scratch[..n].copy_from_slice(&self.contents[offset..offset + n]);
+            Ok(Slice::from(&scratch[..n]))
+        }
+    }
+
+    struct TestHashFilter;
+
+    impl FilterPolicy for TestHashFilter {
+        fn name(&self) -> &'static str {
+            "TestHashFilter"
+        }
+
+        fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut Vec<u8>) {
+            for key in &keys[..n] {
+                coding::put_fixed32(dst, hash::hash(key, 1));
+            }
+        }
+
+        fn key_may_match(&self, key: &Slice, filter: &Slice) -> bool {
+            let h = hash::hash(key, 1);
+            filter
+                .as_bytes()
+                .chunks_exact(4)
+                .any(|c| u32::from_le_bytes(c.try_into().unwrap()) == h)
+        }
+    }
+
+    // A cache that never evicts, for checking that blocks are shared through the block cache.
+    #[derive(Default)]
+    struct TestCacheState {
+        entries: HashMap<Vec<u8>, (Arc<Block>, usize)>,
+        last_id: u64,
+    }
+
+    #[derive(Default)]
+    struct TestCache {
+        state: Mutex<TestCacheState>,
+        // Number of outstanding handles
+        refs: Arc<AtomicUsize>,
+    }
+
+    impl TestCache {
+        fn len(&self) -> usize {
+            self.state.lock().unwrap().entries.len()
+        }
+
+        fn refs(&self) -> usize {
+            self.refs.load(Ordering::SeqCst)
+        }
+
+        fn new_handle(&self, value: Arc<Block>) -> Handle<Block> {
+            self.refs.fetch_add(1, Ordering::SeqCst);
+            let refs = Arc::clone(&self.refs);
+            Handle::new(value, move || {
+                refs.fetch_sub(1, Ordering::SeqCst);
+            })
+        }
+    }
+
+    impl Cache<Block> for TestCache {
+        fn insert(&self, key: &Slice, value: Arc<Block>, charge: usize) -> Handle<Block> {
+            let old = self
+                .state
+                .lock()
+                .unwrap()
+                .entries
+                .insert(key.as_bytes().to_vec(), (Arc::clone(&value), charge));
+            assert!(old.is_none());
+            self.new_handle(value)
+        }
+
+        fn lookup(&self, key: &Slice) -> Option<Handle<Block>> {
+            let value = Arc::clone(&self.state.lock().unwrap().entries.get(key.as_bytes())?.0);
+            Some(self.new_handle(value))
+        }
+
+        fn erase(&self, _key: &Slice) {
+            unimplemented!()
+        }
+
+        fn new_id(&self) -> u64 {
+            let mut state = self.state.lock().unwrap();
+            state.last_id += 1;
+            state.last_id
+        }
+
+        fn total_charge(&self) -> usize {
+            let state = self.state.lock().unwrap();
+            state.entries.values().map(|e| e.1).sum()
+        }
+    }
+
+    impl Drop for TestCache {
+        fn drop(&mut self) {
+            assert_eq!(0, self.refs());
+        }
+    }
+
+    // NOTE: C++ calls it StringSink in table_test.cc
+    #[derive(Default)]
+    struct StringSink {
+        contents: Vec<u8>,
+    }
+
+    impl WritableFile for StringSink {
+        fn append(&mut self, data: &Slice) -> Result<()> {
+            self.contents.extend_from_slice(data.as_bytes());
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn sync(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // Builds a table holding "entries", flushing a data block after every "per_block" entries.
+    fn build_table<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        options: &Options,
+        entries: &[(K, V)],
+        per_block: usize,
+    ) -> Vec<u8> {
+        let mut sink = StringSink::default();
+        let mut builder = TableBuilder::new(options, &mut sink);
+        for (i, (k, v)) in entries.iter().enumerate() {
+            builder.add(&k.as_ref().into(), &v.as_ref().into());
+            if (i + 1) % per_block == 0 {
+                builder.flush();
+            }
+        }
+        assert!(builder.finish().is_ok());
+        assert_eq!(entries.len() as u64, builder.num_entries());
+        let file_size = builder.file_size();
+        drop(builder);
+        assert_eq!(file_size, sink.contents.len() as u64);
+        sink.contents
+    }
+
+    fn entries(n: usize) -> Vec<(String, String)> {
+        (0..n)
+            .map(|i| (format!("k{:04}", i * 2), format!("value{}", i)))
+            .collect()
+    }
+
+    fn open(options: &Options, contents: Vec<u8>) -> Arc<Table> {
+        let size = contents.len() as u64;
+        Arc::new(Table::open(options, Box::new(StringSource { contents }), size).unwrap())
+    }
+
+    fn get(table: &Table, key: &str) -> Option<String> {
+        let mut result = None;
+        let s = table.internal_get(&ReadOptions::default(), &key.into(), &mut |k, v| {
+            if *k == Slice::from(key) {
+                result = Some(String::from_utf8_lossy(v).into_owned());
+            }
+        });
+        assert!(s.ok(), "{}", s);
+        result
+    }
+
+    #[test]
+    fn test_too_short() {
+        let options = Options::default();
+        let contents = vec![0u8; Footer::ENCODED_LENGTH - 1];
+        let s = Table::open(&options, Box::new(StringSource { contents }), 47)
+            .err()
+            .unwrap();
+        assert!(s.is_corruption());
+
+        let contents = vec![0u8; 100];
+        let s = Table::open(&options, Box::new(StringSource { contents }), 100)
+            .err()
+            .unwrap();
+        assert!(s.is_corruption());
+    }
+
+    #[test]
+    fn test_iterate() {
+        let options = Options::default();
+        let entries = entries(100);
+        let table = open(&options, build_table(&options, &entries, 7));
+
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        for (k, v) in &entries {
+            assert!(iter.valid());
+            assert_eq!(k.as_bytes(), iter.key().as_bytes());
+            assert_eq!(v.as_bytes(), iter.value().as_bytes());
+            iter.next();
+        }
+        assert!(!iter.valid());
+
+        iter.seek_to_last();
+        for (k, _) in entries.iter().rev() {
+            assert!(iter.valid());
+            assert_eq!(k.as_bytes(), iter.key().as_bytes());
+            iter.prev();
+        }
+        assert!(!iter.valid());
+
+        // Odd keys land on the following entry, crossing blocks where needed
+        for i in 0..entries.len() {
+            iter.seek(&format!("k{:04}", i * 2 + 1).as_str().into());
+            if i + 1 < entries.len() {
+                assert!(iter.valid());
+                assert_eq!(entries[i + 1].0.as_bytes(), iter.key().as_bytes());
+            } else {
+                assert!(!iter.valid());
+            }
+        }
+        assert!(iter.status().is_ok());
+
+        // The iterator keeps the table alive
+        iter.seek_to_first();
+        drop(table);
+        iter.next();
+        assert_eq!(entries[1].0.as_bytes(), iter.key().as_bytes());
+    }
+
+    #[test]
+    fn test_internal_get() {
+        let options = Options::default();
+        let entries = entries(50);
+        let table = open(&options, build_table(&options, &entries, 4));
+        for (k, v) in &entries {
+            assert_eq!(Some(v.clone()), get(&table, k));
+        }
+        assert_eq!(None, get(&table, "k0001"));
+        assert_eq!(None, get(&table, "z"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let options = Options {
+            filter_policy: Some(Arc::new(TestHashFilter)),
+            ..Default::default()
+        };
+        let entries = entries(50);
+        let table = open(&options, build_table(&options, &entries, 4));
+        assert!(table.rep.filter.is_some());
+        for (k, v) in &entries {
+            assert_eq!(Some(v.clone()), get(&table, k));
+        }
+
+        // Keys that are absent are filtered out before the data block is read
+        let mut called = false;
+        let s = table.internal_get(&ReadOptions::default(), &"k0001".into(), &mut |_, _| {
+            called = true;
+        });
+        assert!(s.ok());
+        assert!(!called);
+
+        // Without a filter policy the filter block is ignored
+        let table = open(&Options::default(), build_table(&options, &entries, 4));
+        assert!(table.rep.filter.is_none());
+        assert_eq!(Some(entries[3].1.clone()), get(&table, &entries[3].0));
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let cache = Arc::new(TestCache::default());
+        let options = Options {
+            block_cache: Some(Arc::clone(&cache) as Arc<dyn Cache<Block>>),
+            ..Default::default()
+        };
+        let entries = entries(20);
+        let contents = build_table(&options, &entries, 5);
+        let table = open(&options, contents.clone());
+        let other = open(&options, contents);
+        assert_ne!(table.rep.cache_id, other.rep.cache_id);
+
+        assert_eq!(Some(entries[0].1.clone()), get(&table, &entries[0].0));
+        assert_eq!(1, cache.len());
+        // Handles are released once the block iterator is dropped
+        assert_eq!(0, cache.refs());
+
+        // A second read is served from the cache
+        assert_eq!(Some(entries[1].1.clone()), get(&table, &entries[1].0));
+        assert_eq!(1, cache.len());
+
+        // Blocks are cached per table
+        assert_eq!(Some(entries[0].1.clone()), get(&other, &entries[0].0));
+        assert_eq!(2, cache.len());
+
+        // Blocks aren't added when fill_cache is false
+        let options = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        let mut iter = table.new_iterator(&options);
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(entries.len(), count);
+        assert_eq!(2, cache.len());
+
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_last();
+        assert!(iter.valid());
+        assert_eq!(1, cache.refs());
+        assert_eq!(3, cache.len());
+        assert!(cache.total_charge() > 0);
+        drop(iter);
+        assert_eq!(0, cache.refs());
+    }
+
+    #[test]
+    fn test_approximate_offset_of() {
+        let options = Options::default();
+        let entries = entries(100);
+        let contents = build_table(&options, &entries, 10);
+        let len = contents.len() as u64;
+        let table = open(&options, contents);
+
+        assert_eq!(0, table.approximate_offset_of(&"a".into()));
+        assert_eq!(
+            0,
+            table.approximate_offset_of(&entries[0].0.as_str().into())
+        );
+        let mut last = 0;
+        for (k, _) in entries.iter().step_by(10).skip(1) {
+            let offset = table.approximate_offset_of(&k.as_str().into());
+            assert!(offset > last, "{} {}", offset, last);
+            last = offset;
+        }
+        let end = table.approximate_offset_of(&"z".into());
+        assert!(end > last && end < len);
+    }
+
+    #[test]
+    fn test_approximate_offset_of_plain() {
+        let options = Options {
+            block_size: 1024,
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        let entries: Vec<(String, String)> = [
+            ("k01", "hello".to_owned()),
+            ("k02", "hello2".to_owned()),
+            ("k03", "x".repeat(10000)),
+            ("k04", "x".repeat(200000)),
+            ("k05", "x".repeat(300000)),
+            ("k06", "hello3".to_owned()),
+            ("k07", "x".repeat(100000)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v))
+        .collect();
+        let table = open(&options, build_table(&options, &entries, usize::MAX));
+
+        let between = |key: &str, low: u64, high: u64| {
+            let offset = table.approximate_offset_of(&key.into());
+            assert!(
+                low <= offset && offset <= high,
+                "Value {} is not in range [{}, {}]",
+                offset,
+                low,
+                high
+            );
+        };
+        between("abc", 0, 0);
+        between("k01", 0, 0);
+        between("k01a", 0, 0);
+        between("k02", 0, 0);
+        between("k03", 0, 0);
+        between("k04", 10000, 11000);
+        between("k04a", 210000, 211000);
+        between("k05", 210000, 211000);
+        between("k06", 510000, 511000);
+        between("k07", 510000, 511000);
+        between("xyz", 610000, 612000);
+    }
+
+    #[test]
+    fn test_builder_blocks() {
+        let options = Options {
+            block_size: 256,
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        let entries = entries(200);
+        let contents = build_table(&options, &entries, usize::MAX);
+
+        // The file ends with the footer magic
+        let magic = &contents[contents.len() - 8..];
+        assert_eq!(TABLE_MAGIC_NUMBER.to_le_bytes(), magic);
+
+        let table = open(&options, contents);
+        let mut index_iter = table
+            .rep
+            .index_block
+            .new_iterator(Arc::clone(&options.comparator));
+        let mut handle = BlockHandle::new();
+        index_iter.seek_to_first();
+
+        // Data blocks are cut at block_size and index keys are shortened to separators
+        let mut block_sizes = Vec::new();
+        let mut last_index_key = Vec::new();
+        while index_iter.valid() {
+            assert!(handle.decode_from(&mut index_iter.value()).ok());
+            block_sizes.push(handle.size() as usize);
+            last_index_key = index_iter.key().as_bytes().to_vec();
+            assert!(last_index_key.len() <= 5);
+            index_iter.next();
+        }
+        assert!(block_sizes.len() > 5, "{}", block_sizes.len());
+        // All but the last block were cut once they reached block_size
+        let (_, full) = block_sizes.split_last().unwrap();
+        assert!(full.iter().all(|&size| size >= options.block_size));
+        // The last index key is the short successor of the last key
+        assert_eq!(b"l", &last_index_key[..]);
+
+        let mut iter = table.new_iterator(&ReadOptions::default());
+        iter.seek_to_first();
+        for (k, v) in &entries {
+            assert!(iter.valid());
+            assert_eq!(k.as_bytes(), iter.key().as_bytes());
+            assert_eq!(v.as_bytes(), iter.value().as_bytes());
+            iter.next();
+        }
+        assert!(!iter.valid());
+    }
+
+    // Returns the compression type stored in the trailer of the first data block
+    fn first_block_type(table: &Table, contents: &[u8]) -> u8 {
+        let mut index_iter = table
+            .rep
+            .index_block
+            .new_iterator(Arc::clone(&table.rep.comparator));
+        index_iter.seek_to_first();
+        let mut handle = BlockHandle::new();
+        assert!(handle.decode_from(&mut index_iter.value()).ok());
+        contents[(handle.offset() + handle.size()) as usize]
+    }
+
+    #[test]
+    fn test_builder_compression() {
+        let options = Options::default();
+        assert_eq!(CompressionType::SnappyCompression, options.compression);
+        let uncompressed = Options {
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+
+        // Compressible blocks are stored compressed
+        let entries: Vec<_> = (0..100)
+            .map(|i| (format!("k{:04}", i), "x".repeat(100)))
+            .collect();
+        let contents = build_table(&options, &entries, 10);
+        let raw_len = build_table(&uncompressed, &entries, 10).len();
+        assert!(
+            contents.len() < raw_len / 2,
+            "{} {}",
+            contents.len(),
+            raw_len
+        );
+        let table = open(&options, contents.clone());
+        assert_eq!(
+            CompressionType::SnappyCompression as u8,
+            first_block_type(&table, &contents)
+        );
+        let read_options = ReadOptions {
+            verify_checksums: true,
+            ..Default::default()
+        };
+        let mut iter = table.new_iterator(&read_options);
+        iter.seek_to_first();
+        for (k, v) in &entries {
+            assert!(iter.valid());
+            assert_eq!(k.as_bytes(), iter.key().as_bytes());
+            assert_eq!(v.as_bytes(), iter.value().as_bytes());
+            iter.next();
+        }
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+
+        // Blocks that compress by less than 12.5% are stored uncompressed
+        let mut rnd = Random::new(301);
+        let entries: Vec<_> = (0..10)
+            .map(|i| {
+                let value: String = (0..100)
+                    .map(|_| (b' ' + rnd.uniform(95) as u8) as char)
+                    .collect();
+                (format!("k{:04}", i), value)
+            })
+            .collect();
+        let contents = build_table(&options, &entries, 10);
+        let table = open(&options, contents.clone());
+        assert_eq!(
+            CompressionType::NoCompression as u8,
+            first_block_type(&table, &contents)
+        );
+        assert_eq!(Some(entries[3].1.clone()), get(&table, &entries[3].0));
+    }
+
+    #[test]
+    fn test_builder_filter_meta_block() {
+        let options = Options {
+            filter_policy: Some(Arc::new(TestHashFilter)),
+            ..Default::default()
+        };
+        let contents = build_table(&options, &entries(10), 3);
+        let table = open(&options, contents.clone());
+
+        let opt = ReadOptions::default();
+        let mut block = BlockContents::default();
+        let file = StringSource { contents };
+        assert!(read_block(&file, &opt, &table.rep.metaindex_handle, &mut block).ok());
+        let meta = Arc::new(Block::new(block));
+        let mut iter = meta.new_iterator(Arc::new(bytewise_comparator()));
+        iter.seek_to_first();
+        assert!(iter.valid());
+        assert_eq!(b"filter.TestHashFilter", iter.key().as_bytes());
+        iter.next();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_builder_change_options() {
+        let options = Options::default();
+        let mut sink = StringSink::default();
+        let mut builder = TableBuilder::new(&options, &mut sink);
+        builder.add(&"a".into(), &"1".into());
+
+        // The comparator can't change while building
+        let other = Options {
+            comparator: Arc::new(InternalKeyComparator::new(Arc::clone(&options.comparator))),
+            ..Default::default()
+        };
+        assert!(builder
+            .change_options(&other)
+            .unwrap_err()
+            .is_invalid_argument());
+
+        let other = Options {
+            block_size: 1,
+            block_restart_interval: 1,
+            compression: CompressionType::NoCompression,
+            ..Default::default()
+        };
+        assert!(builder.change_options(&other).is_ok());
+        // Every entry now gets its own block
+        builder.add(&"b".into(), &"2".into());
+        let offset = builder.file_size();
+        assert!(offset > 0);
+        builder.add(&"c".into(), &"3".into());
+        assert!(builder.file_size() > offset);
+        assert!(builder.finish().is_ok());
+        assert_eq!(3, builder.num_entries());
+        drop(builder);
+
+        let table = open(&options, sink.contents);
+        for (k, v) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            assert_eq!(Some(v.to_owned()), get(&table, k));
+        }
+    }
+
+    #[test]
+    fn test_builder_abandon() {
+        let options = Options::default();
+        let mut sink = StringSink::default();
+        let mut builder = TableBuilder::new(&options, &mut sink);
+        builder.add(&"a".into(), &"1".into());
+        builder.abandon();
+        assert_eq!(1, builder.num_entries());
+        assert_eq!(0, builder.file_size());
+    }
+
+    #[test]
+    fn test_corrupted_block() {
+        let options = Options::default();
+        let entries = entries(10);
+        let mut contents = build_table(&options, &entries, 5);
+        // Flip a bit in the first data block
+        contents[2] ^= 0x10;
+        let table = open(&options, contents);
+
+        let read_options = ReadOptions {
+            verify_checksums: true,
+            ..Default::default()
+        };
+        let mut iter = table.new_iterator(&read_options);
+        iter.seek_to_first();
+        assert!(iter.valid());
+        assert_eq!(entries[5].0.as_bytes(), iter.key().as_bytes());
+        assert!(iter.status().unwrap_err().is_corruption());
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        // Both scans and point lookups, with and without compressed blocks
+        let mut rnd = Random::new(301);
+        for compression in [
+            CompressionType::NoCompression,
+            CompressionType::SnappyCompression,
+        ] {
+            let options = Options {
+                compression,
+                filter_policy: Some(Arc::new(TestHashFilter)),
+                ..Default::default()
+            };
+            for _ in 0..20 {
+                let entries = testutil::random_entries(&mut rnd, 300, 16, 100);
+                let per_block = 1 + rnd.uniform(50) as usize;
+                let table = open(&options, build_table(&options, &entries, per_block));
+
+                let mut iter = table.new_iterator(&ReadOptions::default());
+                iter.seek_to_first();
+                for (k, v) in &entries {
+                    assert!(iter.valid());
+                    assert_eq!(&k[..], &*iter.key());
+                    assert_eq!(&v[..], &*iter.value());
+                    iter.next();
+                }
+                assert!(!iter.valid());
+                assert!(iter.status().is_ok());
+
+                for (k, v) in &entries {
+                    let mut found = None;
+                    let s =
+                        table.internal_get(&ReadOptions::default(), &k.into(), &mut |rk, rv| {
+                            found = Some((rk.to_vec(), rv.to_vec()));
+                        });
+                    assert!(s.ok(), "{}", s);
+                    assert_eq!(Some((k.clone(), v.clone())), found);
+                }
+            }
+        }
+    }
+}
@@ -2,74 +2,384 @@
 // leveldb/table/table_builder.cc
 
 use crate::{
-    comparator, env, filter_policy,
+    comparator::Comparator,
+    env::WritableFile,
+    filter_policy::FilterPolicy,
     options::{CompressionType, Options},
     slice::Slice,
-    status::Status,
-    table::{block_builder, filter_block, format},
+    snappy,
+    status::{Error, Result, Status},
+    table::{
+        block_builder::BlockBuilder,
+        filter_block::FilterBlockBuilder,
+        format::{BlockHandle, Footer, BLOCK_TRAILER_SIZE},
+    },
     util::{coding, crc32c},
 };
+use std::sync::Arc;
 
-pub struct TableBuilder {
-    rep: Rep,
+// TableBuilder provides the interface used to build a Table (an immutable and sorted map from
+// keys to values).
+//
+// Multiple threads can invoke const methods on a TableBuilder without external synchronization,
+// but if any of the threads may call a non-const method, all threads accessing the same
+// TableBuilder must use external synchronization.
+pub struct TableBuilder<'a> {
+    rep: Rep<'a>,
 }
 
-impl TableBuilder {
+impl<'a> TableBuilder<'a> {
     // Create a builder that will store the contents of the table it is building in file. It is up
     // to the caller to close the file after calling finish().
-    pub fn new(...) -> Self;
+    // NOTE: C++ takes a pointer to the file
+    pub fn new(options: &Options, file: &'a mut dyn WritableFile) -> Self {
+        let mut rep = Rep::new(options, file);
+        if let Some(filter_block) = rep.filter_block.as_mut() {
+            filter_block.start_block(0);
+        }
+        Self { rep }
+    }
 
     // Change the options used by this builder. Note: only some of the option fields can be
     // changed after construction. If a field is not allowed to change dynamically and its value
     // in the structure passed to the constructor is different from its value in the structure
     // passed to this method, this method will return an error without changing any fields.
-    pub fn change_options(&mut self, options: &Options) -> Status;
+    pub fn change_options(&mut self, options: &Options) -> Result<()> {
+        // Note: if more fields are added to Options, update this function to catch changes that
+        // should not be allowed to change in the middle of building a Table.
+        // NOTE: C++ compares the comparator pointers; the names are compared since each
+        // Options::default() holds its own Arc
+        if options.comparator.name() != self.rep.comparator.name() {
+            return Err(Error::invalid_argument(
+                &"changing comparator while building table".into(),
+                &Slice::new(),
+            ));
+        }
+
+        // Note that any live BlockBuilders point to rep.options and therefore will automatically
+        // pick up the updated options.
+        // NOTE: The builders hold their own copy of block_restart_interval, so it's updated here
+        let r = &mut self.rep;
+        r.block_size = options.block_size;
+        r.compression = options.compression;
+        r.data_block
+            .set_block_restart_interval(options.block_restart_interval);
+        r.meta_index_block
+            .set_block_restart_interval(options.block_restart_interval);
+        Ok(())
+    }
 
     // Add key,value to the table being constructed.
-    // RQRUIRES: key is after any previously added key according to comparator.
-    // REQUIRES: finish(), abandon() have noe been called.
-    pub fn add(&mut self, key: &Slice, value: &Slice);
+    // REQUIRES: key is after any previously added key according to comparator.
+    // REQUIRES: finish(), abandon() have not been called.
+    pub fn add(&mut self, key: &Slice, value: &Slice) {
+        assert!(!self.rep.closed);
+        if !self.ok() {
+            return;
+        }
+        let r = &mut self.rep;
+        if r.num_entries > 0 {
+            assert!(r.comparator.compare(key, &Slice::from(&r.last_key)) > 0);
+        }
+
+        if r.pending_index_entry {
+            assert!(r.data_block.empty());
+            r.comparator.find_shortest_separator(&mut r.last_key, key);
+            let mut handle_encoding = Vec::new();
+            r.pending_handle.encode_to(&mut handle_encoding);
+            r.index_block
+                .add(&Slice::from(&r.last_key), &Slice::from(&handle_encoding));
+            r.pending_index_entry = false;
+        }
+
+        if let Some(filter_block) = r.filter_block.as_mut() {
+            filter_block.add_key(key);
+        }
+
+        r.last_key.clear();
+        r.last_key.extend_from_slice(key.as_bytes());
+        r.num_entries += 1;
+        r.data_block.add(key, value);
+
+        let estimated_block_size = r.data_block.current_size_estimate();
+        if estimated_block_size >= r.block_size {
+            self.flush();
+        }
+    }
 
     // Advanced operation: flush any buffered key/value pairs to file. Can be used to ensure that
     // two adjacent entries never live in the same data block. Most clients should not need to use
     // this method.
-    // RQRUIRES: finish(), abandon() have not bee called.
-    pub fn flush(&mut self);
+    // REQUIRES: finish(), abandon() have not been called.
+    pub fn flush(&mut self) {
+        assert!(!self.rep.closed);
+        if !self.ok() {
+            return;
+        }
+        if self.rep.data_block.empty() {
+            return;
+        }
+        assert!(!self.rep.pending_index_entry);
+        let mut pending_handle = self.rep.pending_handle;
+        self.rep.write_block(BlockKind::Data, &mut pending_handle);
+        self.rep.pending_handle = pending_handle;
+        if self.ok() {
+            self.rep.pending_index_entry = true;
+            self.rep.status = self.rep.file.flush().into();
+        }
+        if let Some(filter_block) = self.rep.filter_block.as_mut() {
+            filter_block.start_block(self.rep.offset);
+        }
+    }
 
-    // Return non-ok iff some error has been detected.
-    pub fn status(&self) -> Status;
+    // Return an error iff some error has been detected.
+    pub fn status(&self) -> Result<()> {
+        self.rep.status.clone().into()
+    }
 
     // Finish building the table. Stops using the file passed to the constructor after this
     // function returns.
     // REQUIRES: finish(), abandon() have not been called.
-    pub fn finish(&mut self) -> Status;
+    pub fn finish(&mut self) -> Result<()> {
+        self.flush();
+        assert!(!self.rep.closed);
+        self.rep.closed = true;
+
+        let r = &mut self.rep;
+        let mut filter_block_handle = BlockHandle::new();
+        let mut metaindex_block_handle = BlockHandle::new();
+        let mut index_block_handle = BlockHandle::new();
+
+        // Write filter block
+        if r.ok() {
+            if let Some(filter_block) = r.filter_block.as_mut() {
+                // NOTE: The contents are copied since they borrow from r, which is written to below
+                let contents = filter_block.finish().to_vec();
+                r.write_raw_block(
+                    &Slice::from(&contents),
+                    CompressionType::NoCompression,
+                    &mut filter_block_handle,
+                );
+            }
+        }
+
+        // Write metaindex block
+        if r.ok() {
+            if let Some(policy) = &r.filter_policy {
+                // Add mapping from "filter.Name" to location of filter data
+                let key = format!("filter.{}", policy.name());
+                let mut handle_encoding = Vec::new();
+                filter_block_handle.encode_to(&mut handle_encoding);
+                r.meta_index_block
+                    .add(&key.as_str().into(), &Slice::from(&handle_encoding));
+            }
+
+            // TODO(postrelease): Add stats and other meta blocks
+            r.write_block(BlockKind::MetaIndex, &mut metaindex_block_handle);
+        }
+
+        // Write index block
+        if r.ok() {
+            if r.pending_index_entry {
+                r.comparator.find_short_successor(&mut r.last_key);
+                let mut handle_encoding = Vec::new();
+                r.pending_handle.encode_to(&mut handle_encoding);
+                r.index_block
+                    .add(&Slice::from(&r.last_key), &Slice::from(&handle_encoding));
+                r.pending_index_entry = false;
+            }
+            r.write_block(BlockKind::Index, &mut index_block_handle);
+        }
+
+        // Write footer
+        if r.ok() {
+            let mut footer = Footer::new();
+            footer.set_metaindex_handle(&metaindex_block_handle);
+            footer.set_index_handle(&index_block_handle);
+            let mut footer_encoding = Vec::new();
+            footer.encode_to(&mut footer_encoding);
+            r.status = r.file.append(&Slice::from(&footer_encoding)).into();
+            if r.status.ok() {
+                r.offset += footer_encoding.len() as u64;
+            }
+        }
+        r.status.clone().into()
+    }
 
     // Indicate that the contents of this builder should be abandoned. Stops using the file passed
     // to the constructor after this function returns. If the caller is not going to call
     // finish(), it must call abandon() before destroying this builder.
     // REQUIRES: finish(), abandon() have not been called.
+    pub fn abandon(&mut self) {
+        assert!(!self.rep.closed);
+        self.rep.closed = true;
+    }
 
     // Number of calls to add() so far.
-    pub fn num_entries(&self) -> u64;
+    pub fn num_entries(&self) -> u64 {
+        self.rep.num_entries
+    }
 
     // Size of the file generated so far. If invoked after a successful finish() call, returns the
     // size of the final generated file.
-    pub fn file_size(&self) -> u64;
+    pub fn file_size(&self) -> u64 {
+        self.rep.offset
+    }
 
     fn ok(&self) -> bool {
-        self.status().ok()
+        self.rep.ok()
     }
+}
 
-    // NOTE: C++ uses pointers are used instead of references
-    fn write_block(&mut self, block: &mut BlockBuilder, handle: &mut BlockHandle);
+impl Drop for TableBuilder<'_> {
+    // REQUIRES: Either finish() or abandon() has been called.
+    fn drop(&mut self) {
+        // Catch errors where caller forgot to call finish()
+        // NOTE: Skipped while panicking so a failed assertion elsewhere doesn't abort
+        assert!(self.rep.closed || std::thread::panicking());
+    }
+}
 
-    // NOTE: C++ uses pointer instead of mut reference
-    fn write_raw_block(data: &Slice, _: CompressionType, handle: &mut BlockHandle);
+// NOTE: C++ passes a pointer to the block being written; the blocks are fields of Rep, so which
+// one to write is passed instead
+#[derive(Clone, Copy)]
+enum BlockKind {
+    Data,
+    Index,
+    MetaIndex,
 }
 
-impl Drop for TableBuilder {
-    // REQUIRES: Either finish() or abandon() has been called.
-    fn drop(&mut self);
+struct Rep<'a> {
+    // NOTE: C++ keeps copies of the options; only the fields used by the builder are kept
+    comparator: Arc<dyn Comparator>,
+    filter_policy: Option<Arc<dyn FilterPolicy>>,
+    block_size: usize,
+    compression: CompressionType,
+    file: &'a mut dyn WritableFile,
+    offset: u64,
+    status: Status,
+    data_block: BlockBuilder,
+    index_block: BlockBuilder,
+    // NOTE: C++ creates the metaindex block in finish()
+    meta_index_block: BlockBuilder,
+    last_key: Vec<u8>,
+    num_entries: u64,
+    // Either finish() or abandon() has been called.
+    closed: bool,
+    filter_block: Option<FilterBlockBuilder>,
+
+    // We do not emit the index entry for a block until we have seen the first key for the next
+    // data block. This allows us to use shorter keys in the index block. For example, consider a
+    // block boundary between the keys "the quick brown fox" and "the who". We can use "the r" as
+    // the key for the index block entry since it is >= all entries in the first block and < all
+    // entries in subsequent blocks.
+    //
+    // Invariant: r.pending_index_entry is true only if data_block is empty.
+    pending_index_entry: bool,
+    // Handle to add to index block
+    pending_handle: BlockHandle,
+
+    compressed_output: Vec<u8>,
 }
 
-struct Rep {}
+impl<'a> Rep<'a> {
+    fn new(options: &Options, file: &'a mut dyn WritableFile) -> Self {
+        let mut index_block = BlockBuilder::new(options);
+        index_block.set_block_restart_interval(1);
+        Self {
+            comparator: Arc::clone(&options.comparator),
+            filter_policy: options.filter_policy.clone(),
+            block_size: options.block_size,
+            compression: options.compression,
+            file,
+            offset: 0,
+            status: Status::OK(),
+            data_block: BlockBuilder::new(options),
+            index_block,
+            meta_index_block: BlockBuilder::new(options),
+            last_key: Vec::new(),
+            num_entries: 0,
+            closed: false,
+            filter_block: options
+                .filter_policy
+                .as_ref()
+                .map(|policy| FilterBlockBuilder::new(Arc::clone(policy))),
+            pending_index_entry: false,
+            pending_handle: BlockHandle::new(),
+            compressed_output: Vec::new(),
+        }
+    }
+
+    fn ok(&self) -> bool {
+        self.status.ok()
+    }
+
+    fn block(&mut self, kind: BlockKind) -> &mut BlockBuilder {
+        match kind {
+            BlockKind::Data => &mut self.data_block,
+            BlockKind::Index => &mut self.index_block,
+            BlockKind::MetaIndex => &mut self.meta_index_block,
+        }
+    }
+
+    // NOTE: C++ uses pointers are used instead of references
+    fn write_block(&mut self, kind: BlockKind, handle: &mut BlockHandle) {
+        // File format contains a sequence of blocks where each block has:
+        //    block_data: uint8[n]
+        //    type: uint8
+        //    crc: uint32
+        assert!(self.ok());
+        // NOTE: C++ points into the block's buffer; it is copied here since write_raw_block needs
+        // the whole builder mutably
+        let raw_buf = self.block(kind).finish().to_vec();
+        let raw = Slice::from(&raw_buf);
+        let mut compressed = std::mem::take(&mut self.compressed_output);
+
+        let mut block_type = self.compression;
+        // TODO(postrelease): Support more compression options: zlib?
+        let block_contents = match block_type {
+            CompressionType::NoCompression => raw,
+            CompressionType::SnappyCompression => {
+                // NOTE: C++ goes through port::Snappy_Compress, which fails when leveldb is built
+                // without snappy
+                snappy::compress(raw.as_bytes(), &mut compressed);
+                if compressed.len() < raw.size() - (raw.size() / 8) {
+                    Slice::from(&compressed)
+                } else {
+                    // Snappy not supported, or compressed less than 12.5%, so just store
+                    // uncompressed form
+                    block_type = CompressionType::NoCompression;
+                    raw
+                }
+            }
+        };
+        self.write_raw_block(&block_contents, block_type, handle);
+        compressed.clear();
+        self.compressed_output = compressed;
+        self.block(kind).reset();
+    }
+
+    // NOTE: C++ uses pointer instead of mut reference
+    fn write_raw_block(
+        &mut self,
+        block_contents: &Slice,
+        block_type: CompressionType,
+        handle: &mut BlockHandle,
+    ) {
+        handle.set_offset(self.offset);
+        handle.set_size(block_contents.size() as u64);
+        self.status = self.file.append(block_contents).into();
+        if self.status.ok() {
+            let mut trailer = [0u8; BLOCK_TRAILER_SIZE];
+            trailer[0] = block_type as u8;
+            let mut crc = crc32c::value(block_contents);
+            // Extend crc to cover block type
+            crc = crc32c::extend(crc, &trailer[..1]);
+            coding::encode_fixed32(&mut trailer[1..], crc32c::mask(crc));
+            self.status = self.file.append(&Slice::from(&trailer[..])).into();
+            if self.status.ok() {
+                self.offset += (block_contents.size() + BLOCK_TRAILER_SIZE) as u64;
+            }
+        }
+    }
+}
@@ -0,0 +1,377 @@
+// leveldb/table/two_level_iterator.{h,cc}
+
+use crate::{
+    iter::{CleanupNode, Iter},
+    options::ReadOptions,
+    slice::Slice,
+    status::{Result, Status},
+    table::iterator_wrapper::IteratorWrapper,
+};
+
+// Converts an index iterator value (i.e., an encoded BlockHandle) into an iterator over the
+// contents of the corresponding block.
+// NOTE: C++ passes an extra "void* arg"; closures capture whatever state they need instead
+pub(crate) type BlockFunction = Box<dyn Fn(&ReadOptions, &Slice) -> Box<dyn Iter>>;
+
+// Return a new two level iterator. A two-level iterator contains an index iterator whose values
+// point to a sequence of blocks where each block is itself a sequence of key,value pairs. The
+// returned two-level iterator yields the concatenation of all key/value pairs in the sequence of
+// blocks. Takes ownership of "index_iter" and will drop it when no longer needed.
+//
+// Uses a supplied function to convert an index_iter value into an iterator over the contents of
+// the corresponding block.
+pub(crate) fn new_two_level_iterator(
+    index_iter: Box<dyn Iter>,
+    block_function: BlockFunction,
+    options: &ReadOptions,
+) -> Box<dyn Iter> {
+    Box::new(TwoLevelIterator {
+        block_function,
+        options: options.clone(),
+        status: Status::OK(),
+        index_iter: IteratorWrapper::new(Some(index_iter)),
+        data_iter: IteratorWrapper::new(None),
+        data_block_handle: Vec::new(),
+        cleanup_head: CleanupNode::default(),
+    })
+}
+
+struct TwoLevelIterator {
+    block_function: BlockFunction,
+    options: ReadOptions,
+    status: Status,
+    index_iter: IteratorWrapper,
+    // May be None
+    data_iter: IteratorWrapper,
+    // If data_iter is not None, then "data_block_handle" holds the "index_value" passed to
+    // block_function to create the data_iter.
+    data_block_handle: Vec<u8>,
+    cleanup_head: CleanupNode,
+}
+
+impl TwoLevelIterator {
+    fn save_error(&mut self, s: Status) {
+        if self.status.ok() && !s.ok() {
+            self.status = s;
+        }
+    }
+
+    fn skip_empty_data_blocks_forward(&mut self) {
+        while self.data_iter.iter().is_none() || !self.data_iter.valid() {
+            // Move to next block
+            if !self.index_iter.valid() {
+                self.set_data_iterator(None);
+                return;
+            }
+            self.index_iter.next();
+            self.init_data_block();
+            if self.data_iter.iter().is_some() {
+                self.data_iter.seek_to_first();
+            }
+        }
+    }
+
+    fn skip_empty_data_blocks_backward(&mut self) {
+        while self.data_iter.iter().is_none() || !self.data_iter.valid() {
+            // Move to next block
+            if !self.index_iter.valid() {
+                self.set_data_iterator(None);
+                return;
+            }
+            self.index_iter.prev();
+            self.init_data_block();
+            if self.data_iter.iter().is_some() {
+                self.data_iter.seek_to_last();
+            }
+        }
+    }
+
+    fn set_data_iterator(&mut self, data_iter: Option<Box<dyn Iter>>) {
+        if self.data_iter.iter().is_some() {
+            let s = self.data_iter.status().into();
+            self.save_error(s);
+        }
+        self.data_iter.set(data_iter);
+    }
+
+    fn init_data_block(&mut self) {
+        if !self.index_iter.valid() {
+            self.set_data_iterator(None);
+        } else {
+            let handle = self.index_iter.value();
+            if self.data_iter.iter().is_some() && handle.as_bytes() == self.data_block_handle {
+                // data_iter is already constructed with this iterator, so no need to change
+                // anything
+            } else {
+                let iter = (self.block_function)(&self.options, &handle);
+                self.data_block_handle.clear();
+                self.data_block_handle.extend_from_slice(handle.as_bytes());
+                self.set_data_iterator(Some(iter));
+            }
+        }
+    }
+}
+
+impl Iter for TwoLevelIterator {
+    fn cleanup_head(&mut self) -> &mut CleanupNode {
+        &mut self.cleanup_head
+    }
+
+    fn valid(&self) -> bool {
+        self.data_iter.valid()
+    }
+
+    fn seek(&mut self, target: &Slice) {
+        self.index_iter.seek(target);
+        self.init_data_block();
+        if self.data_iter.iter().is_some() {
+            self.data_iter.seek(target);
+        }
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn seek_to_first(&mut self) {
+        self.index_iter.seek_to_first();
+        self.init_data_block();
+        if self.data_iter.iter().is_some() {
+            self.data_iter.seek_to_first();
+        }
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.index_iter.seek_to_last();
+        self.init_data_block();
+        if self.data_iter.iter().is_some() {
+            self.data_iter.seek_to_last();
+        }
+        self.skip_empty_data_blocks_backward();
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.data_iter.next();
+        self.skip_empty_data_blocks_forward();
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        self.data_iter.prev();
+        self.skip_empty_data_blocks_backward();
+    }
+
+    fn key(&self) -> Slice<'_> {
+        assert!(self.valid());
+        self.data_iter.key()
+    }
+
+    fn value(&self) -> Slice<'_> {
+        assert!(self.valid());
+        self.data_iter.value()
+    }
+
+    fn status(&self) -> Result<()> {
+        self.index_iter.status()?;
+        if self.data_iter.iter().is_some() {
+            self.data_iter.status()?;
+        }
+        self.status.clone().into()
+    }
+}
+
+impl Drop for TwoLevelIterator {
+    fn drop(&mut self) {
+        self.drop_func();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::bytewise_comparator;
+    use crate::iter::new_error_iter;
+    use crate::options::Options;
+    use crate::status::Error;
+    use crate::table::block::Block;
+    use crate::table::block_builder::BlockBuilder;
+    use crate::table::format::BlockContents;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    fn build_block(entries: &[(String, String)]) -> Arc<Block> {
+        let mut builder = BlockBuilder::new(&Options::default());
+        for (k, v) in entries {
+            builder.add(&k.into(), &v.into());
+        }
+        let data = builder.finish().as_bytes().to_vec();
+        Arc::new(Block::new(BlockContents {
+            data,
+            cachable: false,
+        }))
+    }
+
+    // A table-like layout: each data block is named in the index by its position, under the key
+    // of the block's last entry. Blocks named "bad" fail to open.
+    struct Harness {
+        index: Arc<Block>,
+        blocks: Rc<Vec<Arc<Block>>>,
+        opened: Rc<Cell<usize>>,
+    }
+
+    impl Harness {
+        // "blocks" lists the keys of each data block; None names a block that fails to open
+        fn new(blocks: &[Option<&[&str]>]) -> Self {
+            let mut index_entries = Vec::new();
+            let mut data_blocks = Vec::new();
+            let mut last_key = String::new();
+            for (i, keys) in blocks.iter().enumerate() {
+                let handle = match keys {
+                    Some(keys) => {
+                        let entries = keys
+                            .iter()
+                            .map(|k| (k.to_string(), format!("v{}", k)))
+                            .collect::<Vec<_>>();
+                        if let Some((k, _)) = entries.last() {
+                            last_key = k.clone();
+                        }
+                        data_blocks.push(build_block(&entries));
+                        format!("{}", data_blocks.len() - 1)
+                    }
+                    None => "bad".to_owned(),
+                };
+                // Empty blocks still need distinct, increasing index keys
+                index_entries.push((format!("{}{}", last_key, i), handle));
+            }
+            Self {
+                index: build_block(&index_entries),
+                blocks: Rc::new(data_blocks),
+                opened: Rc::new(Cell::new(0)),
+            }
+        }
+
+        fn new_iter(&self) -> Box<dyn Iter> {
+            let blocks = Rc::clone(&self.blocks);
+            let opened = Rc::clone(&self.opened);
+            new_two_level_iterator(
+                self.index.new_iterator(Arc::new(bytewise_comparator())),
+                Box::new(move |_options, handle| {
+                    opened.set(opened.get() + 1);
+                    match String::from_utf8_lossy(handle).parse::<usize>() {
+                        Ok(i) => blocks[i].new_iterator(Arc::new(bytewise_comparator())),
+                        Err(_) => new_error_iter(Error::corruption(
+                            &"bad block handle".into(),
+                            &Slice::new(),
+                        )),
+                    }
+                }),
+                &ReadOptions::default(),
+            )
+        }
+    }
+
+    fn scan_forward(iter: &mut dyn Iter) -> Vec<String> {
+        let mut keys = Vec::new();
+        iter.seek_to_first();
+        while iter.valid() {
+            keys.push(String::from_utf8_lossy(&iter.key()).into_owned());
+            iter.next();
+        }
+        keys
+    }
+
+    fn scan_backward(iter: &mut dyn Iter) -> Vec<String> {
+        let mut keys = Vec::new();
+        iter.seek_to_last();
+        while iter.valid() {
+            keys.push(String::from_utf8_lossy(&iter.key()).into_owned());
+            iter.prev();
+        }
+        keys
+    }
+
+    #[test]
+    fn test_empty() {
+        let harness = Harness::new(&[]);
+        let mut iter = harness.new_iter();
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+        iter.seek(&"a".into());
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+        assert_eq!(0, harness.opened.get());
+
+        // Only empty data blocks
+        let harness = Harness::new(&[Some(&[]), Some(&[])]);
+        let mut iter = harness.new_iter();
+        assert!(scan_forward(&mut *iter).is_empty());
+        assert!(scan_backward(&mut *iter).is_empty());
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn test_skip_empty_blocks() {
+        let harness = Harness::new(&[
+            Some(&[]),
+            Some(&["a", "b"]),
+            Some(&[]),
+            Some(&[]),
+            Some(&["c"]),
+            Some(&["d", "e"]),
+            Some(&[]),
+        ]);
+        let mut iter = harness.new_iter();
+        assert_eq!(vec!["a", "b", "c", "d", "e"], scan_forward(&mut *iter));
+        assert_eq!(vec!["e", "d", "c", "b", "a"], scan_backward(&mut *iter));
+
+        // Seeking past the end of a block moves on to the next non-empty one
+        iter.seek(&"bb".into());
+        assert_eq!(b"c", &*iter.key());
+        iter.prev();
+        assert_eq!(b"b", &*iter.key());
+        iter.next();
+        iter.next();
+        assert_eq!(b"d", &*iter.key());
+        assert_eq!(b"vd", &*iter.value());
+        iter.seek(&"f".into());
+        assert!(!iter.valid());
+        assert!(iter.status().is_ok());
+    }
+
+    #[test]
+    fn test_reuse_data_block() {
+        let harness = Harness::new(&[Some(&["a", "b", "c"]), Some(&["d"])]);
+        let mut iter = harness.new_iter();
+        iter.seek(&"a".into());
+        iter.seek(&"c".into());
+        iter.seek(&"b".into());
+        assert_eq!(b"b", &*iter.key());
+        // Seeks within the same block do not reopen it
+        assert_eq!(1, harness.opened.get());
+        iter.seek(&"d".into());
+        assert_eq!(2, harness.opened.get());
+    }
+
+    #[test]
+    fn test_block_function_error() {
+        let harness = Harness::new(&[Some(&["a"]), None, Some(&["c"])]);
+        let mut iter = harness.new_iter();
+
+        // The broken block is skipped, but its error is reported
+        assert_eq!(vec!["a", "c"], scan_forward(&mut *iter));
+        let e = iter.status().unwrap_err();
+        assert!(e.is_corruption(), "{}", e);
+
+        let mut iter = harness.new_iter();
+        assert_eq!(vec!["c", "a"], scan_backward(&mut *iter));
+        assert!(iter.status().unwrap_err().is_corruption());
+
+        // Seeking past "a" has to go through the broken block
+        let mut iter = harness.new_iter();
+        iter.seek(&"a0".into());
+        assert_eq!(b"c", &*iter.key());
+        assert!(iter.status().unwrap_err().is_corruption());
+    }
+}
@@ -2,13 +2,11 @@
 // leveldb/util/arena_test.cc
 
 use std::alloc;
+use std::cell::UnsafeCell;
 use std::ffi::c_void;
 use std::mem::size_of;
 use std::os::raw::c_char;
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc,
-};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 macro_rules! static_assert {
     ($x:expr) => {
@@ -18,50 +16,60 @@ macro_rules! static_assert {
 
 const BLOCK_SIZE: usize = 4096;
 
-pub struct Arena {
-    // Allocation state
+struct AllocState {
     alloc_ptr: *mut c_char,
     alloc_bytes_remaining: usize,
 
     // Array of `alloc`ed memory blocks
     // NOTE: Use "fat" pointer
     blocks: Vec<(*mut c_char, usize)>,
+}
+
+// NOTE: Like in C++, allocating goes through a shared reference, so memory_usage() can be read
+// while another thread allocates (the arena is !Sync; owners that share it, like MemTable, must
+// synchronize allocations themselves).
+pub struct Arena {
+    // Allocation state
+    state: UnsafeCell<AllocState>,
 
     // Total memory usage of the arena.
     //
     // TODO(costan): This member is accessed via atomics, but the others are accessed without any
     // locking. Is this OK?
-    memory_usage: Arc<AtomicUsize>,
+    memory_usage: AtomicUsize,
 }
 
 impl Arena {
     pub fn new() -> Self {
         Self {
-            // Can use null "safely" since it won't ever be dereferenced (I believe)
-            alloc_ptr: std::ptr::null_mut(),
-            alloc_bytes_remaining: 0,
-            blocks: Vec::new(),
-            memory_usage: Arc::new(AtomicUsize::new(0)),
+            state: UnsafeCell::new(AllocState {
+                // Can use null "safely" since it won't ever be dereferenced (I believe)
+                alloc_ptr: std::ptr::null_mut(),
+                alloc_bytes_remaining: 0,
+                blocks: Vec::new(),
+            }),
+            memory_usage: AtomicUsize::new(0),
         }
     }
 
     // Return a pointer to a newly allocated memory block of "bytes" bytes.
     #[inline]
-    pub fn allocate(&mut self, bytes: usize) -> *mut c_char {
+    pub fn allocate(&self, bytes: usize) -> *mut c_char {
         // The semantics of what to return are a bit messy if we allow 0-byte allocations, so we
         // disallow them here (we don't need them for our internal use).
         assert!(bytes > 0);
-        if bytes <= self.alloc_bytes_remaining {
-            let res = self.alloc_ptr;
-            self.alloc_ptr = unsafe { self.alloc_ptr.add(bytes) };
-            self.alloc_bytes_remaining -= bytes;
+        let state = self.state();
+        if bytes <= state.alloc_bytes_remaining {
+            let res = state.alloc_ptr;
+            state.alloc_ptr = unsafe { state.alloc_ptr.add(bytes) };
+            state.alloc_bytes_remaining -= bytes;
             return res;
         }
         self.allocate_fallback(bytes)
     }
 
     // Allocate memory with the normal alignment guarantees provided by malloc.
-    pub fn allocate_aligned(&mut self, bytes: usize) -> *mut c_char {
+    pub fn allocate_aligned(&self, bytes: usize) -> *mut c_char {
         const ALIGN: usize = if size_of::<*const c_void>() > 8 {
             size_of::<*const c_void>()
         } else {
@@ -70,7 +78,8 @@ impl Arena {
         // Pointer size should be a power of 2
         static_assert!(ALIGN & (ALIGN - 1) == 0);
         // NOTE: C++ uses uintptr_t rather than size_t
-        let current_mod = self.alloc_ptr as usize & (ALIGN - 1);
+        let state = self.state();
+        let current_mod = state.alloc_ptr as usize & (ALIGN - 1);
         let slop = if current_mod == 0 {
             0
         } else {
@@ -78,10 +87,10 @@ impl Arena {
         };
         let needed = bytes + slop;
         let res;
-        if needed <= self.alloc_bytes_remaining {
-            res = unsafe { self.alloc_ptr.add(slop) };
-            self.alloc_ptr = unsafe { self.alloc_ptr.add(needed) };
-            self.alloc_bytes_remaining -= needed;
+        if needed <= state.alloc_bytes_remaining {
+            res = unsafe { state.alloc_ptr.add(slop) };
+            state.alloc_ptr = unsafe { state.alloc_ptr.add(needed) };
+            state.alloc_bytes_remaining -= needed;
         } else {
             // AllocateFallback always returned aligned memory
             res = self.allocate_fallback(bytes);
@@ -95,27 +104,36 @@ impl Arena {
         self.memory_usage.load(Ordering::Relaxed)
     }
 
-    fn allocate_fallback(&mut self, bytes: usize) -> *mut c_char {
+    // SAFETY: The arena is !Sync, so only one thread allocates at a time. Callers must be done
+    // with the returned borrow before calling anything that takes another one.
+    #[allow(clippy::mut_from_ref)]
+    fn state(&self) -> &mut AllocState {
+        unsafe { &mut *self.state.get() }
+    }
+
+    fn allocate_fallback(&self, bytes: usize) -> *mut c_char {
         if bytes > BLOCK_SIZE / 4 {
             // Object is more than a quarter of our block size. Allocate it separately to avoid
             // wasting too much space in leftover bytes.
             return self.allocate_new_block(bytes);
         }
         // We waste the remaining space in the current block.
-        self.alloc_ptr = self.allocate_new_block(BLOCK_SIZE);
-        self.alloc_bytes_remaining = BLOCK_SIZE;
-
-        let res = self.alloc_ptr;
-        self.alloc_ptr = unsafe { self.alloc_ptr.add(bytes) };
-        self.alloc_bytes_remaining -= bytes;
+        let alloc_ptr = self.allocate_new_block(BLOCK_SIZE);
+        let state = self.state();
+        state.alloc_ptr = alloc_ptr;
+        state.alloc_bytes_remaining = BLOCK_SIZE;
+
+        let res = state.alloc_ptr;
+        state.alloc_ptr = unsafe { state.alloc_ptr.add(bytes) };
+        state.alloc_bytes_remaining -= bytes;
         res
     }
 
-    fn allocate_new_block(&mut self, block_bytes: usize) -> *mut c_char {
+    fn allocate_new_block(&self, block_bytes: usize) -> *mut c_char {
         let res = unsafe {
             alloc::alloc(alloc::Layout::array::<c_char>(block_bytes).unwrap()) as *mut c_char
         };
-        self.blocks.push((res, block_bytes));
+        self.state().blocks.push((res, block_bytes));
         self.memory_usage
             .fetch_add(block_bytes + size_of::<c_char>(), Ordering::Relaxed);
         res
@@ -124,7 +142,7 @@ impl Arena {
 
 impl Drop for Arena {
     fn drop(&mut self) {
-        for &(p, s) in self.blocks.iter() {
+        for &(p, s) in self.state.get_mut().blocks.iter() {
             unsafe {
                 alloc::dealloc(p as *mut u8, alloc::Layout::array::<c_char>(s).unwrap());
             }
@@ -145,7 +163,7 @@ mod tests {
     #[test]
     fn test_simple() {
         let mut allocated = Vec::new();
-        let mut arena = Arena::new();
+        let arena = Arena::new();
         const N: usize = 100_000;
         let mut bytes = 0usize;
         let mut rnd = Random::new(301);
@@ -169,7 +187,7 @@ mod tests {
                 arena.allocate(s)
             };
 
-            for b in 0..s as usize {
+            for b in 0..s {
                 // Fill the "i"th allocation with a known bit pattern
                 unsafe {
                     *r.add(b) = (i % 256) as c_char;
@@ -182,8 +200,7 @@ mod tests {
                 assert!(arena.memory_usage() < (bytes as f64 * 1.1) as usize);
             }
         }
-        for i in 0..allocated.len() {
-            let (num_bytes, p) = allocated[i];
+        for (i, &(num_bytes, p)) in allocated.iter().enumerate() {
             for b in 0..num_bytes {
                 // Check the "i"th allocation for the known bit pattern
                 unsafe {
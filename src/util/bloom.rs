@@ -38,7 +38,7 @@ impl FilterPolicy for BloomFilterPolicy {
 
     // NOTE: C++ takes 'n' as an int
     // NOTE: 'n' possily unneeded if it will always be set to the length of 'keys'
-    fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut String) {
+    fn create_filter(&self, keys: &[Slice], n: usize, dst: &mut Vec<u8>) {
         // Compute bloom filter size (in both bits and bytes)
         let mut bits = n * self.bits_per_key;
 
@@ -52,25 +52,18 @@ impl FilterPolicy for BloomFilterPolicy {
         bits = bytes * 8;
 
         let init_size = dst.len();
-        unsafe {
-            let v = dst.as_mut_vec();
-            v.resize(init_size + bytes, 0);
-            v.push(self.k as u8); // Remember # of probes in filter
-            // NOTE: C++ code roughly translated to Rust (line below)
-            //let array = &(*v)[init_size];
-            let array = dst.as_mut_ptr().add(init_size);
-            for i in 0..n {
-                // Use double-hashing to generate a sequence of hash values.
-                // See analysis in [Kirsch,Mitzenmacher 2006].
-                let mut h = bloom_hash(&keys[i]);
-                let delta = (h >> 17) | (h << 15); // Rotate right 17 bits
-                for j in 0..self.k {
-                    let bitpos = h % bits;
-                    // NOTE: C++ code rougly translated to Rust (line below)
-                    //array[bitpos / 8] |= (1 << (bitpos % 8));
-                    *array.add(bitpos / 8) |= (1 << (bitpos % 8));
-                    h += delta;
-                }
+        dst.resize(init_size + bytes, 0);
+        dst.push(self.k as u8); // Remember # of probes in filter
+        let array = &mut dst[init_size..];
+        for i in 0..n {
+            // Use double-hashing to generate a sequence of hash values.
+            // See analysis in [Kirsch,Mitzenmacher 2006].
+            let mut h = bloom_hash(&keys[i]);
+            let delta = (h >> 17) | (h << 15); // Rotate right 17 bits
+            for j in 0..self.k {
+                let bitpos = h % bits;
+                array[bitpos / 8] |= (1 << (bitpos % 8));
+                h += delta;
             }
         }
     }
@@ -80,33 +73,31 @@ impl FilterPolicy for BloomFilterPolicy {
         if len < 2 {
             return false;
         }
-        let array = bloom_filter.data();
+        let array = bloom_filter.as_bytes();
         let bits = (len - 1) * 8;
 
-        unsafe {
-            // Use the encoded k so that we can read filters generated by bloom filters created
-            // using different params.
-            let k = *array.add(len - 1);
-            if k > 30 {
-                return true;
-            }
+        // Use the encoded k so that we can read filters generated by bloom filters created using
+        // different params.
+        let k = array[len - 1];
+        if k > 30 {
+            return true;
+        }
 
-            let mut h = bloom_hash(key);
-            let delta = (h >> 17) | (h << 15); // Rotate right 17 bits
-            for j in 0..k {
-                let bitpos = h % bits;
-                if *array.add(bitpos / 8) & (1 << (bitpos % 8)) == 0 {
-                    return false;
-                }
-                h += delta;
+        let mut h = bloom_hash(key);
+        let delta = (h >> 17) | (h << 15); // Rotate right 17 bits
+        for j in 0..k {
+            let bitpos = h % bits;
+            if array[bitpos / 8] & (1 << (bitpos % 8)) == 0 {
+                return false;
             }
+            h += delta;
         }
         true
     }
 }
 
 fn bloom_hash(key: &Slice) -> u32 {
-    db_hash(key.data(), key.size(), 0xbc9f1d34)
+    db_hash(key, 0xbc9f1d34)
 }
 
 #[cfg(test)]
@@ -116,13 +107,12 @@ mod tests {
     use super::*;
     use crate::util::{coding::*, logging::*, testutil::*};
     use std::mem::size_of;
-    use std::os::raw::c_char;
 
     const VERBOSE = 1;
 
     // NOTE: C++ takes 'i' as int
-    fn key(i: u32, buffer: *mut c_char) {
+    fn key(i: u32, buffer: &mut [u8; size_of::<u32>()]) -> Slice<'_> {
         encode_fixed32(buffer, i);
-        Slice::from_raw(buffer as *const c_char, size_of::<u32>())
+        Slice::from(&buffer[..])
     }
 }
@@ -1,11 +1,17 @@
 // leveldb/include/leveldb/cache.h
 // leveldb/util/cache.cc
 
-use std::ffi::c_void;
+use std::alloc::{self, Layout};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::ptr::null_mut;
+use std::sync::{Arc, Mutex};
 // TODO: port modules?
 // TODO: Thread annotations
 use crate::slice::Slice;
-use crate::util::hash;
+use crate::util::clock_cache::ClockCache;
+use crate::util::hash::hash;
+use crate::util::two_queue_cache::TwoQueueCache;
 // NOTE Only imported since C++ imports it; see Note #2 in README.md
 #[allow(unused_imports)]
 use crate::util::mutexlock;
@@ -13,115 +19,142 @@ use crate::util::mutexlock;
 // Create a new cache with a fixed size capacity. This implementation of Cache uses a
 // least-recently-used eviction policy.
 // NOTE: Uses ShardedLRUCache
-// NOTE: Box<dyn _> or Box<ShardedLRUCache> or unboxed?
-pub fn new_lru_cache(capacity: usize) -> Box<dyn Cache> {
-    Box::new(ShardedLRUCache::with_capacity(capacity))
+pub fn new_lru_cache<V: Send + Sync + 'static>(capacity: usize) -> Arc<dyn Cache<V>> {
+    Arc::new(ShardedLRUCache::with_capacity(capacity))
 }
 
-// Opaque handle to an entry stored in the cache.
-pub trait Cache {
-    // NOTE: Virtual deconstructor in C++, just Drop here?
-    // Destroys all existing entries by calling the "deleter" function that was passed to the
-    // constructor
+// Create a new cache with a fixed size capacity that uses the CLOCK eviction policy, an
+// approximation of LRU where a hit only sets a reference bit instead of reordering a list.
+// NOTE: Not part of LevelDB
+pub fn new_clock_cache<V: Send + Sync + 'static>(capacity: usize) -> Arc<dyn Cache<V>> {
+    Arc::new(ShardedCache::<V, ClockCache<V>>::with_capacity(capacity))
+}
+
+// Create a new cache with a fixed size capacity that uses the scan-resistant 2Q eviction policy.
+// Entries read only once, such as the blocks of a large scan, are evicted before entries that
+// were read again, so scans do not flush the frequently read entries.
+// NOTE: Not part of LevelDB
+pub fn new_two_queue_cache<V: Send + Sync + 'static>(capacity: usize) -> Arc<dyn Cache<V>> {
+    Arc::new(ShardedCache::<V, TwoQueueCache<V>>::with_capacity(capacity))
+}
+
+// Handle to an entry stored in the cache. The entry is released when the handle is dropped.
+// NOTE: C++ returns an opaque Cache::Handle pointer that must be passed back to Release(); the
+// handle holds a reference to the value, so Value() is just a deref here
+pub struct Handle<V> {
+    value: Arc<V>,
+    release: Option<Box<dyn FnOnce() + Send + Sync>>,
+}
+
+impl<V> Handle<V> {
+    // Create a handle to "value". Cache implementations pass the function that releases the
+    // entry; it is called once, when the handle is dropped.
+    pub fn new(value: Arc<V>, release: impl FnOnce() + Send + Sync + 'static) -> Self {
+        Self {
+            value,
+            release: Some(Box::new(release)),
+        }
+    }
+
+    // Return the value encapsulated in the handle.
+    pub fn value(&self) -> &Arc<V> {
+        &self.value
+    }
+}
+
+impl<V> Deref for Handle<V> {
+    type Target = V;
 
+    fn deref(&self) -> &V {
+        &self.value
+    }
+}
+
+impl<V> Drop for Handle<V> {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            release();
+        }
+    }
+}
+
+// NOTE: Like in C++, implementations must be internally synchronized, so methods take &self
+// NOTE: C++ stores void pointers with a deleter; the values are reference counted here and are
+// dropped once the cache and every handle are done with them
+pub trait Cache<V>: Send + Sync {
     // Insert a mapping from key.value into the cache and assign it the specified charge against
     // the total capacity.
     //
-    // Returns a handle that corresponds to the mapping. The caller must call release(handle) when
-    // the returned mapping is no longer needed.
-    //
-    // When the isnerted entry is no longer needed, the key and value will be passed to "deleter".
-    // TODO/NOTE: Virtual function in C++
-    // TODO: Use Generic rather than box?
-    fn insert(
-        &mut self,
-        key: &Slice,
-        value: *const c_void,
-        charge: usize,
-        deleter: Box<dyn Fn(&Slice, *const c_void)>,
-    ) -> Box<dyn Handle>;
+    // Returns a handle that corresponds to the mapping. The mapping is pinned until the handle is
+    // dropped.
+    fn insert(&self, key: &Slice, value: Arc<V>, charge: usize) -> Handle<V>;
 
     // If the cache has no mappying for "key", returns None.
     //
-    // Else return a handle that corresponds to the mapping. The caller must call
-    // self.release(&mut self, handle) when the returned mapping is no longer needed.
+    // Else return a handle that corresponds to the mapping. The mapping is pinned until the
+    // handle is dropped.
     // NOTE: Returns nullptr in C++ rather than None
-    // TODO
-    fn lookup(&mut self, key: &Slice) -> Option<Box<dyn Handle>>;
-
-    // Release a mapping returned by a previous lookup(&mut self, ).
-    // REQUIRES: handle must not have been released yet.
-    // REQUIRES: handle must have been returned by a method on self.
-    // TODO: Use generic box?
-    fn release(&mut self, handle: Box<dyn Handle>);
-
-    // Return the value encapsulated in a handle returned by a successful lookup(&mut self, ).
-    // REQUIRES: handle must not have been released yet.
-    // REQUIRES: handle must have been returned by a method on self.
-    fn value(&mut self, handle: Box<dyn Handle>) -> *const c_void;
+    fn lookup(&self, key: &Slice) -> Option<Handle<V>>;
 
     // If the cache contains entry for key, erase it. Note that the underlying entry will be kept
     // around until all existing handles to it have been released.
-    fn erase(&mut self, slice: &Slice);
+    fn erase(&self, key: &Slice);
 
     // Return a new numeric id. May be used by multiple clients who are sharing the same cache to
     // partition to key space. Typically the client will allocate a new id at startup and prepend
     // the id to its cache keys.
-    fn new_id(&mut self) -> u64;
+    fn new_id(&self) -> u64;
 
     // Remove all cache entries that aren't actively in use. Memory-constrained applications may
     // wish to call this method to reduce memory usage.
     // Default implementation of prune() does nothing. Trait implementors are strongly encouraged
     // to override the default implementation. A future release of leveldb my change prune() to a
     // pure abstract method.
-    fn prune(&mut self) {}
+    fn prune(&self) {}
 
     // Return an estimate of the combined charges of all elements stored in the cache.
     fn total_charge(&self) -> usize;
 }
 
-// TODO: Comments
-struct LRUHandle {
-    value: *mut c_void,
-    deleter: fn(&Slice, *mut c_void),
-    next_hash: *mut LRUHandle,
-    next: *mut LRUHandle,
-    prev: *mut LRUHandle,
+// An entry is a variable length heap-allocated structure. Entries are kept in a circular doubly
+// linked list ordered by access time.
+struct LRUHandle<V> {
+    // NOTE: None only for the list heads
+    value: Option<Arc<V>>,
+    next_hash: *mut LRUHandle<V>,
+    next: *mut LRUHandle<V>,
+    prev: *mut LRUHandle<V>,
     charge: usize, // TODO(opt): Only allow u32?; NOTE: This is a source code todo
-    key_length: usize,
     // Whether entry is in cache.
     in_cache: bool,
     // References, including cache reference, if present.
     refs: u32,
     // Hash of key(); used for fast sharding and comparisons
     hash: u32,
-    // Beginning of key
-    // NOTE: Char
-    key_data: *mut u8,
+    // NOTE: C++ allocates the key inline at the end of the handle
+    key_data: Box<[u8]>,
 }
 
-impl LRUHandle {
-    pub fn key(&self) -> Slice {
+impl<V> LRUHandle<V> {
+    pub fn key(&self) -> Slice<'_> {
         // 'next' is only equal to this if the LRU handle is the list head of an empty list. List
         // heads never have meaningful keys.
-        assert_ne!(self.next, self as *mut Self);
-        Slice::from_ptr(self.key_data, self.key_length)
+        assert_ne!(self.next as *const Self, self as *const Self);
+        Slice::from(&*self.key_data)
     }
 
     // NOTE: Done so MaybeUninit doesn't have to be used
     fn _new() -> Self {
         Self {
-            value: null_mut(),
-            deleter: |_, _| {},
+            value: None,
             next_hash: null_mut(),
             next: null_mut(),
             prev: null_mut(),
             charge: 0,
-            key_length: 0,
             in_cache: false,
             refs: 0,
             hash: 0,
-            key_data: null_mut(),
+            key_data: Box::new([]),
         }
     }
 }
@@ -131,32 +164,34 @@ impl LRUHandle {
 // also faster than some of the built-in hash table implementations in some of the
 // compiler/runtime combos tested. E.g., readrandom speeds up by ~5% over the g++ 4.4.3's builtin
 // hashtable
-struct HandleTable {
+struct HandleTable<V> {
     // The table consists of an array of buckets where each bucket is a linked list of cache
     // entries that hash into the bucket
     length: u32,
     elems: u32,
     // NOTE: Could use NonNull
-    list: *mut *mut LRUHandle,
+    list: *mut *mut LRUHandle<V>,
 }
 
-impl HandleTable {
+impl<V> HandleTable<V> {
     fn new() -> Self {
-        Self {
+        let mut table = Self {
             length: 0,
             elems: 0,
             // NOTE: If NonNull, use NonNull::dangling
             list: null_mut(),
-        }
+        };
+        table.resize();
+        table
     }
 
-    pub fn lookup(&mut self, key: &Slice, hash: u32) -> *mut LRUHandle {
-        self.find_pointer(key, hash);
+    pub fn lookup(&self, key: &Slice, hash: u32) -> *mut LRUHandle<V> {
+        unsafe { *self.find_pointer(key, hash) }
     }
 
-    pub fn insert(&mut self, h: *mut LRUHandle) -> *mut LRUHandle {
+    pub fn insert(&mut self, h: *mut LRUHandle<V>) -> *mut LRUHandle<V> {
         unsafe {
-            let ptr = self.find_pointer((*h).key(), (*h).hash);
+            let ptr = self.find_pointer(&(*h).key(), (*h).hash);
             let old = *ptr;
             (*h).next_hash = if old.is_null() {
                 null_mut()
@@ -176,7 +211,7 @@ impl HandleTable {
         }
     }
 
-    pub fn remove(&mut self, key: &Slice, hash: u32) -> *mut LRUHandle {
+    pub fn remove(&mut self, key: &Slice, hash: u32) -> *mut LRUHandle<V> {
         unsafe {
             let ptr = self.find_pointer(key, hash);
             let result = *ptr;
@@ -190,11 +225,11 @@ impl HandleTable {
 
     // Return a pointer to slot that points to a cache entry that matches key/hash. If there is no
     // such cache entry, return a pointer to the trailing slot in the corresponding linked list.
-    fn find_pointer(&self, key: &Slice, hash: u32) -> *mut *mut LRUHandle {
+    fn find_pointer(&self, key: &Slice, hash: u32) -> *mut *mut LRUHandle<V> {
         unsafe {
-            let mut ptr = self.list.add(hash & (self.length - 1) as u32);
-            while !(*ptr).is_null() && ((**ptr).hash != hash || key != (**ptr).key()) {
-                ptr = (&mut (*ptr).next_hash) as _;
+            let mut ptr = self.list.add((hash & (self.length - 1)) as usize);
+            while !(*ptr).is_null() && ((**ptr).hash != hash || *key != (**ptr).key()) {
+                ptr = &mut (**ptr).next_hash as *mut _;
             }
             ptr
         }
@@ -206,86 +241,175 @@ impl HandleTable {
             while new_length < self.elems {
                 new_length *= 2;
             }
-            let new_list = alloc::alloc_zeroed(Layout::array::<*mut LRUHandle>(new_length).unwrap())
-                as *mut *mut LRUHandle;
+            let new_list = alloc::alloc_zeroed(
+                Layout::array::<*mut LRUHandle<V>>(new_length as usize).unwrap(),
+            ) as *mut *mut LRUHandle<V>;
             let mut count = 0u32;
             for i in 0..self.length {
                 let mut h = *self.list.add(i as usize);
                 while !h.is_null() {
                     let next = (*h).next_hash;
                     let hash = (*h).hash;
-                    let ptr = &(*new_list.add(hash as usize & (new_length - 1))) as *mut _;
+                    let ptr = new_list.add((hash & (new_length - 1)) as usize);
                     (*h).next_hash = *ptr;
+                    *ptr = h;
                     h = next;
                     count += 1;
                 }
             }
             assert_eq!(self.elems, count);
-            alloc::dealloc(
-                self.list.cast(),
-                Layout::array::<*mut LRUHandle>(self.length),
-            );
+            if !self.list.is_null() {
+                alloc::dealloc(
+                    self.list.cast(),
+                    Layout::array::<*mut LRUHandle<V>>(self.length as usize).unwrap(),
+                );
+            }
             self.list = new_list;
             self.length = new_length;
         }
     }
 }
 
-impl Drop for HandleTable {
+impl<V> Drop for HandleTable<V> {
     fn drop(&mut self) {
-        unsafe {
-            alloc::dealloc(
-                self.list.cast(),
-                Layout::array::<*mut LRUHandle>(self.length),
-            );
+        if !self.list.is_null() {
+            unsafe {
+                alloc::dealloc(
+                    self.list.cast(),
+                    Layout::array::<*mut LRUHandle<V>>(self.length as usize).unwrap(),
+                );
+            }
         }
     }
 }
 
-struct LRUCacheGuardedData {
+struct LRUCacheGuardedData<V> {
     usage: usize,
     // Dummy head of LRU list.
     // lru.prev is newest entry, lru.next is oldest entry.
     // Entries have refs==1 and in_cache==true.
-    lru: LRUHandle,
+    // NOTE: Boxed so the circular list heads keep a stable address when the cache is moved
+    lru: Box<LRUHandle<V>>,
 
     // Dummy head of in-use list.
     // Entries are in use by clientts, and have refs >= 2 and in_cache==true.
-    in_use: LRUHandle,
+    in_use: Box<LRUHandle<V>>,
 
-    table: HandleTable,
+    table: HandleTable<V>,
+}
+
+// SAFETY: The raw handle pointers are only followed while holding the shard's mutex
+unsafe impl<V: Send + Sync> Send for LRUCacheGuardedData<V> {}
+
+impl<V> LRUCacheGuardedData<V> {
+    fn lru_remove(e: *mut LRUHandle<V>) {
+        unsafe {
+            (*(*e).next).prev = (*e).prev;
+            (*(*e).prev).next = (*e).next;
+        }
+    }
+
+    fn lru_append(list: *mut LRUHandle<V>, e: *mut LRUHandle<V>) {
+        unsafe {
+            // Make "e" newest entry by inserting just before *list.
+            (*e).next = list;
+            (*e).prev = (*list).prev;
+            (*(*e).prev).next = e;
+            (*(*e).next).prev = e;
+        }
+    }
+
+    // NOTE: C++ calls it "Ref"
+    fn ref_(&mut self, e: *mut LRUHandle<V>) {
+        unsafe {
+            if (*e).refs == 1 && (*e).in_cache {
+                // If on self.lru list, move to self.in_use list.
+                Self::lru_remove(e);
+                Self::lru_append(&mut *self.in_use as *mut _, e);
+            }
+            (*e).refs += 1;
+        }
+    }
+
+    fn unref(&mut self, e: *mut LRUHandle<V>) {
+        unsafe {
+            assert!((*e).refs > 0);
+            (*e).refs -= 1;
+            if (*e).refs == 0 {
+                // Deallocate.
+                assert!(!(*e).in_cache);
+                // NOTE: C++ calls the entry's deleter here; dropping the entry drops the cache's
+                // reference to the value
+                drop(Box::from_raw(e));
+            } else if (*e).in_cache && (*e).refs == 1 {
+                // No longer in use; move to self.lru list.
+                Self::lru_remove(e);
+                Self::lru_append(&mut *self.lru as *mut _, e);
+            }
+        }
+    }
+
+    // If e != null, finish removing *e from the cache; it has already been removed from the hash
+    // table. Return whether e != null.
+    // NOTE: Lives with the guarded data since C++ requires the mutex to be held
+    fn finish_erase(&mut self, e: *mut LRUHandle<V>) -> bool {
+        if !e.is_null() {
+            unsafe {
+                assert!((*e).in_cache);
+                Self::lru_remove(e);
+                (*e).in_cache = false;
+                self.usage -= (*e).charge;
+            }
+            self.unref(e);
+        }
+        !e.is_null()
+    }
 }
 
 // A single shard of sharded cache
-struct LRUCache {
+pub(crate) struct LRUCache<V> {
     // Initialized before use.
     capacity: usize,
 
     // mutex protects the following state.
     // NOTE: C++ mutex doesn't own data like Rust and synchronous usage requires a data be owned
     // by mutex (or other) in Rust.
-    mutex: port::Mutex<LRUCacheGuardedData>,
+    mutex: Mutex<LRUCacheGuardedData<V>>,
 }
 
-impl LRUCache {
+// A pointer to an entry, used by a handle to release it.
+struct EntryRef<V>(*mut LRUHandle<V>);
+
+// SAFETY: The entry is only followed while holding the shard's mutex, and the handle's reference
+// keeps it allocated
+unsafe impl<V> Send for EntryRef<V> {}
+unsafe impl<V> Sync for EntryRef<V> {}
+
+impl<V> EntryRef<V> {
+    // NOTE: A method so closures capture the whole EntryRef rather than the raw pointer
+    fn get(&self) -> *mut LRUHandle<V> {
+        self.0
+    }
+}
+
+impl<V: Send + Sync + 'static> LRUCache<V> {
     fn new() -> Self {
         // Make empty circular linked lists.
-        let mut lru = LRUHandle::_new();
-        lru.next = &mut lru as *mut _;
-        lru.prev = &mut lru as *mut _;
-        let mut in_use = LRUHandle::_new();
-        in_use.next = &mut in_use as *mut _;
-        in_use.prev = &mut in_use as *mut _;
-        let mut cache = Self {
+        let mut lru = Box::new(LRUHandle::_new());
+        lru.next = &mut *lru as *mut _;
+        lru.prev = &mut *lru as *mut _;
+        let mut in_use = Box::new(LRUHandle::_new());
+        in_use.next = &mut *in_use as *mut _;
+        in_use.prev = &mut *in_use as *mut _;
+        Self {
             capacity: 0,
-            mutex: port::Mutex::new(LRUCacheGuardedData {
+            mutex: Mutex::new(LRUCacheGuardedData {
                 usage: 0,
-                lru: {
-                },
+                lru,
                 in_use,
-                table: LRUHandle::_new(),
+                table: HandleTable::new(),
             }),
-        };
+        }
     }
 
     // Searate from constructor so caller can easily make an array of LRUCache.
@@ -293,86 +417,114 @@ impl LRUCache {
         self.capacity = capacity;
     }
 
-    // Like Cache methods, but with an extra "hash" parameter.
-    pub fn insert(
-        &mut self,
-        key: &Slice,
-        hash: u32,
-        value: *mut c_void,
-        charge: usize,
-        deleter: fn(&Slice, *mut c_void),
-    ) -> *mut Handle; // TODO: Cache::Handle
-
-    pub fn lookup(&mut self, key: &Slice, hash: u32) -> *mut Handle; // TODO: Cache::Handle
+    fn release(&self, handle: *mut LRUHandle<V>) {
+        let mut data = self.mutex.lock().unwrap();
+        data.unref(handle);
+    }
 
-    pub fn release(&mut self, handle: *mut Handle); // TODO: Cache::Handle
+    // NOTE: C++ hands out the entry itself as the handle
+    fn new_handle(self: &Arc<Self>, value: Arc<V>, e: *mut LRUHandle<V>) -> Handle<V> {
+        let shard = Arc::clone(self);
+        let e = EntryRef(e);
+        Handle::new(value, move || shard.release(e.get()))
+    }
+}
 
-    pub fn erase(&mut self, key: &Slice, hash: u32);
+impl<V: Send + Sync + 'static> CacheShard<V> for LRUCache<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        let mut shard = Self::new();
+        shard.set_capacity(capacity);
+        shard
+    }
 
-    pub fn prune(&mut self);
+    fn insert(self: &Arc<Self>, key: &Slice, hash: u32, value: Arc<V>, charge: usize) -> Handle<V> {
+        let mut data = self.mutex.lock().unwrap();
 
-    pub fn total_charge(&self) -> usize {
-        *self.mutex.lock().unwrap().usage
-    }
+        let e = Box::into_raw(Box::new(LRUHandle {
+            value: Some(Arc::clone(&value)),
+            next_hash: null_mut(),
+            next: null_mut(),
+            prev: null_mut(),
+            charge,
+            in_cache: false,
+            refs: 1, // for the returned handle.
+            hash,
+            key_data: key.as_bytes().into(),
+        }));
 
-    fn lru_remove(&mut self, e: *mut LRUHandle) {
         unsafe {
-            (*(*e).next).prev = (*e).prev;
-            (*(*e).prev).next = (*e).next;
+            if self.capacity > 0 {
+                (*e).refs += 1; // for the cache's reference.
+                (*e).in_cache = true;
+                LRUCacheGuardedData::lru_append(&mut *data.in_use as *mut _, e);
+                data.usage += charge;
+                let old = data.table.insert(e);
+                data.finish_erase(old);
+            } else {
+                // don't cache. (capacity==0 is supported and turns off caching.)
+                // next is read by key() in an assert, so it must be initialized
+                (*e).next = null_mut();
+            }
+            while data.usage > self.capacity && !std::ptr::eq(data.lru.next, &*data.lru) {
+                let old = data.lru.next;
+                assert_eq!((*old).refs, 1);
+                let removed = data.table.remove(&(*old).key(), (*old).hash);
+                let erased = data.finish_erase(removed);
+                assert!(erased);
+            }
         }
+
+        self.new_handle(value, e)
     }
 
-    fn lru_append(&mut self, list: *mut LRUHandle, e: *mut LRUHandle) {
-        unsafe {
-            // Make "e" newest entry by inserting just before *list.
-            (*e).next = list;
-            (*e).prev = (*list).prev;
-            (*(*e).prev).next = e;
-            (*(*e).next).prev = e;
+    fn lookup(self: &Arc<Self>, key: &Slice, hash: u32) -> Option<Handle<V>> {
+        let mut data = self.mutex.lock().unwrap();
+        let e = data.table.lookup(key, hash);
+        if e.is_null() {
+            return None;
         }
+        data.ref_(e);
+        let value = unsafe { Arc::clone((*e).value.as_ref().unwrap()) };
+        Some(self.new_handle(value, e))
     }
 
-    fn ref(&mut self, e: *mut LRUHandle) {
-        unsafe {
-            if (*e).refs == 1 && (*e).in_cache { // If on self.lru list, move to self.in_use list.
-                self.lru_remove(e);
-                self.lru_append(&mut self.in_use as *mut _, e);
-            }
-            (*e).refs += 1;
-        }
+    fn erase(&self, key: &Slice, hash: u32) {
+        let mut data = self.mutex.lock().unwrap();
+        let e = data.table.remove(key, hash);
+        data.finish_erase(e);
     }
 
-    fn unref(&mut self, e: *mut LRUHandle) {
-        unsafe {
-            assert!((*e).refs > 0);
-            (*e).refs -= 1;
-            if (*e).refs == 0 { // Deallocate.
-                assert!(!(*e).in_cache);
-                (*e).deleter((*e).key(), (*e).value);
-                alloc::dealloc(e as *mut _, Layout::new::<LRUHandle>());
-            } else if (*e).in_cache && (*e).refs == 1 {
-                // No longer in use; move to self.lru list.
-                self.lru_remove(e);
-                self.lru_append(&mut self.lru as *mut _, e);
+    fn prune(&self) {
+        let mut data = self.mutex.lock().unwrap();
+        while !std::ptr::eq(data.lru.next, &*data.lru) {
+            let e = data.lru.next;
+            unsafe {
+                assert_eq!((*e).refs, 1);
+                let removed = data.table.remove(&(*e).key(), (*e).hash);
+                let erased = data.finish_erase(removed);
+                assert!(erased);
             }
         }
     }
 
-    fn finish_erase(&mut self, e: *mut LRUHandle);
+    fn total_charge(&self) -> usize {
+        self.mutex.lock().unwrap().usage
+    }
 }
 
-impl Drop for LRUCache {
+impl<V> Drop for LRUCache<V> {
     fn drop(&mut self) {
+        let data = self.mutex.get_mut().unwrap();
         unsafe {
             // Error if caller has an unlreaded handle
-            assert!(self.in_use.next == &self.in_use as *const _);
-            let mut e = self.lru.next;
-            while e != &self.lru as *const _ {
+            assert!(std::ptr::eq(data.in_use.next, &*data.in_use));
+            let mut e = data.lru.next;
+            while !std::ptr::eq(e, &*data.lru) {
                 let next = (*e).next;
                 assert!((*e).in_cache);
                 (*e).in_cache = false;
                 assert_eq!((*e).refs, 1); // Invariant of self.lru list.
-                self.unref(e);
+                data.unref(e);
                 e = next;
             }
         }
@@ -382,19 +534,49 @@ impl Drop for LRUCache {
 const NUM_SHARD_BITS: usize = 4;
 const NUM_SHARDS: usize = 1 << NUM_SHARD_BITS;
 
+// A single partition of a ShardedCache. Like Cache methods, but with an extra "hash" parameter.
+// NOTE: C++ only shards LRUCache; the trait lets the other eviction policies share the sharding.
+// The shard is shared with the handles it returns so they can release themselves.
+pub(crate) trait CacheShard<V>: Send + Sync + Sized {
+    fn with_capacity(capacity: usize) -> Self;
+
+    fn insert(self: &Arc<Self>, key: &Slice, hash: u32, value: Arc<V>, charge: usize) -> Handle<V>;
+
+    fn lookup(self: &Arc<Self>, key: &Slice, hash: u32) -> Option<Handle<V>>;
+
+    fn erase(&self, key: &Slice, hash: u32);
+
+    fn prune(&self);
+
+    fn total_charge(&self) -> usize;
+}
+
 // TODO: Access?
-pub(crate) struct ShardedLRUCache {
-    shard: [LRUCache; NUM_SHARDS],
-    id_mutex: port::Mutex,
-    last_id: u64,
+// NOTE: Each shard is reference counted since outstanding handles release into it
+pub(crate) struct ShardedCache<V, S> {
+    shard: [Arc<S>; NUM_SHARDS],
+    // NOTE: C++ guards last_id with id_mutex
+    last_id: Mutex<u64>,
+    _value: PhantomData<fn() -> V>,
 }
 
-impl ShardedLRUCache {
+pub(crate) type ShardedLRUCache<V> = ShardedCache<V, LRUCache<V>>;
+
+impl<V, S: CacheShard<V>> ShardedCache<V, S> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let per_shard = capacity.div_ceil(NUM_SHARDS);
+        Self {
+            shard: std::array::from_fn(|_| Arc::new(S::with_capacity(per_shard))),
+            last_id: Mutex::new(0),
+            _value: PhantomData,
+        }
+    }
+
     // NOTE: static func in C++
     // TODO: Watch Hash method
     #[inline]
     fn hash_slice(s: &Slice) -> u32 {
-        hash(s.data(), s.size(), 0)
+        hash(s, 0)
     }
 
     fn shard(hash: u32) -> u32 {
@@ -402,15 +584,31 @@ impl ShardedLRUCache {
     }
 }
 
-impl Cache for ShardedLRUCache {
-    fn new_id(&mut self) -> u64 {
-        let mut last_id = id_mutex.lock().unwrap();
-        // TODO
+impl<V, S: CacheShard<V>> Cache<V> for ShardedCache<V, S> {
+    fn insert(&self, key: &Slice, value: Arc<V>, charge: usize) -> Handle<V> {
+        let hash = Self::hash_slice(key);
+        self.shard[Self::shard(hash) as usize].insert(key, hash, value, charge)
     }
 
-    fn prune(&mut self) {
+    fn lookup(&self, key: &Slice) -> Option<Handle<V>> {
+        let hash = Self::hash_slice(key);
+        self.shard[Self::shard(hash) as usize].lookup(key, hash)
+    }
+
+    fn erase(&self, key: &Slice) {
+        let hash = Self::hash_slice(key);
+        self.shard[Self::shard(hash) as usize].erase(key, hash);
+    }
+
+    fn new_id(&self) -> u64 {
+        let mut last_id = self.last_id.lock().unwrap();
+        *last_id += 1;
+        *last_id
+    }
+
+    fn prune(&self) {
         for s in 0..NUM_SHARDS {
-            shars[s].prune();
+            self.shard[s].prune();
         }
     }
 
@@ -422,3 +620,273 @@ impl Cache for ShardedLRUCache {
         total
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Conversions between numeric keys and the slices expected by Cache.
+    fn encode_key(k: i32) -> [u8; 4] {
+        (k as u32).to_le_bytes()
+    }
+
+    // A cached value that records its key and value when it is dropped.
+    // NOTE: Stands in for the deleter callback used by the C++ tests
+    struct Value {
+        key: i32,
+        number: i32,
+        deleted: Arc<Mutex<Vec<(i32, i32)>>>,
+    }
+
+    impl Drop for Value {
+        fn drop(&mut self) {
+            self.deleted.lock().unwrap().push((self.key, self.number));
+        }
+    }
+
+    const CACHE_SIZE: usize = 1000;
+
+    struct CacheTest {
+        deleted: Arc<Mutex<Vec<(i32, i32)>>>,
+        cache: Arc<dyn Cache<Value>>,
+    }
+
+    impl CacheTest {
+        fn new() -> Self {
+            Self {
+                deleted: Arc::new(Mutex::new(Vec::new())),
+                cache: new_lru_cache(CACHE_SIZE),
+            }
+        }
+
+        fn deleted_keys(&self) -> Vec<i32> {
+            self.deleted.lock().unwrap().iter().map(|d| d.0).collect()
+        }
+
+        fn deleted_values(&self) -> Vec<i32> {
+            self.deleted.lock().unwrap().iter().map(|d| d.1).collect()
+        }
+
+        fn lookup_handle(&self, key: i32) -> Option<Handle<Value>> {
+            let key = encode_key(key);
+            self.cache.lookup(&Slice::from(&key[..]))
+        }
+
+        fn lookup(&self, key: i32) -> i32 {
+            self.lookup_handle(key).map_or(-1, |h| h.number)
+        }
+
+        fn insert(&self, key: i32, value: i32) {
+            self.insert_with_charge(key, value, 1);
+        }
+
+        fn insert_with_charge(&self, key: i32, value: i32, charge: usize) {
+            drop(self.insert_and_return_handle(key, value, charge));
+        }
+
+        fn insert_and_return_handle(&self, key: i32, value: i32, charge: usize) -> Handle<Value> {
+            let value = Value {
+                key,
+                number: value,
+                deleted: Arc::clone(&self.deleted),
+            };
+            let key = encode_key(key);
+            self.cache
+                .insert(&Slice::from(&key[..]), Arc::new(value), charge)
+        }
+
+        fn erase(&self, key: i32) {
+            let key = encode_key(key);
+            self.cache.erase(&Slice::from(&key[..]));
+        }
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let t = CacheTest::new();
+        assert_eq!(-1, t.lookup(100));
+
+        t.insert(100, 101);
+        assert_eq!(101, t.lookup(100));
+        assert_eq!(-1, t.lookup(200));
+        assert_eq!(-1, t.lookup(300));
+
+        t.insert(200, 201);
+        assert_eq!(101, t.lookup(100));
+        assert_eq!(201, t.lookup(200));
+        assert_eq!(-1, t.lookup(300));
+
+        t.insert(100, 102);
+        assert_eq!(102, t.lookup(100));
+        assert_eq!(201, t.lookup(200));
+        assert_eq!(-1, t.lookup(300));
+
+        assert_eq!(vec![100], t.deleted_keys());
+        assert_eq!(vec![101], t.deleted_values());
+    }
+
+    #[test]
+    fn test_erase() {
+        let t = CacheTest::new();
+        t.erase(200);
+        assert!(t.deleted_keys().is_empty());
+
+        t.insert(100, 101);
+        t.insert(200, 201);
+        t.erase(100);
+        assert_eq!(-1, t.lookup(100));
+        assert_eq!(201, t.lookup(200));
+        assert_eq!(vec![100], t.deleted_keys());
+        assert_eq!(vec![101], t.deleted_values());
+
+        t.erase(100);
+        assert_eq!(-1, t.lookup(100));
+        assert_eq!(201, t.lookup(200));
+        assert_eq!(1, t.deleted_keys().len());
+    }
+
+    #[test]
+    fn test_entries_are_pinned() {
+        let t = CacheTest::new();
+        t.insert(100, 101);
+        let h1 = t.lookup_handle(100).unwrap();
+        assert_eq!(101, h1.number);
+
+        t.insert(100, 102);
+        let h2 = t.lookup_handle(100).unwrap();
+        assert_eq!(102, h2.number);
+        assert!(t.deleted_keys().is_empty());
+
+        drop(h1);
+        assert_eq!(vec![100], t.deleted_keys());
+        assert_eq!(vec![101], t.deleted_values());
+
+        t.erase(100);
+        assert_eq!(-1, t.lookup(100));
+        assert_eq!(1, t.deleted_keys().len());
+
+        drop(h2);
+        assert_eq!(vec![100, 100], t.deleted_keys());
+        assert_eq!(vec![101, 102], t.deleted_values());
+    }
+
+    #[test]
+    fn test_eviction_policy() {
+        let t = CacheTest::new();
+        t.insert(100, 101);
+        t.insert(200, 201);
+        t.insert(300, 301);
+        let h = t.lookup_handle(300).unwrap();
+
+        // Frequently used entry must be kept around, as must things that are still in use.
+        for i in 0..(CACHE_SIZE as i32 + 100) {
+            t.insert(1000 + i, 2000 + i);
+            assert_eq!(2000 + i, t.lookup(1000 + i));
+            assert_eq!(101, t.lookup(100));
+        }
+        assert_eq!(101, t.lookup(100));
+        assert_eq!(-1, t.lookup(200));
+        assert_eq!(301, t.lookup(300));
+        drop(h);
+    }
+
+    #[test]
+    fn test_use_exceeds_cache_size() {
+        let t = CacheTest::new();
+        // Overfill the cache, keeping handles on all inserted entries.
+        let h: Vec<_> = (0..(CACHE_SIZE as i32 + 100))
+            .map(|i| t.insert_and_return_handle(1000 + i, 2000 + i, 1))
+            .collect();
+
+        // Check that all the entries can be found in the cache.
+        for i in 0..h.len() as i32 {
+            assert_eq!(2000 + i, t.lookup(1000 + i));
+        }
+    }
+
+    #[test]
+    fn test_heavy_entries() {
+        let t = CacheTest::new();
+        // Add a bunch of light and heavy entries and then count the combined size of items still
+        // in the cache, which must be approximately the same as the total capacity.
+        const LIGHT: usize = 1;
+        const HEAVY: usize = 10;
+        let mut added = 0;
+        let mut index = 0;
+        while added < 2 * CACHE_SIZE {
+            let weight = if index & 1 == 0 { LIGHT } else { HEAVY };
+            t.insert_with_charge(index, 1000 + index, weight);
+            added += weight;
+            index += 1;
+        }
+
+        let mut cached_weight = 0;
+        for i in 0..index {
+            let weight = if i & 1 == 0 { LIGHT } else { HEAVY };
+            let r = t.lookup(i);
+            if r >= 0 {
+                cached_weight += weight;
+                assert_eq!(1000 + i, r);
+            }
+        }
+        assert!(cached_weight <= CACHE_SIZE + CACHE_SIZE / 10);
+    }
+
+    #[test]
+    fn test_new_id() {
+        let t = CacheTest::new();
+        let a = t.cache.new_id();
+        let b = t.cache.new_id();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_prune() {
+        let t = CacheTest::new();
+        t.insert(1, 100);
+        t.insert(2, 200);
+
+        let handle = t.lookup_handle(1).unwrap();
+        t.cache.prune();
+        drop(handle);
+
+        assert_eq!(100, t.lookup(1));
+        assert_eq!(-1, t.lookup(2));
+    }
+
+    #[test]
+    fn test_zero_size_cache() {
+        let mut t = CacheTest::new();
+        t.cache = new_lru_cache(0);
+
+        t.insert(1, 100);
+        assert_eq!(-1, t.lookup(1));
+        assert_eq!(vec![1], t.deleted_keys());
+    }
+
+    #[test]
+    fn test_drop_deletes_entries() {
+        let t = CacheTest::new();
+        t.insert(1, 100);
+        t.insert(2, 200);
+        assert_eq!(2, t.cache.total_charge());
+
+        let CacheTest { deleted, cache } = t;
+        drop(cache);
+        let mut deleted = deleted.lock().unwrap().clone();
+        deleted.sort();
+        assert_eq!(vec![(1, 100), (2, 200)], deleted);
+    }
+
+    #[test]
+    fn test_handle_outlives_cache() {
+        let t = CacheTest::new();
+        let h = t.insert_and_return_handle(1, 100, 1);
+        let CacheTest { deleted, cache } = t;
+        drop(cache);
+        assert!(deleted.lock().unwrap().is_empty());
+        assert_eq!(100, h.number);
+        drop(h);
+        assert_eq!(vec![(1, 100)], *deleted.lock().unwrap());
+    }
+}
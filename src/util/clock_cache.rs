@@ -0,0 +1,269 @@
+// NOTE: Not part of LevelDB; an alternative eviction policy for Cache.
+//
+// CLOCK approximates LRU without relinking entries on every hit: a hit only sets the entry's
+// reference bit, and the clock hand gives referenced entries a second chance before evicting
+// them. Entries are kept in a ring of slots that the hand sweeps.
+
+use crate::slice::Slice;
+use crate::util::cache::{CacheShard, Handle};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+struct ClockEntry<V> {
+    key: Box<[u8]>,
+    value: Arc<V>,
+    charge: usize,
+    // Distinguishes the entry from later occupants of its slot, so handles to an erased entry do
+    // not release the entry that replaced it.
+    id: u64,
+    // Number of outstanding handles. Pinned entries are never evicted.
+    refs: u32,
+    // Set on every hit and cleared as the hand passes.
+    referenced: bool,
+}
+
+struct ClockState<V> {
+    slots: Vec<Option<ClockEntry<V>>>,
+    // Indexes of the empty slots in slots.
+    free: Vec<usize>,
+    index: HashMap<Box<[u8]>, usize>,
+    hand: usize,
+    usage: usize,
+    next_id: u64,
+}
+
+impl<V> ClockState<V> {
+    fn remove(&mut self, slot: usize) {
+        let e = self.slots[slot].take().unwrap();
+        self.index.remove(&e.key);
+        self.free.push(slot);
+        self.usage -= e.charge;
+    }
+
+    // Sweep the hand until usage fits in capacity. Every unpinned entry is evicted by its second
+    // visit, so two turns suffice; whatever is left after that is pinned.
+    fn evict(&mut self, capacity: usize) {
+        let mut steps = 2 * self.slots.len();
+        while self.usage > capacity && steps > 0 {
+            steps -= 1;
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            match &mut self.slots[slot] {
+                Some(e) if e.refs == 0 && !e.referenced => self.remove(slot),
+                Some(e) if e.refs == 0 => e.referenced = false,
+                _ => {}
+            }
+        }
+    }
+}
+
+// A single shard of a sharded CLOCK cache.
+pub(crate) struct ClockCache<V> {
+    capacity: usize,
+    state: Mutex<ClockState<V>>,
+}
+
+impl<V: Send + Sync + 'static> ClockCache<V> {
+    fn release(&self, slot: usize, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(e) = &mut state.slots[slot] {
+            if e.id == id {
+                assert!(e.refs > 0);
+                e.refs -= 1;
+            }
+        }
+    }
+
+    fn new_handle(self: &Arc<Self>, value: Arc<V>, slot: usize, id: u64) -> Handle<V> {
+        let shard = Arc::clone(self);
+        Handle::new(value, move || shard.release(slot, id))
+    }
+}
+
+impl<V: Send + Sync + 'static> CacheShard<V> for ClockCache<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(ClockState {
+                slots: Vec::new(),
+                free: Vec::new(),
+                index: HashMap::new(),
+                hand: 0,
+                usage: 0,
+                next_id: 0,
+            }),
+        }
+    }
+
+    fn insert(
+        self: &Arc<Self>,
+        key: &Slice,
+        _hash: u32,
+        value: Arc<V>,
+        charge: usize,
+    ) -> Handle<V> {
+        if self.capacity == 0 {
+            // A capacity of zero turns off caching, as it does for LRUCache.
+            return Handle::new(value, || {});
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(&old) = state.index.get(key.as_bytes()) {
+            state.remove(old);
+        }
+
+        state.next_id += 1;
+        let id = state.next_id;
+        let entry = ClockEntry {
+            key: key.as_bytes().into(),
+            value: Arc::clone(&value),
+            charge,
+            id,
+            refs: 1, // for the returned handle.
+            referenced: false,
+        };
+        let slot = match state.free.pop() {
+            Some(slot) => {
+                state.slots[slot] = Some(entry);
+                slot
+            }
+            None => {
+                state.slots.push(Some(entry));
+                state.slots.len() - 1
+            }
+        };
+        state.index.insert(key.as_bytes().into(), slot);
+        state.usage += charge;
+        state.evict(self.capacity);
+
+        self.new_handle(value, slot, id)
+    }
+
+    fn lookup(self: &Arc<Self>, key: &Slice, _hash: u32) -> Option<Handle<V>> {
+        let mut state = self.state.lock().unwrap();
+        let slot = *state.index.get(key.as_bytes())?;
+        let e = state.slots[slot].as_mut().unwrap();
+        e.refs += 1;
+        e.referenced = true;
+        let (value, id) = (Arc::clone(&e.value), e.id);
+        Some(self.new_handle(value, slot, id))
+    }
+
+    fn erase(&self, key: &Slice, _hash: u32) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(&slot) = state.index.get(key.as_bytes()) {
+            state.remove(slot);
+        }
+    }
+
+    fn prune(&self) {
+        let mut state = self.state.lock().unwrap();
+        for slot in 0..state.slots.len() {
+            if matches!(&state.slots[slot], Some(e) if e.refs == 0) {
+                state.remove(slot);
+            }
+        }
+    }
+
+    fn total_charge(&self) -> usize {
+        self.state.lock().unwrap().usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_shard(capacity: usize) -> Arc<ClockCache<i32>> {
+        Arc::new(ClockCache::with_capacity(capacity))
+    }
+
+    fn insert(cache: &Arc<ClockCache<i32>>, key: &str, value: i32) {
+        drop(cache.insert(&key.into(), 0, Arc::new(value), 1));
+    }
+
+    fn lookup(cache: &Arc<ClockCache<i32>>, key: &str) -> i32 {
+        cache.lookup(&key.into(), 0).map_or(-1, |h| *h)
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = new_shard(10);
+        assert_eq!(-1, lookup(&cache, "a"));
+        insert(&cache, "a", 1);
+        insert(&cache, "b", 2);
+        assert_eq!(1, lookup(&cache, "a"));
+        assert_eq!(2, lookup(&cache, "b"));
+
+        insert(&cache, "a", 3);
+        assert_eq!(3, lookup(&cache, "a"));
+        assert_eq!(2, cache.total_charge());
+
+        cache.erase(&"a".into(), 0);
+        assert_eq!(-1, lookup(&cache, "a"));
+        assert_eq!(1, cache.total_charge());
+    }
+
+    #[test]
+    fn test_second_chance() {
+        let cache = new_shard(3);
+        insert(&cache, "a", 1);
+        insert(&cache, "b", 2);
+        insert(&cache, "c", 3);
+
+        // "a" was referenced, so the hand passes it over and evicts "b" instead
+        assert_eq!(1, lookup(&cache, "a"));
+        insert(&cache, "d", 4);
+        assert_eq!(1, lookup(&cache, "a"));
+        assert_eq!(-1, lookup(&cache, "b"));
+        assert_eq!(3, lookup(&cache, "c"));
+        assert_eq!(4, lookup(&cache, "d"));
+        assert_eq!(3, cache.total_charge());
+    }
+
+    #[test]
+    fn test_pinned_entries_are_not_evicted() {
+        let cache = new_shard(2);
+        let a = cache.insert(&"a".into(), 0, Arc::new(1), 1);
+        let b = cache.insert(&"b".into(), 0, Arc::new(2), 1);
+        insert(&cache, "c", 3);
+
+        // Every entry was pinned while "c" was inserted, so nothing could be evicted
+        assert_eq!(3, cache.total_charge());
+
+        drop(a);
+        insert(&cache, "d", 4);
+        assert_eq!(2, cache.total_charge());
+        assert_eq!(-1, lookup(&cache, "a"));
+        assert_eq!(2, lookup(&cache, "b"));
+        assert_eq!(-1, lookup(&cache, "c"));
+        assert_eq!(4, lookup(&cache, "d"));
+        drop(b);
+    }
+
+    #[test]
+    fn test_handle_to_replaced_entry() {
+        let cache = new_shard(10);
+        let old = cache.insert(&"a".into(), 0, Arc::new(1), 1);
+        insert(&cache, "a", 2);
+        let new = cache.lookup(&"a".into(), 0).unwrap();
+
+        // Releasing the handle to the replaced entry must not unpin its replacement
+        drop(old);
+        cache.prune();
+        assert_eq!(2, *new);
+        assert_eq!(2, lookup(&cache, "a"));
+        drop(new);
+        cache.prune();
+        assert_eq!(-1, lookup(&cache, "a"));
+        assert_eq!(0, cache.total_charge());
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let cache = new_shard(0);
+        let h = cache.insert(&"a".into(), 0, Arc::new(1), 1);
+        assert_eq!(1, *h);
+        assert_eq!(-1, lookup(&cache, "a"));
+    }
+}
@@ -13,7 +13,7 @@ use crate::util::no_destructor;
 // a database. A Comparator implementation must be thread-safe since levelerdb may invode its
 // methods concurrently from multiple threads.
 // TODO: Remove 'self' param from methods?
-pub trait Comparator {
+pub trait Comparator: Send + Sync {
     // Three-way comparison. Returns value:
     //   < 0 iff 'a' < 'b', ("iff" means if and only if)
     //   == 0 iff 'a' == 'b',
@@ -37,12 +37,32 @@ pub trait Comparator {
     // If 'start' < 'limit', changes 'start' to a short string in ['start','limit'). Simple
     // comparator implementations may return with 'start' unchanged, i.e., an implementation of
     // this method that does nothing is correct.
-    fn find_shortest_separator(&self, start: &mut String, limit: &Slice);
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &Slice);
 
     // Changes 'key' to a short string >= 'key'.
     // Simple comparator implementations may return with 'key' unchanged, i.e., an implementation
     // of this method that does nothing is correct.
-    fn find_short_successor(&self, start: &mut String);
+    fn find_short_successor(&self, start: &mut Vec<u8>);
+}
+
+// NOTE: Allows a borrowed comparator (e.g., the result of bytewise_comparator()) to be used where
+// an owned one is expected
+impl<C: Comparator + ?Sized> Comparator for &C {
+    fn compare(&self, a: &Slice, b: &Slice) -> i32 {
+        (**self).compare(a, b)
+    }
+
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &Slice) {
+        (**self).find_shortest_separator(start, limit)
+    }
+
+    fn find_short_successor(&self, key: &mut Vec<u8>) {
+        (**self).find_short_successor(key)
+    }
 }
 
 // Return a builtin comparator that uses lexicographic byte-wise ordering. The result repains the
@@ -64,36 +84,34 @@ impl Comparator for BytewiseComparatorImpl {
         "levelerdb.BytewiseComparator"
     }
 
-    fn find_shortest_separator(&self, start: &mut String, limit: &Slice) {
+    fn find_shortest_separator(&self, start: &mut Vec<u8>, limit: &Slice) {
         // Find length of common prefix
         let min_length = start.len().min(limit.size());
         let mut diff_index = 0;
-        let start_bytes = unsafe { start.as_mut_vec() };
-        while diff_index < min_length && start_bytes[diff_index] == limit[diff_index] {
+        while diff_index < min_length && start[diff_index] == limit[diff_index] {
             diff_index += 1;
         }
 
         if diff_index >= min_length {
             // Do not shorted in one string is a prefix of the other
         } else {
-            let diff_byte = start_bytes[diff_index];
+            let diff_byte = start[diff_index];
             if diff_byte < 0xff && diff_byte + 1 < limit[diff_index] {
-                start_bytes[diff_index] += 1;
-                start_bytes.resize(diff_index + 1, 0);
-                assert!(self.compare(&start.as_str().into(), limit) < 0);
+                start[diff_index] += 1;
+                start.truncate(diff_index + 1);
+                assert!(self.compare(&Slice::from(&*start), limit) < 0);
             }
         }
     }
 
-    fn find_short_successor(&self, key: &mut String) {
+    fn find_short_successor(&self, key: &mut Vec<u8>) {
         // Find first character than can be incremented
         let n = key.len();
-        let key_bytes = unsafe { key.as_mut_vec() };
         for i in 0..n {
-            let byte = key_bytes[i];
+            let byte = key[i];
             if byte != 0xff {
-                key_bytes[i] = byte + 1;
-                key_bytes.resize(i + 1, 0);
+                key[i] = byte + 1;
+                key.truncate(i + 1);
                 return;
             }
         }
@@ -0,0 +1,924 @@
+// leveldb/util/env_posix.cc
+
+use crate::{
+    env::{Env, FileLock, Logger, RandomAccessFile, SequentialFile, WritableFile},
+    slice::Slice,
+    status::{Error, Result},
+    util::posix_logger::PosixLogger,
+};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// NOTE: C++ opens every file with O_CLOEXEC; the standard library already does so.
+
+const WRITABLE_FILE_BUFFER_SIZE: usize = 65536;
+
+// Return the maximum number of read-only files to keep open. Reads from files opened past the
+// limit reopen the file for each read instead of holding a descriptor.
+// NOTE: C++ caches the result in a global; it is computed once per PosixEnv here
+fn max_open_files() -> i32 {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: "rlim" is a valid rlimit for getrlimit() to fill in
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } != 0 {
+        // getrlimit failed, fallback to hard-coded default.
+        50
+    } else if rlim.rlim_cur == libc::RLIM_INFINITY {
+        i32::MAX
+    } else {
+        // Allow use of 20% of available file descriptors for read-only files.
+        (rlim.rlim_cur / 5).min(i32::MAX as libc::rlim_t) as i32
+    }
+}
+
+fn posix_error(context: &str, err: &io::Error) -> Error {
+    let msg = err.to_string();
+    if err.kind() == io::ErrorKind::NotFound {
+        Error::not_found(&context.into(), &msg.as_str().into())
+    } else {
+        Error::io_error(&context.into(), &msg.as_str().into())
+    }
+}
+
+// Helper class to limit resource usage to avoid exhaustion. Currently used to limit read-only
+// file descriptors so that we do not run out of file descriptors for very large databases.
+struct Limiter {
+    // The number of available resources.
+    //
+    // This is a counter and is not tied to the invariants of any other class, so it can be
+    // operated on safely using relaxed ordering.
+    acquires_allowed: AtomicI32,
+}
+
+impl Limiter {
+    // Limit maximum number of resources to |max_acquires|.
+    fn new(max_acquires: i32) -> Self {
+        assert!(max_acquires >= 0);
+        Self {
+            acquires_allowed: AtomicI32::new(max_acquires),
+        }
+    }
+
+    // If another resource is available, acquire it and return true. Else return false.
+    fn acquire(&self) -> bool {
+        let old_acquires_allowed = self.acquires_allowed.fetch_sub(1, Ordering::Relaxed);
+        if old_acquires_allowed > 0 {
+            return true;
+        }
+        self.acquires_allowed.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    // Release a resource acquired by a previous call to acquire() that returned true.
+    fn release(&self) {
+        self.acquires_allowed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Implements sequential read access in a file using read().
+//
+// Instances of this class are thread-friendly but not thread-safe, as required by the
+// SequentialFile API.
+struct PosixSequentialFile {
+    file: File,
+    filename: String,
+}
+
+impl SequentialFile for PosixSequentialFile {
+    fn read<'a>(&mut self, n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>> {
+        let n = n.min(scratch.len());
+        loop {
+            match self.file.read(&mut scratch[..n]) {
+                Ok(read_size) => return Ok(Slice::from(&scratch[..read_size])),
+                // Retry
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(posix_error(&self.filename, &e)),
+            }
+        }
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        match self.file.seek(SeekFrom::Current(n as i64)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(posix_error(&self.filename, &e)),
+        }
+    }
+}
+
+// Implements random read access in a file using pread().
+//
+// Instances of this class are thread-safe, as required by the RandomAccessFile API. Instances are
+// immutable and read() only calls thread-safe library functions.
+// NOTE: C++ memory-maps a limited number of read-only files instead; the standard library has no
+// mmap so every file is read with pread().
+struct PosixRandomAccessFile {
+    // Some if the file descriptor is kept open for the lifetime of this object; None if the file
+    // is opened on every read.
+    file: Option<File>,
+    fd_limiter: Arc<Limiter>,
+    filename: String,
+}
+
+impl PosixRandomAccessFile {
+    // The new instance takes ownership of |file|. |fd_limiter| must outlive this instance, and
+    // will be used to determine if the file is kept open.
+    fn new(filename: String, file: File, fd_limiter: Arc<Limiter>) -> Self {
+        let has_permanent_fd = fd_limiter.acquire();
+        Self {
+            // If the limit was exceeded, the file is closed here and reopened by read()
+            file: has_permanent_fd.then_some(file),
+            fd_limiter,
+            filename,
+        }
+    }
+}
+
+impl Drop for PosixRandomAccessFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            self.fd_limiter.release();
+        }
+    }
+}
+
+impl RandomAccessFile for PosixRandomAccessFile {
+    fn read<'a>(&self, offset: u64, n: usize, scratch: &'a mut [u8]) -> Result<Slice<'a>> {
+        let reopened;
+        let file = match &self.file {
+            Some(file) => file,
+            None => {
+                reopened =
+                    File::open(&self.filename).map_err(|e| posix_error(&self.filename, &e))?;
+                &reopened
+            }
+        };
+
+        // NOTE: C++ issues a single pread(); short reads are retried here until EOF
+        let n = n.min(scratch.len());
+        let mut read_size = 0;
+        while read_size < n {
+            match file.read_at(&mut scratch[read_size..n], offset + read_size as u64) {
+                Ok(0) => break,
+                Ok(size) => read_size += size,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(posix_error(&self.filename, &e)),
+            }
+        }
+        Ok(Slice::from(&scratch[..read_size]))
+    }
+}
+
+struct PosixWritableFile {
+    // buf[0, pos - 1] contains data to be written to file.
+    // NOTE: C++ uses a fixed-size array and a separate position
+    buf: Vec<u8>,
+    // None once the file has been closed
+    file: Option<File>,
+
+    // True if the file's name starts with MANIFEST.
+    is_manifest: bool,
+    filename: String,
+    // The directory of filename.
+    dirname: String,
+}
+
+impl PosixWritableFile {
+    fn new(filename: String, file: File) -> Self {
+        Self {
+            buf: Vec::with_capacity(WRITABLE_FILE_BUFFER_SIZE),
+            file: Some(file),
+            is_manifest: Self::is_manifest(&filename),
+            dirname: Self::dirname(&filename).to_owned(),
+            filename,
+        }
+    }
+
+    fn flush_buffer(&mut self) -> Result<()> {
+        let buf = std::mem::take(&mut self.buf);
+        let status = self.write_unbuffered(&buf);
+        self.buf = buf;
+        self.buf.clear();
+        status
+    }
+
+    fn write_unbuffered(&mut self, data: &[u8]) -> Result<()> {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => {
+                return Err(Error::io_error(
+                    &self.filename.as_str().into(),
+                    &"closed".into(),
+                ))
+            }
+        };
+        match file.write_all(data) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(posix_error(&self.filename, &e)),
+        }
+    }
+
+    fn sync_dir_if_manifest(&self) -> Result<()> {
+        if !self.is_manifest {
+            return Ok(());
+        }
+
+        match File::open(&self.dirname) {
+            Ok(dir) => Self::sync_fd(&dir, &self.dirname),
+            Err(e) => Err(posix_error(&self.dirname, &e)),
+        }
+    }
+
+    // Ensures that all the caches associated with the given file descriptor's data are flushed
+    // all the way to durable media, and can withstand power failures.
+    //
+    // The path argument is only used to populate the description string in the returned Error
+    // if an error occurs.
+    fn sync_fd(file: &File, fd_path: &str) -> Result<()> {
+        // NOTE: C++ uses fdatasync() where available and F_FULLFSYNC on macOS
+        match file.sync_data() {
+            Ok(()) => Ok(()),
+            Err(e) => Err(posix_error(fd_path, &e)),
+        }
+    }
+
+    // Returns the directory name in a path pointing to a file.
+    //
+    // Returns "." if the path does not contain any directory separator.
+    fn dirname(filename: &str) -> &str {
+        match filename.rfind('/') {
+            None => ".",
+            // The filename component should not contain a path separator. If it does, the
+            // splitting was done incorrectly.
+            Some(separator_pos) => &filename[..separator_pos],
+        }
+    }
+
+    // Extracts the file name from a path pointing to a file.
+    //
+    // The returned str points to |filename|'s data buffer, so it is only valid while |filename|
+    // is alive and unchanged.
+    fn basename(filename: &str) -> &str {
+        match filename.rfind('/') {
+            None => filename,
+            Some(separator_pos) => &filename[separator_pos + 1..],
+        }
+    }
+
+    // True if the given file is a manifest file.
+    fn is_manifest(filename: &str) -> bool {
+        Self::basename(filename).starts_with("MANIFEST")
+    }
+}
+
+impl WritableFile for PosixWritableFile {
+    fn append(&mut self, data: &Slice) -> Result<()> {
+        let mut write_data = data.as_bytes();
+
+        // Fit as much as possible into buffer.
+        let copy_size = write_data
+            .len()
+            .min(WRITABLE_FILE_BUFFER_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&write_data[..copy_size]);
+        write_data = &write_data[copy_size..];
+        if write_data.is_empty() {
+            return Ok(());
+        }
+
+        // Can't fit in buffer, so need to do at least one write.
+        self.flush_buffer()?;
+
+        // Small writes go to buffer, large writes are written directly.
+        if write_data.len() < WRITABLE_FILE_BUFFER_SIZE {
+            self.buf.extend_from_slice(write_data);
+            return Ok(());
+        }
+        self.write_unbuffered(write_data)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let status = self.flush_buffer();
+        // Dropping the file closes it
+        self.file = None;
+        status
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buffer()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        // Ensure new files referred to by the manifest are in the filesystem.
+        //
+        // This needs to happen before the manifest file is flushed to disk, to avoid crashing in
+        // a state where the manifest refers to files that are not yet on disk.
+        self.sync_dir_if_manifest()?;
+        self.flush_buffer()?;
+
+        match &self.file {
+            Some(file) => Self::sync_fd(file, &self.filename),
+            None => Err(Error::io_error(
+                &self.filename.as_str().into(),
+                &"closed".into(),
+            )),
+        }
+    }
+}
+
+impl Drop for PosixWritableFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            // Ignoring any potential errors
+            let _ = self.close();
+        }
+    }
+}
+
+fn lock_or_unlock(file: &File, lock: bool) -> io::Result<()> {
+    // SAFETY: An all-zero flock is a valid value; the fields that matter are set below
+    let mut file_lock_info: libc::flock = unsafe { std::mem::zeroed() };
+    file_lock_info.l_type = if lock { libc::F_WRLCK } else { libc::F_UNLCK } as libc::c_short;
+    file_lock_info.l_whence = libc::SEEK_SET as libc::c_short;
+    file_lock_info.l_start = 0;
+    file_lock_info.l_len = 0; // Lock/unlock entire file.
+                              // SAFETY: The descriptor stays open while "file" is borrowed
+    let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &file_lock_info) };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// Tracks the files locked by PosixEnv::lock_file().
+//
+// We maintain a separate set in addition to the fcntl(F_SETLK) lock because fcntl(F_SETLK) does
+// not provide any protection against multiple uses from the same process.
+//
+// Instances are thread-safe because all member data is guarded by a mutex.
+type PosixLockTable = Arc<Mutex<HashSet<String>>>;
+
+// Instances are thread-safe because they are immutable.
+struct PosixFileLock {
+    // Held open for the lifetime of the lock
+    file: File,
+    filename: String,
+    locks: PosixLockTable,
+}
+
+impl FileLock for PosixFileLock {}
+
+impl Drop for PosixFileLock {
+    fn drop(&mut self) {
+        // Errors are ignored since closing the file below releases the lock anyway
+        let _ = lock_or_unlock(&self.file, false);
+        self.locks.lock().unwrap().remove(&self.filename);
+    }
+}
+
+// The work queue shared by a PosixEnv and its background thread.
+// NOTE: C++ keeps these as members of PosixEnv guarded by background_work_mutex_
+struct BackgroundWork {
+    state: Mutex<BackgroundWorkState>,
+    cv: Condvar,
+}
+
+struct BackgroundWorkState {
+    started_background_thread: bool,
+    queue: VecDeque<Box<dyn FnOnce() + Send>>,
+}
+
+impl BackgroundWork {
+    fn background_thread_main(&self) {
+        loop {
+            let mut state = self.state.lock().unwrap();
+
+            // Wait until there is work to be done.
+            while state.queue.is_empty() {
+                state = self.cv.wait(state).unwrap();
+            }
+
+            let background_work_function = state.queue.pop_front().unwrap();
+            drop(state);
+            background_work_function();
+        }
+    }
+}
+
+pub struct PosixEnv {
+    background_work: Arc<BackgroundWork>,
+
+    locks: PosixLockTable,
+    // Thread-safe.
+    fd_limiter: Arc<Limiter>,
+}
+
+impl PosixEnv {
+    pub fn new() -> Self {
+        Self::with_read_only_fd_limit(max_open_files())
+    }
+
+    // NOTE: C++ sets the limit through the global EnvPosixTestHelper before the default Env is
+    // created
+    fn with_read_only_fd_limit(limit: i32) -> Self {
+        Self {
+            background_work: Arc::new(BackgroundWork {
+                state: Mutex::new(BackgroundWorkState {
+                    started_background_thread: false,
+                    queue: VecDeque::new(),
+                }),
+                cv: Condvar::new(),
+            }),
+            locks: Arc::new(Mutex::new(HashSet::new())),
+            fd_limiter: Arc::new(Limiter::new(limit)),
+        }
+    }
+}
+
+impl Default for PosixEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Env for PosixEnv {
+    fn new_sequential_file(&self, fname: &str) -> Result<Box<dyn SequentialFile>> {
+        let file = File::open(fname).map_err(|e| posix_error(fname, &e))?;
+        Ok(Box::new(PosixSequentialFile {
+            file,
+            filename: fname.to_owned(),
+        }))
+    }
+
+    fn new_random_access_file(&self, fname: &str) -> Result<Box<dyn RandomAccessFile>> {
+        let file = File::open(fname).map_err(|e| posix_error(fname, &e))?;
+        Ok(Box::new(PosixRandomAccessFile::new(
+            fname.to_owned(),
+            file,
+            Arc::clone(&self.fd_limiter),
+        )))
+    }
+
+    fn new_writable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(fname)
+            .map_err(|e| posix_error(fname, &e))?;
+        Ok(Box::new(PosixWritableFile::new(fname.to_owned(), file)))
+    }
+
+    fn new_appendable_file(&self, fname: &str) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(fname)
+            .map_err(|e| posix_error(fname, &e))?;
+        Ok(Box::new(PosixWritableFile::new(fname.to_owned(), file)))
+    }
+
+    fn file_exists(&self, fname: &str) -> bool {
+        fs::metadata(fname).is_ok()
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>> {
+        let entries = fs::read_dir(dir).map_err(|e| posix_error(dir, &e))?;
+        let mut result = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| posix_error(dir, &e))?;
+            result.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        Ok(result)
+    }
+
+    fn remove_file(&self, fname: &str) -> Result<()> {
+        match fs::remove_file(fname) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(posix_error(fname, &e)),
+        }
+    }
+
+    fn create_dir(&self, dirname: &str) -> Result<()> {
+        match fs::create_dir(dirname) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(posix_error(dirname, &e)),
+        }
+    }
+
+    fn remove_dir(&self, dirname: &str) -> Result<()> {
+        match fs::remove_dir(dirname) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(posix_error(dirname, &e)),
+        }
+    }
+
+    fn get_file_size(&self, fname: &str) -> Result<u64> {
+        fs::metadata(fname)
+            .map(|metadata| metadata.len())
+            .map_err(|e| posix_error(fname, &e))
+    }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<()> {
+        match fs::rename(src, target) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(posix_error(src, &e)),
+        }
+    }
+
+    fn lock_file(&self, fname: &str) -> Result<Box<dyn FileLock>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(fname)
+            .map_err(|e| posix_error(fname, &e))?;
+
+        if !self.locks.lock().unwrap().insert(fname.to_owned()) {
+            return Err(Error::io_error(
+                &format!("lock {}", fname).as_str().into(),
+                &"already held by process".into(),
+            ));
+        }
+
+        if let Err(e) = lock_or_unlock(&file, true) {
+            self.locks.lock().unwrap().remove(fname);
+            return Err(posix_error(&format!("lock {}", fname), &e));
+        }
+
+        Ok(Box::new(PosixFileLock {
+            file,
+            filename: fname.to_owned(),
+            locks: Arc::clone(&self.locks),
+        }))
+    }
+
+    fn unlock_file(&self, lock: Box<dyn FileLock>) -> Result<()> {
+        // Dropping the lock releases the OS lock, closes the file and removes it from the table
+        drop(lock);
+        Ok(())
+    }
+
+    fn schedule(&self, background_work_function: Box<dyn FnOnce() + Send>) {
+        let mut state = self.background_work.state.lock().unwrap();
+
+        // Start the background thread, if we haven't done so already.
+        if !state.started_background_thread {
+            state.started_background_thread = true;
+            let background_work = Arc::clone(&self.background_work);
+            std::thread::spawn(move || background_work.background_thread_main());
+        }
+
+        // If the queue is empty, the background thread may be waiting for work.
+        if state.queue.is_empty() {
+            self.background_work.cv.notify_one();
+        }
+
+        state.queue.push_back(background_work_function);
+    }
+
+    fn start_thread(&self, thread_main: Box<dyn FnOnce() + Send>) {
+        std::thread::spawn(thread_main);
+    }
+
+    fn get_test_directory(&self) -> Result<String> {
+        let result = match std::env::var("TEST_TMPDIR") {
+            Ok(env) if !env.is_empty() => env,
+            _ => std::env::temp_dir()
+                .join("levelerdbtest")
+                .to_string_lossy()
+                .into_owned(),
+        };
+
+        // The create_dir status is ignored because the directory may already exist.
+        let _ = self.create_dir(&result);
+
+        Ok(result)
+    }
+
+    fn new_logger(&self, fname: &str) -> Result<Arc<dyn Logger>> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(fname)
+            .map_err(|e| posix_error(fname, &e))?;
+        Ok(Arc::new(PosixLogger::new(file)))
+    }
+
+    fn now_micros(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0)
+    }
+
+    fn sleep_for_microseconds(&self, micros: i32) {
+        std::thread::sleep(Duration::from_micros(micros.max(0) as u64));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{self, default_env};
+    use crate::util::random::Random;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+
+    fn test_dir(name: &str) -> String {
+        let dir = format!(
+            "{}/env_posix_test_{}",
+            default_env().get_test_directory().unwrap(),
+            name
+        );
+        let _ = fs::remove_dir_all(&dir);
+        assert!(default_env().create_dir(&dir).is_ok());
+        dir
+    }
+
+    fn read_all(env: &dyn Env, fname: &str) -> String {
+        String::from_utf8(env::read_file_to_string(env, fname).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_read_write() {
+        let env = default_env();
+        let mut rnd = Random::new(301);
+        let test_file_name = format!("{}/open_on_read.txt", test_dir("read_write"));
+        let mut writable_file = env.new_writable_file(&test_file_name).unwrap();
+
+        // Fill a file with data generated via a sequence of randomly sized writes.
+        const DATA_SIZE: usize = 10 * 1048576;
+        let mut data = Vec::new();
+        while data.len() < DATA_SIZE {
+            let len = rnd.skewed(18) as usize; // Up to 2^18 - 1, but typically much smaller
+            let piece: Vec<u8> = (0..len).map(|_| b' ' + rnd.uniform(95) as u8).collect();
+            assert!(writable_file.append(&Slice::from(&piece[..])).is_ok());
+            data.extend_from_slice(&piece);
+            if rnd.one_in(10) {
+                assert!(writable_file.flush().is_ok());
+            }
+        }
+        assert!(writable_file.sync().is_ok());
+        assert!(writable_file.close().is_ok());
+        drop(writable_file);
+
+        // Read all data using a sequence of randomly sized reads.
+        let mut sequential_file = env.new_sequential_file(&test_file_name).unwrap();
+        let mut read_result = Vec::new();
+        let mut scratch = Vec::new();
+        while read_result.len() < data.len() {
+            let len = (rnd.skewed(18) as usize).min(data.len() - read_result.len());
+            scratch.resize(len.max(1), 0); // at least 1 so go to next line
+            let read = sequential_file.read(len, &mut scratch).unwrap();
+            if len > 0 {
+                assert!(read.size() > 0);
+            }
+            assert!(read.size() <= len);
+            read_result.extend_from_slice(read.as_bytes());
+        }
+        assert_eq!(data, read_result);
+
+        // Random reads see the same data
+        let random_file = env.new_random_access_file(&test_file_name).unwrap();
+        for _ in 0..100 {
+            let offset = rnd.uniform(data.len() as u32) as usize;
+            let len = rnd.skewed(12) as usize;
+            scratch.resize(len, 0);
+            let read = random_file.read(offset as u64, len, &mut scratch).unwrap();
+            let end = (offset + len).min(data.len());
+            assert_eq!(&data[offset..end], read.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_run_immediately() {
+        let env = default_env();
+        let (tx, rx) = mpsc::channel();
+        env.schedule(Box::new(move || tx.send(()).unwrap()));
+        rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn test_run_many() {
+        let env = default_env();
+        let last_id = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+
+        // The background thread runs the callbacks in the order they were scheduled
+        for id in 1..=4 {
+            let last_id = Arc::clone(&last_id);
+            let tx = tx.clone();
+            env.schedule(Box::new(move || {
+                assert_eq!(id - 1, last_id.load(Ordering::SeqCst));
+                last_id.store(id, Ordering::SeqCst);
+                tx.send(id).unwrap();
+            }));
+        }
+
+        for id in 1..=4 {
+            assert_eq!(id, rx.recv_timeout(Duration::from_secs(10)).unwrap());
+        }
+        assert_eq!(4, last_id.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_start_thread() {
+        let env = default_env();
+        let (tx, rx) = mpsc::channel();
+        for i in 0..3 {
+            let tx = tx.clone();
+            env.start_thread(Box::new(move || tx.send(i).unwrap()));
+        }
+        let mut ids: Vec<i32> = (0..3)
+            .map(|_| rx.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(vec![0, 1, 2], ids);
+    }
+
+    #[test]
+    fn test_open_non_existent_file() {
+        let env = default_env();
+        let non_existent_file = format!("{}/non_existent_file", test_dir("non_existent"));
+        assert!(!env.file_exists(&non_existent_file));
+
+        let status = env
+            .new_random_access_file(&non_existent_file)
+            .err()
+            .unwrap();
+        assert!(status.is_not_found(), "{}", status);
+
+        let status = env.new_sequential_file(&non_existent_file).err().unwrap();
+        assert!(status.is_not_found(), "{}", status);
+
+        let status = env.get_file_size(&non_existent_file).err().unwrap();
+        assert!(status.is_not_found(), "{}", status);
+    }
+
+    #[test]
+    fn test_reopen_writable_file() {
+        let env = default_env();
+        let test_file_name = format!("{}/reopen_writable_file.txt", test_dir("reopen_writable"));
+
+        let mut writable_file = env.new_writable_file(&test_file_name).unwrap();
+        assert!(writable_file.append(&"hello world!".into()).is_ok());
+        assert!(writable_file.close().is_ok());
+        drop(writable_file);
+
+        let mut writable_file = env.new_writable_file(&test_file_name).unwrap();
+        assert!(writable_file.append(&"42".into()).is_ok());
+        assert!(writable_file.close().is_ok());
+        drop(writable_file);
+
+        assert_eq!("42", read_all(&*env, &test_file_name));
+        assert_eq!(2, env.get_file_size(&test_file_name).unwrap());
+    }
+
+    #[test]
+    fn test_reopen_appendable_file() {
+        let env = default_env();
+        let test_file_name = format!(
+            "{}/reopen_appendable_file.txt",
+            test_dir("reopen_appendable")
+        );
+
+        let mut appendable_file = env.new_appendable_file(&test_file_name).unwrap();
+        assert!(appendable_file.append(&"hello world!".into()).is_ok());
+        assert!(appendable_file.close().is_ok());
+        drop(appendable_file);
+
+        let mut appendable_file = env.new_appendable_file(&test_file_name).unwrap();
+        assert!(appendable_file.append(&"42".into()).is_ok());
+        assert!(appendable_file.close().is_ok());
+        drop(appendable_file);
+
+        assert_eq!("hello world!42", read_all(&*env, &test_file_name));
+    }
+
+    #[test]
+    fn test_random_access_fd_limit() {
+        // Only one of the files keeps its descriptor; the others are reopened on every read
+        let env = PosixEnv::with_read_only_fd_limit(1);
+        let dir = test_dir("fd_limit");
+        let mut files = Vec::new();
+        for i in 0..3 {
+            let fname = format!("{}/{}.txt", dir, i);
+            assert!(env::write_string_to_file(
+                &env,
+                &format!("file {}", i).as_str().into(),
+                &fname
+            )
+            .is_ok());
+            files.push(env.new_random_access_file(&fname).unwrap());
+        }
+        assert_eq!(0, env.fd_limiter.acquires_allowed.load(Ordering::Relaxed));
+
+        let mut scratch = [0u8; 16];
+        for (i, file) in files.iter().enumerate() {
+            let read = file.read(5, 16, &mut scratch).unwrap();
+            assert_eq!(i.to_string().as_bytes(), read.as_bytes());
+        }
+
+        drop(files);
+        assert_eq!(1, env.fd_limiter.acquires_allowed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let env = default_env();
+        let fname = format!("{}/LOCK", test_dir("lock_file"));
+
+        let lock = env.lock_file(&fname).unwrap();
+        // A second lock from the same process fails
+        assert!(env.lock_file(&fname).is_err());
+        assert!(env.unlock_file(lock).is_ok());
+
+        let lock = env.lock_file(&fname).unwrap();
+        drop(lock);
+        assert!(env.lock_file(&fname).is_ok());
+    }
+
+    #[test]
+    fn test_file_operations() {
+        let env = default_env();
+        let dir = test_dir("file_operations");
+
+        assert!(
+            env::write_string_to_file_sync(&*env, &"abc".into(), &format!("{}/a", dir)).is_ok()
+        );
+        assert!(env.create_dir(&format!("{}/sub", dir)).is_ok());
+        let mut children = env.get_children(&dir).unwrap();
+        children.sort();
+        assert_eq!(vec!["a", "sub"], children);
+
+        assert!(env
+            .rename_file(&format!("{}/a", dir), &format!("{}/b", dir))
+            .is_ok());
+        assert!(!env.file_exists(&format!("{}/a", dir)));
+        assert_eq!(3, env.get_file_size(&format!("{}/b", dir)).unwrap());
+
+        assert!(env.remove_file(&format!("{}/b", dir)).is_ok());
+        assert!(env
+            .remove_file(&format!("{}/b", dir))
+            .unwrap_err()
+            .is_not_found());
+        assert!(env.remove_dir(&format!("{}/sub", dir)).is_ok());
+        assert!(env.get_children(&dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_manifest_sync() {
+        let env = default_env();
+        let fname = format!("{}/MANIFEST-000001", test_dir("manifest_sync"));
+        let mut file = env.new_writable_file(&fname).unwrap();
+        assert!(file.append(&"edit".into()).is_ok());
+        assert!(file.sync().is_ok());
+        assert!(file.close().is_ok());
+        // A closed file can't be synced
+        assert!(file.sync().unwrap_err().is_io_error());
+
+        assert_eq!("dir", PosixWritableFile::dirname("dir/MANIFEST"));
+        assert_eq!(".", PosixWritableFile::dirname("MANIFEST"));
+        assert!(PosixWritableFile::is_manifest("dir/MANIFEST-000001"));
+        assert!(!PosixWritableFile::is_manifest("MANIFEST/000001.log"));
+    }
+
+    #[test]
+    fn test_logger() {
+        let env = default_env();
+        let fname = format!("{}/LOG", test_dir("logger"));
+        let logger = env.new_logger(&fname).unwrap();
+        env::log(Some(&*logger), format_args!("hello {}", 42));
+        env::log(Some(&*logger), format_args!("world\n"));
+        drop(logger);
+
+        let contents = read_all(&*env, &fname);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].ends_with(" hello 42"), "{}", lines[0]);
+        assert!(lines[1].ends_with(" world"), "{}", lines[1]);
+        // "YYYY/MM/DD-HH:MM:SS.UUUUUU "
+        let header = lines[0].as_bytes();
+        assert_eq!(b'/', header[4]);
+        assert_eq!(b'-', header[10]);
+        assert_eq!(b'.', header[19]);
+        assert_eq!(b' ', header[26]);
+    }
+
+    #[test]
+    fn test_now_micros() {
+        let env = default_env();
+        let start = env.now_micros();
+        env.sleep_for_microseconds(1000);
+        assert!(env.now_micros() >= start + 1000);
+    }
+}
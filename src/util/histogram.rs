@@ -1,6 +1,8 @@
 // leveldb/util/histogram.{h,cc}
 
 #![allow(dead_code)]
+use std::fmt;
+
 pub(crate) struct Histogram {
     min: f64,
     max: f64,
@@ -117,8 +119,8 @@ impl Histogram {
     }
 }
 
-impl ToString for Histogram {
-    fn to_string(&self) -> String {
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut r = format!(
             "Count: {:.0}  Average: {:.4}  StdDev: {:.2}\n",
             self.num,
@@ -152,7 +154,7 @@ impl ToString for Histogram {
             r += &"#".repeat(marks);
             r.push('\n');
         }
-        r
+        f.write_str(&r)
     }
 }
 
@@ -19,7 +19,7 @@ pub fn append_number_to(s: &mut String, num: u64) {
 pub fn append_escaped_string_to(s: &mut String, value: &Slice) {
     for i in 0..value.size() {
         let c = value[i] as char;
-        if c >= ' ' && c <= '~' {
+        if (' '..='~').contains(&c) {
             s.push(c);
         } else {
             s.push_str(&format!("\\x{:02x}", c as u32 & 0xff,));
@@ -57,13 +57,9 @@ pub fn consume_decimal_number(in_slice: &mut Slice, val: &mut u64) -> bool {
     const LAST_DIGIT_OF_MAX_U64: u8 = (('0' as u64) + (MAX_U64 % 10)) as u8;
     let mut value = 0;
 
-    // Cast from *const c_char to *const u8 to avoid signedness
-    let start = in_slice.data() as *const u8;
-    let end = unsafe { start.add(in_slice.size()) };
-    let mut current = start;
-    while current != end {
-        let ch = unsafe { *current };
-        if ch < b'0' || ch > b'9' {
+    let mut digits_consumed = 0;
+    for &ch in in_slice.iter() {
+        if !ch.is_ascii_digit() {
             break;
         }
         // Overflow check.
@@ -72,11 +68,10 @@ pub fn consume_decimal_number(in_slice: &mut Slice, val: &mut u64) -> bool {
             return false;
         }
         value = (value * 10) + (ch - b'0') as u64;
-        current = unsafe { current.add(1) };
+        digits_consumed += 1;
     }
 
     *val = value;
-    let digits_consumed = unsafe { current.offset_from(start) as usize };
     in_slice.remove_prefix(digits_consumed);
     digits_consumed != 0
 }
@@ -133,9 +128,7 @@ mod tests {
         let mut result = 0;
         assert!(consume_decimal_number(&mut output, &mut result));
         assert_eq!(number, result);
-        assert_eq!(decimal_number.len(), unsafe {
-            output.data().offset_from(input.data()) as usize
-        },);
+        assert_eq!(decimal_number.len(), input.size() - output.size());
         assert_eq!(padding.len(), output.size());
     }
 
@@ -186,7 +179,7 @@ mod tests {
         let input = Slice::from(input_string);
         let mut output = input;
         let mut result = 0;
-        assert_eq!(false, consume_decimal_number(&mut output, &mut result));
+        assert!(!consume_decimal_number(&mut output, &mut result));
     }
 
     #[test]
@@ -213,9 +206,8 @@ mod tests {
         let input = Slice::from(input_string);
         let mut output = input;
         let mut result = 0;
-        assert_eq!(false, consume_decimal_number(&mut output, &mut result));
-        assert_eq!(input.data(), output.data());
-        assert_eq!(input.size(), output.size());
+        assert!(!consume_decimal_number(&mut output, &mut result));
+        assert_eq!(input, output);
     }
 
     #[test]
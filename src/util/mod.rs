@@ -1,11 +1,18 @@
 pub mod arena;
 pub mod cache;
+mod clock_cache;
 pub mod coding;
 pub mod comparator;
+pub mod crc32c;
+pub mod env_posix;
 pub mod hash;
 pub mod histogram;
 pub mod logging;
 mod mutexlock;
 mod no_destructor;
-mod random;
+mod posix_logger;
+pub(crate) mod random;
 pub mod status;
+#[cfg(test)]
+pub(crate) mod testutil;
+mod two_queue_cache;
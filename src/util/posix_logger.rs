@@ -0,0 +1,98 @@
+// leveldb/util/posix_logger.h
+//
+// Logger implementation that can be shared by all environments where enough posix functionality
+// is available.
+
+use crate::env::Logger;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) struct PosixLogger {
+    // NOTE: C++ relies on the locking done by FILE*; a Mutex serializes the writes here
+    fp: Mutex<File>,
+}
+
+impl PosixLogger {
+    // Creates a logger that writes to the given file.
+    pub(crate) fn new(fp: File) -> Self {
+        Self { fp: Mutex::new(fp) }
+    }
+}
+
+impl Logger for PosixLogger {
+    fn logv(&self, args: std::fmt::Arguments) {
+        // Record the time as close to the Logv() call as possible.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // NOTE: C++ prints the local time from localtime_r(); the standard library has no time
+        // zone support so UTC is used
+        let secs = now.as_secs() as i64;
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let secs_of_day = secs.rem_euclid(86400);
+
+        // Record the thread ID.
+        // NOTE: C++ truncates the thread ID to 32 characters
+        let mut thread_id = format!("{:?}", std::thread::current().id());
+        thread_id.truncate(32);
+
+        // NOTE: C++ formats into a stack buffer and falls back to a heap buffer if the message is
+        // too long; a String grows as needed here
+        let mut buffer = String::new();
+        let _ = write!(
+            buffer,
+            "{:04}/{:02}/{:02}-{:02}:{:02}:{:02}.{:06} {} ",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            now.subsec_micros(),
+            thread_id,
+        );
+        let _ = buffer.write_fmt(args);
+
+        // Add a newline if necessary.
+        if !buffer.ends_with('\n') {
+            buffer.push('\n');
+        }
+
+        // Errors are ignored as in C++, which has no way to report them
+        let mut fp = self.fp.lock().unwrap();
+        let _ = fp.write_all(buffer.as_bytes());
+        let _ = fp.flush();
+    }
+}
+
+// Returns the (year, month, day) of the date that is "days" days after 1970-01-01.
+// NOTE: Not in C++, which uses localtime_r()
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((1969, 12, 31), civil_from_days(-1));
+        assert_eq!((2000, 2, 29), civil_from_days(11016));
+        assert_eq!((2000, 3, 1), civil_from_days(11017));
+        assert_eq!((2022, 1, 1), civil_from_days(18993));
+    }
+}
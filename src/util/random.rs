@@ -47,13 +47,14 @@ impl Random {
     // Randomly returns true ~"1/n" of the time, and false otherwise
     // NOTE: C++ takes 'n' as an int
     pub fn one_in(&mut self, n: u32) -> bool {
-        self.next() % n == 0
+        self.next().is_multiple_of(n)
     }
 
     // Skewed: pick "base" uniformly from range [0,max_log] and then return "base" random bits.
     // The effect is to pick a number in the range [0,2^max_log-1] with exponential bias towards
     // smaller numbers.
     // NOTE: C++ takes 'n' as an int
+    #[cfg(test)]
     pub fn skewed(&mut self, max_log: u32) -> u32 {
         let u = self.uniform(max_log + 1);
         // NOTE: May overflow
@@ -1,32 +1,123 @@
 // leveldb/include/leveldb/status.h
 // leveldb/util/status.cc
 
-#![allow(dead_code)]
+// NOTE: C++ reports every outcome through a Status. Public APIs here return Result<T, Error>
+// instead; Status remains as a thin wrapper around an optional Error for the code that mirrors
+// C++ by threading a status through a sequence of steps, and converts to and from Result<()>.
+
 use crate::slice::Slice;
-use std::alloc;
-use std::mem::size_of;
-use std::ptr::null;
+use std::fmt;
+use std::io;
+
+// The error returned by fallible operations. Each variant carries a description of the failure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    NotFound(String),
+    Corruption(String),
+    NotSupported(String),
+    InvalidArgument(String),
+    IoError(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Join the two parts of an error message like C++ does: "msg: msg2", or "msg" if msg2 is empty.
+fn join_msgs(msg: &Slice, msg2: &Slice) -> String {
+    let mut result = String::from_utf8_lossy(msg.as_bytes()).into_owned();
+    if !msg2.empty() {
+        result.push_str(": ");
+        result.push_str(&String::from_utf8_lossy(msg2.as_bytes()));
+    }
+    result
+}
+
+impl Error {
+    // Return an error of an appropriate type.
+    pub fn not_found(msg: &Slice, msg2: &Slice) -> Self {
+        Self::NotFound(join_msgs(msg, msg2))
+    }
+
+    pub fn corruption(msg: &Slice, msg2: &Slice) -> Self {
+        Self::Corruption(join_msgs(msg, msg2))
+    }
+
+    pub fn not_supported(msg: &Slice, msg2: &Slice) -> Self {
+        Self::NotSupported(join_msgs(msg, msg2))
+    }
+
+    pub fn invalid_argument(msg: &Slice, msg2: &Slice) -> Self {
+        Self::InvalidArgument(join_msgs(msg, msg2))
+    }
+
+    pub fn io_error(msg: &Slice, msg2: &Slice) -> Self {
+        Self::IoError(join_msgs(msg, msg2))
+    }
+
+    // Returns true iff the error is a NotFound error.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Self::NotFound(_))
+    }
+
+    // Returns true iff the error is a Corruption error.
+    pub fn is_corruption(&self) -> bool {
+        matches!(self, Self::Corruption(_))
+    }
+
+    // Returns true iff the error is an IoError.
+    pub fn is_io_error(&self) -> bool {
+        matches!(self, Self::IoError(_))
+    }
+
+    // Returns true iff the error is a NotSupported error.
+    pub fn is_not_supported_error(&self) -> bool {
+        matches!(self, Self::NotSupported(_))
+    }
+
+    // Returns true iff the error is an InvalidArgument error.
+    pub fn is_invalid_argument(&self) -> bool {
+        matches!(self, Self::InvalidArgument(_))
+    }
+}
+
+impl fmt::Display for Error {
+    // Return a string representation of this error suitable for printing.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, msg) = match self {
+            Self::NotFound(msg) => ("NotFound: ", msg),
+            Self::Corruption(msg) => ("Corruption: ", msg),
+            Self::NotSupported(msg) => ("Not implemented: ", msg),
+            Self::InvalidArgument(msg) => ("Invalid argument: ", msg),
+            Self::IoError(msg) => ("IO error: ", msg),
+        };
+        write!(f, "{}{}", kind, msg)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::NotFound {
+            Self::NotFound(e.to_string())
+        } else {
+            Self::IoError(e.to_string())
+        }
+    }
+}
 
+#[derive(Clone, Default)]
 pub struct Status {
-    // OK status has a None 'state'. Otherwise, 'state' is an alloced array of memory of the
-    // following form:
-    //    state[0..3] == length of message
-    //    state[4]    == code
-    //    state[5..]  == message
-    // TODO: Use *const u8? Test to see if conversion from mut to const to mut causes error
-    // NOTE: C++ uses char
-    //state: Option<*const u8>,
-    state: *const u8,
+    // OK status has a None 'error'.
+    // NOTE: C++ keeps the code and message in a manually allocated array
+    error: Option<Error>,
 }
 
 impl Status {
     // Create a success state
     pub fn new() -> Self {
-        Self { state: null() }
+        Self { error: None }
     }
 
-    // TODO: Copy
-
     // Return a success state.
     // NOTE: Kept all caps to align with C++ name and not class with 'ok' method below
     #[allow(non_snake_case)]
@@ -36,192 +127,125 @@ impl Status {
 
     // Return erorr status of an appropriate type.
     pub fn not_found(msg: &Slice, msg2: &Slice) -> Self {
-        Self::from_code_msgs(Code::NotFound, msg, msg2)
+        Error::not_found(msg, msg2).into()
     }
 
     pub fn corruption(msg: &Slice, msg2: &Slice) -> Self {
-        Self::from_code_msgs(Code::Corruption, msg, msg2)
+        Error::corruption(msg, msg2).into()
     }
 
     pub fn not_supported(msg: &Slice, msg2: &Slice) -> Self {
-        Self::from_code_msgs(Code::NotSupported, msg, msg2)
+        Error::not_supported(msg, msg2).into()
     }
 
     pub fn invalid_argument(msg: &Slice, msg2: &Slice) -> Self {
-        Self::from_code_msgs(Code::InvalidArgument, msg, msg2)
+        Error::invalid_argument(msg, msg2).into()
     }
 
     pub fn io_error(msg: &Slice, msg2: &Slice) -> Self {
-        Self::from_code_msgs(Code::IOError, msg, msg2)
+        Error::io_error(msg, msg2).into()
     }
 
     // Returns true iff the status indicates success.
-    // NOTE: C++ checks if state is nullptr
     pub fn ok(&self) -> bool {
-        self.state.is_null()
+        self.error.is_none()
     }
 
     // Returns true iff the status indicates a NotFound error.
     pub fn is_not_found(&self) -> bool {
-        self.code() == Code::NotFound
+        self.error.as_ref().is_some_and(Error::is_not_found)
     }
 
     // Returns true iff the status indicates a Corruption error.
     pub fn is_corruption(&self) -> bool {
-        self.code() == Code::Corruption
+        self.error.as_ref().is_some_and(Error::is_corruption)
     }
 
     // Returns true iff the status indicates a IOError.
     pub fn is_io_error(&self) -> bool {
-        self.code() == Code::IOError
+        self.error.as_ref().is_some_and(Error::is_io_error)
     }
 
     // Returns true iff the status indicates a NotSupported error.
     pub fn is_not_supported_error(&self) -> bool {
-        self.code() == Code::NotSupported
+        self.error
+            .as_ref()
+            .is_some_and(Error::is_not_supported_error)
     }
 
     // Returns true iff the status indicates a InvalidArgument error.
     pub fn is_invalid_argument(&self) -> bool {
-        self.code() == Code::InvalidArgument
-    }
-
-    fn code(&self) -> Code {
-        if !self.state.is_null() {
-            unsafe { Code::try_from(*self.state.add(4)).unwrap() }
-        } else {
-            Code::Ok
-        }
+        self.error.as_ref().is_some_and(Error::is_invalid_argument)
     }
+}
 
-    fn from_code_msgs(code: Code, msg: &Slice, msg2: &Slice) -> Self {
-        assert!(code != Code::Ok);
-        let (len1, len2) = (msg.size(), msg2.size());
-        let size = (len1 + if len2 != 0 { 2 + len2 } else { 0 }) as u32;
-        unsafe {
-            let layout = alloc::Layout::array::<u8>(size as usize + 5).unwrap();
-            let result = alloc::alloc(layout);
-            // NOTE: C++ uses Rust equivalent of size_of_val
-            result.copy_from((&size) as *const u32 as *const u8, size_of::<u32>());
-            *result.add(4) = code as u8;
-            result.add(5).copy_from(msg.data().cast(), len1);
-            if len2 != 0 {
-                *result.add(5 + len1) = b':';
-                *result.add(6 + len1) = b' ';
-                result.add(7 + len1).copy_from(msg.data().cast(), len2);
-            }
-            Self {
-                state: result as *const u8,
-            }
-        }
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        Self { error: Some(error) }
     }
+}
 
-    fn copy_state(state: *const u8) -> *const u8 {
-        unsafe {
-            let mut size = 0u32;
-            // NOTE: C++ uses Rust equivalent of size_of_val
-            state.copy_to((&mut size) as *mut u32 as *mut u8, size_of::<u32>());
-            let size = size as usize;
-            let layout = alloc::Layout::array::<u8>(size + 5).unwrap();
-            let result = alloc::alloc(layout);
-            result.copy_from(state, size + 5);
-            result as *const u8
+impl From<Result<()>> for Status {
+    fn from(result: Result<()>) -> Self {
+        Self {
+            error: result.err(),
         }
     }
 }
 
-impl Clone for Status {
-    fn clone(&self) -> Self {
-        //let state = self.state.map(|p| Self::copy_state(p as *const _));
-        Self {
-            state: if self.state.is_null() {
-                null()
-            } else {
-                Self::copy_state(self.state)
-            },
+impl From<Status> for Result<()> {
+    fn from(status: Status) -> Self {
+        match status.error {
+            Some(error) => Err(error),
+            None => Ok(()),
         }
-        //Self { state }
     }
 }
 
-// NOTE: `Copy` can't be implemented like it is in C++ since types implementing `Copy` can't have
-// destructors
-//impl Copy for Status {}
-
-impl ToString for Status {
+impl fmt::Display for Status {
     // Return a string representation of this status suitable for printing.
     // Returns the string "OK" for success
-    fn to_string(&self) -> String {
-        if !self.state.is_null() {
-            // NOTE: C++ handles a default case for an unknown code. However, this cannot happen
-            // with Rust's enums
-            let mut result = match self.code() {
-                Code::Ok => "OK",
-                Code::NotFound => "NotFound: ",
-                Code::Corruption => "Corruption: ",
-                Code::NotSupported => "Not implemented: ",
-                Code::InvalidArgument => "Invalid argument: ",
-                Code::IOError => "IO error: ",
-            }
-            .to_owned();
-            unsafe {
-                let mut length = 0u32;
-                // NOTE: C++ uses the Rust equivalent of size_of_val
-                ((&mut length) as *mut u32)
-                    .cast::<u8>()
-                    .copy_from(self.state, size_of::<u32>());
-                let (l, length) = (result.len(), length as usize);
-                result.push_str(&"\0".repeat(length));
-                result
-                    .as_mut_ptr()
-                    .add(l)
-                    .copy_from(self.state.add(5), length);
-            }
-            result
-        } else {
-            String::from("ok")
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            Some(error) => fmt::Display::fmt(error, f),
+            None => f.write_str("OK"),
         }
     }
 }
 
-impl Drop for Status {
-    fn drop(&mut self) {
-        if !self.state.is_null() {
-            unsafe {
-                let mut size = 0u32;
-                self.state
-                    .copy_to((&mut size) as *mut u32 as *mut u8, size_of::<u32>());
-                alloc::dealloc(
-                    self.state as *mut u8,
-                    alloc::Layout::array::<u8>(size as usize + 5).unwrap(),
-                );
-            }
-        }
+impl fmt::Debug for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
     }
 }
 
-#[derive(Clone, Copy, PartialOrd, PartialEq, Ord, Eq)]
-#[repr(u8)]
-enum Code {
-    Ok,
-    NotFound,
-    Corruption,
-    NotSupported,
-    InvalidArgument,
-    IOError,
-}
-
-impl std::convert::TryFrom<u8> for Code {
-    type Error = String;
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(Self::Ok),
-            1 => Ok(Self::NotFound),
-            2 => Ok(Self::Corruption),
-            3 => Ok(Self::NotSupported),
-            4 => Ok(Self::InvalidArgument),
-            5 => Ok(Self::IOError),
-            _ => Err(format!("unknown code: {}", value)),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!("OK", Status::OK().to_string());
+        let s = Status::corruption(&"bad block".into(), &"000001.ldb".into());
+        assert_eq!("Corruption: bad block: 000001.ldb", s.to_string());
+        let e = Error::io_error(&"disk full".into(), &Slice::new());
+        assert_eq!("IO error: disk full", e.to_string());
+    }
+
+    #[test]
+    fn test_result_round_trip() {
+        let s = Status::not_found(&"missing".into(), &Slice::new());
+        let r: Result<()> = s.clone().into();
+        assert_eq!(Err(Error::NotFound("missing".to_owned())), r);
+        assert!(Status::from(r).is_not_found());
+        assert!(Status::from(Ok(())).ok());
+    }
+
+    #[test]
+    fn test_from_io_error() {
+        let e: Error = io::Error::from(io::ErrorKind::NotFound).into();
+        assert!(e.is_not_found());
+        let e: Error = io::Error::from(io::ErrorKind::PermissionDenied).into();
+        assert!(e.is_io_error());
     }
 }
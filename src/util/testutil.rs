@@ -0,0 +1,31 @@
+// leveldb/util/testutil.{h,cc}
+
+use crate::util::random::Random;
+use std::collections::BTreeMap;
+
+// Return a random byte string of length "len".
+// NOTE: C++ RandomString only produces printable characters; every byte value is used here so
+// tests also cover keys and values that aren't valid UTF-8
+pub(crate) fn random_bytes(rnd: &mut Random, len: usize) -> Vec<u8> {
+    (0..len).map(|_| rnd.uniform(256) as u8).collect()
+}
+
+// Return up to "n" random key,value pairs with keys shorter than "max_key_len" and values shorter
+// than "max_value_len", sorted and deduplicated by the byte-wise order of their keys.
+pub(crate) fn random_entries(
+    rnd: &mut Random,
+    n: usize,
+    max_key_len: u32,
+    max_value_len: u32,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    (0..n)
+        .map(|_| {
+            let key_len = rnd.uniform(max_key_len) as usize;
+            let key = random_bytes(rnd, key_len);
+            let value_len = rnd.uniform(max_value_len) as usize;
+            (key, random_bytes(rnd, value_len))
+        })
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .collect()
+}
@@ -0,0 +1,505 @@
+// NOTE: Not part of LevelDB; an alternative eviction policy for Cache.
+//
+// 2Q (Johnson and Shasha, "2Q: A Low Overhead High Performance Buffer Management Replacement
+// Algorithm") keeps entries seen once in a FIFO queue (A1in) apart from the LRU queue of entries
+// seen repeatedly (Am). Entries leaving A1in are remembered by key in a ghost queue (A1out). An
+// entry is admitted to Am once it is read again, either by a hit in A1in or by being re-inserted
+// while its key is still remembered. A large scan reads each block once, so it churns through
+// A1in and cannot flush the frequently used entries in Am.
+//
+// NOTE: The paper's full version leaves hits in A1in where they are, to ignore correlated
+// references; a hot set that fits in the cache would then never reach Am.
+
+use crate::slice::Slice;
+use crate::util::cache::{CacheShard, Handle};
+use std::collections::HashMap;
+use std::ptr::null_mut;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Queue {
+    In,
+    Main,
+    // A key remembered in A1out, with no value.
+    Out,
+}
+
+// An entry is kept in one of the circular doubly linked lists of TwoQueueState, like the entries
+// of LRUCache.
+struct TwoQueueHandle<V> {
+    // NOTE: None for the list heads and the keys in A1out
+    value: Option<Arc<V>>,
+    next: *mut TwoQueueHandle<V>,
+    prev: *mut TwoQueueHandle<V>,
+    charge: usize,
+    // Whether entry is in cache.
+    in_cache: bool,
+    // References, including cache reference, if present.
+    refs: u32,
+    queue: Queue,
+    key_data: Box<[u8]>,
+}
+
+impl<V> TwoQueueHandle<V> {
+    fn new_list() -> Box<Self> {
+        let mut head = Box::new(Self {
+            value: None,
+            next: null_mut(),
+            prev: null_mut(),
+            charge: 0,
+            in_cache: false,
+            refs: 0,
+            queue: Queue::Out,
+            key_data: Box::new([]),
+        });
+        head.next = &mut *head as *mut _;
+        head.prev = &mut *head as *mut _;
+        head
+    }
+}
+
+struct TwoQueueState<V> {
+    // Entries in the cache, by key.
+    table: HashMap<Box<[u8]>, *mut TwoQueueHandle<V>>,
+    // Dummy heads of the lists below. head.next is the oldest entry, head.prev the newest.
+    // NOTE: Boxed so the circular list heads keep a stable address when the state is moved
+    //
+    // A1in, entries with refs==1 that were seen once.
+    recent: Box<TwoQueueHandle<V>>,
+    recent_usage: usize,
+    // Am, entries with refs==1 that were seen again.
+    frequent: Box<TwoQueueHandle<V>>,
+    // Entries in use by clients, with refs >= 2, from either queue. They go back to the newest end
+    // of their queue once released.
+    in_use: Box<TwoQueueHandle<V>>,
+    usage: usize,
+    // A1out, the keys that left A1in along with the charge they had.
+    ghosts: Box<TwoQueueHandle<V>>,
+    ghost_table: HashMap<Box<[u8]>, *mut TwoQueueHandle<V>>,
+    ghost_usage: usize,
+}
+
+// SAFETY: The raw handle pointers are only followed while holding the shard's mutex
+unsafe impl<V: Send + Sync> Send for TwoQueueState<V> {}
+
+impl<V> TwoQueueState<V> {
+    fn list_remove(e: *mut TwoQueueHandle<V>) {
+        unsafe {
+            (*(*e).next).prev = (*e).prev;
+            (*(*e).prev).next = (*e).next;
+        }
+    }
+
+    fn list_append(list: *mut TwoQueueHandle<V>, e: *mut TwoQueueHandle<V>) {
+        unsafe {
+            // Make "e" newest entry by inserting just before *list.
+            (*e).next = list;
+            (*e).prev = (*list).prev;
+            (*(*e).prev).next = e;
+            (*(*e).next).prev = e;
+        }
+    }
+
+    fn list_head(&mut self, queue: Queue) -> *mut TwoQueueHandle<V> {
+        match queue {
+            Queue::In => &mut *self.recent,
+            Queue::Main => &mut *self.frequent,
+            Queue::Out => &mut *self.ghosts,
+        }
+    }
+
+    // Return the oldest entry of the list, or None if it is empty.
+    fn oldest(&mut self, queue: Queue) -> Option<*mut TwoQueueHandle<V>> {
+        let head = self.list_head(queue);
+        let e = unsafe { (*head).next };
+        (e != head).then_some(e)
+    }
+
+    // NOTE: C++ LRUCache calls it "Ref"
+    fn ref_(&mut self, e: *mut TwoQueueHandle<V>) {
+        unsafe {
+            if (*e).refs == 1 && (*e).in_cache {
+                // If on a queue, move to self.in_use list.
+                Self::list_remove(e);
+                Self::list_append(&mut *self.in_use, e);
+            }
+            (*e).refs += 1;
+        }
+    }
+
+    fn unref(&mut self, e: *mut TwoQueueHandle<V>) {
+        unsafe {
+            assert!((*e).refs > 0);
+            (*e).refs -= 1;
+            if (*e).refs == 0 {
+                // Deallocate.
+                assert!(!(*e).in_cache);
+                drop(Box::from_raw(e));
+            } else if (*e).in_cache && (*e).refs == 1 {
+                // No longer in use; move to the newest end of its queue.
+                Self::list_remove(e);
+                let head = self.list_head((*e).queue);
+                Self::list_append(head, e);
+            }
+        }
+    }
+
+    // Finish removing *e from the cache; it has already been removed from the table.
+    fn finish_erase(&mut self, e: *mut TwoQueueHandle<V>) {
+        unsafe {
+            assert!((*e).in_cache);
+            Self::list_remove(e);
+            (*e).in_cache = false;
+            self.usage -= (*e).charge;
+            if (*e).queue == Queue::In {
+                self.recent_usage -= (*e).charge;
+            }
+        }
+        self.unref(e);
+    }
+
+    // Forget "key" if it is remembered in A1out. Return whether it was.
+    fn remove_ghost(&mut self, key: &[u8]) -> bool {
+        match self.ghost_table.remove(key) {
+            Some(g) => unsafe {
+                Self::list_remove(g);
+                self.ghost_usage -= (*g).charge;
+                drop(Box::from_raw(g));
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Evict until usage fits in capacity. A1in gives up entries while it holds more than its
+    // share; otherwise Am does. Only entries leaving A1in are remembered in A1out.
+    fn evict(&mut self, capacity: usize, recent_capacity: usize, ghost_capacity: usize) {
+        while self.usage > capacity {
+            let (first, second) = if self.recent_usage > recent_capacity {
+                (Queue::In, Queue::Main)
+            } else {
+                (Queue::Main, Queue::In)
+            };
+            let Some(e) = self.oldest(first).or_else(|| self.oldest(second)) else {
+                // Everything left is in use.
+                break;
+            };
+
+            unsafe {
+                assert_eq!((*e).refs, 1);
+                let (key, _) = self.table.remove_entry(&*(*e).key_data).unwrap();
+                if (*e).queue == Queue::In {
+                    // Keep the entry's allocation to remember its key in A1out.
+                    Self::list_remove(e);
+                    (*e).in_cache = false;
+                    self.usage -= (*e).charge;
+                    self.recent_usage -= (*e).charge;
+                    (*e).value = None;
+                    (*e).queue = Queue::Out;
+                    Self::list_append(&mut *self.ghosts, e);
+                    self.ghost_table.insert(key, e);
+                    self.ghost_usage += (*e).charge;
+                } else {
+                    self.finish_erase(e);
+                }
+            }
+        }
+
+        while self.ghost_usage > ghost_capacity {
+            let g = self.oldest(Queue::Out).unwrap();
+            let removed = self.remove_ghost(unsafe { &(*g).key_data });
+            assert!(removed);
+        }
+    }
+}
+
+// A single shard of a sharded 2Q cache.
+pub(crate) struct TwoQueueCache<V> {
+    capacity: usize,
+    // Share of the capacity for A1in (Kin in the paper).
+    recent_capacity: usize,
+    // Combined charge of the keys remembered in A1out (Kout in the paper).
+    ghost_capacity: usize,
+    state: Mutex<TwoQueueState<V>>,
+}
+
+// A pointer to an entry, used by a handle to release it.
+struct EntryRef<V>(*mut TwoQueueHandle<V>);
+
+// SAFETY: The entry is only followed while holding the shard's mutex, and the handle's reference
+// keeps it allocated
+unsafe impl<V> Send for EntryRef<V> {}
+unsafe impl<V> Sync for EntryRef<V> {}
+
+impl<V> EntryRef<V> {
+    // NOTE: A method so closures capture the whole EntryRef rather than the raw pointer
+    fn get(&self) -> *mut TwoQueueHandle<V> {
+        self.0
+    }
+}
+
+impl<V: Send + Sync + 'static> TwoQueueCache<V> {
+    fn release(&self, e: *mut TwoQueueHandle<V>) {
+        self.state.lock().unwrap().unref(e);
+    }
+
+    fn new_handle(self: &Arc<Self>, value: Arc<V>, e: *mut TwoQueueHandle<V>) -> Handle<V> {
+        let shard = Arc::clone(self);
+        let e = EntryRef(e);
+        Handle::new(value, move || shard.release(e.get()))
+    }
+}
+
+impl<V: Send + Sync + 'static> CacheShard<V> for TwoQueueCache<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        // The paper recommends a quarter of the capacity for A1in and remembering about half as
+        // many entries as the cache holds.
+        Self {
+            capacity,
+            recent_capacity: capacity / 4,
+            ghost_capacity: capacity / 2,
+            state: Mutex::new(TwoQueueState {
+                table: HashMap::new(),
+                recent: TwoQueueHandle::new_list(),
+                recent_usage: 0,
+                frequent: TwoQueueHandle::new_list(),
+                in_use: TwoQueueHandle::new_list(),
+                usage: 0,
+                ghosts: TwoQueueHandle::new_list(),
+                ghost_table: HashMap::new(),
+                ghost_usage: 0,
+            }),
+        }
+    }
+
+    fn insert(
+        self: &Arc<Self>,
+        key: &Slice,
+        _hash: u32,
+        value: Arc<V>,
+        charge: usize,
+    ) -> Handle<V> {
+        if self.capacity == 0 {
+            // A capacity of zero turns off caching, as it does for LRUCache.
+            return Handle::new(value, || {});
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let key = key.as_bytes();
+        let queue = match state.table.remove(key) {
+            Some(old) => {
+                // Replacing a cached entry keeps it in Am if it had made it there.
+                let queue = unsafe { (*old).queue };
+                state.finish_erase(old);
+                queue
+            }
+            None if state.remove_ghost(key) => Queue::Main,
+            None => Queue::In,
+        };
+
+        let e = Box::into_raw(Box::new(TwoQueueHandle {
+            value: Some(Arc::clone(&value)),
+            next: null_mut(),
+            prev: null_mut(),
+            charge,
+            in_cache: true,
+            refs: 2, // for the cache's reference and the returned handle.
+            queue,
+            key_data: key.into(),
+        }));
+        TwoQueueState::list_append(&mut *state.in_use, e);
+        state.table.insert(key.into(), e);
+        state.usage += charge;
+        if queue == Queue::In {
+            state.recent_usage += charge;
+        }
+        state.evict(self.capacity, self.recent_capacity, self.ghost_capacity);
+
+        self.new_handle(value, e)
+    }
+
+    fn lookup(self: &Arc<Self>, key: &Slice, _hash: u32) -> Option<Handle<V>> {
+        let mut state = self.state.lock().unwrap();
+        let e = *state.table.get(key.as_bytes())?;
+        state.ref_(e);
+        unsafe {
+            // The entry goes to the newest end of Am once released.
+            if (*e).queue == Queue::In {
+                state.recent_usage -= (*e).charge;
+                (*e).queue = Queue::Main;
+            }
+            let value = Arc::clone((*e).value.as_ref().unwrap());
+            Some(self.new_handle(value, e))
+        }
+    }
+
+    fn erase(&self, key: &Slice, _hash: u32) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(e) = state.table.remove(key.as_bytes()) {
+            state.finish_erase(e);
+        }
+    }
+
+    fn prune(&self) {
+        let mut state = self.state.lock().unwrap();
+        for queue in [Queue::In, Queue::Main] {
+            while let Some(e) = state.oldest(queue) {
+                let removed = state.table.remove(unsafe { &*(*e).key_data }).unwrap();
+                state.finish_erase(removed);
+            }
+        }
+    }
+
+    fn total_charge(&self) -> usize {
+        self.state.lock().unwrap().usage
+    }
+}
+
+impl<V> Drop for TwoQueueCache<V> {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        // Handles keep the shard alive, so none are outstanding
+        assert!(std::ptr::eq(state.in_use.next, &*state.in_use));
+        for (_, e) in state.table.drain() {
+            unsafe {
+                assert_eq!((*e).refs, 1);
+                drop(Box::from_raw(e));
+            }
+        }
+        for (_, g) in state.ghost_table.drain() {
+            drop(unsafe { Box::from_raw(g) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_shard(capacity: usize) -> Arc<TwoQueueCache<i32>> {
+        Arc::new(TwoQueueCache::with_capacity(capacity))
+    }
+
+    fn insert(cache: &Arc<TwoQueueCache<i32>>, key: i32) {
+        drop(cache.insert(&key.to_string().as_str().into(), 0, Arc::new(key), 1));
+    }
+
+    fn lookup(cache: &Arc<TwoQueueCache<i32>>, key: i32) -> bool {
+        let found = cache.lookup(&key.to_string().as_str().into(), 0);
+        if let Some(h) = &found {
+            assert_eq!(key, **h);
+        }
+        found.is_some()
+    }
+
+    // Look a key up, inserting it on a miss like the block cache does.
+    fn access(cache: &Arc<TwoQueueCache<i32>>, key: i32) -> bool {
+        let hit = lookup(cache, key);
+        if !hit {
+            insert(cache, key);
+        }
+        hit
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = new_shard(10);
+        assert!(!lookup(&cache, 1));
+        insert(&cache, 1);
+        insert(&cache, 2);
+        assert!(lookup(&cache, 1));
+        assert!(lookup(&cache, 2));
+        assert_eq!(2, cache.total_charge());
+
+        cache.erase(&"1".into(), 0);
+        assert!(!lookup(&cache, 1));
+        assert_eq!(1, cache.total_charge());
+    }
+
+    #[test]
+    fn test_reinserted_entries_are_promoted() {
+        let cache = new_shard(8);
+        for i in 0..8 {
+            insert(&cache, i);
+        }
+        // 8 pushes 0 out of A1in and into A1out; coming back it is admitted to Am
+        insert(&cache, 8);
+        assert!(!lookup(&cache, 0));
+        insert(&cache, 0);
+        let e = cache.state.lock().unwrap().table[&b"0"[..]];
+        assert_eq!(Queue::Main, unsafe { (*e).queue });
+        assert_eq!(8, cache.total_charge());
+    }
+
+    #[test]
+    fn test_hits_are_promoted() {
+        let cache = new_shard(8);
+        insert(&cache, 0);
+        assert!(lookup(&cache, 0));
+        for i in 1..20 {
+            insert(&cache, i);
+        }
+        assert!(lookup(&cache, 0));
+        assert!(!lookup(&cache, 1));
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        let cache = new_shard(100);
+        for i in (0..50).chain(0..50) {
+            access(&cache, i);
+        }
+        // Reads that push 0..50 out of A1in before they are read again are admitted too
+        for i in (1000..1050).chain(2000..2040).chain(1000..1025) {
+            access(&cache, i);
+        }
+
+        // A long scan of keys that are never read again
+        for i in 10000..20000 {
+            access(&cache, i);
+        }
+        for i in (0..50).chain(1000..1025) {
+            assert!(lookup(&cache, i), "{} was evicted by the scan", i);
+        }
+        assert_eq!(100, cache.total_charge());
+    }
+
+    #[test]
+    fn test_pinned_entries_are_not_evicted() {
+        let cache = new_shard(4);
+        let pinned: Vec<_> = (0..4)
+            .map(|i| cache.insert(&i.to_string().as_str().into(), 0, Arc::new(i), 1))
+            .collect();
+        insert(&cache, 4);
+        assert_eq!(5, cache.total_charge());
+
+        drop(pinned);
+        insert(&cache, 5);
+        assert_eq!(4, cache.total_charge());
+        assert!(lookup(&cache, 5));
+    }
+
+    #[test]
+    fn test_handle_to_replaced_entry() {
+        let cache = new_shard(10);
+        let old = cache.insert(&"a".into(), 0, Arc::new(1), 1);
+        drop(cache.insert(&"a".into(), 0, Arc::new(2), 1));
+        let new = cache.lookup(&"a".into(), 0).unwrap();
+
+        // Releasing the handle to the replaced entry must not unpin its replacement
+        drop(old);
+        cache.prune();
+        assert_eq!(2, *new);
+        drop(new);
+        cache.prune();
+        assert_eq!(0, cache.total_charge());
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let cache = new_shard(0);
+        let h = cache.insert(&"a".into(), 0, Arc::new(1), 1);
+        assert_eq!(1, *h);
+        assert!(!lookup(&cache, 1));
+    }
+}